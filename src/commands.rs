@@ -0,0 +1,266 @@
+//! Command lines terminals run to attach to their session
+//!
+//! Attach commands nest several layers of quoting (the terminal's `bash -c`,
+//! ssh, the remote bash). They are built here, apart from the code spawning
+//! terminals, so the quoting can be tested without a WM or a remote host.
+
+use crate::config::{self, Config, Shell};
+use crate::connection;
+
+/// ssh options every attach connection uses, sharing one master per user, host and port
+pub const SSH_MASTER_OPTIONS: &str =
+    "-o ControlPath=/tmp/i3mux/sockets/%r@%h:%p -o ControlMaster=auto -o ControlPersist=10m";
+
+/// A command line for `bash -c`, plus variables to set on the terminal process
+///
+/// Values passed through the environment need no quoting at all, so local
+/// commands prefer it for anything user-supplied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    pub line: String,
+    pub env: Vec<(&'static str, String)>,
+}
+
+impl CommandSpec {
+    fn new(line: String) -> Self {
+        Self { line, env: Vec::new() }
+    }
+}
+
+/// Attach command for one terminal: its host ("local" or `user@host`) and abduco socket
+pub struct AttachCommand<'a> {
+    pub config: &'a Config,
+    pub host: &'a str,
+    pub socket: &'a str,
+    /// Session the workspace is saved as, for per-session shell settings
+    pub session: Option<&'a str>,
+    /// Shell for new local sessions when none is configured (the user's $SHELL)
+    pub user_shell: &'a str,
+    /// Sent to the remote helper as CLIENT_ENV, naming this machine
+    pub client: &'a str,
+}
+
+impl AttachCommand<'_> {
+    fn shell(&self) -> Shell {
+        self.config.shell_for(self.host, self.session)
+    }
+
+    /// Local terminals, and every terminal under `--offline`
+    fn is_local(&self) -> bool {
+        self.host == "local" || connection::is_offline()
+    }
+
+    /// `TERM=... ` for local terminals, empty to keep the emulator's TERM
+    fn local_term(&self) -> String {
+        self.config.term_for(self.host).map(|t| format!("TERM={} ", t)).unwrap_or_default()
+    }
+
+    /// `ssh ... host '<remote>'` with the host's environment and option settings
+    fn ssh(&self, tty: &str, remote: &str) -> String {
+        format!(
+            "{}TERM={} ssh {}{} {} {} '{}={} {}'",
+            self.config.attach_env_prefix(self.host),
+            self.config.term_for(self.host).unwrap_or(config::DEFAULT_REMOTE_TERM),
+            SSH_MASTER_OPTIONS,
+            self.config.attach_ssh_options(self.host),
+            tty,
+            self.host,
+            connection::CLIENT_ENV,
+            self.client,
+            remote
+        )
+    }
+
+    /// Attach command for a new terminal, running `exec` instead of a shell in a new session
+    pub fn launch(&self, exec: Option<&str>) -> CommandSpec {
+        let shell = self.shell();
+        if self.is_local() {
+            let cmd_to_run = exec.map(String::from).unwrap_or_else(|| shell.session_command(self.user_shell, None));
+            let mut spec = CommandSpec::new(format!("{}abduco -A /tmp/{} {}", self.local_term(), self.socket, cmd_to_run));
+            if let Some(rc) = shell.rc {
+                spec.env.push((Shell::RC_ENV, rc));
+            }
+            return spec;
+        }
+
+        // The helper sets up PATH and TERM remotely before attaching
+        let remote = match exec {
+            Some(exec) => format!(r#"{} -c "exec {} attach {} -- {}""#, shell.remote_bash(), crate::remote_helper_path(), self.socket, exec),
+            None => format!(
+                r#"{}{} -c "exec {} attach {}""#,
+                shell.remote_env(),
+                shell.remote_bash(),
+                crate::remote_helper_path(),
+                self.socket
+            ),
+        };
+        CommandSpec::new(self.ssh("-tt", &remote))
+    }
+
+    /// Attach command for a terminal restored from a layout
+    ///
+    /// Attaching recreates a session that is gone; it then runs `run` (the
+    /// command recorded at detach) before the shell.
+    pub fn restore(&self, run: Option<&str>) -> CommandSpec {
+        let shell = self.shell();
+        if self.is_local() {
+            // The command and the rc go through the environment to avoid quoting
+            let mut env = Vec::new();
+            if let Some(rc) = &shell.rc {
+                env.push((Shell::RC_ENV, rc.clone()));
+            }
+            let run = run.map(|command| {
+                env.push(("I3MUX_RUN", command.to_string()));
+                r#"eval "$I3MUX_RUN""#
+            });
+            let line = format!("{}abduco -A /tmp/{} {}", self.local_term(), self.socket, shell.session_command(self.user_shell, run));
+            return CommandSpec { line, env };
+        }
+
+        // Hex-encode the command to restart so it survives the nested quoting
+        let run_arg = run
+            .map(|c| format!(" --run {}", c.bytes().map(|b| format!("{:02x}", b)).collect::<String>()))
+            .unwrap_or_default();
+        let remote = format!(
+            r#"{}exec {} -c "{} attach {}{}""#,
+            shell.remote_env(),
+            shell.remote_bash(),
+            crate::remote_helper_path(),
+            self.socket,
+            run_arg
+        );
+        CommandSpec::new(self.ssh("-t", &remote))
+    }
+}
+
+/// Command to attach to a terminal's session by hand (shown by `i3mux which`)
+pub fn manual_attach(host: &str, socket: &str) -> String {
+    if host == "local" {
+        format!("abduco -a /tmp/{}", socket)
+    } else {
+        format!(r#"ssh -t {} 'bash -lc "{} attach {}"'"#, host, crate::remote_helper_path(), socket)
+    }
+}
+
+/// A command for `i3mux exec-all`, with one terminal's values filled in
+///
+/// `{index}` is the terminal's 1-based position in the workspace, `{name}` its
+/// window title (or socket when untitled) and `{socket}` its socket; values go
+/// in as they are, without quoting. Other braces (`${HOME}`) are left alone.
+pub fn fill_template(template: &str, index: usize, name: &str, socket: &str) -> String {
+    template.replace("{index}", &index.to_string()).replace("{name}", name).replace("{socket}", socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attach<'a>(config: &'a Config, host: &'a str, session: Option<&'a str>) -> AttachCommand<'a> {
+        AttachCommand { config, host, socket: "ws2-001", session, user_shell: "/bin/bash", client: "laptop" }
+    }
+
+    #[test]
+    fn test_fill_template() {
+        assert_eq!(fill_template("source ~/.env", 1, "api", "ws2-001"), "source ~/.env");
+        assert_eq!(
+            fill_template("echo {index} {name} > /tmp/{socket}.log; cd ${HOME}", 3, "db", "ws2-004"),
+            "echo 3 db > /tmp/ws2-004.log; cd ${HOME}"
+        );
+        assert_eq!(fill_template("{index}{index}", 12, "", ""), "1212");
+    }
+
+    #[test]
+    fn test_local_commands() {
+        let config = Config::default();
+        let local = attach(&config, "local", None);
+        assert_eq!(local.launch(None), CommandSpec::new("abduco -A /tmp/ws2-001 /bin/bash".into()));
+        assert_eq!(local.launch(Some("htop -d 5")).line, "abduco -A /tmp/ws2-001 htop -d 5");
+
+        // The recorded command travels in the environment, quotes and all
+        let spec = local.restore(Some(r#"tail -f "my log.txt" | grep 'a b'"#));
+        assert_eq!(spec.line, r#"abduco -A /tmp/ws2-001 /bin/bash -c 'eval "$I3MUX_RUN"; exec /bin/bash'"#);
+        assert_eq!(spec.env, [("I3MUX_RUN", r#"tail -f "my log.txt" | grep 'a b'"#.to_string())]);
+    }
+
+    #[test]
+    fn test_local_shell_settings() {
+        let config = Config::parse(
+            r#"
+            term = "tmux-256color"
+
+            [hosts.local]
+            shell = "zsh"
+            shell_rc = "cd ~/src && export X='$HOME'"
+            "#,
+        )
+        .unwrap();
+        let spec = attach(&config, "local", None).launch(None);
+        assert_eq!(spec.line, r#"TERM=tmux-256color abduco -A /tmp/ws2-001 zsh -c 'eval "$I3MUX_RC"; exec zsh'"#);
+        assert_eq!(spec.env, [("I3MUX_RC", "cd ~/src && export X='$HOME'".to_string())]);
+    }
+
+    #[test]
+    fn test_remote_commands() {
+        let config = Config::default();
+        let remote = attach(&config, "deploy@build-01.example.com", None);
+        let ssh = "TERM=xterm-256color ssh -o ControlPath=/tmp/i3mux/sockets/%r@%h:%p -o ControlMaster=auto -o ControlPersist=10m";
+
+        let spec = remote.launch(None);
+        assert_eq!(
+            spec.line,
+            format!(r#"{} -tt deploy@build-01.example.com 'I3MUX_CLIENT=laptop bash -l -c "exec /tmp/i3mux-helper.sh attach ws2-001"'"#, ssh)
+        );
+        assert!(spec.env.is_empty());
+
+        assert_eq!(
+            remote.launch(Some("htop -d 5")).line,
+            format!(r#"{} -tt deploy@build-01.example.com 'I3MUX_CLIENT=laptop bash -l -c "exec /tmp/i3mux-helper.sh attach ws2-001 -- htop -d 5"'"#, ssh)
+        );
+
+        // Quotes in the recorded command would end the ssh argument; hex keeps them inert
+        assert_eq!(
+            remote.restore(Some("echo 'hi'")).line,
+            format!(r#"{} -t deploy@build-01.example.com 'I3MUX_CLIENT=laptop exec bash -l -c "/tmp/i3mux-helper.sh attach ws2-001 --run 6563686f2027686927"'"#, ssh)
+        );
+
+        // Hosts without a user (and ports from ssh_config) work as ssh sees them;
+        // %p in the ControlPath keeps masters for different ports apart
+        assert!(attach(&config, "build", None).launch(None).line.contains(" -tt build 'I3MUX_CLIENT=laptop "));
+    }
+
+    #[test]
+    fn test_remote_host_settings() {
+        let config = Config::parse(
+            r#"
+            [hosts."user@prod"]
+            forward_agent = true
+            send_env = ["LC_*"]
+            term = "screen-256color"
+            login_shell = false
+
+            [sessions.web]
+            shell = "fish"
+            shell_rc = "echo 'it''s'"
+            "#,
+        )
+        .unwrap();
+
+        let line = attach(&config, "user@prod", Some("web")).launch(None).line;
+        assert_eq!(
+            line,
+            format!(
+                r#"TERM=screen-256color ssh {} -o ForwardAgent=yes -o 'SendEnv=LC_*' -tt user@prod 'I3MUX_CLIENT=laptop I3MUX_SHELL=fish I3MUX_RC=6563686f2027697427277327 bash -c "exec /tmp/i3mux-helper.sh attach ws2-001"'"#,
+                SSH_MASTER_OPTIONS
+            )
+        );
+    }
+
+    #[test]
+    fn test_manual_attach() {
+        assert_eq!(manual_attach("local", "ws1-002"), "abduco -a /tmp/ws1-002");
+        assert_eq!(
+            manual_attach("user@box", "ws1-002"),
+            r#"ssh -t user@box 'bash -lc "/tmp/i3mux-helper.sh attach ws1-002"'"#
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Window manager compatibility for layout restore
+//!
+//! Sessions capture split sizes as the `percent` each container occupies of
+//! its parent, whichever WM they were captured under (Sway's are worked out
+//! from the rects at capture). Applying them differs:
+//!
+//! - i3 interprets `resize set ... ppt` relative to the container's parent
+//! - Sway interprets it relative to the whole workspace
+//!
+//! This module translates captured sizes into resize commands for the target
+//! backend, so a session saved under i3 restores with the same proportions
+//! under Sway and vice versa.
+
+use crate::layout::Layout;
+use crate::wm::WmType;
+
+/// A resize to apply after the layout structure has been restored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resize {
+    /// A terminal inside the container to resize (the WM resizes the ancestor
+    /// that sits in the split along the resize axis)
+    pub socket: String,
+    /// Resize command without criteria (e.g. "resize set width 70 ppt")
+    pub command: String,
+}
+
+/// Resize commands that reproduce the captured split sizes on `target`
+///
+/// Splits where any child lacks a captured size are left at equal sizes. The
+/// last child of each split is never resized; it takes up the remainder.
+pub fn resize_plan(layout: &Layout, target: WmType) -> Vec<Resize> {
+    let mut plan = Vec::new();
+    collect_resizes(layout, target, 1.0, 1.0, &mut plan);
+    plan
+}
+
+/// Walk the layout, tracking the fraction of the workspace each container spans
+fn collect_resizes(layout: &Layout, target: WmType, width: f64, height: f64, plan: &mut Vec<Resize>) {
+    let (children, horizontal) = match layout {
+        Layout::Terminal { .. } => return,
+        Layout::HSplit { children, .. } => (children, Some(true)),
+        Layout::VSplit { children, .. } => (children, Some(false)),
+        Layout::Tabbed { children } | Layout::Stacked { children } => (children, None),
+    };
+
+    let percents: Option<Vec<f64>> = children.iter().map(child_percent).collect();
+
+    for (i, child) in children.iter().enumerate() {
+        let percent = percents.as_ref().map(|p| p[i]);
+
+        let (child_width, child_height) = match (horizontal, percent) {
+            (Some(true), Some(p)) => (width * p, height),
+            (Some(false), Some(p)) => (width, height * p),
+            (Some(true), None) => (width / children.len() as f64, height),
+            (Some(false), None) => (width, height / children.len() as f64),
+            (None, _) => (width, height),
+        };
+
+        if let (Some(horizontal), Some(p), Some(socket)) = (horizontal, percent, child.get_first_socket()) {
+            if i + 1 < children.len() {
+                let (axis, fraction) = match (horizontal, target) {
+                    (true, WmType::I3) => ("width", p),
+                    (false, WmType::I3) => ("height", p),
+                    (true, WmType::Sway) => ("width", child_width),
+                    (false, WmType::Sway) => ("height", child_height),
+                };
+                plan.push(Resize {
+                    socket,
+                    command: format!("resize set {} {} ppt", axis, (fraction * 100.0).round() as u32),
+                });
+            }
+        }
+
+        collect_resizes(child, target, child_width, child_height, plan);
+    }
+}
+
+fn child_percent(layout: &Layout) -> Option<f64> {
+    match layout {
+        Layout::Terminal { percent, .. } | Layout::HSplit { percent, .. } | Layout::VSplit { percent, .. } => {
+            percent.filter(|p| *p > 0.0 && *p <= 1.0)
+        }
+        // Tabbed/stacked containers don't carry a size of their own
+        Layout::Tabbed { .. } | Layout::Stacked { .. } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(socket: &str, percent: Option<f64>) -> Layout {
+        Layout::Terminal {
+            socket: socket.to_string(),
+            percent,
+            command: None,
+            host: None,
+            attach: None,
+            target: None,
+            title: None,
+        }
+    }
+
+    fn commands(plan: &[Resize]) -> Vec<(&str, &str)> {
+        plan.iter().map(|r| (r.socket.as_str(), r.command.as_str())).collect()
+    }
+
+    #[test]
+    fn test_top_level_split_same_on_both() {
+        let layout = Layout::HSplit {
+            children: vec![term("ws1-001", Some(0.7)), term("ws1-002", Some(0.3))],
+            percent: None,
+        };
+        let expected = vec![("ws1-001", "resize set width 70 ppt")];
+        assert_eq!(commands(&resize_plan(&layout, WmType::I3)), expected);
+        assert_eq!(commands(&resize_plan(&layout, WmType::Sway)), expected);
+    }
+
+    #[test]
+    fn test_nested_split_relative_to_workspace_on_sway() {
+        // Left 60% | right 40% split into two columns of 75% / 25%
+        let layout = Layout::HSplit {
+            children: vec![
+                term("ws1-001", Some(0.6)),
+                Layout::HSplit {
+                    children: vec![term("ws1-002", Some(0.75)), term("ws1-003", Some(0.25))],
+                    percent: Some(0.4),
+                },
+            ],
+            percent: None,
+        };
+
+        assert_eq!(
+            commands(&resize_plan(&layout, WmType::I3)),
+            vec![("ws1-001", "resize set width 60 ppt"), ("ws1-002", "resize set width 75 ppt")]
+        );
+        assert_eq!(
+            commands(&resize_plan(&layout, WmType::Sway)),
+            vec![("ws1-001", "resize set width 60 ppt"), ("ws1-002", "resize set width 30 ppt")]
+        );
+    }
+
+    #[test]
+    fn test_missing_percent_leaves_split_alone() {
+        let layout = Layout::VSplit {
+            children: vec![
+                term("ws1-001", None),
+                Layout::HSplit {
+                    children: vec![term("ws1-002", Some(0.2)), term("ws1-003", Some(0.8))],
+                    percent: None,
+                },
+            ],
+            percent: None,
+        };
+
+        // The vsplit is skipped, but the nested hsplit is still sized
+        assert_eq!(
+            commands(&resize_plan(&layout, WmType::Sway)),
+            vec![("ws1-002", "resize set width 20 ppt")]
+        );
+    }
+}
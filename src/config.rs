@@ -0,0 +1,953 @@
+//! User configuration for i3mux
+//!
+//! Configuration is read from `~/.config/i3mux/config.toml`. Every field is
+//! optional; a missing file is equivalent to an empty one.
+//!
+//! ## Example
+//!
+//! ```toml
+//! # Remote used by `i3mux toggle` when --remote is not given
+//! default_remote = "user@devbox"
+//!
+//! # Terminal titles: "marker" sets "i3mux:host:socket", "hidden" leaves them alone
+//! title_mode = "marker"
+//! title_marker = "i3mux:"
+//!
+//! # Kill a terminal's abduco session when its window is closed externally
+//! kill_on_close = false
+//!
+//! # Make `i3mux kill` also terminate the session's abduco processes
+//! kill_terminates = false
+//!
+//! # Programs restarted on attach if their terminal's session is gone
+//! restore_programs = ["ssh", "tail", "htop", "watch"]
+//!
+//! # Record attach/detach durations locally for `i3mux stats`
+//! metrics = false
+//!
+//! # TERM for attached terminals (remote default: "xterm-256color")
+//! term = "tmux-256color"
+//!
+//! # When detach finds the session saved from elsewhere since the attach:
+//! # "ask" (abort unless on a terminal), "abort", "overwrite" or "merge"
+//! on_conflict = "ask"
+//!
+//! # Adapt layouts saved on a bigger screen: "keep", "collapse-to-tabs" or "map"
+//! [reflow]
+//! policy = "collapse-to-tabs"
+//! min_width = 400
+//! min_height = 150
+//! map = { hsplit = "vsplit" }
+//!
+//! # Other names for the same machine; marks, sessions, locks and ssh
+//! # connections all use the canonical name on the right
+//! [aliases]
+//! "devbox" = "user@devbox.lan"
+//! "devbox.lan" = "user@devbox.lan"
+//!
+//! # Per-host settings (keyed by canonical name)
+//! [hosts."user@prod"]
+//! border = "normal 3"
+//! # Title color: i3/Sway can't color one window's border
+//! color = "#ff0000"
+//! forward_agent = true
+//! send_env = ["LANG", "COLORTERM", "LC_*"]
+//! strip_env = ["LC_*"]
+//! term = "screen-256color"
+//! shell = "zsh"
+//! login_shell = true
+//! shell_rc = "cd ~/src"
+//! # Keep sessions/ and locks/ here instead of /tmp/i3mux-<uid>
+//! storage_dir = "/srv/state/$USER/i3mux"
+//!
+//! # Per-session settings (take precedence over host settings)
+//! [sessions.webdev]
+//! color = "#00aa00"
+//!
+//! # Host groups, used as `--remote @cluster` ("{1..8}" expands to 1 through 8)
+//! [groups]
+//! cluster = ["user@node{1..8}", "user@bastion"]
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::reflow::ReflowConfig;
+use crate::session::ConflictPolicy;
+
+/// Default prefix for terminal titles ("i3mux:host:socket")
+pub const DEFAULT_TITLE_MARKER: &str = "i3mux:";
+
+/// TERM for remote terminals unless configured otherwise
+pub const DEFAULT_REMOTE_TERM: &str = "xterm-256color";
+
+/// User configuration loaded from `config.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Remote host used by `toggle` when no --remote is given
+    pub default_remote: Option<String>,
+
+    /// Whether i3mux sets terminal titles
+    pub title_mode: TitleMode,
+
+    /// Prefix for terminal titles in marker mode (defaults to "i3mux:")
+    pub title_marker: Option<String>,
+
+    /// Kill a terminal's abduco session when its window is closed externally
+    /// (e.g. `i3-msg kill`) instead of leaving it running detached
+    pub kill_on_close: bool,
+
+    /// Make `kill` terminate the session's abduco sessions by default
+    /// (overridable per invocation with --terminate / --keep-running)
+    pub kill_terminates: bool,
+
+    /// Rename workspaces bound to a remote host to "{num}: {glyph} {host}" while
+    /// they are bound, so the bar tells them apart
+    pub workspace_labels: bool,
+
+    /// Programs that may be re-run on attach when a terminal's abduco session
+    /// no longer exists (matched against the recorded command's program name)
+    pub restore_programs: Vec<String>,
+
+    /// Record operation durations and failures in
+    /// `~/.local/state/i3mux/metrics.json` (shown by `i3mux stats`)
+    pub metrics: bool,
+
+    /// TERM for attached terminals on every host (overridable per host)
+    pub term: Option<String>,
+
+    /// How `attach` adapts layouts to a workspace of a different size
+    pub reflow: ReflowConfig,
+
+    /// What `detach` does when the session was saved from elsewhere since the attach
+    pub on_conflict: ConflictPolicy,
+
+    /// Other spellings of a host, mapped to its canonical name
+    pub aliases: HashMap<String, String>,
+
+    /// Per-host settings, keyed by host ("local" for local sessions)
+    pub hosts: HashMap<String, HostConfig>,
+
+    /// Per-session settings, keyed by session name
+    pub sessions: HashMap<String, SessionConfig>,
+
+    /// Host groups, keyed by name (used as `@name`); entries may contain `{a..b}` ranges
+    pub groups: HashMap<String, Vec<String>>,
+}
+
+/// How i3mux titles its terminals
+///
+/// Windows are identified by hidden marks, so the title is purely cosmetic
+/// unless i3 `for_window [title=...]` rules depend on it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleMode {
+    /// Title terminals "{marker}{host}:{socket}" and keep it set from the prompt
+    #[default]
+    Marker,
+    /// Leave titles to the shell and programs running in the terminal
+    Hidden,
+}
+
+/// Settings applied to every terminal on a host
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+    /// Border style applied after marking (e.g. "pixel 3", "normal 2", "none")
+    pub border: Option<String>,
+
+    /// Title color as `#rrggbb` (rendered via pango markup in title_format);
+    /// i3 and Sway have no per-window border color, so this colors the title
+    pub color: Option<String>,
+
+    /// Forward the local SSH agent into interactive attach sessions
+    pub forward_agent: bool,
+
+    /// Environment variables (or patterns like `LC_*`) sent to interactive
+    /// attach sessions; the server's `AcceptEnv` must allow them
+    pub send_env: Vec<String>,
+
+    /// Environment variables (or patterns) removed before starting interactive
+    /// attach sessions, so ssh_config's `SendEnv` can't forward them (e.g. locales
+    /// the host doesn't have)
+    pub strip_env: Vec<String>,
+
+    /// TERM for terminals on this host (e.g. "screen-256color")
+    pub term: Option<String>,
+
+    /// Shell started in new sessions (default: the user's $SHELL on the host)
+    pub shell: Option<String>,
+
+    /// Start that shell as a login shell; `false` also skips the login shell
+    /// remote attach commands run in (and with it ~/.profile)
+    pub login_shell: Option<bool>,
+
+    /// Shell code run inside new sessions before the interactive shell starts
+    pub shell_rc: Option<String>,
+
+    /// Glyph shown before the host in workspace labels (see `workspace_labels`)
+    pub glyph: Option<String>,
+
+    /// Directory holding this host's sessions/ and locks/ (remote hosts only);
+    /// may use variables like `$USER`
+    pub storage_dir: Option<String>,
+}
+
+/// Settings applied to every terminal of a session
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Border style applied after marking (overrides the host setting)
+    pub border: Option<String>,
+
+    /// Title color as `#rrggbb` (overrides the host setting)
+    pub color: Option<String>,
+
+    /// Shell started in new sessions (overrides the host setting)
+    pub shell: Option<String>,
+
+    /// Start that shell as a login shell (overrides the host setting)
+    pub login_shell: Option<bool>,
+
+    /// Shell code run inside new sessions (overrides the host setting)
+    pub shell_rc: Option<String>,
+}
+
+/// Resolved shell settings for a terminal's session
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Shell {
+    /// Program to run (None: the user's $SHELL)
+    pub program: Option<String>,
+    /// None keeps the defaults: a plain shell, remote commands in a login bash
+    pub login: Option<bool>,
+    pub rc: Option<String>,
+}
+
+impl Shell {
+    /// Environment variable the local session reads `rc` from
+    pub const RC_ENV: &'static str = "I3MUX_RC";
+
+    /// Command abduco starts for a new local session
+    ///
+    /// `run` is code to run first (e.g. `eval "$I3MUX_RUN"`); `rc` is taken from
+    /// [`Shell::RC_ENV`], which the caller sets on the terminal.
+    pub fn session_command(&self, default_program: &str, run: Option<&str>) -> String {
+        let program = self.program.as_deref().unwrap_or(default_program);
+        let shell = if self.login == Some(true) { format!("{} -l", program) } else { program.to_string() };
+
+        let mut prelude = String::new();
+        if self.rc.is_some() {
+            prelude.push_str(&format!(r#"eval "${}"; "#, Self::RC_ENV));
+        }
+        if let Some(run) = run {
+            prelude.push_str(&format!("{}; ", run));
+        }
+        if prelude.is_empty() {
+            shell
+        } else {
+            format!("{} -c '{}exec {}'", program, prelude, shell)
+        }
+    }
+
+    /// The bash remote attach commands run in (a login shell unless disabled)
+    pub fn remote_bash(&self) -> &'static str {
+        if self.login == Some(false) { "bash" } else { "bash -l" }
+    }
+
+    /// Variable assignments telling the remote helper how to start the shell
+    /// (e.g. "I3MUX_SHELL=zsh I3MUX_LOGIN=1 "); `rc` is hex-encoded to survive quoting
+    pub fn remote_env(&self) -> String {
+        let mut env = String::new();
+        if let Some(program) = &self.program {
+            env.push_str(&format!("I3MUX_SHELL={} ", program));
+        }
+        if self.login == Some(true) {
+            env.push_str("I3MUX_LOGIN=1 ");
+        }
+        if let Some(rc) = &self.rc {
+            let hex: String = rc.bytes().map(|b| format!("{:02x}", b)).collect();
+            env.push_str(&format!("{}={} ", Self::RC_ENV, hex));
+        }
+        env
+    }
+}
+
+/// Resolved window decoration for a terminal
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub border: Option<String>,
+    /// Title text color (border colors are global in i3 and Sway)
+    pub color: Option<String>,
+    /// Title shown in the title bar whatever the shell or program sets
+    pub title: Option<String>,
+}
+
+impl Theme {
+    /// WM commands (without a criteria prefix) that apply this theme to a window
+    pub fn commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        if let Some(border) = &self.border {
+            commands.push(format!("border {}", border));
+        }
+        let title = self.title.as_deref().map(|t| t.replace('\\', "\\\\").replace('"', "\\\""));
+        if let Some(color) = &self.color {
+            let title = title.map_or_else(
+                || "%title".to_string(),
+                |t| t.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"),
+            );
+            commands.push(format!(
+                "title_format \"<span foreground='{}'>{}</span>\"",
+                color, title
+            ));
+        } else if let Some(title) = title {
+            commands.push(format!("title_format \"{}\"", title));
+        }
+        commands
+    }
+}
+
+impl Config {
+    fn path() -> Result<PathBuf> {
+        let config_dir = dirs::config_dir()
+            .context("Could not find config directory")?
+            .join("i3mux");
+        Ok(config_dir.join("config.toml"))
+    }
+
+    /// Load the user configuration, falling back to defaults if no file exists
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        Self::parse(&contents)
+            .with_context(|| format!("Invalid config file: {}", path.display()))
+    }
+
+    /// Parse configuration from TOML text
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config: Config = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Title for a terminal, or None if titles are hidden
+    pub fn title_for(&self, host: &str, socket: &str) -> Option<String> {
+        match self.title_mode {
+            TitleMode::Marker => {
+                let marker = self.title_marker.as_deref().unwrap_or(DEFAULT_TITLE_MARKER);
+                Some(format!("{}{}:{}", marker, host, socket))
+            }
+            TitleMode::Hidden => None,
+        }
+    }
+
+    /// Extra ssh options for interactive attach connections to `host`
+    ///
+    /// Each option is preceded by a space. Control commands never get these,
+    /// so agent access and forwarded variables stay confined to the terminals.
+    pub fn attach_ssh_options(&self, host: &str) -> String {
+        let Some(host_config) = self.hosts.get(host) else {
+            return String::new();
+        };
+        let mut options = String::new();
+        if host_config.forward_agent {
+            options.push_str(" -o ForwardAgent=yes");
+        }
+        for var in &host_config.send_env {
+            // Quoted, since the options end up in a shell command and patterns would glob
+            options.push_str(&format!(" -o 'SendEnv={}'", var));
+        }
+        options
+    }
+
+    /// `env -u ...` prefix for interactive attach commands to `host`
+    ///
+    /// Patterns in `strip_env` are matched against the current environment,
+    /// which terminals inherit. Empty if nothing needs stripping.
+    pub fn attach_env_prefix(&self, host: &str) -> String {
+        let Some(host_config) = self.hosts.get(host) else {
+            return String::new();
+        };
+        let mut names: Vec<String> = std::env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .filter(|name| host_config.strip_env.iter().any(|p| env_pattern_matches(p, name)))
+            .collect();
+        if names.is_empty() {
+            return String::new();
+        }
+        names.sort();
+        format!("env -u {} ", names.join(" -u "))
+    }
+
+    /// TERM configured for terminals on `host`, the host setting winning over the global one
+    ///
+    /// Remote terminals fall back to `DEFAULT_REMOTE_TERM`; local ones keep the
+    /// terminal emulator's TERM unless one is configured.
+    pub fn term_for(&self, host: &str) -> Option<&str> {
+        self.hosts
+            .get(host)
+            .and_then(|h| h.term.as_deref())
+            .or(self.term.as_deref())
+    }
+
+    /// Name for workspace `num` while bound to `host`, or None to leave it as it is
+    ///
+    /// Only remote workspaces are labelled, with the host name without its user
+    /// ("3: devbox", or "3: 🐧 devbox" with a glyph).
+    pub fn workspace_label(&self, num: i32, host: &str) -> Option<String> {
+        if !self.workspace_labels || host == "local" {
+            return None;
+        }
+        let name = host.rsplit_once('@').map_or(host, |(_, name)| name);
+        Some(match self.hosts.get(host).and_then(|h| h.glyph.as_deref()) {
+            Some(glyph) => format!("{}: {} {}", num, glyph, name),
+            None => format!("{}: {}", num, name),
+        })
+    }
+
+    /// The canonical name of `host` (`host` itself unless it is an alias)
+    pub fn canonical_host(&self, host: &str) -> String {
+        self.aliases.get(host).cloned().unwrap_or_else(|| host.to_string())
+    }
+
+    /// Hosts of the group `@name`, with ranges expanded and aliases resolved
+    pub fn group_hosts(&self, name: &str) -> Result<Vec<String>> {
+        let entries = self
+            .groups
+            .get(name)
+            .with_context(|| format!("Unknown host group '@{}' (define it under [groups] in config.toml)", name))?;
+        let mut hosts = Vec::new();
+        for entry in entries {
+            hosts.extend(expand_host_range(entry)?.iter().map(|host| self.canonical_host(host)));
+        }
+        hosts.dedup();
+        Ok(hosts)
+    }
+
+    /// Every remote host the config names: the default remote, per-host
+    /// settings, alias targets and group members, canonical and sorted
+    pub fn configured_hosts(&self) -> Result<Vec<String>> {
+        let mut hosts: std::collections::BTreeSet<String> =
+            self.default_remote.iter().chain(self.hosts.keys()).map(|host| self.canonical_host(host)).collect();
+        hosts.extend(self.aliases.values().cloned());
+        for name in self.groups.keys() {
+            hosts.extend(self.group_hosts(name)?);
+        }
+        hosts.remove("local");
+        Ok(hosts.into_iter().collect())
+    }
+
+    /// Where i3mux keeps its files on remote `host`
+    pub fn remote_paths(&self, host: &str) -> crate::connection::RemotePaths {
+        match self.hosts.get(host).and_then(|h| h.storage_dir.as_deref()) {
+            Some(dir) => crate::connection::RemotePaths::under(dir),
+            None => crate::connection::RemotePaths::remote_default(),
+        }
+    }
+
+    /// Whether a command recorded at detach may be re-run on attach
+    pub fn may_restore(&self, command: &str) -> bool {
+        let program = command
+            .split_whitespace()
+            .next()
+            .map(|p| p.rsplit('/').next().unwrap_or(p))
+            .unwrap_or("");
+        !program.is_empty() && self.restore_programs.iter().any(|p| p == program)
+    }
+
+    /// Resolve the window theme for a terminal, session settings winning over host settings
+    pub fn theme_for(&self, host: &str, session: Option<&str>) -> Theme {
+        let host_config = self.hosts.get(host);
+        let session_config = session.and_then(|s| self.sessions.get(s));
+
+        Theme {
+            border: session_config
+                .and_then(|s| s.border.clone())
+                .or_else(|| host_config.and_then(|h| h.border.clone())),
+            color: session_config
+                .and_then(|s| s.color.clone())
+                .or_else(|| host_config.and_then(|h| h.color.clone())),
+            title: None,
+        }
+    }
+
+    /// Resolve the shell settings for a terminal, session settings winning over host settings
+    pub fn shell_for(&self, host: &str, session: Option<&str>) -> Shell {
+        let host_config = self.hosts.get(host);
+        let session_config = session.and_then(|s| self.sessions.get(s));
+
+        Shell {
+            program: session_config
+                .and_then(|s| s.shell.clone())
+                .or_else(|| host_config.and_then(|h| h.shell.clone())),
+            login: session_config
+                .and_then(|s| s.login_shell)
+                .or_else(|| host_config.and_then(|h| h.login_shell)),
+            rc: session_config
+                .and_then(|s| s.shell_rc.clone())
+                .or_else(|| host_config.and_then(|h| h.shell_rc.clone())),
+        }
+    }
+
+    // Values end up in WM commands, so reject anything that isn't well-formed
+    fn validate(&self) -> Result<()> {
+        if let Some(marker) = &self.title_marker {
+            if marker.chars().any(|c| c.is_control() || "'\"\\$`".contains(c)) {
+                anyhow::bail!(
+                    "Invalid title_marker '{}': quotes, backslashes, '$', '`' and control characters are not allowed",
+                    marker
+                );
+            }
+        }
+
+        let terms = self.hosts.iter().map(|(k, h)| (k.as_str(), &h.term)).chain([("term", &self.term)]);
+        for (key, term) in terms {
+            if let Some(term) = term {
+                if term.is_empty() || !term.chars().all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c)) {
+                    anyhow::bail!("Invalid TERM '{}' for '{}': expected a terminfo name like 'tmux-256color'", term, key);
+                }
+            }
+        }
+
+        for (key, host) in &self.hosts {
+            let vars = host.send_env.iter().map(|v| ("send_env", v));
+            for (setting, var) in vars.chain(host.strip_env.iter().map(|v| ("strip_env", v))) {
+                let valid = !var.is_empty()
+                    && !var.starts_with(|c: char| c.is_ascii_digit())
+                    && var.chars().all(|c| c.is_ascii_alphanumeric() || "_*?".contains(c));
+                if !valid {
+                    anyhow::bail!("Invalid {} entry '{}' for '{}': expected a variable name or pattern", setting, var, key);
+                }
+            }
+
+            // Ends up quoted in a WM command
+            if let Some(glyph) = &host.glyph {
+                if glyph.is_empty() || glyph.chars().any(|c| c.is_control() || c.is_whitespace() || "\"\\".contains(c)) {
+                    anyhow::bail!("Invalid glyph '{}' for '{}': expected a symbol without spaces or quotes", glyph, key);
+                }
+            }
+
+            // Expanded by the remote shell, so variables are fine but nothing that runs commands
+            if let Some(dir) = &host.storage_dir {
+                if key == "local" {
+                    anyhow::bail!("Invalid storage_dir for 'local': only remote hosts' storage can be moved");
+                }
+                let valid = dir.starts_with(['/', '$'])
+                    && !dir.contains("..")
+                    && dir.chars().all(|c| c.is_ascii_alphanumeric() || "/_.-+@${}".contains(c));
+                if !valid {
+                    anyhow::bail!(
+                        "Invalid storage_dir '{}' for '{}': expected an absolute path like '/srv/state/$USER/i3mux'",
+                        dir, key
+                    );
+                }
+            }
+        }
+
+        let shells = self.hosts.iter().map(|(k, h)| (k, &h.shell)).chain(self.sessions.iter().map(|(k, s)| (k, &s.shell)));
+        for (key, shell) in shells {
+            if let Some(shell) = shell {
+                if shell.is_empty() || !shell.chars().all(|c| c.is_ascii_alphanumeric() || "/-_.+".contains(c)) {
+                    anyhow::bail!("Invalid shell '{}' for '{}': expected a program name or path like '/bin/zsh'", shell, key);
+                }
+            }
+        }
+
+        for (alias, host) in &self.aliases {
+            if alias == "local" || host == "local" {
+                anyhow::bail!("Invalid alias '{}' = '{}': 'local' cannot be aliased", alias, host);
+            }
+            if self.aliases.contains_key(host) && alias != host {
+                anyhow::bail!("Invalid alias '{}': '{}' is itself an alias (point it at the canonical name)", alias, host);
+            }
+            crate::types::RemoteHost::new(host.as_str()).with_context(|| format!("In alias '{}'", alias))?;
+        }
+
+        for (name, entries) in &self.groups {
+            for entry in entries {
+                for host in expand_host_range(entry).with_context(|| format!("In group '{}'", name))? {
+                    crate::types::RemoteHost::new(host).with_context(|| format!("In group '{}'", name))?;
+                }
+            }
+        }
+
+        let hosts = self.hosts.iter().map(|(k, h)| (k, &h.border, &h.color));
+        let sessions = self.sessions.iter().map(|(k, s)| (k, &s.border, &s.color));
+
+        for (key, border, color) in hosts.chain(sessions) {
+            if let Some(border) = border {
+                validate_border(border).with_context(|| format!("In settings for '{}'", key))?;
+            }
+            if let Some(color) = color {
+                validate_color(color).with_context(|| format!("In settings for '{}'", key))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Expand a `{a..b}` range in a group entry ("node{01..03}" gives node01, node02, node03)
+fn expand_host_range(entry: &str) -> Result<Vec<String>> {
+    let Some((head, rest)) = entry.split_once('{') else {
+        return Ok(vec![entry.to_string()]);
+    };
+    let (range, tail) = rest
+        .split_once('}')
+        .with_context(|| format!("Invalid host range '{}': missing '}}'", entry))?;
+    let parse = |(start, end): (&str, &str)| Some((start.parse::<u32>().ok()?, end.parse::<u32>().ok()?, start.len()));
+    let (start, end, width) = range
+        .split_once("..")
+        .and_then(parse)
+        .filter(|(start, end, _)| start <= end)
+        .with_context(|| format!("Invalid host range '{}': expected {{first..last}}", entry))?;
+
+    Ok((start..=end).map(|n| format!("{}{:0width$}{}", head, n, tail, width = width)).collect())
+}
+
+/// Whether an environment variable name matches a `send_env`-style pattern
+/// (`*` matches any run of characters, `?` a single one)
+fn env_pattern_matches(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[u8], name: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some((b'*', rest)) => (0..=name.len()).any(|i| matches(rest, &name[i..])),
+            Some((&p, rest)) => name.split_first().is_some_and(|(&n, name)| (p == b'?' || p == n) && matches(rest, name)),
+        }
+    }
+    matches(pattern.as_bytes(), name.as_bytes())
+}
+
+fn validate_border(border: &str) -> Result<()> {
+    let mut parts = border.split_whitespace();
+    let style = parts.next().unwrap_or("");
+    let width = parts.next();
+
+    let valid = match style {
+        "none" => width.is_none(),
+        "normal" | "pixel" => width.is_none_or(|w| w.parse::<u32>().is_ok()),
+        _ => false,
+    };
+
+    if !valid || parts.next().is_some() {
+        anyhow::bail!(
+            "Invalid border '{}': expected 'none', 'normal [width]' or 'pixel [width]'",
+            border
+        );
+    }
+    Ok(())
+}
+
+fn validate_color(color: &str) -> Result<()> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+    if !valid {
+        anyhow::bail!("Invalid color '{}': expected #rrggbb", color);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config() {
+        let config = Config::parse("").unwrap();
+        assert!(config.default_remote.is_none());
+    }
+
+    #[test]
+    fn test_default_remote() {
+        let config = Config::parse(r#"default_remote = "user@devbox""#).unwrap();
+        assert_eq!(config.default_remote.as_deref(), Some("user@devbox"));
+    }
+
+    #[test]
+    fn test_title_modes() {
+        let config = Config::default();
+        assert_eq!(config.title_for("user@host", "ws2-001").as_deref(), Some("i3mux:user@host:ws2-001"));
+
+        let config = Config::parse(r#"title_marker = "⚡ ""#).unwrap();
+        assert_eq!(config.title_for("local", "ws1-001").as_deref(), Some("⚡ local:ws1-001"));
+
+        let config = Config::parse(r#"title_mode = "hidden""#).unwrap();
+        assert_eq!(config.title_for("local", "ws1-001"), None);
+
+        assert!(Config::parse(r#"title_mode = "invisible""#).is_err());
+        assert!(Config::parse(r#"title_marker = "it's""#).is_err());
+    }
+
+    #[test]
+    fn test_workspace_label() {
+        let config = Config::parse(
+            r#"
+            workspace_labels = true
+            [hosts."user@prod"]
+            glyph = "🔥"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.workspace_label(3, "user@devbox").as_deref(), Some("3: devbox"));
+        assert_eq!(config.workspace_label(4, "user@prod").as_deref(), Some("4: 🔥 prod"));
+        assert_eq!(config.workspace_label(5, "local"), None);
+        assert_eq!(Config::default().workspace_label(3, "user@devbox"), None);
+
+        assert!(Config::parse("[hosts.box]\nglyph = 'a b'").is_err());
+        assert!(Config::parse("[hosts.box]\nglyph = '\"'").is_err());
+    }
+
+    #[test]
+    fn test_may_restore() {
+        let config = Config::parse(r#"restore_programs = ["tail", "htop"]"#).unwrap();
+        assert!(config.may_restore("tail -f /var/log/syslog"));
+        assert!(config.may_restore("/usr/bin/htop"));
+        assert!(!config.may_restore("rm -rf build"));
+        assert!(!config.may_restore(""));
+        assert!(!Config::default().may_restore("htop"));
+    }
+
+    #[test]
+    fn test_theme_session_overrides_host() {
+        let config = Config::parse(
+            r##"
+            [hosts."user@prod"]
+            border = "pixel 3"
+            color = "#ff0000"
+
+            [sessions.webdev]
+            color = "#00aa00"
+            "##,
+        )
+        .unwrap();
+
+        let theme = config.theme_for("user@prod", Some("webdev"));
+        assert_eq!(theme.border.as_deref(), Some("pixel 3"));
+        assert_eq!(theme.color.as_deref(), Some("#00aa00"));
+
+        assert_eq!(config.theme_for("local", None), Theme::default());
+    }
+
+    #[test]
+    fn test_theme_commands() {
+        let mut theme = Theme {
+            border: Some("normal 2".to_string()),
+            color: Some("#ff0000".to_string()),
+            title: None,
+        };
+        assert_eq!(
+            theme.commands(),
+            vec![
+                "border normal 2".to_string(),
+                "title_format \"<span foreground='#ff0000'>%title</span>\"".to_string(),
+            ]
+        );
+
+        theme.title = Some("i3mux:a&b:ws1-001".to_string());
+        assert_eq!(theme.commands()[1], "title_format \"<span foreground='#ff0000'>i3mux:a&amp;b:ws1-001</span>\"");
+        theme.color = None;
+        assert_eq!(theme.commands()[1], "title_format \"i3mux:a&b:ws1-001\"");
+    }
+
+    #[test]
+    fn test_invalid_theme_rejected() {
+        assert!(Config::parse("[hosts.a]\nborder = \"thick\"").is_err());
+        assert!(Config::parse("[hosts.a]\nborder = \"pixel 3; kill\"").is_err());
+        assert!(Config::parse("[hosts.a]\ncolor = \"red\"").is_err());
+        assert!(Config::parse("[sessions.a]\ncolor = \"#ff00zz\"").is_err());
+    }
+
+    #[test]
+    fn test_attach_ssh_options() {
+        let config = Config::parse(
+            r#"
+            [hosts."user@devbox"]
+            forward_agent = true
+            send_env = ["LANG", "LC_*"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.attach_ssh_options("user@devbox"),
+            " -o ForwardAgent=yes -o 'SendEnv=LANG' -o 'SendEnv=LC_*'"
+        );
+        assert_eq!(config.attach_ssh_options("user@other"), "");
+
+        assert!(Config::parse("[hosts.a]\nsend_env = [\"FOO BAR\"]").is_err());
+        assert!(Config::parse("[hosts.a]\nsend_env = [\"X;rm\"]").is_err());
+    }
+
+    #[test]
+    fn test_env_patterns() {
+        assert!(env_pattern_matches("LC_*", "LC_ALL"));
+        assert!(env_pattern_matches("LC_*", "LC_"));
+        assert!(!env_pattern_matches("LC_*", "LANG"));
+        assert!(env_pattern_matches("LANG", "LANG"));
+        assert!(!env_pattern_matches("LANG", "LANGUAGE"));
+        assert!(env_pattern_matches("L?NG*", "LANGUAGE"));
+        assert!(env_pattern_matches("*_PATH", "XDG_DATA_PATH"));
+        assert!(!env_pattern_matches("*_PATH", "PATH"));
+
+        assert!(Config::parse("[hosts.a]\nstrip_env = [\"LC_*\"]").is_ok());
+        assert!(Config::parse("[hosts.a]\nstrip_env = [\"$(reboot)\"]").is_err());
+    }
+
+    #[test]
+    fn test_group_hosts() {
+        let config = Config::parse(
+            r#"
+            [groups]
+            cluster = ["user@node{1..3}", "user@bastion"]
+            racks = ["rack{08..10}.dc"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.group_hosts("cluster").unwrap(),
+            ["user@node1", "user@node2", "user@node3", "user@bastion"]
+        );
+        assert_eq!(config.group_hosts("racks").unwrap(), ["rack08.dc", "rack09.dc", "rack10.dc"]);
+        assert!(config.group_hosts("missing").is_err());
+
+        assert!(Config::parse("[groups]\nbad = [\"node{3..1}\"]").is_err());
+        assert!(Config::parse("[groups]\nbad = [\"node{1..2\"]").is_err());
+        assert!(Config::parse("[groups]\nbad = [\"node;reboot\"]").is_err());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let config = Config::parse(
+            r#"
+            [aliases]
+            "devbox" = "user@devbox.lan"
+            "user@devbox" = "user@devbox.lan"
+
+            [groups]
+            dev = ["devbox", "user@prod"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.canonical_host("devbox"), "user@devbox.lan");
+        assert_eq!(config.canonical_host("user@devbox"), "user@devbox.lan");
+        assert_eq!(config.canonical_host("user@devbox.lan"), "user@devbox.lan");
+        assert_eq!(config.group_hosts("dev").unwrap(), ["user@devbox.lan", "user@prod"]);
+
+        // Chains, 'local' and invalid targets are rejected
+        assert!(Config::parse("[aliases]\na = \"b\"\nb = \"c\"").is_err());
+        assert!(Config::parse("[aliases]\nbox = \"local\"").is_err());
+        assert!(Config::parse("[aliases]\nbox = \"user@box; reboot\"").is_err());
+    }
+
+    #[test]
+    fn test_remote_paths() {
+        let config = Config::parse(
+            r#"
+            [hosts."user@prod"]
+            storage_dir = "/srv/state/${USER}/i3mux"
+            "#,
+        )
+        .unwrap();
+        let paths = config.remote_paths("user@prod");
+        assert_eq!(paths.sessions, "/srv/state/${USER}/i3mux/sessions");
+        assert_eq!(paths.locks, "/srv/state/${USER}/i3mux/locks");
+        assert_eq!(config.remote_paths("user@other"), crate::connection::RemotePaths::remote_default());
+
+        assert!(Config::parse("[hosts.a]\nstorage_dir = \"state/i3mux\"").is_err());
+        assert!(Config::parse("[hosts.a]\nstorage_dir = \"/srv/$(reboot)\"").is_err());
+        assert!(Config::parse("[hosts.a]\nstorage_dir = \"/srv/../etc\"").is_err());
+        assert!(Config::parse("[hosts.local]\nstorage_dir = \"/srv/state\"").is_err());
+    }
+
+    #[test]
+    fn test_configured_hosts() {
+        let config = Config::parse(
+            r#"
+            default_remote = "devbox"
+
+            [aliases]
+            "devbox" = "user@devbox.lan"
+
+            [hosts.local]
+            border = "none"
+
+            [hosts."user@prod"]
+            term = "xterm-256color"
+
+            [groups]
+            cluster = ["user@node{1..2}", "user@prod"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.configured_hosts().unwrap(),
+            ["user@devbox.lan", "user@node1", "user@node2", "user@prod"]
+        );
+        assert!(Config::default().configured_hosts().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_shell_for() {
+        let config = Config::parse(
+            r#"
+            [hosts."user@box"]
+            shell = "zsh"
+            login_shell = true
+            shell_rc = "export EDITOR=vim"
+
+            [sessions.plain]
+            login_shell = false
+            "#,
+        )
+        .unwrap();
+
+        let shell = config.shell_for("user@box", None);
+        assert_eq!(shell.session_command("bash", None), r#"zsh -c 'eval "$I3MUX_RC"; exec zsh -l'"#);
+        assert_eq!(shell.remote_bash(), "bash -l");
+        assert_eq!(shell.remote_env(), "I3MUX_SHELL=zsh I3MUX_LOGIN=1 I3MUX_RC=6578706f727420454449544f523d76696d ");
+
+        let plain = config.shell_for("user@box", Some("plain"));
+        assert_eq!(plain.login, Some(false));
+        assert_eq!(plain.remote_bash(), "bash");
+
+        let default = config.shell_for("local", None);
+        assert_eq!(default.session_command("/bin/bash", None), "/bin/bash");
+        assert_eq!(
+            default.session_command("/bin/bash", Some(r#"eval "$I3MUX_RUN""#)),
+            r#"/bin/bash -c 'eval "$I3MUX_RUN"; exec /bin/bash'"#
+        );
+        assert_eq!(default.remote_env(), "");
+
+        assert!(Config::parse("[hosts.x]\nshell = \"zsh; reboot\"").is_err());
+    }
+
+    #[test]
+    fn test_term_for() {
+        let config = Config::parse(
+            r#"
+            term = "tmux-256color"
+            [hosts."user@old"]
+            term = "screen-256color"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.term_for("user@old"), Some("screen-256color"));
+        assert_eq!(config.term_for("local"), Some("tmux-256color"));
+        assert_eq!(Config::default().term_for("user@devbox"), None);
+
+        assert!(Config::parse(r#"term = "xterm; rm -rf ~""#).is_err());
+        assert!(Config::parse("[hosts.a]\nterm = \"\"").is_err());
+    }
+
+    #[test]
+    fn test_unknown_field_rejected() {
+        assert!(Config::parse(r#"no_such_option = true"#).is_err());
+    }
+}
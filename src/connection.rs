@@ -1,11 +1,135 @@
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
+use crate::exec::{self, Executor};
 use crate::session::SessionLock;
+use crate::types::{Mount, PortForward};
 
+/// Shared directory of older versions, which kept every user's sessions together
 const BASE_DIR: &str = "/tmp/i3mux";
 
+/// The user's own session directory on a remote host, for use in (double-quoted) shell commands
+///
+/// A sibling of BASE_DIR rather than a subdirectory, so it never depends on
+/// who created the shared one.
+const REMOTE_USER_DIR: &str = "/tmp/i3mux-$(id -u)";
+
+/// Where a host keeps i3mux's files: saved sessions and their locks
+///
+/// Remote paths are used in double-quoted shell commands, so they may contain
+/// variables (`storage_dir = "/srv/state/$USER"` in the host's config).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemotePaths {
+    /// Private directory (mode 700, owned by the user) holding the others
+    pub root: String,
+    pub sessions: String,
+    pub locks: String,
+}
+
+impl RemotePaths {
+    /// The usual `sessions/` and `locks/` layout under `root`
+    pub fn under(root: &str) -> Self {
+        let root = root.trim_end_matches('/');
+        Self {
+            root: root.to_string(),
+            sessions: format!("{}/sessions", root),
+            locks: format!("{}/locks", root),
+        }
+    }
+
+    /// Layout on remote hosts without a `storage_dir`
+    pub fn remote_default() -> Self {
+        Self::under(&format!("{}{}", REMOTE_USER_DIR, crate::instance::suffix()))
+    }
+
+    /// Layout on this machine
+    pub fn local() -> Self {
+        Self::under(&user_dir().to_string_lossy())
+    }
+
+    pub fn session_file(&self, name: &str) -> String {
+        format!("{}/{}.json", self.sessions, name)
+    }
+
+    pub fn lock_file(&self, name: &str) -> String {
+        format!("{}/{}.lock", self.locks, name)
+    }
+
+    /// Variables pointing the helper script at this layout, for its environment
+    fn helper_env(&self) -> [(&'static str, &str); 2] {
+        [("I3MUX_SESSIONS_DIR", &self.sessions), ("I3MUX_LOCKS_DIR", &self.locks)]
+    }
+}
+
+/// Free space a remote filesystem needs before i3mux writes to it
+const REMOTE_MIN_FREE_KB: u64 = 1024;
+
+/// Shell snippet creating the root of `paths` (mode 700) and its sessions and
+/// locks directories, refusing a root owned by someone else, then checking
+/// it like [`remote_preflight`]
+fn remote_ensure_dirs(paths: &RemotePaths) -> String {
+    format!(
+        r#"D="{root}"; mkdir -p -m 700 "$D" 2>/dev/null || {{ echo "i3mux-preflight unwritable $D" >&2; exit 1; }}; [ -O "$D" ] || {{ echo "i3mux-preflight foreign $D" >&2; exit 1; }}; mkdir -p "{sessions}" "{locks}" 2>/dev/null && [ -w "{sessions}" ] && [ -w "{locks}" ] || {{ echo "i3mux-preflight unwritable $D" >&2; exit 1; }}; {check}"#,
+        root = paths.root,
+        sessions = paths.sessions,
+        locks = paths.locks,
+        check = free_space_check()
+    )
+}
+
+/// Shell snippet failing unless `dir` on a remote host is writable and has
+/// REMOTE_MIN_FREE_KB free
+///
+/// Problems are reported on stderr as `i3mux-preflight <problem> <dir> [free KiB]`,
+/// for [`preflight_error`] to turn into something actionable.
+pub fn remote_preflight(dir: &str) -> String {
+    format!(
+        r#"D="{dir}"; [ -d "$D" ] && [ -w "$D" ] || {{ echo "i3mux-preflight unwritable $D" >&2; exit 1; }}; {check}"#,
+        dir = dir,
+        check = free_space_check()
+    )
+}
+
+/// The free space part of the preflight, for the directory in `$D`
+///
+/// Hosts without a POSIX `df` pass: a full disk still fails the write itself.
+fn free_space_check() -> String {
+    format!(
+        r#"F=$(df -Pk "$D" 2>/dev/null | awk 'NR==2 {{print $4}}'); [ "${{F:-{min}}}" -ge {min} ] 2>/dev/null || {{ echo "i3mux-preflight full $D $F" >&2; exit 1; }}"#,
+        min = REMOTE_MIN_FREE_KB
+    )
+}
+
+/// The error behind a failed preflight on `host`, given the command's stderr
+pub fn preflight_error(host: &str, stderr: &str) -> Option<crate::error::Error> {
+    let line = stderr.lines().find_map(|line| Some(line.split_once("i3mux-preflight ")?.1))?;
+    let mut words = line.split_whitespace();
+    let (problem, dir) = (words.next()?, words.next()?.to_string());
+    let problem = match problem {
+        "full" => format!("its filesystem is full ({} KiB free); free up space there", words.next().unwrap_or("0")),
+        "foreign" => "it is owned by another user; remove it or have its owner do so".to_string(),
+        _ => "it is not writable; check its permissions".to_string(),
+    };
+    Some(crate::error::Error::RemoteStorage { host: host.to_string(), dir, problem })
+}
+
+/// Where the shared master connections' sockets live (see [`crate::commands::SSH_MASTER_OPTIONS`])
+pub const MASTER_SOCKET_DIR: &str = "/tmp/i3mux/sockets";
+
+/// ssh's exit status when the connection itself failed (vs. the remote command)
+pub const SSH_CONNECTION_FAILED: i32 = 255;
+
+/// Environment variable identifying the machine an abduco client was attached from
+pub const CLIENT_ENV: &str = "I3MUX_CLIENT";
+
+/// Largest session accepted as JSON, to catch runaway files rather than to ration space
+pub const MAX_SESSION_BYTES: usize = 16 << 20;
+
 /// High-level abstraction for managing sessions and terminals on local or remote hosts
 pub trait Connection: Send + Sync {
     // Session persistence
@@ -17,36 +141,293 @@ pub trait Connection: Send + Sync {
     fn acquire_lock(&self, session_name: &str, force: bool) -> Result<(SessionLock, Option<std::process::Child>)>;
     fn is_lock_valid(&self, lock: &SessionLock) -> Result<bool>;
     fn release_lock(&self, session_name: &str) -> Result<()>;
+    /// Block until the session is no longer locked with `nonce` (it was
+    /// locked again, detached or deleted) or another machine asked for it
+    fn wait_for_lock_change(&self, session_name: &str, nonce: &str) -> Result<()>;
 
     // Session deletion
     fn delete_session(&self, name: &str) -> Result<()>;
+
+    // Terminal liveness
+    /// abduco sockets named `{prefix}-*` on the host (every workspace's without a prefix)
+    fn list_sockets(&self, prefix: Option<&str>) -> Result<Vec<AbducoSocket>>;
+    /// Whether any abduco sockets named `{prefix}-*` still exist on the host
+    fn has_live_sockets(&self, prefix: &str) -> Result<bool> {
+        Ok(!self.list_sockets(Some(prefix))?.is_empty())
+    }
+    /// Terminate the abduco sessions (and their processes) behind the given sockets
+    fn kill_sockets(&self, sockets: &[String]) -> Result<()>;
+    /// Foreground process of each socket's shell (via the helper's `ps`)
+    fn process_info(&self, sockets: &[String]) -> Result<Vec<ProcessInfo>>;
+    /// Detach the socket's abduco clients, except those attached from `keep_client`;
+    /// returns how many were detached
+    fn boot_clients(&self, socket: &str, keep_client: &str) -> Result<u32>;
+    /// Make the sockets' abduco clients attached from `client` resend their window size
+    fn resize_clients(&self, sockets: &[String], client: &str) -> Result<()>;
+    /// Type each `(socket, input)`'s input into the socket's abduco session;
+    /// returns how many sessions got it (ones no longer running don't)
+    fn send_input(&self, inputs: &[(String, String)]) -> Result<u32>;
+
+    // Port forwarding (on the shared SSH control connection)
+    fn add_forward(&self, forward: &PortForward) -> Result<()>;
+    fn cancel_forward(&self, forward: &PortForward) -> Result<()>;
+
+    // sshfs mounts (reusing the SSH control connection)
+    fn mount(&self, mount: &Mount) -> Result<()>;
+    fn unmount(&self, mount: &Mount) -> Result<()>;
+}
+
+/// Foreground process running in a terminal's abduco session
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub socket: String,
+    /// None if no abduco session is running for the socket
+    pub pid: Option<u32>,
+    pub cpu: String,
+    pub mem: String,
+    /// Whether a program other than the shell itself is in the foreground
+    pub busy: bool,
+    /// abduco clients attached to the session; more than one means it is also viewed elsewhere
+    pub clients: u32,
+    pub command: String,
+}
+
+/// An abduco socket on a host, from the helper's `list-sockets`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbducoSocket {
+    pub name: String,
+    /// When the session was started (the socket's modification time, seconds since the epoch)
+    pub created: Option<i64>,
+    /// abduco clients attached to the session
+    pub clients: u32,
+    /// PID of the session's shell; None for a socket left behind by a session that is gone
+    pub shell_pid: Option<u32>,
+}
+
+impl AbducoSocket {
+    /// Whether a session is still running behind the socket
+    pub fn is_running(&self) -> bool {
+        self.shell_pid.is_some()
+    }
+}
+
+/// Sockets from the helper's `list-sockets` response
+fn parse_sockets(records: &[HelperRecord]) -> Vec<AbducoSocket> {
+    records
+        .iter()
+        .filter(|r| r.kind == "socket")
+        .filter_map(|r| {
+            Some(AbducoSocket {
+                name: r.get("name")?.to_string(),
+                created: r.get("created")?.parse().ok(),
+                clients: r.get("clients")?.parse().unwrap_or(0),
+                shell_pid: r.get("shell")?.parse().ok(),
+            })
+        })
+        .collect()
+}
+
+/// One line of a helper response: `@<type>`, then tab-separated `key=value` fields
+///
+/// See the protocol notes at the top of remote-helper.sh.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelperRecord {
+    pub kind: String,
+    fields: Vec<(String, String)>,
+}
+
+impl HelperRecord {
+    /// Parse a response line, or None for output that isn't a record
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split('\t');
+        let kind = parts.next()?.strip_prefix('@')?;
+        if kind.is_empty() {
+            return None;
+        }
+        let fields = parts
+            .map(|field| {
+                let (key, value) = field.split_once('=')?;
+                Some((key.to_string(), unescape_field(value)))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self { kind: kind.to_string(), fields })
+    }
+
+    /// Value of a field, if present
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Value of a field the record must have
+    pub fn field(&self, key: &str) -> Result<&str> {
+        self.get(key)
+            .with_context(|| format!("Helper response '{}' is missing '{}'", self.kind, key))
+    }
+}
+
+/// Undo the helper's escaping of backslash, tab and newline
+fn unescape_field(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// A failure the helper reported with an `@error` record
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct HelperError {
+    /// Stable identifier, e.g. `abduco_missing` or `usage`
+    pub code: String,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+/// The records of a helper response, or the error it reported
+///
+/// Lines that aren't records (login banners, motd) are skipped.
+pub fn parse_helper_response(output: &str) -> Result<Vec<HelperRecord>> {
+    let records: Vec<HelperRecord> = output.lines().filter_map(HelperRecord::parse).collect();
+    if let Some(error) = records.iter().find(|r| r.kind == "error") {
+        return Err(HelperError {
+            code: error.get("code").unwrap_or("unknown").to_string(),
+            message: error.get("message").unwrap_or("helper command failed").to_string(),
+            hint: error.get("hint").map(String::from),
+        }
+        .into());
+    }
+    Ok(records)
+}
+
+/// Parse the output of the helper subcommand `subcommand`, failing if it failed
+pub fn helper_response(subcommand: &str, output: &std::process::Output) -> Result<Vec<HelperRecord>> {
+    let records = parse_helper_response(&String::from_utf8_lossy(&output.stdout))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Helper command '{}' failed: {}",
+            subcommand,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(records)
+}
+
+/// The first record of type `kind` in a response
+///
+/// A response without one most likely comes from an outdated helper.
+pub fn find_record<'a>(records: &'a [HelperRecord], kind: &str) -> Result<&'a HelperRecord> {
+    records
+        .iter()
+        .find(|r| r.kind == kind)
+        .with_context(|| format!("No '{}' response from the i3mux helper (is it out of date?)", kind))
+}
+
+/// Processes from the helper's `ps` response
+fn parse_process_info(records: &[HelperRecord]) -> Vec<ProcessInfo> {
+    records
+        .iter()
+        .filter(|r| r.kind == "process")
+        .filter_map(|r| {
+            Some(ProcessInfo {
+                socket: r.get("socket")?.to_string(),
+                pid: r.get("pid")?.parse().ok(),
+                cpu: r.get("cpu")?.to_string(),
+                mem: r.get("mem")?.to_string(),
+                busy: r.get("busy")? == "1",
+                clients: r.get("clients")?.parse().unwrap_or(0),
+                command: r.get("command")?.to_string(),
+            })
+        })
+        .collect()
 }
 
 /// Local connection (executes commands directly on localhost)
-pub struct LocalConnection;
+pub struct LocalConnection {
+    exec: Arc<dyn Executor>,
+    paths: RemotePaths,
+}
 
 impl LocalConnection {
     pub fn new() -> Result<Self> {
-        Ok(Self)
+        Ok(Self::with_executor(exec::current()))
+    }
+
+    pub fn with_executor(exec: Arc<dyn Executor>) -> Self {
+        Self { exec, paths: RemotePaths::local() }
+    }
+
+    /// Keep files in `paths` rather than the user's directory
+    #[cfg(test)]
+    pub fn with_paths(self, paths: RemotePaths) -> Self {
+        Self { paths, ..self }
+    }
+
+    fn sessions_dir(&self) -> PathBuf {
+        PathBuf::from(&self.paths.sessions)
+    }
+
+    fn locks_dir(&self) -> PathBuf {
+        PathBuf::from(&self.paths.locks)
     }
 
-    fn sessions_dir() -> PathBuf {
-        PathBuf::from(BASE_DIR).join("sessions")
+    /// Create the root of the layout (private to the user)
+    fn ensure_root(&self) -> Result<()> {
+        ensure_private_dir(std::path::Path::new(&self.paths.root))?;
+        Ok(())
     }
 
-    fn locks_dir() -> PathBuf {
-        PathBuf::from(BASE_DIR).join("locks")
+    /// Session file left in the shared directory by an older version, if it is ours
+    fn legacy_session_file(name: &str) -> Option<PathBuf> {
+        let path = PathBuf::from(BASE_DIR).join("sessions").join(format!("{}.json", name));
+        owned_by_current_user(&path).then_some(path)
     }
 
     fn check(&self, cmd: &str) -> Result<bool> {
-        let status = Command::new("bash")
-            .arg("-c")
-            .arg(cmd)
-            .status()
+        let status = self
+            .exec
+            .run(Command::new("bash").arg("-c").arg(cmd))
             .context("Failed to execute local command")?;
 
         Ok(status.success())
     }
+
+    /// Run a helper subcommand locally (the embedded script is fed on stdin)
+    fn run_helper(&self, args: &[&str]) -> Result<Vec<HelperRecord>> {
+        use std::io::Write;
+
+        let mut child = self
+            .exec
+            .spawn(
+                Command::new("bash")
+                    .arg("-s")
+                    .arg("--")
+                    .args(args)
+                    .envs(self.paths.helper_env())
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped()),
+            )
+            .context("Failed to run helper script")?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(crate::REMOTE_HELPER_SCRIPT.as_bytes())
+                .context("Failed to write helper script")?;
+        }
+
+        let output = child.wait_with_output().context("Failed to wait for helper script")?;
+        helper_response(args[0], &output)
+    }
 }
 
 impl Default for LocalConnection {
@@ -57,7 +438,8 @@ impl Default for LocalConnection {
 
 impl Connection for LocalConnection {
     fn save_session_data(&self, name: &str, data: &str) -> Result<()> {
-        let dir = Self::sessions_dir();
+        self.ensure_root()?;
+        let dir = self.sessions_dir();
         std::fs::create_dir_all(&dir)?;
         let path = dir.join(format!("{}.json", name));
         std::fs::write(&path, data)
@@ -65,43 +447,52 @@ impl Connection for LocalConnection {
     }
 
     fn load_session_data(&self, name: &str) -> Result<String> {
-        let path = Self::sessions_dir().join(format!("{}.json", name));
+        let mut path = self.sessions_dir().join(format!("{}.json", name));
+        if !path.exists() {
+            path = Self::legacy_session_file(name).unwrap_or(path);
+        }
         std::fs::read_to_string(&path)
             .with_context(|| format!("Failed to load session '{}' from {}", name, path.display()))
     }
 
     fn list_session_names(&self) -> Result<Vec<String>> {
-        let dir = Self::sessions_dir();
         let mut sessions = Vec::new();
 
-        if let Ok(entries) = std::fs::read_dir(&dir) {
-            for entry in entries.flatten() {
-                if let Some(name) = entry.file_name().to_str() {
-                    if name.ends_with(".json") {
-                        sessions.push(name.trim_end_matches(".json").to_string());
+        // Other users' sessions in the shared legacy directory are not listed
+        let legacy_dir = PathBuf::from(BASE_DIR).join("sessions");
+        for dir in [self.sessions_dir(), legacy_dir] {
+            if let Ok(entries) = std::fs::read_dir(&dir) {
+                for entry in entries.flatten() {
+                    if let Some(name) = entry.file_name().to_str() {
+                        if name.ends_with(".json") && owned_by_current_user(&entry.path()) {
+                            sessions.push(name.trim_end_matches(".json").to_string());
+                        }
                     }
                 }
             }
         }
 
+        sessions.sort();
+        sessions.dedup();
         Ok(sessions)
     }
 
     fn delete_session(&self, name: &str) -> Result<()> {
-        let path = Self::sessions_dir().join(format!("{}.json", name));
-        match std::fs::remove_file(&path) {
-            Ok(_) => Ok(()),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
-            Err(e) => Err(e).with_context(|| format!("Failed to delete session file: {}", path.display())),
+        let path = self.sessions_dir().join(format!("{}.json", name));
+        for path in std::iter::once(path).chain(Self::legacy_session_file(name)) {
+            match std::fs::remove_file(&path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to delete session file: {}", path.display())),
+            }
         }
+        Ok(())
     }
 
     fn acquire_lock(&self, session_name: &str, force: bool) -> Result<(SessionLock, Option<std::process::Child>)> {
-        let hostname = gethostname::gethostname()
-            .into_string()
-            .unwrap_or_else(|_| "unknown".to_string());
+        let hostname = hostname();
 
-        let locks_dir = Self::locks_dir();
+        let locks_dir = self.locks_dir();
         let lock_path = locks_dir.join(format!("{}.lock", session_name));
 
         // Check if lock already exists
@@ -109,12 +500,7 @@ impl Connection for LocalConnection {
             if let Ok(lock_content) = std::fs::read_to_string(&lock_path) {
                 if let Ok(lock) = serde_json::from_str::<SessionLock>(&lock_content) {
                     if self.is_lock_valid(&lock)? {
-                        anyhow::bail!(
-                            "Session '{}' is locked by {} (acquired {}). Use --force to break lock.",
-                            session_name,
-                            lock.locked_by,
-                            lock.locked_at
-                        );
+                        return Err(lock_held(session_name, Some(&lock)));
                     }
                 }
             }
@@ -125,6 +511,7 @@ impl Connection for LocalConnection {
         let lock = SessionLock::new(hostname, pid);
 
         // Write lock file
+        self.ensure_root()?;
         std::fs::create_dir_all(&locks_dir)?;
         let lock_json = serde_json::to_string(&lock)?;
         std::fs::write(&lock_path, &lock_json)
@@ -140,23 +527,98 @@ impl Connection for LocalConnection {
     }
 
     fn release_lock(&self, session_name: &str) -> Result<()> {
-        let lock_path = Self::locks_dir().join(format!("{}.lock", session_name));
+        let lock_path = self.locks_dir().join(format!("{}.lock", session_name));
         match std::fs::remove_file(&lock_path) {
             Ok(_) => Ok(()),
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
             Err(e) => Err(e).with_context(|| format!("Failed to release lock: {}", lock_path.display())),
         }
     }
+
+    fn wait_for_lock_change(&self, session_name: &str, nonce: &str) -> Result<()> {
+        let records = self.run_helper(&["watch-session", session_name, nonce])?;
+        find_record(&records, "session")?;
+        Ok(())
+    }
+
+    fn list_sockets(&self, prefix: Option<&str>) -> Result<Vec<AbducoSocket>> {
+        let mut args = vec!["list-sockets"];
+        args.extend(prefix);
+        Ok(parse_sockets(&self.run_helper(&args)?))
+    }
+
+    fn kill_sockets(&self, sockets: &[String]) -> Result<()> {
+        for socket in sockets {
+            // The abduco server keeps the client's command line; killing it hangs up the shell
+            self.check(&format!(
+                "pkill -f '^abduco -A /tmp/{socket}( |$)'; rm -f /tmp/{socket}",
+                socket = socket
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn process_info(&self, sockets: &[String]) -> Result<Vec<ProcessInfo>> {
+        let mut args = vec!["ps"];
+        args.extend(sockets.iter().map(|s| s.as_str()));
+        Ok(parse_process_info(&self.run_helper(&args)?))
+    }
+
+    fn boot_clients(&self, socket: &str, keep_client: &str) -> Result<u32> {
+        let records = self.run_helper(&["boot", socket, keep_client])?;
+        Ok(find_record(&records, "boot")?.field("booted")?.parse()?)
+    }
+
+    fn resize_clients(&self, sockets: &[String], client: &str) -> Result<()> {
+        let mut args = vec!["resize", client];
+        args.extend(sockets.iter().map(|s| s.as_str()));
+        self.run_helper(&args)?;
+        Ok(())
+    }
+
+    fn send_input(&self, inputs: &[(String, String)]) -> Result<u32> {
+        let inputs = hex_inputs(inputs);
+        let mut args = vec!["send"];
+        args.extend(inputs.iter().flat_map(|(socket, input)| [socket.as_str(), input.as_str()]));
+        Ok(find_record(&self.run_helper(&args)?, "send")?.field("sent")?.parse()?)
+    }
+
+    fn add_forward(&self, _forward: &PortForward) -> Result<()> {
+        anyhow::bail!("Port forwards are only available for remote sessions")
+    }
+
+    fn cancel_forward(&self, _forward: &PortForward) -> Result<()> {
+        anyhow::bail!("Port forwards are only available for remote sessions")
+    }
+
+    fn mount(&self, _mount: &Mount) -> Result<()> {
+        anyhow::bail!("Mounts are only available for remote sessions")
+    }
+
+    fn unmount(&self, _mount: &Mount) -> Result<()> {
+        anyhow::bail!("Mounts are only available for remote sessions")
+    }
 }
 
 /// SSH connection (executes commands via SSH with ControlMaster)
 pub struct SshConnection {
     host: String,
+    exec: Arc<dyn Executor>,
+    paths: RemotePaths,
 }
 
 impl SshConnection {
     pub fn new(host: String) -> Self {
-        Self { host }
+        Self::with_executor(host, exec::current())
+    }
+
+    pub fn with_executor(host: String, exec: Arc<dyn Executor>) -> Self {
+        Self { host, exec, paths: RemotePaths::remote_default() }
+    }
+
+    /// Keep files in `paths` on the host rather than the default directory
+    pub fn with_paths(self, paths: RemotePaths) -> Self {
+        Self { paths, ..self }
     }
 
     // Private helper methods
@@ -172,14 +634,22 @@ impl SshConnection {
     }
 
     fn execute(&self, cmd: &str) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.execute_raw(cmd)?).to_string())
+    }
+
+    /// Run `cmd`, returning its output as bytes
+    fn execute_raw(&self, cmd: &str) -> Result<Vec<u8>> {
         let mut command = Command::new("ssh");
         for arg in self.ssh_base_args() {
             command.arg(arg);
         }
         command.arg(&self.host).arg(cmd);
 
-        let output = command.output().context("Failed to execute SSH command")?;
+        let output = self.exec.output(&mut command).context("Failed to execute SSH command")?;
 
+        if output.status.code() == Some(SSH_CONNECTION_FAILED) {
+            return Err(ssh_unreachable(&self.host, &String::from_utf8_lossy(&output.stderr)).into());
+        }
         if !output.status.success() {
             anyhow::bail!(
                 "SSH command failed: {}",
@@ -187,7 +657,76 @@ impl SshConnection {
             );
         }
 
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        Ok(output.stdout)
+    }
+
+    /// Create the storage directories, failing with an actionable error when
+    /// the host can't store files in them
+    fn ensure_dirs(&self) -> Result<()> {
+        self.execute(&remote_ensure_dirs(&self.paths)).map_err(|e| match preflight_error(&self.host, &e.to_string()) {
+            Some(err) => err.into(),
+            None => e,
+        })?;
+        Ok(())
+    }
+
+    /// Run a helper subcommand (the helper is uploaded by the abduco check)
+    fn run_helper(&self, args: &[&str]) -> Result<Vec<HelperRecord>> {
+        // The helper finds the default layout itself
+        let env: String = if self.paths == RemotePaths::under(REMOTE_USER_DIR) {
+            String::new()
+        } else {
+            self.paths.helper_env().iter().map(|(name, value)| format!("{}=\"{}\" ", name, value)).collect()
+        };
+        let mut command = Command::new("ssh");
+        command
+            .args(self.ssh_base_args())
+            .arg(&self.host)
+            .arg(format!("bash -lc '{}{} {}'", env, crate::remote_helper_path(), args.join(" ")));
+
+        let output = self.exec.output(&mut command).context("Failed to execute SSH command")?;
+        if output.status.code() == Some(SSH_CONNECTION_FAILED) {
+            return Err(ssh_unreachable(&self.host, &String::from_utf8_lossy(&output.stderr)).into());
+        }
+        helper_response(args[0], &output)
+    }
+
+    /// PID of a session's lock holder, if it is still running
+    fn lock_holder(&self, session_name: &str) -> Result<Option<u32>> {
+        let records = self.run_helper(&["lock-status", session_name])?;
+        let lock = find_record(&records, "lock")?;
+        if lock.field("state")? != "held" {
+            return Ok(None);
+        }
+        Ok(Some(lock.field("pid")?.parse()?))
+    }
+
+    /// Send a control request (`ssh -O ...`) to the master connection
+    fn control(&self, operation: &str, forward: &PortForward) -> Result<()> {
+        // Make sure a master is running to take the request
+        self.execute("true")?;
+
+        let output = self
+            .exec
+            .output(
+                Command::new("ssh")
+                    .args(self.ssh_base_args())
+                    .arg("-O")
+                    .arg(operation)
+                    .args(forward.ssh_args())
+                    .arg(&self.host),
+            )
+            .context("Failed to execute SSH control command")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to {} {}: {}",
+                operation,
+                forward,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
     }
 
     fn check(&self, cmd: &str) -> Result<bool> {
@@ -197,11 +736,11 @@ impl SshConnection {
         }
         command.arg(&self.host).arg(cmd);
 
-        let status = command.status().context("Failed to execute SSH command")?;
+        let status = self.exec.run(&mut command).context("Failed to execute SSH command")?;
         Ok(status.success())
     }
 
-    fn write_remote_file(&self, path: &str, content: &str) -> Result<()> {
+    fn write_remote_file(&self, path: &str, content: &[u8]) -> Result<()> {
         let mut command = Command::new("ssh");
         for arg in self.ssh_base_args() {
             command.arg(arg);
@@ -211,38 +750,49 @@ impl SshConnection {
             .arg(format!("cat > {}", path))
             .stdin(std::process::Stdio::piped());
 
-        let mut child = command.spawn().context("Failed to start SSH write")?;
+        let mut child = self.exec.spawn(&mut command).context("Failed to start SSH write")?;
 
         use std::io::Write;
         if let Some(stdin) = child.stdin.as_mut() {
             stdin
-                .write_all(content.as_bytes())
+                .write_all(content)
                 .context("Failed to write to SSH stdin")?;
         }
 
-        child.wait().context("Failed to wait for SSH write")?;
+        let status = child.wait().context("Failed to wait for SSH write")?;
+        if !status.success() {
+            anyhow::bail!("Failed to write {} on {}", path, self.host);
+        }
         Ok(())
     }
 }
 
 impl Connection for SshConnection {
     fn save_session_data(&self, name: &str, data: &str) -> Result<()> {
-        let path = format!("{}/sessions/{}.json", BASE_DIR, name);
-        // Ensure parent directory exists
-        self.execute(&format!("mkdir -p {}/sessions", BASE_DIR))?;
-        self.write_remote_file(&path, data)
+        let path = self.paths.session_file(name);
+        // Ensure parent directory exists (and is ours) with room for the session
+        self.ensure_dirs()?;
+        self.write_remote_file(&path, &compress_session(data)?)
     }
 
     fn load_session_data(&self, name: &str) -> Result<String> {
-        let path = format!("{}/sessions/{}.json", BASE_DIR, name);
-        self.execute(&format!("cat '{}'", path))
-            .with_context(|| format!("Session '{}' not found on {}", name, self.host))
+        // Fall back to a session of ours saved in the shared directory by an older version
+        let data = self
+            .execute_raw(&format!(
+                r#"cat "{file}" 2>/dev/null || {{ f="{legacy}/sessions/{name}.json"; [ -O "$f" ] && cat "$f"; }}"#,
+                file = self.paths.session_file(name),
+                legacy = BASE_DIR,
+                name = name
+            ))
+            .with_context(|| format!("Session '{}' not found on {}", name, self.host))?;
+        decompress_session(&data).with_context(|| format!("Failed to read session '{}' from {}", name, self.host))
     }
 
     fn list_session_names(&self) -> Result<Vec<String>> {
+        // Other users' sessions in the shared legacy directory are not listed
         let output = self.execute(&format!(
-            "ls {}/sessions/*.json 2>/dev/null | xargs -n1 basename -s .json || true",
-            BASE_DIR
+            r#"find "{}" {}/sessions -maxdepth 1 -name '*.json' -user "$(id -u)" 2>/dev/null | xargs -n1 basename -s .json | sort -u || true"#,
+            self.paths.sessions, BASE_DIR
         ))?;
         Ok(output
             .lines()
@@ -252,53 +802,43 @@ impl Connection for SshConnection {
     }
 
     fn delete_session(&self, name: &str) -> Result<()> {
-        let path = format!("{}/sessions/{}.json", BASE_DIR, name);
-        self.execute(&format!("rm -f '{}'", path))?;
+        self.execute(&format!(
+            r#"rm -f "{file}"; find {legacy}/sessions -maxdepth 1 -name '{name}.json' -user "$(id -u)" -delete 2>/dev/null || true"#,
+            file = self.paths.session_file(name),
+            legacy = BASE_DIR,
+            name = name
+        ))?;
         Ok(())
     }
 
     fn acquire_lock(&self, session_name: &str, force: bool) -> Result<(SessionLock, Option<std::process::Child>)> {
-        let hostname = gethostname::gethostname()
-            .into_string()
-            .unwrap_or_else(|_| "unknown".to_string());
+        let hostname = hostname();
 
-        let lock_file = format!("{}/locks/{}.lock", BASE_DIR, session_name);
-        let pid_file = format!("{}/locks/{}.lock.pid", BASE_DIR, session_name);
+        let lock_file = self.paths.lock_file(session_name);
+        let pid_file = format!("{}.pid", lock_file);
 
         // Check if lock already exists
-        if !force {
-            let pid_str = self.execute(&format!("cat '{}' 2>/dev/null || echo ''", pid_file))?;
-            if !pid_str.trim().is_empty() {
-                if let Ok(remote_pid) = pid_str.trim().parse::<u32>() {
-                    if self.check(&format!("kill -0 {} 2>/dev/null", remote_pid))? {
-                        // Lock still valid - try to load session for better error message
-                        if let Ok(session_data) = self.load_session_data(session_name) {
-                            if let Ok(session) = serde_json::from_str::<crate::session::RemoteSession>(&session_data) {
-                                if let Some(lock) = session.lock {
-                                    anyhow::bail!(
-                                        "Session '{}' is locked by {} (acquired {}). Use --force to break lock.",
-                                        session_name,
-                                        lock.locked_by,
-                                        lock.locked_at
-                                    );
-                                }
-                            }
-                        }
-                        anyhow::bail!("Session '{}' is locked. Use --force to break lock.", session_name);
+        if !force && self.lock_holder(session_name)?.is_some() {
+            // Lock still valid - try to load session for better error message
+            if let Ok(session_data) = self.load_session_data(session_name) {
+                if let Ok(session) = serde_json::from_str::<crate::session::RemoteSession>(&session_data) {
+                    if let Some(lock) = session.lock {
+                        return Err(lock_held(session_name, Some(&lock)));
                     }
                 }
             }
+            return Err(lock_held(session_name, None));
         }
 
         // Ensure lock directory exists
-        self.execute(&format!("mkdir -p {}/locks", BASE_DIR))?;
+        self.ensure_dirs()?;
 
         // Start background SSH process that holds the lock
         let lock_script = format!(
             r#"
             set -e
-            LOCKFILE='{lock_file}'
-            PIDFILE='{pid_file}'
+            LOCKFILE="{lock_file}"
+            PIDFILE="{pid_file}"
             echo $$ > "$PIDFILE"
             trap "rm -f '$LOCKFILE' '$PIDFILE'" EXIT
             echo "Lock acquired by {hostname}" > "$LOCKFILE"
@@ -326,18 +866,16 @@ impl Connection for SshConnection {
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::null());
 
-        let child = command
-            .spawn()
+        let child = self
+            .exec
+            .spawn(&mut command)
             .context("Failed to start lock holder process")?;
 
         std::thread::sleep(std::time::Duration::from_millis(500));
 
-        let pid_str = self.execute(&format!("cat '{}' 2>/dev/null || echo 0", pid_file))?;
-        let remote_pid: u32 = pid_str.trim().parse().unwrap_or(0);
-
-        if remote_pid == 0 {
-            anyhow::bail!("Failed to acquire lock - could not get remote PID");
-        }
+        let remote_pid = self
+            .lock_holder(session_name)?
+            .context("Failed to acquire lock - could not get remote PID")?;
 
         let lock = SessionLock::new(hostname, remote_pid);
         Ok((lock, Some(child)))
@@ -348,23 +886,837 @@ impl Connection for SshConnection {
     }
 
     fn release_lock(&self, session_name: &str) -> Result<()> {
-        let lock_file = format!("{}/locks/{}.lock", BASE_DIR, session_name);
-        let pid_file = format!("{}/locks/{}.lock.pid", BASE_DIR, session_name);
+        self.run_helper(&["unlock", session_name])?;
+        Ok(())
+    }
 
-        self.execute(&format!(
-            "test -f '{pid_file}' && kill $(cat '{pid_file}') 2>/dev/null; rm -f '{lock_file}' '{pid_file}'",
-            pid_file = pid_file,
-            lock_file = lock_file
-        ))?;
+    fn wait_for_lock_change(&self, session_name: &str, nonce: &str) -> Result<()> {
+        let records = self.run_helper(&["watch-session", session_name, nonce])?;
+        find_record(&records, "session")?;
         Ok(())
     }
+
+    fn list_sockets(&self, prefix: Option<&str>) -> Result<Vec<AbducoSocket>> {
+        let mut args = vec!["list-sockets"];
+        args.extend(prefix);
+        Ok(parse_sockets(&self.run_helper(&args)?))
+    }
+
+    fn kill_sockets(&self, sockets: &[String]) -> Result<()> {
+        if sockets.is_empty() {
+            return Ok(());
+        }
+        let mut args = vec!["kill-sockets"];
+        args.extend(sockets.iter().map(|s| s.as_str()));
+        self.run_helper(&args)?;
+        Ok(())
+    }
+
+    fn process_info(&self, sockets: &[String]) -> Result<Vec<ProcessInfo>> {
+        let mut args = vec!["ps"];
+        args.extend(sockets.iter().map(|s| s.as_str()));
+        Ok(parse_process_info(&self.run_helper(&args)?))
+    }
+
+    fn boot_clients(&self, socket: &str, keep_client: &str) -> Result<u32> {
+        let records = self.run_helper(&["boot", socket, keep_client])?;
+        Ok(find_record(&records, "boot")?.field("booted")?.parse()?)
+    }
+
+    fn resize_clients(&self, sockets: &[String], client: &str) -> Result<()> {
+        let mut args = vec!["resize", client];
+        args.extend(sockets.iter().map(|s| s.as_str()));
+        self.run_helper(&args)?;
+        Ok(())
+    }
+
+    fn send_input(&self, inputs: &[(String, String)]) -> Result<u32> {
+        let inputs = hex_inputs(inputs);
+        let mut args = vec!["send"];
+        args.extend(inputs.iter().flat_map(|(socket, input)| [socket.as_str(), input.as_str()]));
+        Ok(find_record(&self.run_helper(&args)?, "send")?.field("sent")?.parse()?)
+    }
+
+    fn add_forward(&self, forward: &PortForward) -> Result<()> {
+        self.control("forward", forward)
+    }
+
+    fn cancel_forward(&self, forward: &PortForward) -> Result<()> {
+        self.control("cancel", forward)
+    }
+
+    fn mount(&self, mount: &Mount) -> Result<()> {
+        let local = mount.local_path();
+        if is_mounted(&local) {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&local)
+            .with_context(|| format!("Failed to create mount point {}", local.display()))?;
+
+        let ssh_command = format!("ssh {}", self.ssh_base_args().join(" "));
+        let output = self
+            .exec
+            .output(
+                Command::new("sshfs")
+                    .arg("-o")
+                    .arg(format!("ssh_command={}", ssh_command))
+                    .arg(format!("{}:{}", self.host, mount.remote_path()))
+                    .arg(&local),
+            )
+            .context("Failed to run sshfs (is it installed?)")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to mount {}: {}",
+                mount,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    fn unmount(&self, mount: &Mount) -> Result<()> {
+        let local = mount.local_path();
+        if !is_mounted(&local) {
+            return Ok(());
+        }
+
+        // fusermount3 ships with FUSE 3, fusermount with FUSE 2
+        for program in ["fusermount3", "fusermount"] {
+            match self.exec.output(Command::new(program).arg("-u").arg(&local)) {
+                Ok(output) if output.status.success() => return Ok(()),
+                Ok(output) => anyhow::bail!(
+                    "Failed to unmount {}: {}",
+                    local.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(_) => continue,
+            }
+        }
+        anyhow::bail!("Failed to unmount {}: fusermount not found", local.display())
+    }
+}
+
+/// Connection keeping sessions and locks in memory, for tests and `--offline`
+///
+/// Opened on a file, the contents are saved after every change and survive
+/// between invocations, so the CLI can run a whole activate/detach/attach
+/// cycle without SSH. Terminals are local abduco sessions either way; port
+/// forwards and mounts are refused.
+#[derive(Default)]
+pub struct MemoryConnection {
+    store: Mutex<MemoryStore>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct MemoryStore {
+    sessions: BTreeMap<String, String>,
+    locks: BTreeMap<String, SessionLock>,
+}
+
+impl MemoryConnection {
+    /// A connection backed by `path` (created on the first change)
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let store = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => MemoryStore::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self { store: Mutex::new(store), path: Some(path) })
+    }
+
+    /// Stand-in for `host` under `--offline`
+    fn offline(host: &str) -> Result<Self> {
+        let dir = ensure_user_dir()?.join("offline");
+        std::fs::create_dir_all(&dir)?;
+        Self::open(dir.join(format!("{}.json", host)))
+    }
+
+    fn update<T>(&self, change: impl FnOnce(&mut MemoryStore) -> Result<T>) -> Result<T> {
+        let mut store = self.store.lock().unwrap();
+        let result = change(&mut store)?;
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_string_pretty(&*store)?)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(result)
+    }
+}
+
+impl Connection for MemoryConnection {
+    fn save_session_data(&self, name: &str, data: &str) -> Result<()> {
+        self.update(|store| {
+            store.sessions.insert(name.to_string(), data.to_string());
+            Ok(())
+        })
+    }
+
+    fn load_session_data(&self, name: &str) -> Result<String> {
+        self.store.lock().unwrap().sessions.get(name).cloned().with_context(|| format!("Failed to load session '{}'", name))
+    }
+
+    fn list_session_names(&self) -> Result<Vec<String>> {
+        Ok(self.store.lock().unwrap().sessions.keys().cloned().collect())
+    }
+
+    fn acquire_lock(&self, session_name: &str, force: bool) -> Result<(SessionLock, Option<std::process::Child>)> {
+        self.update(|store| {
+            if let (false, Some(lock)) = (force, store.locks.get(session_name)) {
+                return Err(lock_held(session_name, Some(lock)));
+            }
+            let lock = SessionLock::new(client_id(), std::process::id());
+            store.locks.insert(session_name.to_string(), lock.clone());
+            Ok((lock, None))
+        })
+    }
+
+    /// A lock is held until released or broken (there is no process to outlive)
+    fn is_lock_valid(&self, lock: &SessionLock) -> Result<bool> {
+        Ok(self.store.lock().unwrap().locks.values().any(|held| held.nonce == lock.nonce))
+    }
+
+    fn release_lock(&self, session_name: &str) -> Result<()> {
+        self.update(|store| {
+            store.locks.remove(session_name);
+            Ok(())
+        })
+    }
+
+    fn delete_session(&self, name: &str) -> Result<()> {
+        self.update(|store| {
+            store.sessions.remove(name);
+            Ok(())
+        })
+    }
+
+    fn wait_for_lock_change(&self, _session_name: &str, _nonce: &str) -> Result<()> {
+        anyhow::bail!("Locks are not watched under --offline")
+    }
+
+    fn list_sockets(&self, prefix: Option<&str>) -> Result<Vec<AbducoSocket>> {
+        LocalConnection::default().list_sockets(prefix)
+    }
+
+    fn kill_sockets(&self, sockets: &[String]) -> Result<()> {
+        LocalConnection::default().kill_sockets(sockets)
+    }
+
+    fn process_info(&self, sockets: &[String]) -> Result<Vec<ProcessInfo>> {
+        LocalConnection::default().process_info(sockets)
+    }
+
+    fn boot_clients(&self, socket: &str, keep_client: &str) -> Result<u32> {
+        LocalConnection::default().boot_clients(socket, keep_client)
+    }
+
+    fn resize_clients(&self, sockets: &[String], client: &str) -> Result<()> {
+        LocalConnection::default().resize_clients(sockets, client)
+    }
+
+    fn send_input(&self, inputs: &[(String, String)]) -> Result<u32> {
+        LocalConnection::default().send_input(inputs)
+    }
+
+    fn add_forward(&self, _forward: &PortForward) -> Result<()> {
+        anyhow::bail!("Port forwards are not available offline")
+    }
+
+    fn cancel_forward(&self, _forward: &PortForward) -> Result<()> {
+        anyhow::bail!("Port forwards are not available offline")
+    }
+
+    fn mount(&self, _mount: &Mount) -> Result<()> {
+        anyhow::bail!("Mounts are not available offline")
+    }
+
+    fn unmount(&self, _mount: &Mount) -> Result<()> {
+        anyhow::bail!("Mounts are not available offline")
+    }
+}
+
+/// Inputs for the helper's `send`, hex-encoded so they survive the (ssh) command line
+fn hex_inputs(inputs: &[(String, String)]) -> Vec<(String, String)> {
+    inputs
+        .iter()
+        .map(|(socket, input)| (socket.clone(), input.bytes().map(|b| format!("{:02x}", b)).collect()))
+        .collect()
+}
+
+/// Session JSON as written to SSH hosts: gzip-compressed, so it crosses slow links quickly
+///
+/// The file keeps its `.json` name; `zcat -f` shows either kind.
+fn compress_session(json: &str) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(json.as_bytes())?;
+    Ok(encoder.finish()?)
+}
+
+/// Session JSON read from an SSH host, compressed or (from older versions) plain
+fn decompress_session(data: &[u8]) -> Result<String> {
+    use std::io::Read;
+
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return String::from_utf8(data.to_vec()).context("Session file is neither gzip nor UTF-8 JSON");
+    }
+    // Stop reading past the limit instead of inflating whatever the file claims to hold
+    let mut json = String::new();
+    flate2::read::GzDecoder::new(data)
+        .take(MAX_SESSION_BYTES as u64 + 1)
+        .read_to_string(&mut json)
+        .context("Session file is damaged (gzip)")?;
+    if json.len() > MAX_SESSION_BYTES {
+        anyhow::bail!("Session file is over the {} MiB limit", MAX_SESSION_BYTES >> 20);
+    }
+    Ok(json)
+}
+
+/// Error for a session whose lock is held by another client
+fn lock_held(session_name: &str, lock: Option<&SessionLock>) -> anyhow::Error {
+    let holder = match lock {
+        Some(lock) => format!("{} (acquired {})", lock.locked_by, lock.locked_at),
+        None => "another client".to_string(),
+    };
+    crate::error::Error::LockHeld { session: session_name.to_string(), holder }.into()
+}
+
+/// Whether `path` is a mount point (per /proc/mounts)
+fn is_mounted(path: &std::path::Path) -> bool {
+    let Ok(mounts) = std::fs::read_to_string("/proc/mounts") else {
+        return false;
+    };
+    // Mount points are the second field, with spaces escaped as \040
+    let path = path.to_string_lossy().replace(' ', "\\040");
+    mounts
+        .lines()
+        .filter_map(|line| line.split(' ').nth(1))
+        .any(|mount_point| mount_point == path)
+}
+
+/// What a remote host's login shell runs on
+#[derive(Debug, Clone, PartialEq)]
+pub enum Platform {
+    /// Linux, macOS or a BSD: the helper runs as is
+    Unix,
+    /// Linux inside WSL, reached through its own sshd: works like Unix
+    Wsl,
+    /// Somewhere the helper can't run (described for the error message)
+    Unsupported(String),
+}
+
+/// Ask `host` for its platform
+pub fn probe_platform(host: &str) -> Result<Platform> {
+    let output = exec::current()
+        .output(ssh_command(host).arg("uname -sr"))
+        .context("Failed to run ssh")?;
+    // ssh exits 255 when it couldn't connect: nothing is known about the host then
+    if output.status.code() == Some(SSH_CONNECTION_FAILED) {
+        return Err(ssh_unreachable(host, &String::from_utf8_lossy(&output.stderr)).into());
+    }
+    let uname = if output.status.success() {
+        String::from_utf8_lossy(&output.stdout).to_string()
+    } else {
+        String::new()
+    };
+    Ok(parse_platform(&uname))
+}
+
+/// Why ssh couldn't connect, as told by its error output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SshFailure {
+    /// The host presented a different key than known_hosts has
+    HostKeyChanged,
+    /// The host's key isn't in known_hosts and ssh couldn't ask
+    HostKeyUnknown,
+    /// No key or password was accepted
+    AuthFailed,
+    /// Nothing answered in time (or no route to it)
+    Timeout,
+    /// The host name didn't resolve
+    UnknownHost,
+    /// The host answered, but nothing listens for SSH
+    Refused,
+}
+
+impl SshFailure {
+    /// Recognize a failure class in ssh's stderr
+    pub fn classify(stderr: &str) -> Option<Self> {
+        let classes: [(&[&str], SshFailure); 6] = [
+            (&["REMOTE HOST IDENTIFICATION HAS CHANGED"], SshFailure::HostKeyChanged),
+            (&["Host key verification failed", "host key is known for"], SshFailure::HostKeyUnknown),
+            (&["Permission denied (", "Too many authentication failures", "Authentication failed"], SshFailure::AuthFailed),
+            (&["timed out", "No route to host", "Network is unreachable"], SshFailure::Timeout),
+            (&["Could not resolve hostname", "Name or service not known"], SshFailure::UnknownHost),
+            (&["Connection refused"], SshFailure::Refused),
+        ];
+        classes
+            .into_iter()
+            .find(|(patterns, _)| patterns.iter().any(|p| stderr.contains(p)))
+            .map(|(_, class)| class)
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            SshFailure::HostKeyChanged => "its host key has changed",
+            SshFailure::HostKeyUnknown => "its host key is not known",
+            SshFailure::AuthFailed => "authentication failed",
+            SshFailure::Timeout => "the connection timed out",
+            SshFailure::UnknownHost => "the host name could not be resolved",
+            SshFailure::Refused => "the connection was refused",
+        }
+    }
+
+    /// What to do about it, including how to see ssh's side interactively
+    fn hint(self, host: &str) -> String {
+        let name = host.rsplit('@').next().unwrap_or(host);
+        match self {
+            SshFailure::HostKeyChanged => format!(
+                "If {name} was reinstalled or its key rotated, remove the old key with 'ssh-keygen -R {name}' \
+                 and accept the new one by running 'ssh {host}'. Otherwise, the connection may be intercepted."
+            ),
+            SshFailure::HostKeyUnknown => format!("Run 'ssh {host}' once to check and accept its key."),
+            SshFailure::AuthFailed => format!(
+                "If {name} asks for a password or one-time code, log in with 'i3mux ssh connect --remote {host}' \
+                 first. Otherwise check that your key is loaded ('ssh-add -l') and hasn't expired; \
+                 'ssh -v {host}' shows which keys were offered."
+            ),
+            SshFailure::Timeout => {
+                format!("Check that {name} is up and reachable from this network (VPN?), then try 'ssh -v {host}'.")
+            }
+            SshFailure::UnknownHost => format!(
+                "Check the spelling, or define it in ~/.ssh/config or i3mux's [aliases]. 'ssh -v {host}' shows what ssh tried."
+            ),
+            SshFailure::Refused => format!("Check that sshd is running on {name}, then try 'ssh -v {host}'."),
+        }
+    }
+}
+
+/// An ssh command for `host` that shares i3mux's master connection to it
+///
+/// ssh gives up when the socket's directory is missing, so it is created here.
+pub fn ssh_command(host: &str) -> Command {
+    let _ = std::fs::create_dir_all(MASTER_SOCKET_DIR);
+    let mut command = Command::new("ssh");
+    command.args(crate::commands::SSH_MASTER_OPTIONS.split(' ')).arg(host);
+    command
+}
+
+/// Whether a master connection to `host` is up
+pub fn master_running(host: &str) -> bool {
+    let mut command = Command::new("ssh");
+    command.args(crate::commands::SSH_MASTER_OPTIONS.split(' ')).args(["-O", "check", host]);
+    exec::current().output(&mut command).is_ok_and(|output| output.status.success())
+}
+
+/// The error for ssh failing to connect to `host` with `stderr`
+pub fn ssh_unreachable(host: &str, stderr: &str) -> crate::error::Error {
+    let (reason, hint) = match SshFailure::classify(stderr) {
+        Some(failure) => (failure.reason().to_string(), failure.hint(host)),
+        None => (
+            stderr.trim().trim_end_matches('.').to_string(),
+            format!("Run 'ssh {}' to see what is wrong.", host),
+        ),
+    };
+    crate::error::Error::SshUnreachable { host: host.to_string(), reason, hint }
+}
+
+/// Classify `uname -sr` output (empty if uname couldn't run)
+fn parse_platform(uname: &str) -> Platform {
+    let uname = uname.trim();
+    let system = uname.split_whitespace().next().unwrap_or("");
+    match system {
+        "" => Platform::Unsupported("a Windows shell (cmd.exe or PowerShell)".to_string()),
+        s if s.starts_with("MINGW") || s.starts_with("MSYS") || s.starts_with("CYGWIN") => {
+            Platform::Unsupported(format!("{} on Windows", s))
+        }
+        "Linux" if uname.to_lowercase().contains("microsoft") => Platform::Wsl,
+        _ => Platform::Unix,
+    }
+}
+
+/// The current user's private directory for sessions and locks on this machine
+pub fn user_dir() -> PathBuf {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    PathBuf::from(format!("{}-{}{}", BASE_DIR, uid, crate::instance::suffix()))
+}
+
+/// Create `user_dir` (mode 700), refusing one that belongs to another user
+pub fn ensure_user_dir() -> Result<PathBuf> {
+    let dir = user_dir();
+    ensure_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Create `dir` (mode 700), refusing one that belongs to another user
+fn ensure_private_dir(dir: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    if !owned_by_current_user(dir) {
+        anyhow::bail!("{} is owned by another user; refusing to use it", dir.display());
+    }
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    Ok(())
+}
+
+/// Whether a file exists and belongs to the current user
+fn owned_by_current_user(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    std::fs::symlink_metadata(path).is_ok_and(|m| m.uid() == uid)
+}
+
+/// This machine's hostname, as recorded in the locks it takes
+pub fn hostname() -> String {
+    gethostname::gethostname()
+        .into_string()
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// This machine's client ID, passed to abduco clients in `CLIENT_ENV`
+///
+/// The hostname, restricted to characters that need no quoting in shell commands.
+pub fn client_id() -> String {
+    let hostname = gethostname::gethostname().to_string_lossy().to_string();
+    let id: String = hostname.chars().filter(|c| c.is_ascii_alphanumeric() || "-_.".contains(*c)).collect();
+    if id.is_empty() { "unknown".to_string() } else { id }
 }
 
 /// Create a connection from an optional host string
 /// None means local, Some(host) means remote SSH connection
+/// With `--offline`, remote hosts are served by a [`MemoryConnection`] instead
 pub fn create_connection(host: Option<&str>) -> Result<Box<dyn Connection>> {
     match host {
         None => Ok(Box::new(LocalConnection::new()?)),
-        Some(h) => Ok(Box::new(SshConnection::new(h.to_string()))),
+        Some(h) if is_offline() => Ok(Box::new(MemoryConnection::offline(h)?)),
+        Some(h) => {
+            let paths = crate::config::Config::load()?.remote_paths(h);
+            Ok(Box::new(SshConnection::new(h.to_string()).with_paths(paths)))
+        }
+    }
+}
+
+/// Set by `--offline`: remote hosts are never contacted
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+/// Whether remote hosts are stood in for locally (`--offline`)
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_helper_response() {
+        let output = "Welcome to box!\n@version\tversion=1.1.0\tprotocol=1\n@t\ta=x\\ty\tb=c\\\\d\tc=1\\n2\tempty=\n";
+        let records = parse_helper_response(output).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(find_record(&records, "version").unwrap().field("protocol").unwrap(), "1");
+        let t = find_record(&records, "t").unwrap();
+        assert_eq!(t.get("a"), Some("x\ty"));
+        assert_eq!(t.get("b"), Some("c\\d"));
+        assert_eq!(t.get("c"), Some("1\n2"));
+        assert_eq!(t.get("empty"), Some(""));
+        assert!(t.field("missing").is_err());
+        assert!(find_record(&records, "lock").is_err());
+
+        // An old helper's bare output has no records
+        assert!(parse_helper_response("1.0.15\n").unwrap().is_empty());
+
+        let err = parse_helper_response("@error\tcode=abduco_missing\tmessage=abduco not found\thint=Install it:\\n  apt install abduco\n").unwrap_err();
+        let err = err.downcast_ref::<HelperError>().unwrap();
+        assert_eq!(err.code, "abduco_missing");
+        assert_eq!(err.hint.as_deref(), Some("Install it:\n  apt install abduco"));
+    }
+
+    #[test]
+    fn test_helper_script_responses() {
+        let conn = LocalConnection::default();
+        let records = conn.run_helper(&["version"]).unwrap();
+        assert_eq!(find_record(&records, "version").unwrap().field("protocol").unwrap(), "1");
+        assert!(!conn.has_live_sockets("i3mux-test-no-such-prefix").unwrap());
+
+        let err = conn.run_helper(&["bogus"]).unwrap_err();
+        assert_eq!(err.downcast_ref::<HelperError>().unwrap().code, "usage");
+    }
+
+    #[test]
+    fn test_watch_session() {
+        // The helper looks in the connection's layout, not the user's directory
+        let dir = tempfile::TempDir::new().unwrap();
+        let paths = RemotePaths::under(dir.path().to_str().unwrap());
+        let conn = LocalConnection::default().with_paths(paths.clone());
+        let name = "watched".to_string();
+        let watch = |nonce: &str| {
+            let records = conn.run_helper(&["watch-session", &name, nonce, "0.1"]).unwrap();
+            find_record(&records, "session").unwrap().field("state").unwrap().to_string()
+        };
+        assert_eq!(watch("n1"), "deleted");
+
+        let session = |nonce: &str| format!("{{\n  \"lock\": {{\n    \"nonce\": \"{}\"\n  }}\n}}", nonce);
+        conn.save_session_data(&name, &session("n1")).unwrap();
+        assert_eq!(watch("n2"), "changed");
+        // A detach request wakes the lock holder's watch
+        let requested = "{\n  \"lock\": {\n    \"nonce\": \"n1\"\n  },\n  \"detach_request\": {}\n}";
+        conn.save_session_data(&name, requested).unwrap();
+        assert_eq!(watch("n1"), "changed");
+
+        // Blocks while the lock is unchanged, in compressed files as well
+        let path = std::path::PathBuf::from(paths.session_file(&name));
+        std::fs::write(&path, compress_session(&session("n1")).unwrap()).unwrap();
+        let started = std::time::Instant::now();
+        let relock = {
+            let (conn, name, data) = (LocalConnection::default().with_paths(paths.clone()), name.clone(), session("n2"));
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                conn.save_session_data(&name, &data).unwrap();
+            })
+        };
+        assert_eq!(watch("n1"), "changed");
+        assert!(started.elapsed() >= std::time::Duration::from_millis(300));
+        relock.join().unwrap();
+        conn.delete_session(&name).unwrap();
+    }
+
+    #[test]
+    fn test_list_sockets() {
+        let prefix = format!("i3mux-test{}", std::process::id());
+        let path = format!("/tmp/{}-001", prefix);
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        // Other files are no sockets
+        std::fs::write(format!("/tmp/{}-002", prefix), "").unwrap();
+
+        let conn = LocalConnection::default();
+        let sockets = conn.list_sockets(Some(&prefix));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(format!("/tmp/{}-002", prefix)).unwrap();
+
+        let sockets = sockets.unwrap();
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets[0].name, format!("{}-001", prefix));
+        assert!(sockets[0].created.is_some());
+        // No abduco session behind it
+        assert!(!sockets[0].is_running());
+        assert!(!conn.has_live_sockets(&prefix).unwrap());
+
+        let records = parse_helper_response("@socket\tname=ws3-001\tcreated=1700000000\tclients=2\tshell=4242\n").unwrap();
+        assert_eq!(
+            parse_sockets(&records),
+            [AbducoSocket { name: "ws3-001".into(), created: Some(1_700_000_000), clients: 2, shell_pid: Some(4242) }]
+        );
+    }
+
+    #[test]
+    fn test_parse_process_info() {
+        let output = "@process\tsocket=ws3-001\tpid=1234\tcpu=0.0\tmem=0.1\tbusy=0\tclients=1\tcommand=bash\n\
+                      @process\tsocket=ws3-002\tpid=5678\tcpu=98.2\tmem=3.4\tbusy=1\tclients=2\tcommand=cargo build --release\n\
+                      @process\tsocket=ws3-003\tpid=-\tcpu=-\tmem=-\tbusy=0\tclients=0\tcommand=-\n";
+        let info = parse_process_info(&parse_helper_response(output).unwrap());
+
+        assert_eq!(info.len(), 3);
+        assert_eq!(info[0].pid, Some(1234));
+        assert!(!info[0].busy);
+        assert!(info[1].busy);
+        assert_eq!(info[0].clients, 1);
+        assert_eq!(info[1].clients, 2);
+        assert_eq!(info[1].command, "cargo build --release");
+        assert_eq!(info[1].cpu, "98.2");
+        assert_eq!(info[2].socket, "ws3-003");
+        assert_eq!(info[2].pid, None);
+    }
+
+    #[test]
+    fn test_parse_platform() {
+        assert_eq!(parse_platform("Linux 6.8.0-45-generic\n"), Platform::Unix);
+        assert_eq!(parse_platform("Darwin 23.5.0"), Platform::Unix);
+        assert_eq!(parse_platform("Linux 5.15.153.1-microsoft-standard-WSL2"), Platform::Wsl);
+        assert_eq!(parse_platform("Linux 4.4.0-19041-Microsoft"), Platform::Wsl);
+        assert!(matches!(parse_platform("MINGW64_NT-10.0-19045 3.4.10"), Platform::Unsupported(_)));
+        assert!(matches!(parse_platform(""), Platform::Unsupported(_)));
+    }
+
+    #[test]
+    fn test_user_dir_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = ensure_user_dir().unwrap();
+        assert!(owned_by_current_user(&dir));
+        assert_eq!(std::fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+        assert!(!owned_by_current_user(std::path::Path::new("/nonexistent/i3mux")));
+    }
+
+    #[test]
+    fn test_ssh_commands() {
+        let recorder = exec::Recorder::new(|line| match line.last().map(String::as_str) {
+            Some(cmd) if cmd.contains("find ") => exec::reply(0, "api\nweb\n", ""),
+            Some(cmd) if cmd.contains("list-sockets") => exec::reply(0, "", ""),
+            _ => exec::reply(0, "", ""),
+        });
+        let conn = SshConnection::with_executor("user@box".into(), recorder.clone());
+
+        assert_eq!(conn.list_session_names().unwrap(), ["api", "web"]);
+        assert!(!conn.has_live_sockets("ws3").unwrap());
+        conn.save_session_data("web", "{}").unwrap();
+
+        let calls = recorder.calls();
+        let base = ["ssh", "-o", "ControlPath=/tmp/i3mux/sockets/%r@%h:%p", "-o", "ControlMaster=auto", "-o", "ControlPersist=10m", "user@box"];
+        assert!(calls.iter().all(|call| call[..8] == base));
+        assert_eq!(calls[1][8], "bash -lc '/tmp/i3mux-helper.sh list-sockets ws3'");
+        assert_eq!(calls[2][8], remote_ensure_dirs(&RemotePaths::remote_default()));
+        assert_eq!(calls[3][8], r#"cat > /tmp/i3mux-$(id -u)/sessions/web.json"#);
+
+        // A host with its own storage_dir tells the helper where to look
+        let recorder = exec::Recorder::new(|_| exec::reply(0, "", ""));
+        let conn = SshConnection::with_executor("user@box".into(), recorder.clone())
+            .with_paths(RemotePaths::under("/srv/state/$USER/"));
+        conn.save_session_data("web", "{}").unwrap();
+        conn.release_lock("web").unwrap();
+        let calls = recorder.calls();
+        assert!(calls[0][8].starts_with(r#"D="/srv/state/$USER"; "#));
+        assert_eq!(calls[1][8], r#"cat > /srv/state/$USER/sessions/web.json"#);
+        assert_eq!(
+            calls[2][8],
+            r#"bash -lc 'I3MUX_SESSIONS_DIR="/srv/state/$USER/sessions" I3MUX_LOCKS_DIR="/srv/state/$USER/locks" /tmp/i3mux-helper.sh unlock web'"#
+        );
+    }
+
+    #[test]
+    fn test_preflight_failures_are_actionable() {
+        let recorder = exec::Recorder::new(|_| exec::reply(1, "", "i3mux-preflight full /tmp/i3mux-1000 212\n"));
+        let conn = SshConnection::with_executor("user@box".into(), recorder.clone());
+        let err = conn.save_session_data("web", "{}").unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 10);
+        assert_eq!(
+            err.to_string(),
+            "Cannot store i3mux files in /tmp/i3mux-1000 on user@box: its filesystem is full (212 KiB free); free up space there"
+        );
+        // Nothing is written once the preflight fails
+        assert_eq!(recorder.calls().len(), 1);
+
+        let err = preflight_error("box", "i3mux-preflight unwritable /tmp").unwrap();
+        assert!(err.to_string().contains("/tmp on box: it is not writable"));
+        assert!(preflight_error("box", "cat: write error: No space left on device").is_none());
+    }
+
+    #[test]
+    fn test_session_compression() {
+        let json = serde_json::json!({ "name": "dev", "layout": "x".repeat(10_000) }).to_string();
+        let compressed = compress_session(&json).unwrap();
+        assert!(compressed.len() < json.len() / 10);
+        assert_eq!(decompress_session(&compressed).unwrap(), json);
+        // Sessions saved by older versions are plain JSON
+        assert_eq!(decompress_session(json.as_bytes()).unwrap(), json);
+
+        let huge = compress_session(&" ".repeat(MAX_SESSION_BYTES + 1)).unwrap();
+        assert!(decompress_session(&huge).unwrap_err().to_string().contains("limit"));
+        assert!(decompress_session(&compressed[..compressed.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_ssh_failure_is_reported() {
+        let recorder = exec::Recorder::new(|_| exec::reply(255, "", "ssh: connect to host box port 22: Connection refused\n"));
+        let conn = SshConnection::with_executor("box".into(), recorder);
+        let err = conn.delete_session("web").unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 5);
+        assert_eq!(
+            err.to_string(),
+            "Could not connect to box over SSH: the connection was refused. Check that sshd is running on box, \
+             then try 'ssh -v box'."
+        );
+
+        // An unreachable host is not mistaken for one without a POSIX shell
+        let platform = exec::scoped(exec::Recorder::new(|_| exec::reply(255, "", "ssh: Could not resolve hostname box\n")), || probe_platform("box"));
+        assert!(platform.is_err());
+    }
+
+    #[test]
+    fn test_ssh_failure_classes() {
+        let changed = "@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n\
+                       @    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @\n\
+                       Host key verification failed.\n";
+        assert_eq!(SshFailure::classify(changed), Some(SshFailure::HostKeyChanged));
+        assert_eq!(
+            SshFailure::classify("No ED25519 host key is known for box and you have requested strict checking.\nHost key verification failed.\n"),
+            Some(SshFailure::HostKeyUnknown)
+        );
+        assert_eq!(SshFailure::classify("user@box: Permission denied (publickey).\n"), Some(SshFailure::AuthFailed));
+        assert_eq!(SshFailure::classify("ssh: connect to host box port 22: Connection timed out\n"), Some(SshFailure::Timeout));
+        assert_eq!(
+            SshFailure::classify("ssh: Could not resolve hostname box: Name or service not known\n"),
+            Some(SshFailure::UnknownHost)
+        );
+        assert_eq!(SshFailure::classify("kex_exchange_identification: read: Connection reset by peer"), None);
+
+        // The guidance names the host without its user, and how to retry by hand
+        let err = ssh_unreachable("me@box", changed);
+        assert!(err.to_string().starts_with("Could not connect to me@box over SSH: its host key has changed. "));
+        assert!(err.to_string().contains("'ssh-keygen -R box'"));
+        assert!(err.to_string().contains("'ssh me@box'"));
+        let err = ssh_unreachable("me@box", "me@box: Permission denied (keyboard-interactive).\n");
+        assert!(err.to_string().contains("'i3mux ssh connect --remote me@box'"));
+        assert_eq!(
+            ssh_unreachable("box", "kex_exchange_identification: Connection closed by remote host.\n").to_string(),
+            "Could not connect to box over SSH: kex_exchange_identification: Connection closed by remote host. \
+             Run 'ssh box' to see what is wrong."
+        );
+    }
+
+    #[test]
+    fn test_memory_connection_locks() {
+        let conn = MemoryConnection::default();
+        let (lock, holder) = conn.acquire_lock("dev", false).unwrap();
+        assert!(holder.is_none());
+        assert!(conn.is_lock_valid(&lock).unwrap());
+
+        let err = conn.acquire_lock("dev", false).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+
+        // Breaking the lock invalidates the old holder's
+        let (forced, _) = conn.acquire_lock("dev", true).unwrap();
+        assert!(!conn.is_lock_valid(&lock).unwrap());
+        assert!(conn.is_lock_valid(&forced).unwrap());
+
+        conn.release_lock("dev").unwrap();
+        assert!(!conn.is_lock_valid(&forced).unwrap());
+        assert!(conn.acquire_lock("dev", false).is_ok());
+    }
+
+    #[test]
+    fn test_memory_connection_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("user@box.json");
+
+        let conn = MemoryConnection::open(path.clone()).unwrap();
+        conn.save_session_data("web", "{}").unwrap();
+        conn.save_session_data("api", "{}").unwrap();
+        let (lock, _) = conn.acquire_lock("web", false).unwrap();
+
+        let reopened = MemoryConnection::open(path).unwrap();
+        assert_eq!(reopened.list_session_names().unwrap(), ["api", "web"]);
+        assert!(reopened.is_lock_valid(&lock).unwrap());
+
+        reopened.delete_session("web").unwrap();
+        assert!(reopened.load_session_data("web").is_err());
+        assert_eq!(reopened.load_session_data("api").unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_parse_process_info_ignores_garbage() {
+        assert!(parse_process_info(&parse_helper_response("motd banner\n\n@process\tsocket=ws1-001\n").unwrap()).is_empty());
     }
 }
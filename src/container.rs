@@ -0,0 +1,138 @@
+//! Terminals attached to Docker/Podman containers via `exec`
+//!
+//! A container keeps its name across daemon restarts, so terminals record the
+//! name and the container's start time. On attach the container is checked to
+//! still exist and run; a changed start time means it was restarted and
+//! anything started in the old shell is gone.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+use crate::target::EXEC_SHELL;
+
+/// Container runtime CLI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    Docker,
+    Podman,
+}
+
+impl Runtime {
+    fn program(self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+        }
+    }
+}
+
+/// The container a terminal attaches to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerTarget {
+    pub runtime: Runtime,
+    /// Container name (or ID)
+    pub name: String,
+    /// When the container was started, as last seen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+}
+
+impl ContainerTarget {
+    /// Creates a new ContainerTarget after validation.
+    ///
+    /// # Errors
+    /// Returns error if the name contains characters not allowed in container names.
+    pub fn new(runtime: Runtime, name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+
+        let valid_start = name.starts_with(|c: char| c.is_ascii_alphanumeric());
+        if !valid_start || !name.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)) {
+            anyhow::bail!(
+                "Invalid container name '{}': only letters, digits, '_', '.' and '-' are allowed",
+                name
+            );
+        }
+
+        Ok(Self { runtime, name, started_at: None })
+    }
+
+    /// Check the container exists and is running, noting whether it restarted
+    pub fn check(&mut self) -> Result<()> {
+        let output = Command::new(self.runtime.program())
+            .arg("inspect")
+            .arg("-f")
+            .arg("{{.State.Running}} {{.State.StartedAt}}")
+            .arg(&self.name)
+            .output()
+            .with_context(|| format!("Failed to run {}", self.runtime.program()))?;
+
+        if !output.status.success() {
+            anyhow::bail!("Container {} does not exist", self);
+        }
+
+        let (running, started_at) = parse_state(&String::from_utf8_lossy(&output.stdout))
+            .with_context(|| format!("Unexpected inspect output for container {}", self))?;
+        if !running {
+            anyhow::bail!(
+                "Container {} is not running (start it with '{} start {}')",
+                self,
+                self.runtime.program(),
+                self.name
+            );
+        }
+        if self.started_at.as_ref().is_some_and(|previous| *previous != started_at) {
+            println!("  Container {} was restarted since detach; its previous shells are gone", self);
+        }
+        self.started_at = Some(started_at);
+        Ok(())
+    }
+
+    /// Command that attaches a terminal to the container
+    pub fn attach_command(&self) -> String {
+        format!("{} exec -it {} {}", self.runtime.program(), self.name, EXEC_SHELL)
+    }
+}
+
+impl std::fmt::Display for ContainerTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.runtime.program(), self.name)
+    }
+}
+
+/// Parse "true 2024-05-01T10:00:00.123Z" from `inspect -f`
+fn parse_state(output: &str) -> Option<(bool, String)> {
+    let (running, started_at) = output.trim().split_once(' ')?;
+    Some((running.parse().ok()?, started_at.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        assert!(ContainerTarget::new(Runtime::Docker, "web-1").is_ok());
+        assert!(ContainerTarget::new(Runtime::Podman, "my_app.db").is_ok());
+        assert!(ContainerTarget::new(Runtime::Docker, "").is_err());
+        assert!(ContainerTarget::new(Runtime::Docker, "-rm").is_err());
+        assert!(ContainerTarget::new(Runtime::Docker, "web; reboot").is_err());
+    }
+
+    #[test]
+    fn test_attach_command() {
+        let target = ContainerTarget::new(Runtime::Podman, "web").unwrap();
+        assert_eq!(target.attach_command(), format!("podman exec -it web {}", EXEC_SHELL));
+    }
+
+    #[test]
+    fn test_parse_state() {
+        assert_eq!(
+            parse_state("true 2024-05-01T10:00:00.123456789Z\n"),
+            Some((true, "2024-05-01T10:00:00.123456789Z".to_string()))
+        );
+        assert_eq!(parse_state("false 0001-01-01T00:00:00Z"), Some((false, "0001-01-01T00:00:00Z".to_string())));
+        assert_eq!(parse_state("garbage"), None);
+    }
+}
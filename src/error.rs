@@ -0,0 +1,132 @@
+//! Error kinds that callers of the CLI can rely on
+//!
+//! Most failures are plain `anyhow` errors with context. The ones scripts and
+//! pickers need to tell apart are raised as an [`Error`] variant (still carried
+//! inside `anyhow::Error`), which maps to a stable exit code and, with --json,
+//! a machine-readable error object.
+
+use serde_json::json;
+
+/// Exit code for errors without a specific kind
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Exit code when `attach` needs --session to choose between several sessions
+pub const EXIT_AMBIGUOUS_SESSION: i32 = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Session '{session}' is locked by {holder}. Use --here-then-close-origin to have it detach first, or --force to break lock.")]
+    LockHeld { session: String, holder: String },
+
+    #[error("Session '{session}' not found on {host}")]
+    SessionNotFound { session: String, host: String },
+
+    #[error("Multiple sessions on {host} ({}). Specify one with -s/--session", .sessions.join(", "))]
+    AmbiguousSession { host: String, sessions: Vec<String> },
+
+    #[error("Could not connect to {host} over SSH: {reason}. {hint}")]
+    SshUnreachable { host: String, reason: String, hint: String },
+
+    #[error("No running window manager (i3 or Sway) detected. Ensure I3SOCK or SWAYSOCK is set.")]
+    WmUnavailable,
+
+    #[error("abduco not found on {host}. {hint}")]
+    AbducoMissing { host: String, hint: String },
+
+    #[error("Workspace not i3mux-bound")]
+    NotBound,
+
+    #[error("Session '{session}' on {host} was saved from {saved_by} since this workspace attached it. Use --on-conflict merge or overwrite.")]
+    SessionChanged { session: String, host: String, saved_by: String },
+
+    #[error("Cannot store i3mux files in {dir} on {host}: {problem}")]
+    RemoteStorage { host: String, dir: String, problem: String },
+}
+
+impl Error {
+    /// Exit code for this kind (stable across releases)
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::LockHeld { .. } => 3,
+            Error::SessionNotFound { .. } => 4,
+            Error::AmbiguousSession { .. } => EXIT_AMBIGUOUS_SESSION,
+            Error::SshUnreachable { .. } => 5,
+            Error::WmUnavailable => 6,
+            Error::AbducoMissing { .. } => 7,
+            Error::NotBound => 8,
+            Error::SessionChanged { .. } => 9,
+            Error::RemoteStorage { .. } => 10,
+        }
+    }
+
+    /// Name of this kind in JSON output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::LockHeld { .. } => "lock_held",
+            Error::SessionNotFound { .. } => "session_not_found",
+            Error::AmbiguousSession { .. } => "ambiguous_session",
+            Error::SshUnreachable { .. } => "ssh_unreachable",
+            Error::WmUnavailable => "wm_unavailable",
+            Error::AbducoMissing { .. } => "abduco_missing",
+            Error::NotBound => "not_bound",
+            Error::SessionChanged { .. } => "session_changed",
+            Error::RemoteStorage { .. } => "remote_storage",
+        }
+    }
+}
+
+/// The first i3mux error kind in an error's chain, if any
+fn find(err: &anyhow::Error) -> Option<&Error> {
+    err.chain().find_map(|cause| cause.downcast_ref::<Error>())
+}
+
+/// Exit code for any error returned by a command
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    find(err).map_or(EXIT_FAILURE, Error::exit_code)
+}
+
+/// JSON object describing an error: `{"error": {"kind", "message", "exit_code"}}`
+pub fn to_json(err: &anyhow::Error) -> serde_json::Value {
+    json!({
+        "error": {
+            "kind": find(err).map_or("other", Error::kind),
+            "message": format!("{:#}", err),
+            "exit_code": exit_code(err),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_kind_found_under_context() {
+        let err = Err::<(), _>(Error::SessionNotFound { session: "dev".into(), host: "box".into() })
+            .context("Failed to attach")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), 4);
+
+        let json = to_json(&err);
+        assert_eq!(json["error"]["kind"], "session_not_found");
+        assert_eq!(json["error"]["message"], "Failed to attach: Session 'dev' not found on box");
+        assert_eq!(json["error"]["exit_code"], 4);
+    }
+
+    #[test]
+    fn test_ambiguous_session() {
+        let err: anyhow::Error = Error::AmbiguousSession { host: "box".into(), sessions: vec!["api".into(), "web".into()] }.into();
+        assert_eq!(exit_code(&err), EXIT_AMBIGUOUS_SESSION);
+        let json = to_json(&err);
+        assert_eq!(json["error"]["kind"], "ambiguous_session");
+        assert_eq!(json["error"]["message"], "Multiple sessions on box (api, web). Specify one with -s/--session");
+    }
+
+    #[test]
+    fn test_plain_errors() {
+        let err = anyhow::anyhow!("something else");
+        assert_eq!(exit_code(&err), EXIT_FAILURE);
+        assert_eq!(to_json(&err)["error"]["kind"], "other");
+    }
+}
@@ -0,0 +1,152 @@
+//! Running external programs (ssh, abduco checks, terminals)
+//!
+//! Everything that starts a process goes through an [`Executor`], so the
+//! commands i3mux builds can be checked in unit tests with a [`Recorder`]
+//! instead of a real SSH server or terminal. Connections take an executor when
+//! created; code in main.rs uses the current thread's, see [`current`].
+
+use std::cell::RefCell;
+use std::io;
+use std::process::{Child, Command, ExitStatus, Output};
+use std::sync::Arc;
+
+/// Starts processes
+pub trait Executor: Send + Sync {
+    /// Run to completion, capturing stdout and stderr
+    fn output(&self, cmd: &mut Command) -> io::Result<Output>;
+    /// Run to completion with inherited stdio
+    fn run(&self, cmd: &mut Command) -> io::Result<ExitStatus>;
+    /// Start without waiting
+    fn spawn(&self, cmd: &mut Command) -> io::Result<Child>;
+}
+
+/// Runs commands for real
+pub struct System;
+
+impl Executor for System {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        cmd.output()
+    }
+
+    fn run(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        cmd.status()
+    }
+
+    fn spawn(&self, cmd: &mut Command) -> io::Result<Child> {
+        cmd.spawn()
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Arc<dyn Executor>> = RefCell::new(Arc::new(System));
+}
+
+/// The executor of the current thread ([`System`] unless inside [`scoped`])
+pub fn current() -> Arc<dyn Executor> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Run `f` with `executor` as the current thread's executor
+///
+/// Threads started inside `f` begin with [`System`]; hand them
+/// `current()` to keep using the same one.
+pub fn scoped<T>(executor: Arc<dyn Executor>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(executor));
+    let result = f();
+    CURRENT.with(|current| current.replace(previous));
+    result
+}
+
+/// Executor that records commands instead of running them
+///
+/// Replies come from a closure over the command line; spawned commands are
+/// replaced by `cat`, which takes (and discards) whatever is written to stdin.
+#[cfg(test)]
+pub struct Recorder {
+    calls: std::sync::Mutex<Vec<Vec<String>>>,
+    reply: Box<Reply>,
+}
+
+/// Output for a recorded command line (program first)
+#[cfg(test)]
+type Reply = dyn Fn(&[String]) -> Output + Send + Sync;
+
+#[cfg(test)]
+impl Recorder {
+    pub fn new(reply: impl Fn(&[String]) -> Output + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self { calls: Default::default(), reply: Box::new(reply) })
+    }
+
+    /// Command lines run so far, in order
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, cmd: &Command) -> Output {
+        let line: Vec<String> = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let output = (self.reply)(&line);
+        self.calls.lock().unwrap().push(line);
+        output
+    }
+}
+
+#[cfg(test)]
+impl Executor for Recorder {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        Ok(self.record(cmd))
+    }
+
+    fn run(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        Ok(self.record(cmd).status)
+    }
+
+    fn spawn(&self, cmd: &mut Command) -> io::Result<Child> {
+        self.record(cmd);
+        Command::new("cat")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+    }
+}
+
+/// Output of a command that exited with `code`
+#[cfg(test)]
+pub fn reply(code: i32, stdout: &str, stderr: &str) -> Output {
+    use std::os::unix::process::ExitStatusExt;
+
+    Output {
+        status: ExitStatus::from_raw(code << 8),
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: stderr.as_bytes().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder_replies_and_records() {
+        let recorder = Recorder::new(|line| if line[0] == "true" { reply(0, "yes", "") } else { reply(1, "", "no") });
+
+        let output = recorder.output(Command::new("true").arg("-x")).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"yes");
+        assert_eq!(recorder.run(&mut Command::new("false")).unwrap().code(), Some(1));
+        assert_eq!(recorder.calls(), [vec!["true", "-x"], vec!["false"]]);
+    }
+
+    #[test]
+    fn test_scoped_executor() {
+        let recorder = Recorder::new(|_| reply(0, "", ""));
+        scoped(recorder.clone(), || {
+            current().run(&mut Command::new("ssh")).unwrap();
+        });
+        assert_eq!(recorder.calls(), [vec!["ssh"]]);
+        // Back to running commands for real
+        assert!(current().output(&mut Command::new("true")).unwrap().status.success());
+    }
+}
@@ -0,0 +1,251 @@
+//! One-shot triage of a host's sessions (`i3mux health`)
+//!
+//! Checks what attach and detach rely on: the helper script and abduco on the
+//! host, and for each saved session whether its file still reads, whether its
+//! lock is held or stale, and how many of its terminals' abduco sessions are
+//! still running. Meant to be run (and its output pasted) before filing a bug.
+
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fmt;
+use std::process::Command;
+
+use crate::connection::{self, Connection};
+use crate::exec;
+use crate::session::RemoteSession;
+use crate::style;
+
+/// One thing checked, and whether it needs attention
+#[derive(Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub subject: &'static str,
+    pub detail: String,
+    pub problem: bool,
+}
+
+impl Finding {
+    fn ok(subject: &'static str, detail: impl Into<String>) -> Self {
+        Self { subject, detail: detail.into(), problem: false }
+    }
+
+    fn problem(subject: &'static str, detail: impl Into<String>) -> Self {
+        Self { subject, detail: detail.into(), problem: true }
+    }
+}
+
+/// Everything checked on a host
+pub struct Report {
+    pub host: String,
+    /// The helper, abduco and the socket listing
+    pub host_findings: Vec<Finding>,
+    /// Findings per saved session, by name
+    pub sessions: Vec<(String, Vec<Finding>)>,
+}
+
+impl Report {
+    /// Number of findings that need attention
+    pub fn problems(&self) -> usize {
+        self.host_findings
+            .iter()
+            .chain(self.sessions.iter().flat_map(|(_, findings)| findings))
+            .filter(|f| f.problem)
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn findings(f: &mut fmt::Formatter, findings: &[Finding]) -> fmt::Result {
+            for finding in findings {
+                let marker = if finding.problem { style::problem() } else { style::ok() };
+                writeln!(f, "  {:<8} {} {}", finding.subject, marker, finding.detail)?;
+            }
+            Ok(())
+        }
+
+        writeln!(f, "Health of {}:\n", style::host(&self.host))?;
+        findings(f, &self.host_findings)?;
+        if self.sessions.is_empty() {
+            writeln!(f, "\nNo sessions saved")?;
+        }
+        for (name, session) in &self.sessions {
+            writeln!(f, "\nSession '{}':", name)?;
+            findings(f, session)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check `host` (None for this machine) and its saved sessions
+pub fn run(host: Option<&str>) -> Result<Report> {
+    let conn = connection::create_connection(host)?;
+    let host_name = host.unwrap_or("local");
+
+    let mut host_findings = check_tools(host);
+    let live = match conn.list_sockets(None) {
+        Ok(sockets) => Some(sockets.into_iter().filter(|s| s.is_running()).map(|s| s.name).collect()),
+        Err(e) => {
+            host_findings.push(Finding::problem("sockets", format!("could not list abduco sessions: {:#}", e)));
+            None
+        }
+    };
+
+    let sessions = RemoteSession::list_remote_sessions(conn.as_ref())?
+        .into_iter()
+        .map(|name| {
+            let findings = check_session(conn.as_ref(), host_name, &name, live.as_ref());
+            (name, findings)
+        })
+        .collect();
+    Ok(Report { host: host_name.to_string(), host_findings, sessions })
+}
+
+/// The helper script and abduco on `host`
+fn check_tools(host: Option<&str>) -> Vec<Finding> {
+    if connection::is_offline() {
+        return vec![Finding::ok("tools", "not checked (--offline)")];
+    }
+    let shipped = crate::helper_script_version();
+    let Some(host) = host else {
+        let abduco = exec::current().output(Command::new("abduco").arg("-v"));
+        return vec![
+            Finding::ok("helper", format!("{} (built in)", shipped)),
+            abduco_finding(abduco.ok().as_ref()),
+        ];
+    };
+
+    let helper = match crate::installed_helper_version(host) {
+        Ok(installed) if installed == shipped => Finding::ok("helper", installed),
+        Ok(installed) if installed.is_empty() => {
+            Finding::problem("helper", "not installed (uploaded on the next attach)")
+        }
+        Ok(installed) => Finding::problem(
+            "helper",
+            format!("{}, this i3mux ships {} (replaced on the next attach)", installed, shipped),
+        ),
+        Err(e) => Finding::problem("helper", format!("{:#}", e)),
+    };
+    // Through a login shell, as attaching runs it
+    let abduco = exec::current().output(connection::ssh_command(host).arg("bash -lc 'abduco -v' 2>&1"));
+    vec![helper, abduco_finding(abduco.ok().as_ref())]
+}
+
+/// abduco's version from `abduco -v` output ("abduco-0.6 © ...")
+fn abduco_finding(output: Option<&std::process::Output>) -> Finding {
+    let version = output.and_then(|output| {
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+        let token = stdout.split_whitespace().find_map(|word| word.strip_prefix("abduco-"))?;
+        Some(token.to_string())
+    });
+    match version {
+        Some(version) => Finding::ok("abduco", version),
+        None => Finding::problem("abduco", "not found (attach needs it)"),
+    }
+}
+
+/// Storage, lock and sockets of one saved session on `host`
+///
+/// `live` are the host's running abduco sessions (None if they couldn't be listed).
+pub fn check_session(conn: &dyn Connection, host: &str, name: &str, live: Option<&HashSet<String>>) -> Vec<Finding> {
+    let session = match RemoteSession::load_from_remote(conn, name) {
+        Ok(session) => session,
+        Err(e) => return vec![Finding::problem("storage", format!("unreadable: {:#}", e))],
+    };
+
+    let mut findings = Vec::new();
+    findings.push(Finding::ok(
+        "storage",
+        match &session.saved_by {
+            Some(saved_by) => format!("ok (revision {}, saved by {})", session.revision, saved_by),
+            None => "ok".to_string(),
+        },
+    ));
+
+    findings.push(match &session.lock {
+        None => Finding::ok("lock", "free"),
+        Some(lock) => match conn.is_lock_valid(lock) {
+            Ok(true) => Finding::ok("lock", format!("held by {}", lock.locked_by)),
+            Ok(false) => Finding::problem(
+                "lock",
+                format!("stale: taken by {}, whose lock holder is gone (attach --force takes it over)", lock.locked_by),
+            ),
+            Err(e) => Finding::problem("lock", format!("could not check: {:#}", e)),
+        },
+    });
+
+    let by_host = session.layout.get_sockets_by_host(host);
+    let elsewhere: usize = by_host.iter().filter(|(h, _)| h.as_str() != host).map(|(_, s)| s.len()).sum();
+    let note = if elsewhere > 0 { format!(" (+{} on other hosts, not checked)", elsewhere) } else { String::new() };
+    let sockets = by_host.get(host).cloned().unwrap_or_default();
+    findings.push(match live {
+        None => Finding::problem("sockets", format!("{} terminals, not checked{}", sockets.len(), note)),
+        Some(live) => {
+            let gone: Vec<&str> = sockets.iter().filter(|s| !live.contains(*s)).map(String::as_str).collect();
+            let summary = format!("{} of {} running{}", sockets.len() - gone.len(), sockets.len(), note);
+            if gone.is_empty() {
+                Finding::ok("sockets", summary)
+            } else {
+                Finding::problem("sockets", format!("{}; gone: {} (attach starts fresh shells)", summary, gone.join(", ")))
+            }
+        }
+    });
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::MemoryConnection;
+    use crate::layout::Layout;
+
+    fn terminal(socket: &str, host: Option<&str>) -> Layout {
+        Layout::Terminal { socket: socket.into(), percent: None, command: None, host: host.map(Into::into), attach: None, target: None, title: None }
+    }
+
+    #[test]
+    fn test_check_session() {
+        let conn = MemoryConnection::default();
+        let layout = Layout::HSplit {
+            children: vec![terminal("ws2-001", None), terminal("ws2-002", None), terminal("ws2-003", Some("db"))],
+            percent: None,
+        };
+        let mut session = RemoteSession::new("dev".into(), "2".into(), "box".into(), layout).unwrap();
+        session.save_to_remote(&conn).unwrap();
+        conn.save_session_data("broken", "{ not json").unwrap();
+
+        let live: HashSet<String> = ["ws2-001".to_string()].into();
+        let findings = check_session(&conn, "box", "dev", Some(&live));
+        let saved_by = connection::hostname();
+        assert_eq!(
+            findings,
+            [
+                Finding::ok("storage", format!("ok (revision 1, saved by {})", saved_by)),
+                Finding::ok("lock", "free"),
+                Finding::problem(
+                    "sockets",
+                    "1 of 2 running (+1 on other hosts, not checked); gone: ws2-002 (attach starts fresh shells)"
+                ),
+            ]
+        );
+
+        let broken = check_session(&conn, "box", "broken", Some(&live));
+        assert_eq!(broken.len(), 1);
+        assert!(broken[0].problem && broken[0].detail.starts_with("unreadable: "));
+
+        let report = Report {
+            host: "box".into(),
+            host_findings: vec![Finding::ok("helper", "1.4.0")],
+            sessions: vec![("dev".into(), findings), ("broken".into(), broken)],
+        };
+        assert_eq!(report.problems(), 2);
+    }
+
+    #[test]
+    fn test_abduco_finding() {
+        let output = exec::reply(0, "abduco-0.6 © 2013-2018 Marc André Tanner\n", "");
+        assert_eq!(abduco_finding(Some(&output)), Finding::ok("abduco", "0.6"));
+        let missing = exec::reply(127, "bash: line 1: abduco: command not found\n", "");
+        assert!(abduco_finding(Some(&missing)).problem);
+        assert!(abduco_finding(None).problem);
+    }
+}
@@ -0,0 +1,57 @@
+//! Readiness events for test automation (--test-hooks)
+//!
+//! Some work finishes after the command that started it returns, or in another
+//! process: terminals are marked once their window shows up, and cleanup runs
+//! in `_wrap` when a terminal exits. With `--test-hooks <FILE>`, each such step
+//! appends a line `<event> <subject>` to FILE, so test harnesses can wait for
+//! it instead of sleeping. The path is passed on through the environment, so
+//! terminals started by the command report to the same file.
+
+use std::io::Write;
+
+/// Environment variable holding the hooks file
+const ENV: &str = "I3MUX_TEST_HOOKS";
+
+/// A step automation can wait for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A terminal's window was marked (subject: the mark)
+    WindowMarked,
+    /// A terminal's cleanup finished (subject: its socket)
+    CleanupDone,
+    /// A session lock was taken (subject: the session)
+    LockAcquired,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::WindowMarked => "window-marked",
+            Event::CleanupDone => "cleanup-done",
+            Event::LockAcquired => "lock-acquired",
+        }
+    }
+}
+
+/// Report events to `path`, from this process and the ones it starts
+pub fn enable(path: &str) {
+    std::env::set_var(ENV, path);
+}
+
+/// Record that `event` happened for `subject` (nothing without --test-hooks)
+///
+/// Each event is a single append, so lines from concurrent processes don't mix.
+pub fn emit(event: Event, subject: &str) {
+    let Ok(path) = std::env::var(ENV) else {
+        return;
+    };
+    let line = format!("{} {}\n", event.name(), subject);
+    let written = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = written {
+        eprintln!("[i3mux] Could not write test hook to {}: {}", path, e);
+    }
+}
@@ -0,0 +1,93 @@
+//! Side-by-side installations (`--instance NAME`)
+//!
+//! Two builds of i3mux (say a release and a development build) would otherwise
+//! share window marks, local state and abduco socket names, and each would
+//! adopt or clean up the other's terminals. An instance name keeps them apart:
+//! marks become `_i3mux-NAME:...`, sockets `NAME-ws4-001`, the local state
+//! moves to `~/.config/i3mux/instances/NAME/` and the per-user directories
+//! (here and on remote hosts) and the remote helper get a `-NAME` suffix.
+//! The config file and SSH master connections stay shared.
+//!
+//! The name is passed on in the environment, so terminals and background
+//! watchers started by an instance belong to it.
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+/// Environment variable holding the instance name
+pub const ENV: &str = "I3MUX_INSTANCE";
+
+/// Use instance `name` for this process and the ones it starts (None keeps
+/// the one inherited from the environment, if any)
+pub fn init(name: Option<&str>) -> Result<()> {
+    let Some(name) = name else {
+        return Ok(());
+    };
+    validate(name)?;
+    std::env::set_var(ENV, name);
+    Ok(())
+}
+
+fn validate(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 32
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-');
+    if !valid {
+        anyhow::bail!("Invalid instance name '{}': use lowercase letters, digits and '-' (like 'dev')", name);
+    }
+    Ok(())
+}
+
+/// The instance name, if one is set
+pub fn name() -> Option<String> {
+    std::env::var(ENV).ok().filter(|name| validate(name).is_ok())
+}
+
+/// Appended to names the instance must not share: `-NAME`, or nothing
+pub fn suffix() -> String {
+    name().map(|name| format!("-{}", name)).unwrap_or_default()
+}
+
+/// Prefix of the abduco sockets of workspace `ws_name` (`ws4` or `NAME-ws4`)
+pub fn socket_prefix(ws_name: &str) -> String {
+    match name() {
+        Some(name) => format!("{}-ws{}", name, ws_name),
+        None => format!("ws{}", ws_name),
+    }
+}
+
+/// Prefix of the abduco sockets of a session created from a spec (`spec.NAME`
+/// or `INSTANCE.spec.NAME`)
+///
+/// Session and instance names have no dots, so these never match a
+/// workspace's sockets: a session named `ws2` doesn't take over `ws2-001`.
+pub fn spec_socket_prefix(session: &str) -> String {
+    match name() {
+        Some(name) => format!("{}.spec.{}", name, session),
+        None => format!("spec.{}", session),
+    }
+}
+
+/// Directory for the local state (`~/.config/i3mux`, or the instance's own below it)
+pub fn state_dir() -> Result<PathBuf> {
+    let dir = dirs::config_dir().context("Could not find config directory")?.join("i3mux");
+    Ok(match name() {
+        Some(name) => dir.join("instances").join(name),
+        None => dir,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("dev").is_ok());
+        assert!(validate("build-2").is_ok());
+        for bad in ["", "Dev", "-dev", "a/b", "a b", "dev;rm"] {
+            assert!(validate(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
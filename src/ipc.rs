@@ -0,0 +1,246 @@
+//! Native i3/Sway IPC and event subscriptions
+//!
+//! Commands and queries go through `i3-msg`/`swaymsg`, but events can only be
+//! received by staying connected to the IPC socket. Both WMs speak the same
+//! protocol: a message is the magic string `i3-ipc`, the payload length and
+//! the message type (both u32 in native byte order), followed by a JSON
+//! payload. Events are messages whose type has the high bit set.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc;
+
+use crate::wm::WmNode;
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+
+/// Message type of a SUBSCRIBE request (and its reply)
+const SUBSCRIBE: u32 = 2;
+
+/// Set on the type of every event message
+const EVENT_BIT: u32 = 1 << 31;
+
+/// Events i3mux can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Workspace,
+    Window,
+    Binding,
+    Shutdown,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 4] = [EventKind::Workspace, EventKind::Window, EventKind::Binding, EventKind::Shutdown];
+
+    /// Name in a SUBSCRIBE payload
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Workspace => "workspace",
+            EventKind::Window => "window",
+            EventKind::Binding => "binding",
+            EventKind::Shutdown => "shutdown",
+        }
+    }
+
+    /// Event type, without the event bit (same numbers on i3 and Sway)
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(EventKind::Workspace),
+            3 => Some(EventKind::Window),
+            5 => Some(EventKind::Binding),
+            6 => Some(EventKind::Shutdown),
+            _ => None,
+        }
+    }
+}
+
+/// An event from the window manager
+///
+/// Only window events carry their payload: `change` is passed through as
+/// reported ("new", "close", "focus", "mark", "title", ...). The others just
+/// say that something happened.
+#[derive(Debug, Clone)]
+pub enum WmEvent {
+    Window { change: String, container: Box<WmNode> },
+    /// A workspace was focused, created, emptied, ...
+    Workspace,
+    /// A key binding ran
+    Binding,
+    /// The WM is exiting or (i3 only) restarting in place
+    Shutdown,
+}
+
+#[derive(Deserialize)]
+struct WindowPayload {
+    change: String,
+    container: Box<WmNode>,
+}
+
+#[derive(Deserialize)]
+struct SubscribeReply {
+    success: bool,
+}
+
+impl WmEvent {
+    fn parse(kind: EventKind, payload: &[u8]) -> Result<Self> {
+        Ok(match kind {
+            EventKind::Window => {
+                let p: WindowPayload = serde_json::from_slice(payload)?;
+                WmEvent::Window { change: p.change, container: p.container }
+            }
+            EventKind::Workspace => WmEvent::Workspace,
+            EventKind::Binding => WmEvent::Binding,
+            EventKind::Shutdown => WmEvent::Shutdown,
+        })
+    }
+}
+
+fn write_message(stream: &mut UnixStream, message_type: u32, payload: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(14 + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload);
+    stream.write_all(&message)
+}
+
+fn read_message(stream: &mut UnixStream) -> io::Result<(u32, Vec<u8>)> {
+    let mut header = [0u8; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an i3 IPC message"));
+    }
+    let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((message_type, payload))
+}
+
+/// Events from a subscribed IPC connection
+///
+/// Ends when the WM closes the connection (after a shutdown event, or if it
+/// crashes). Events of kinds that weren't subscribed to are skipped.
+pub struct EventStream {
+    stream: UnixStream,
+}
+
+impl EventStream {
+    /// Subscribe to `kinds` on the IPC socket at `socket_path`
+    pub fn subscribe(socket_path: &str, kinds: &[EventKind]) -> Result<Self> {
+        let stream = UnixStream::connect(socket_path)
+            .with_context(|| format!("Failed to connect to WM IPC socket {}", socket_path))?;
+        Self::subscribe_on(stream, kinds)
+    }
+
+    fn subscribe_on(mut stream: UnixStream, kinds: &[EventKind]) -> Result<Self> {
+        let names: Vec<&str> = kinds.iter().map(|k| k.name()).collect();
+        write_message(&mut stream, SUBSCRIBE, serde_json::to_string(&names)?.as_bytes())
+            .context("Failed to send subscribe request")?;
+
+        // Events can't arrive before the reply: nothing is subscribed yet
+        let (message_type, payload) = read_message(&mut stream).context("Failed to read subscribe reply")?;
+        if message_type != SUBSCRIBE {
+            anyhow::bail!("Unexpected IPC reply type {} to subscribe", message_type);
+        }
+        let reply: SubscribeReply = serde_json::from_slice(&payload).context("Failed to parse subscribe reply")?;
+        if !reply.success {
+            anyhow::bail!("Window manager refused subscription to {}", names.join(", "));
+        }
+        Ok(Self { stream })
+    }
+
+    /// Deliver events on a channel from a background thread
+    ///
+    /// The thread exits when the stream ends or the receiver is dropped.
+    pub fn into_channel(self) -> mpsc::Receiver<Result<WmEvent>> {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for event in self {
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = Result<WmEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (message_type, payload) = match read_message(&mut self.stream) {
+                Ok(message) => message,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+                Err(e) => return Some(Err(anyhow::Error::new(e).context("Failed to read WM event"))),
+            };
+            if message_type & EVENT_BIT == 0 {
+                continue;
+            }
+            if let Some(kind) = EventKind::from_code(message_type & !EVENT_BIT) {
+                return Some(WmEvent::parse(kind, &payload).context("Failed to parse WM event"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Play the WM side of a subscription: acknowledge it, then send `events`
+    fn fake_wm(events: Vec<(u32, &'static str)>) -> UnixStream {
+        let (client, mut wm) = UnixStream::pair().unwrap();
+        std::thread::spawn(move || {
+            let (message_type, payload) = read_message(&mut wm).unwrap();
+            assert_eq!(message_type, SUBSCRIBE);
+            assert_eq!(payload, br#"["window","shutdown"]"#);
+            write_message(&mut wm, SUBSCRIBE, br#"{"success":true}"#).unwrap();
+            for (code, payload) in events {
+                write_message(&mut wm, EVENT_BIT | code, payload.as_bytes()).unwrap();
+            }
+        });
+        client
+    }
+
+    #[test]
+    fn test_events_until_disconnect() {
+        let client = fake_wm(vec![
+            (3, r#"{"change": "new", "container": {"id": 7, "type": "con", "app_id": "foot", "marks": []}}"#),
+            (1, r#"{"change": "unspecified"}"#), // output event: not subscribed, skipped
+            (3, r#"{"change": "mark", "container": {"id": 7, "marks": ["_i3mux:local:ws1-001"]}}"#),
+            (6, r#"{"change": "restart"}"#),
+        ]);
+        let events: Vec<WmEvent> = EventStream::subscribe_on(client, &[EventKind::Window, EventKind::Shutdown])
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(events.len(), 3);
+        match &events[0] {
+            WmEvent::Window { change, container } => {
+                assert_eq!(change, "new");
+                assert_eq!(container.instance(), Some("foot"));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        match &events[1] {
+            WmEvent::Window { container, .. } => assert_eq!(container.marks, ["_i3mux:local:ws1-001"]),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(&events[2], WmEvent::Shutdown));
+    }
+
+    #[test]
+    fn test_workspace_and_binding_events() {
+        let workspace = br#"{"change": "focus", "current": {"id": 4, "type": "workspace", "num": 2}, "old": null}"#;
+        assert!(matches!(WmEvent::parse(EventKind::Workspace, workspace).unwrap(), WmEvent::Workspace));
+
+        let binding = br#"{"change": "run", "binding": {"command": "exec i3mux terminal", "symbol": "Return"}}"#;
+        assert!(matches!(WmEvent::parse(EventKind::Binding, binding).unwrap(), WmEvent::Binding));
+    }
+}
@@ -0,0 +1,147 @@
+//! Terminals attached to Kubernetes pods via `kubectl exec`
+//!
+//! Pods come and go, so a terminal records the label selector that finds its
+//! pod rather than the pod itself. The pod name is resolved when the terminal
+//! is launched and again on every attach; the last one is kept in the layout
+//! for reference.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+use crate::target::EXEC_SHELL;
+
+/// The pod a terminal attaches to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PodTarget {
+    /// Label selector (e.g. "app=api,tier=backend")
+    pub selector: String,
+    /// Namespace (None: kubectl's current namespace)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Container within the pod (None: the pod's default container)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Pod the selector resolved to most recently
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod: Option<String>,
+}
+
+impl PodTarget {
+    /// Creates a new PodTarget after validation.
+    ///
+    /// # Errors
+    /// Returns error if any part contains characters not allowed by Kubernetes.
+    pub fn new(selector: impl Into<String>, namespace: Option<String>, container: Option<String>) -> Result<Self> {
+        let selector = selector.into();
+
+        if selector.is_empty() {
+            anyhow::bail!("Pod selector cannot be empty");
+        }
+        if !selector.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=,!".contains(c)) {
+            anyhow::bail!("Invalid pod selector '{}': expected labels like 'app=api,tier=backend'", selector);
+        }
+        for (what, name) in [("namespace", &namespace), ("container", &container)] {
+            if let Some(name) = name {
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+                    anyhow::bail!("Invalid {} '{}': only lowercase letters, digits and hyphens are allowed", what, name);
+                }
+            }
+        }
+
+        Ok(Self { selector, namespace, container, pod: None })
+    }
+
+    /// Find the running pod matching the selector and remember it
+    pub fn resolve(&mut self) -> Result<String> {
+        let mut command = Command::new("kubectl");
+        command.arg("get").arg("pods");
+        if let Some(namespace) = &self.namespace {
+            command.arg("-n").arg(namespace);
+        }
+        let output = command
+            .arg("-l")
+            .arg(&self.selector)
+            .arg("--field-selector=status.phase=Running")
+            .arg("-o")
+            .arg("jsonpath={.items[*].metadata.name}")
+            .output()
+            .context("Failed to run kubectl")?;
+
+        if !output.status.success() {
+            anyhow::bail!("kubectl get pods failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        let pod = first_pod(&String::from_utf8_lossy(&output.stdout))
+            .with_context(|| format!("No running pod matches {}", self))?;
+        if self.pod.as_deref().is_some_and(|previous| previous != pod) {
+            println!("  {} now resolves to pod {}", self, pod);
+        }
+        self.pod = Some(pod.clone());
+        Ok(pod)
+    }
+
+    /// Command that attaches a terminal to `pod`
+    pub fn attach_command(&self, pod: &str) -> String {
+        let mut command = String::from("kubectl exec -it");
+        if let Some(namespace) = &self.namespace {
+            command.push_str(&format!(" -n {}", namespace));
+        }
+        command.push_str(&format!(" {}", pod));
+        if let Some(container) = &self.container {
+            command.push_str(&format!(" -c {}", container));
+        }
+        format!("{} -- {}", command, EXEC_SHELL)
+    }
+}
+
+impl std::fmt::Display for PodTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.namespace {
+            Some(namespace) => write!(f, "{}/{}", namespace, self.selector),
+            None => write!(f, "{}", self.selector),
+        }
+    }
+}
+
+/// First pod name in `kubectl get pods -o jsonpath` output, if it is a valid name
+fn first_pod(output: &str) -> Option<String> {
+    output
+        .split_whitespace()
+        .next()
+        .filter(|pod| pod.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.'))
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        assert!(PodTarget::new("app.kubernetes.io/name=api,tier!=db", Some("prod".into()), None).is_ok());
+        assert!(PodTarget::new("", None, None).is_err());
+        assert!(PodTarget::new("app=api; rm -rf ~", None, None).is_err());
+        assert!(PodTarget::new("app=api", Some("Prod".into()), None).is_err());
+        assert!(PodTarget::new("app=api", None, Some("".into())).is_err());
+    }
+
+    #[test]
+    fn test_attach_command() {
+        let target = PodTarget::new("app=api", Some("prod".into()), Some("main".into())).unwrap();
+        assert_eq!(
+            target.attach_command("api-7d9f-x2k"),
+            format!("kubectl exec -it -n prod api-7d9f-x2k -c main -- {}", EXEC_SHELL)
+        );
+
+        let target = PodTarget::new("app=api", None, None).unwrap();
+        assert_eq!(target.attach_command("api-1"), format!("kubectl exec -it api-1 -- {}", EXEC_SHELL));
+    }
+
+    #[test]
+    fn test_first_pod() {
+        assert_eq!(first_pod("api-1 api-2"), Some("api-1".to_string()));
+        assert_eq!(first_pod("\n"), None);
+        assert_eq!(first_pod("$(evil)"), None);
+    }
+}
@@ -5,9 +5,11 @@
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
-use crate::window::I3muxWindow;
-use crate::wm::WmBackend;
+use crate::target::AttachTarget;
+use crate::window::{self, I3muxWindow};
+use crate::wm::{WmBackend, WmNode, WmType};
 
 /// Simplified i3 layout representation for serialization
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -43,6 +45,23 @@ pub enum Layout {
         socket: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         percent: Option<f64>,
+        /// Foreground command running at detach (None if the shell was idle)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
+        /// Host the terminal runs on (None: the session's host)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        host: Option<String>,
+        /// Command that attaches the terminal in place of abduco (e.g. a serial
+        /// console or `kubectl exec`); such terminals always run locally
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        attach: Option<String>,
+        /// Pod or container the terminal attaches to, re-resolved on every attach
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<AttachTarget>,
+        /// Window title at capture, shown on the restored window until the
+        /// terminal sets its own
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
     },
 }
 
@@ -56,14 +75,20 @@ impl Layout {
             .context("Failed to get window manager tree")?;
 
         // Find the workspace node
-        let ws_node = find_workspace_node(&tree, workspace_num);
+        let ws_node = window::find_workspace_node(&tree, workspace_num);
 
         match ws_node {
-            Some(node) => capture_node_from_json(node),
+            Some(node) => Self::capture_from_node(node, backend.wm_type()),
             None => Ok(None),
         }
     }
 
+    /// Capture the i3mux terminals under a node of the WM tree (usually a workspace)
+    /// of the window manager `wm`
+    pub fn capture_from_node(node: &WmNode, wm: WmType) -> Result<Option<Self>> {
+        capture_node(node, wm)
+    }
+
     /// Get list of all socket IDs in this layout
     pub fn get_sockets(&self) -> Vec<String> {
         match self {
@@ -77,6 +102,88 @@ impl Layout {
         }
     }
 
+    /// Check what restoring relies on, for layouts edited by hand (`i3mux edit`)
+    ///
+    /// Sockets must be unique and safe to put in shell commands, containers
+    /// must hold something, and a split's sizes must be shares between 0 and
+    /// 1 that, when every child has one, add up to about 1.
+    pub fn check(&self) -> Result<()> {
+        match self.problems().into_iter().next() {
+            Some(problem) => anyhow::bail!(problem),
+            None => Ok(()),
+        }
+    }
+
+    /// Everything [`Layout::check`] objects to, each naming where it is
+    /// (`layout.children[1]`), for `i3mux validate`
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut seen: HashMap<String, String> = HashMap::new();
+        self.collect_problems("layout", &mut seen, &mut problems);
+        problems
+    }
+
+    fn collect_problems(&self, path: &str, seen: &mut HashMap<String, String>, problems: &mut Vec<String>) {
+        let (kind, children) = match self {
+            Layout::Terminal { socket, .. } => {
+                if socket.is_empty() || !socket.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+                    problems.push(format!("Invalid socket '{}' at {}: use letters, digits, '-', '_' and '.'", socket, path));
+                } else if let Some(first) = seen.get(socket) {
+                    problems.push(format!("Socket {} is in the layout more than once ({} and {})", socket, first, path));
+                } else {
+                    seen.insert(socket.clone(), path.to_string());
+                }
+                return;
+            }
+            Layout::HSplit { children, .. } => ("hsplit", children),
+            Layout::VSplit { children, .. } => ("vsplit", children),
+            Layout::Tabbed { children } => ("tabbed", children),
+            Layout::Stacked { children } => ("stacked", children),
+        };
+        if children.is_empty() {
+            problems.push(format!("Empty {} container at {}: give it terminals or remove it", kind, path));
+            return;
+        }
+
+        let child_path = |i: usize| format!("{}.children[{}]", path, i);
+        for (i, percent) in children.iter().enumerate().filter_map(|(i, c)| Some((i, c.percent()?))) {
+            if !(percent > 0.0 && percent <= 1.0) {
+                problems.push(format!(
+                    "Invalid percent {} at {}: sizes are shares of the split, above 0 and at most 1",
+                    percent,
+                    child_path(i)
+                ));
+            }
+        }
+        let percents: Vec<f64> = children.iter().filter_map(Layout::percent).collect();
+        let sum: f64 = percents.iter().sum();
+        if matches!(self, Layout::HSplit { .. } | Layout::VSplit { .. })
+            && percents.len() == children.len()
+            && (sum - 1.0).abs() > 0.05
+        {
+            problems.push(format!("The sizes in the {} at {} add up to {:.2} instead of 1", kind, path, sum));
+        }
+        for (i, child) in children.iter().enumerate() {
+            child.collect_problems(&child_path(i), seen, problems);
+        }
+    }
+
+    /// Record the share of its parent split (containers that can't be sized ignore it)
+    fn set_percent(&mut self, share: Option<f64>) {
+        match self {
+            Layout::Terminal { percent, .. } | Layout::HSplit { percent, .. } | Layout::VSplit { percent, .. } => *percent = share,
+            Layout::Tabbed { .. } | Layout::Stacked { .. } => {}
+        }
+    }
+
+    /// Share of its parent split, if recorded
+    fn percent(&self) -> Option<f64> {
+        match self {
+            Layout::Terminal { percent, .. } | Layout::HSplit { percent, .. } | Layout::VSplit { percent, .. } => *percent,
+            Layout::Tabbed { .. } | Layout::Stacked { .. } => None,
+        }
+    }
+
     /// Get the first (leftmost/topmost) terminal socket in this layout
     pub fn get_first_socket(&self) -> Option<String> {
         match self {
@@ -90,6 +197,415 @@ impl Layout {
         }
     }
 
+    /// Host of each terminal keyed by socket, using `default_host` where none is recorded
+    pub fn get_terminal_hosts(&self, default_host: &str) -> HashMap<String, String> {
+        match self {
+            Layout::Terminal { socket, host, .. } => HashMap::from([(
+                socket.clone(),
+                host.clone().unwrap_or_else(|| default_host.to_string()),
+            )]),
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => {
+                children.iter().flat_map(|c| c.get_terminal_hosts(default_host)).collect()
+            }
+        }
+    }
+
+    /// Sockets grouped by the host they run on (in layout order within each host)
+    pub fn get_sockets_by_host(&self, default_host: &str) -> BTreeMap<String, Vec<String>> {
+        let hosts = self.get_terminal_hosts(default_host);
+        let mut grouped: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for socket in self.get_sockets() {
+            grouped.entry(hosts[&socket].clone()).or_default().push(socket);
+        }
+        grouped
+    }
+
+    /// Record the foreground command of each terminal, keyed by socket
+    pub fn set_commands(&mut self, commands: &HashMap<String, String>) {
+        match self {
+            Layout::Terminal { socket, command, .. } => {
+                *command = commands.get(socket.as_str()).cloned();
+            }
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => {
+                for child in children {
+                    child.set_commands(commands);
+                }
+            }
+        }
+    }
+
+    /// Recorded foreground commands, keyed by socket
+    pub fn get_commands(&self) -> HashMap<String, String> {
+        match self {
+            Layout::Terminal { socket, command: Some(command), .. } => {
+                HashMap::from([(socket.clone(), command.clone())])
+            }
+            Layout::Terminal { .. } => HashMap::new(),
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => {
+                children.iter().flat_map(|c| c.get_commands()).collect()
+            }
+        }
+    }
+
+    /// Captured window titles, keyed by socket
+    pub fn get_titles(&self) -> HashMap<String, String> {
+        match self {
+            Layout::Terminal { socket, title: Some(title), .. } => HashMap::from([(socket.clone(), title.clone())]),
+            Layout::Terminal { .. } => HashMap::new(),
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => children.iter().flat_map(|c| c.get_titles()).collect(),
+        }
+    }
+
+    /// Record the custom attach command of each terminal, keyed by socket
+    pub fn set_attach_commands(&mut self, attach_commands: &HashMap<String, String>) {
+        match self {
+            Layout::Terminal { socket, attach, .. } => {
+                *attach = attach_commands.get(socket.as_str()).cloned();
+            }
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => {
+                for child in children {
+                    child.set_attach_commands(attach_commands);
+                }
+            }
+        }
+    }
+
+    /// Custom attach commands, keyed by socket
+    pub fn get_attach_commands(&self) -> HashMap<String, String> {
+        match self {
+            Layout::Terminal { socket, attach: Some(attach), .. } => {
+                HashMap::from([(socket.clone(), attach.clone())])
+            }
+            Layout::Terminal { .. } => HashMap::new(),
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => {
+                children.iter().flat_map(|c| c.get_attach_commands()).collect()
+            }
+        }
+    }
+
+    /// Record the pod or container each terminal attaches to, keyed by socket
+    pub fn set_targets(&mut self, targets: &HashMap<String, AttachTarget>) {
+        match self {
+            Layout::Terminal { socket, target, .. } => {
+                *target = targets.get(socket.as_str()).cloned();
+            }
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => {
+                for child in children {
+                    child.set_targets(targets);
+                }
+            }
+        }
+    }
+
+    /// Pods and containers terminals attach to, keyed by socket
+    pub fn get_targets(&self) -> HashMap<String, AttachTarget> {
+        match self {
+            Layout::Terminal { socket, target: Some(target), .. } => {
+                HashMap::from([(socket.clone(), target.clone())])
+            }
+            Layout::Terminal { .. } => HashMap::new(),
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => {
+                children.iter().flat_map(|c| c.get_targets()).collect()
+            }
+        }
+    }
+
+    /// Return this layout with the terminal for `socket` removed
+    ///
+    /// Containers left without children are dropped as well. Returns None if
+    /// nothing remains.
+    pub fn without_socket(&self, socket: &str) -> Option<Layout> {
+        let prune = |children: &[Layout]| -> Vec<Layout> {
+            children.iter().filter_map(|c| c.without_socket(socket)).collect()
+        };
+
+        let layout = match self {
+            Layout::Terminal { socket: s, .. } if s == socket => return None,
+            Layout::Terminal { .. } => return Some(self.clone()),
+            Layout::HSplit { children, percent } => Layout::HSplit { children: prune(children), percent: *percent },
+            Layout::VSplit { children, percent } => Layout::VSplit { children: prune(children), percent: *percent },
+            Layout::Tabbed { children } => Layout::Tabbed { children: prune(children) },
+            Layout::Stacked { children } => Layout::Stacked { children: prune(children) },
+        };
+
+        if layout.get_sockets().is_empty() {
+            None
+        } else {
+            Some(layout)
+        }
+    }
+
+    /// Exchange the terminals for sockets `a` and `b`
+    ///
+    /// Sizes belong to the positions, as when the WM swaps two windows. Does
+    /// nothing unless both terminals are in the layout.
+    pub fn swap_terminals(&mut self, a: &str, b: &str) {
+        let (Some(first), Some(second)) = (self.find_terminal(a).cloned(), self.find_terminal(b).cloned()) else {
+            return;
+        };
+        self.replace_terminals(&|socket| match socket {
+            s if s == a => Some(&second),
+            s if s == b => Some(&first),
+            _ => None,
+        });
+    }
+
+    /// Swaps of two terminals each that turn this layout into `target`
+    ///
+    /// None unless both have the same terminals in the same container
+    /// structure; sizes are not compared.
+    pub fn swaps_to(&self, target: &Layout) -> Option<Vec<(String, String)>> {
+        if self.skeleton() != target.skeleton() {
+            return None;
+        }
+        let mut current = self.get_sockets();
+        let wanted = target.get_sockets();
+        let mut sorted = (current.clone(), wanted.clone());
+        sorted.0.sort();
+        sorted.1.sort();
+        if sorted.0 != sorted.1 {
+            return None;
+        }
+
+        let mut swaps = Vec::new();
+        for i in 0..current.len() {
+            if current[i] != wanted[i] {
+                let j = current.iter().position(|s| *s == wanted[i])?;
+                swaps.push((current[i].clone(), current[j].clone()));
+                current.swap(i, j);
+            }
+        }
+        Some(swaps)
+    }
+
+    /// The container structure, with terminals as `t` (e.g. `h[t v[t t]]`)
+    fn skeleton(&self) -> String {
+        let (kind, children) = match self {
+            Layout::Terminal { .. } => return "t".to_string(),
+            Layout::HSplit { children, .. } => ("h", children),
+            Layout::VSplit { children, .. } => ("v", children),
+            Layout::Tabbed { children } => ("tabbed", children),
+            Layout::Stacked { children } => ("stacked", children),
+        };
+        format!("{}[{}]", kind, children.iter().map(Layout::skeleton).collect::<Vec<_>>().join(" "))
+    }
+
+    fn find_terminal(&self, socket: &str) -> Option<&Layout> {
+        match self {
+            Layout::Terminal { socket: s, .. } => (s == socket).then_some(self),
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => children.iter().find_map(|c| c.find_terminal(socket)),
+        }
+    }
+
+    /// Put `replacement(socket)` in place of terminals it returns one for, keeping their size
+    fn replace_terminals<'a>(&mut self, replacement: &dyn Fn(&str) -> Option<&'a Layout>) {
+        match self {
+            Layout::Terminal { socket, percent, .. } if replacement(socket).is_some() => {
+                let size = *percent;
+                *self = replacement(socket).unwrap().clone();
+                if let Layout::Terminal { percent, .. } = self {
+                    *percent = size;
+                }
+            }
+            Layout::Terminal { .. } => {}
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => {
+                for child in children {
+                    child.replace_terminals(replacement);
+                }
+            }
+        }
+    }
+
+    /// Return this layout with the children of every split at equal sizes
+    ///
+    /// Tabbed and stacked containers have no size of their own, so splits
+    /// holding one keep theirs (see `compat::resize_plan`).
+    pub fn evened(&self) -> Layout {
+        let even = |children: &[Layout]| -> Vec<Layout> {
+            let share = 1.0 / children.len() as f64;
+            children
+                .iter()
+                .map(|child| match child.evened() {
+                    Layout::Terminal { socket, command, host, attach, target, title, .. } => {
+                        Layout::Terminal { socket, percent: Some(share), command, host, attach, target, title }
+                    }
+                    Layout::HSplit { children, .. } => Layout::HSplit { children, percent: Some(share) },
+                    Layout::VSplit { children, .. } => Layout::VSplit { children, percent: Some(share) },
+                    other => other,
+                })
+                .collect()
+        };
+
+        match self {
+            Layout::Terminal { .. } => self.clone(),
+            Layout::HSplit { children, percent } => Layout::HSplit { children: even(children), percent: *percent },
+            Layout::VSplit { children, percent } => Layout::VSplit { children: even(children), percent: *percent },
+            Layout::Tabbed { children } => Layout::Tabbed { children: children.iter().map(Layout::evened).collect() },
+            Layout::Stacked { children } => Layout::Stacked { children: children.iter().map(Layout::evened).collect() },
+        }
+    }
+
+    /// This layout plus the terminals of `other` it lacks, placed beside it
+    ///
+    /// Used to merge two versions of a session: the terminals only `other`
+    /// has end up in a tabbed container to the right, so neither version's
+    /// arrangement is squeezed. Sizes of the added terminals are dropped.
+    pub fn merged_with(&self, other: &Layout) -> Layout {
+        fn terminals(layout: &Layout, found: &mut Vec<Layout>) {
+            match layout {
+                Layout::Terminal { .. } => found.push(layout.clone()),
+                Layout::HSplit { children, .. }
+                | Layout::VSplit { children, .. }
+                | Layout::Tabbed { children }
+                | Layout::Stacked { children } => children.iter().for_each(|c| terminals(c, found)),
+            }
+        }
+
+        let ours = self.get_sockets();
+        let mut extra = Vec::new();
+        terminals(other, &mut extra);
+        extra.retain(|t| matches!(t, Layout::Terminal { socket, .. } if !ours.contains(socket)));
+        if extra.is_empty() {
+            return self.clone();
+        }
+        for terminal in &mut extra {
+            if let Layout::Terminal { percent, .. } = terminal {
+                *percent = None;
+            }
+        }
+        let added = if extra.len() == 1 { extra.remove(0) } else { Layout::Tabbed { children: extra } };
+        Layout::HSplit { children: vec![self.clone(), added], percent: None }
+    }
+
+    /// Steps that recreate this layout in an empty workspace
+    ///
+    /// Terminals are launched depth-first. Once a container's first child is
+    /// in place, focus moves up to that child and the container is created
+    /// around it, so the remaining children open next to it. Commands are
+    /// meant to run with focus on the most recently launched terminal (see
+    /// [`relative_command`]).
+    pub fn restore_plan(&self) -> Vec<RestoreStep<'_>> {
+        let mut steps = Vec::new();
+        self.plan_into(&mut steps, true);
+        steps
+    }
+
+    /// Steps that open this layout next to the focused window of a workspace
+    /// that already has others (`grid --layout`)
+    pub fn open_plan(&self) -> Vec<RestoreStep<'_>> {
+        let mut steps = Vec::new();
+        self.plan_into(&mut steps, false);
+        steps
+    }
+
+    /// `at_workspace`: this layout starts out alone on the workspace
+    fn plan_into<'a>(&'a self, steps: &mut Vec<RestoreStep<'a>>, at_workspace: bool) {
+        let children = match self {
+            Layout::Terminal { socket, host, attach, .. } => {
+                steps.push(RestoreStep::Launch { socket, host: host.as_deref(), attach: attach.as_deref() });
+                return;
+            }
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => children,
+        };
+        let Some((first, rest)) = children.split_first() else {
+            return;
+        };
+        first.plan_into(steps, at_workspace);
+        if rest.is_empty() {
+            return;
+        }
+
+        let mut commands = vec!["focus parent"; first.depth()];
+        match self {
+            // Set split mode ONCE: the remaining children join the same
+            // split container as equal siblings
+            Layout::HSplit { .. } => commands.push("split h"),
+            Layout::VSplit { .. } => commands.push("split v"),
+            Layout::Tabbed { .. } | Layout::Stacked { .. } => {
+                // "layout" changes the focused container's parent, so give the
+                // first child a split container of its own first. A split
+                // alone on the workspace *is* the workspace; splitting that
+                // moves its windows into a new container, which needs focus.
+                commands.push("split h");
+                if at_workspace && first.is_split() {
+                    commands.push("focus child");
+                }
+                commands.push(if matches!(self, Layout::Tabbed { .. }) { "layout tabbed" } else { "layout stacking" });
+            }
+            Layout::Terminal { .. } => unreachable!(),
+        }
+        steps.push(RestoreStep::Commands(commands));
+
+        for (i, child) in rest.iter().enumerate() {
+            child.plan_into(steps, false);
+            // Open the next child next to this one rather than inside it
+            if i + 1 < rest.len() && child.depth() > 0 {
+                steps.push(RestoreStep::Commands(vec!["focus parent"; child.depth()]));
+            }
+        }
+    }
+
+    /// Levels of containers from the last terminal up to this layout, as
+    /// restoring creates them (single-child containers never exist in the WM)
+    fn depth(&self) -> usize {
+        match self {
+            Layout::Terminal { .. } => 0,
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => match children.as_slice() {
+                [] => 0,
+                [only] => only.depth(),
+                [.., last] => 1 + last.depth(),
+            },
+        }
+    }
+
+    /// Whether this is (or, through single-child containers, comes down to) a split
+    fn is_split(&self) -> bool {
+        match self {
+            Layout::HSplit { children, .. } | Layout::VSplit { children, .. } if children.len() > 1 => true,
+            Layout::HSplit { children, .. }
+            | Layout::VSplit { children, .. }
+            | Layout::Tabbed { children }
+            | Layout::Stacked { children } => matches!(children.as_slice(), [only] if only.is_split()),
+            Layout::Terminal { .. } => false,
+        }
+    }
+
     /// Generate i3 commands to recreate this layout
     pub fn generate_i3_commands(&self, depth: usize) -> Vec<String> {
         let mut commands = Vec::new();
@@ -136,43 +652,54 @@ impl Layout {
     }
 }
 
-// ============ Internal JSON-based capture (uses marks) ============
+/// One step of restoring a layout, see [`Layout::restore_plan`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreStep<'a> {
+    /// Launch the terminal for `socket` and wait for its window
+    Launch { socket: &'a str, host: Option<&'a str>, attach: Option<&'a str> },
+    /// WM commands to run relative to the most recently launched terminal
+    Commands(Vec<&'static str>),
+}
 
-fn capture_node_from_json(node: &serde_json::Value) -> Result<Option<Layout>> {
-    // Check if this node is an i3mux terminal by looking at marks
-    if let Some(marks) = node.get("marks").and_then(|m| m.as_array()) {
-        for mark in marks {
-            if let Some(mark_str) = mark.as_str() {
-                if let Some(identity) = I3muxWindow::from_mark(mark_str) {
-                    // This is an i3mux terminal
-                    let percent = node.get("percent").and_then(|p| p.as_f64());
-                    return Ok(Some(Layout::Terminal {
-                        socket: identity.socket,
-                        percent,
-                    }));
-                }
-            }
-        }
+/// WM command chain running `commands` with focus on `container_id`
+///
+/// Focus is re-established in the same chain, so the commands are unaffected
+/// by the user switching workspaces in between.
+pub fn relative_command(container_id: Option<u64>, commands: &[&str]) -> String {
+    let mut chain = Vec::new();
+    if let Some(container_id) = container_id {
+        chain.push(format!("[con_id=\"{}\"] focus", container_id));
     }
+    chain.extend(commands.iter().map(|c| c.to_string()));
+    chain.join("; ")
+}
 
-    // Not a terminal, check if it's a container with i3mux children
-    let mut children = Vec::new();
+// ============ Internal tree-based capture (uses marks) ============
 
-    // Check regular nodes
-    if let Some(nodes) = node.get("nodes").and_then(|n| n.as_array()) {
-        for child in nodes {
-            if let Some(layout) = capture_node_from_json(child)? {
-                children.push(layout);
-            }
-        }
+fn capture_node(node: &WmNode, wm: WmType) -> Result<Option<Layout>> {
+    // Check if this node is an i3mux terminal by looking at marks
+    if let Some(identity) = node.marks.iter().find_map(|m| I3muxWindow::from_mark(m)) {
+        // i3mux's own "{marker}{host}:{socket}" title says nothing the mark doesn't
+        let own_title = format!("{}:{}", identity.host, identity.socket);
+        let title = node.name.clone().filter(|t| !t.trim().is_empty() && !t.ends_with(&own_title));
+        return Ok(Some(Layout::Terminal {
+            socket: identity.socket,
+            percent: None,
+            command: None,
+            host: Some(identity.host),
+            attach: None,
+            target: None,
+            title,
+        }));
     }
 
-    // Check floating nodes
-    if let Some(nodes) = node.get("floating_nodes").and_then(|n| n.as_array()) {
-        for child in nodes {
-            if let Some(layout) = capture_node_from_json(child)? {
-                children.push(layout);
-            }
+    // Not a terminal, check if it's a container with i3mux children
+    let mut captured = Vec::new();
+    let mut children = Vec::new();
+    for child in node.children() {
+        if let Some(layout) = capture_node(child, wm)? {
+            captured.push(child);
+            children.push(layout);
         }
     }
 
@@ -180,48 +707,84 @@ fn capture_node_from_json(node: &serde_json::Value) -> Result<Option<Layout>> {
         return Ok(None);
     }
 
-    // Determine container type from layout
-    let layout_type = node.get("layout").and_then(|l| l.as_str()).unwrap_or("splith");
-    let percent = node.get("percent").and_then(|p| p.as_f64());
+    // A split's children get their shares of it; the container's own share
+    // is up to its parent
+    let horizontal = match node.layout.as_str() {
+        "splith" => Some(true),
+        "tabbed" | "stacked" => None,
+        _ => Some(false),
+    };
+    if let Some(horizontal) = horizontal {
+        let gap = inner_gap(node, horizontal);
+        for (child, share) in children.iter_mut().zip(split_shares(&captured, horizontal, gap, wm)) {
+            child.set_percent(share);
+        }
+    }
 
-    let layout = match layout_type {
-        "splith" => Layout::HSplit { children, percent },
-        "splitv" => Layout::VSplit { children, percent },
+    // Determine container type from layout
+    let layout = match node.layout.as_str() {
+        "splith" => Layout::HSplit { children, percent: None },
+        "splitv" => Layout::VSplit { children, percent: None },
         "tabbed" => Layout::Tabbed { children },
         "stacked" => Layout::Stacked { children },
-        _ => Layout::VSplit { children, percent }, // Default
+        _ => Layout::VSplit { children, percent: None }, // Default
     };
 
     Ok(Some(layout))
 }
 
-fn find_workspace_node(node: &serde_json::Value, workspace_num: i32) -> Option<&serde_json::Value> {
-    // Check if this is the workspace we're looking for
-    if let Some(node_type) = node.get("type").and_then(|t| t.as_str()) {
-        if node_type == "workspace" {
-            if let Some(num) = node.get("num").and_then(|n| n.as_i64()) {
-                if num == workspace_num as i64 {
-                    return Some(node);
-                }
-            }
-        }
-    }
-
-    // Recurse into children
-    if let Some(nodes) = node.get("nodes").and_then(|n| n.as_array()) {
-        for child in nodes {
-            if let Some(found) = find_workspace_node(child, workspace_num) {
-                return Some(found);
-            }
-        }
+/// Share of their split each captured child takes
+///
+/// i3 reports `percent` relative to the parent. Sway's is unreliable (null
+/// at times, and 1.0 for every child of some containers), so there, and on i3
+/// when it's missing, the shares come from the rects along the split axis.
+/// Gaps skew those: Sway takes the `gap` between children off the split
+/// before sharing it out, which keeps the rects proportional, but i3 shares
+/// first and then takes the gap off every child, so it's added back there.
+/// Shares are among the captured children: windows that aren't i3mux
+/// terminals don't come back on restore.
+fn split_shares(nodes: &[&WmNode], horizontal: bool, gap: u32, wm: WmType) -> Vec<Option<f64>> {
+    let reported: Option<Vec<f64>> = match wm {
+        WmType::I3 => nodes.iter().map(|n| n.percent.filter(|p| *p > 0.0 && *p <= 1.0)).collect(),
+        WmType::Sway => None,
+    };
+    let gap = if wm == WmType::I3 { gap } else { 0 };
+    let sizes = reported.unwrap_or_else(|| {
+        nodes.iter().map(|n| f64::from(if horizontal { n.rect.width } else { n.rect.height } + gap)).collect()
+    });
+    let total: f64 = sizes.iter().sum();
+    if total <= 0.0 {
+        return vec![None; nodes.len()];
     }
+    // Four decimals are plenty for a resize in ppt, and keep session files readable
+    sizes.iter().map(|size| Some((size / total * 10_000.0).round() / 10_000.0)).collect()
+}
 
-    None
+/// Pixels between neighbouring children of a split, as laid out (0 without gaps)
+fn inner_gap(node: &WmNode, horizontal: bool) -> u32 {
+    let span = |n: &WmNode| match horizontal {
+        true => (i64::from(n.rect.x), i64::from(n.rect.width)),
+        false => (i64::from(n.rect.y), i64::from(n.rect.height)),
+    };
+    let children: Vec<&WmNode> = node.children().collect();
+    children
+        .windows(2)
+        .map(|pair| {
+            let ((start, size), (next, _)) = (span(pair[0]), span(pair[1]));
+            next - (start + size)
+        })
+        .find(|gap| *gap >= 0)
+        .map_or(0, |gap| gap as u32)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::wmsim::SimWm;
+
+    fn term(socket: &str) -> Layout {
+        Layout::Terminal { socket: socket.to_string(), percent: None, command: None, host: None, attach: None, target: None, title: None }
+    }
 
     #[test]
     fn test_get_sockets() {
@@ -230,16 +793,31 @@ mod tests {
                 Layout::Terminal {
                     socket: "ws4-001".to_string(),
                     percent: Some(0.5),
+                    command: None,
+                    host: None,
+                    attach: None,
+                    target: None,
+                    title: None,
                 },
                 Layout::VSplit {
                     children: vec![
                         Layout::Terminal {
                             socket: "ws4-002".to_string(),
                             percent: Some(0.5),
+                            command: None,
+                            host: None,
+                            attach: None,
+                            target: None,
+                            title: None,
                         },
                         Layout::Terminal {
                             socket: "ws4-003".to_string(),
                             percent: Some(0.5),
+                            command: None,
+                            host: None,
+                            attach: None,
+                            target: None,
+                            title: None,
                         },
                     ],
                     percent: Some(0.5),
@@ -251,4 +829,390 @@ mod tests {
         let sockets = layout.get_sockets();
         assert_eq!(sockets, vec!["ws4-001", "ws4-002", "ws4-003"]);
     }
+
+    #[test]
+    fn test_check() {
+        let sized = |socket: &str, percent: f64| Layout::Terminal {
+            socket: socket.into(),
+            percent: Some(percent),
+            command: None,
+            host: None,
+            attach: None,
+            target: None,
+            title: None,
+        };
+        assert!(hsplit(vec![term("ws1-001"), term("ws1-002")]).check().is_ok());
+        assert!(hsplit(vec![sized("ws1-001", 0.3), sized("ws1-002", 0.7)]).check().is_ok());
+
+        let error = |layout: Layout| layout.check().unwrap_err().to_string();
+        assert_eq!(
+            error(hsplit(vec![term("ws1-001"), term("ws1-001")])),
+            "Socket ws1-001 is in the layout more than once (layout.children[0] and layout.children[1])"
+        );
+        assert!(error(term("ws1-001; reboot")).starts_with("Invalid socket 'ws1-001; reboot' at layout:"));
+        assert!(error(vsplit(vec![term("ws1-001"), Layout::Tabbed { children: vec![] }]))
+            .starts_with("Empty tabbed container at layout.children[1]"));
+        assert!(error(hsplit(vec![sized("ws1-001", 1.5), term("ws1-002")])).starts_with("Invalid percent 1.5 at layout.children[0]"));
+        assert_eq!(
+            error(hsplit(vec![sized("ws1-001", 0.5), sized("ws1-002", 0.8)])),
+            "The sizes in the hsplit at layout add up to 1.30 instead of 1"
+        );
+
+        // Every problem is reported, not just the first
+        let layout = vsplit(vec![term("bad socket"), hsplit(vec![sized("ws1-002", 0.5), sized("ws1-003", 0.8)])]);
+        assert_eq!(layout.problems().len(), 2);
+    }
+
+    #[test]
+    fn test_without_socket() {
+        let layout = Layout::HSplit {
+            children: vec![
+                term("ws4-001"),
+                Layout::Tabbed {
+                    children: vec![term("ws4-002")],
+                },
+            ],
+            percent: None,
+        };
+
+        // Removing the only tab drops its container too
+        let pruned = layout.without_socket("ws4-002").unwrap();
+        assert_eq!(pruned.get_sockets(), vec!["ws4-001"]);
+        assert!(matches!(&pruned, Layout::HSplit { children, .. } if children.len() == 1));
+
+        // Unknown sockets leave the layout untouched
+        assert_eq!(layout.without_socket("ws4-999").unwrap().get_sockets(), vec!["ws4-001", "ws4-002"]);
+
+        // Removing the last terminal leaves nothing
+        assert!(pruned.without_socket("ws4-001").is_none());
+    }
+
+    #[test]
+    fn test_sockets_by_host() {
+        let layout = Layout::HSplit {
+            children: vec![
+                term("ws1-001"),
+                Layout::Terminal {
+                    socket: "ws1-002".to_string(),
+                    percent: None,
+                    command: None,
+                    host: Some("user@hostb".to_string()),
+                    attach: None,
+                    target: None,
+                    title: None,
+                },
+                term("ws1-003"),
+            ],
+            percent: None,
+        };
+
+        let grouped = layout.get_sockets_by_host("user@hosta");
+        assert_eq!(grouped["user@hosta"], vec!["ws1-001", "ws1-003"]);
+        assert_eq!(grouped["user@hostb"], vec!["ws1-002"]);
+        assert_eq!(layout.get_terminal_hosts("user@hosta")["ws1-002"], "user@hostb");
+    }
+
+    #[test]
+    fn test_capture_records_local_terminals() {
+        let remote = I3muxWindow::mark_from_parts("user@host", "ws2-001");
+        let local = I3muxWindow::mark_from_parts("local", "ws2-002");
+        let node: WmNode = serde_json::from_value(serde_json::json!({
+            "layout": "splith",
+            "nodes": [
+                { "marks": [remote], "percent": 0.5, "name": "vim notes.md", "nodes": [] },
+                { "marks": [local], "percent": 0.5, "name": "i3mux:local:ws2-002", "nodes": [] },
+            ],
+        }))
+        .unwrap();
+
+        let layout = capture_node(&node, WmType::I3).unwrap().unwrap();
+        let grouped = layout.get_sockets_by_host("user@host");
+        assert_eq!(grouped["user@host"], vec!["ws2-001"]);
+        assert_eq!(grouped["local"], vec!["ws2-002"]);
+        // Titles are kept, except i3mux's own
+        assert_eq!(layout.get_titles(), HashMap::from([("ws2-001".to_string(), "vim notes.md".to_string())]));
+    }
+
+    #[test]
+    fn test_capture_split_ratios() {
+        let mark = |socket: &str| I3muxWindow::mark_from_parts("local", socket);
+        let percents = |layout: &Layout| match layout {
+            Layout::HSplit { children, .. } | Layout::VSplit { children, .. } => {
+                children.iter().map(Layout::percent).collect::<Vec<_>>()
+            }
+            _ => unreachable!(),
+        };
+
+        // Sway: percent missing or 1.0, the rects tell
+        let node: WmNode = serde_json::from_value(serde_json::json!({
+            "layout": "splith",
+            "nodes": [
+                { "marks": [mark("ws1-001")], "percent": null, "rect": { "x": 0, "y": 0, "width": 1280, "height": 1080 } },
+                { "marks": [mark("ws1-002")], "percent": 1.0, "rect": { "x": 1280, "y": 0, "width": 640, "height": 1080 } },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(percents(&capture_node(&node, WmType::Sway).unwrap().unwrap()), [Some(0.6667), Some(0.3333)]);
+
+        // i3: percent as reported, shared among the i3mux terminals only
+        let node: WmNode = serde_json::from_value(serde_json::json!({
+            "layout": "splitv",
+            "nodes": [
+                { "marks": [mark("ws1-001")], "percent": 0.3 },
+                { "marks": [], "percent": 0.4 },
+                { "marks": [mark("ws1-002")], "percent": 0.3 },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(percents(&capture_node(&node, WmType::I3).unwrap().unwrap()), [Some(0.5), Some(0.5)]);
+
+        // i3 without percents, with 10px gaps: 1270 + 10 and 630 + 10 of 1920
+        let node: WmNode = serde_json::from_value(serde_json::json!({
+            "layout": "splith",
+            "nodes": [
+                { "marks": [mark("ws1-001")], "rect": { "x": 5, "y": 0, "width": 1270, "height": 1080 } },
+                { "marks": [mark("ws1-002")], "rect": { "x": 1285, "y": 0, "width": 630, "height": 1080 } },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(percents(&capture_node(&node, WmType::I3).unwrap().unwrap()), [Some(0.6667), Some(0.3333)]);
+
+        // Nothing to go by: left at equal sizes
+        let node: WmNode = serde_json::from_value(serde_json::json!({
+            "layout": "splith",
+            "nodes": [{ "marks": [mark("ws1-001")] }, { "marks": [mark("ws1-002")] }],
+        }))
+        .unwrap();
+        assert_eq!(percents(&capture_node(&node, WmType::Sway).unwrap().unwrap()), [None, None]);
+    }
+
+    #[test]
+    fn test_attach_commands_roundtrip() {
+        let mut layout = Layout::Tabbed {
+            children: vec![
+                term("ws3-001"),
+                term("ws3-002"),
+            ],
+        };
+        let attach = HashMap::from([("ws3-002".to_string(), "picocom /dev/ttyUSB0".to_string())]);
+        layout.set_attach_commands(&attach);
+
+        let json = serde_json::to_string(&layout).unwrap();
+        let restored: Layout = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get_attach_commands(), attach);
+    }
+
+    #[test]
+    fn test_commands_roundtrip() {
+        let mut layout = Layout::VSplit {
+            children: vec![
+                term("ws4-001"),
+                term("ws4-002"),
+            ],
+            percent: None,
+        };
+
+        let commands = HashMap::from([("ws4-002".to_string(), "tail -f /var/log/syslog".to_string())]);
+        layout.set_commands(&commands);
+        assert_eq!(layout.get_commands(), commands);
+
+        // Old session files without the field still load
+        let json = r#"{"type": "terminal", "socket": "ws4-001"}"#;
+        let terminal: Layout = serde_json::from_str(json).unwrap();
+        assert!(terminal.get_commands().is_empty());
+    }
+
+    /// Run the restore plan for `layout` on a simulated workspace and capture the result
+    fn restore_simulated(layout: &Layout) -> Result<Layout> {
+        let mut wm = SimWm::new();
+        run_simulated(&mut wm, layout.restore_plan())?;
+        Ok(Layout::capture_from_node(&wm.workspace(), WmType::I3)?.unwrap())
+    }
+
+    fn run_simulated(wm: &mut SimWm, plan: Vec<RestoreStep>) -> Result<()> {
+        let mut last_container = None;
+        for step in plan {
+            match step {
+                RestoreStep::Launch { socket, .. } => {
+                    last_container = Some(wm.open_window(&I3muxWindow::mark_from_parts("local", socket)));
+                }
+                RestoreStep::Commands(commands) => wm.run(&relative_command(last_container, &commands))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Container types and sockets, without single-child wrappers
+    fn shape(layout: &Layout) -> String {
+        let (name, children) = match layout {
+            Layout::Terminal { socket, .. } => return socket.clone(),
+            Layout::HSplit { children, .. } => ("H", children),
+            Layout::VSplit { children, .. } => ("V", children),
+            Layout::Tabbed { children } => ("T", children),
+            Layout::Stacked { children } => ("S", children),
+        };
+        match children.as_slice() {
+            [only] => shape(only),
+            _ => format!("{}[{}]", name, children.iter().map(shape).collect::<Vec<_>>().join(" ")),
+        }
+    }
+
+    fn hsplit(children: Vec<Layout>) -> Layout {
+        Layout::HSplit { children, percent: None }
+    }
+
+    fn vsplit(children: Vec<Layout>) -> Layout {
+        Layout::VSplit { children, percent: None }
+    }
+
+    #[test]
+    fn test_swap_terminals() {
+        let busy = Layout::Terminal {
+            socket: "ws1-001".to_string(),
+            percent: Some(0.7),
+            command: Some("htop".to_string()),
+            host: None,
+            attach: None,
+            target: None,
+            title: None,
+        };
+        let mut layout = hsplit(vec![busy, vsplit(vec![term("ws1-002"), term("ws1-003")])]);
+        layout.swap_terminals("ws1-001", "ws1-003");
+        assert_eq!(shape(&layout), "H[ws1-003 V[ws1-002 ws1-001]]");
+        // The command moves with its terminal, the size stays with the position
+        let Layout::HSplit { children, .. } = &layout else { unreachable!() };
+        assert!(matches!(&children[0], Layout::Terminal { command: None, percent: Some(p), .. } if *p == 0.7));
+        assert_eq!(layout.get_commands()["ws1-001"], "htop");
+
+        layout.swap_terminals("ws1-001", "ws9-009");
+        assert_eq!(shape(&layout), "H[ws1-003 V[ws1-002 ws1-001]]");
+    }
+
+    #[test]
+    fn test_swaps_to() {
+        let locked = hsplit(vec![term("ws1-001"), vsplit(vec![term("ws1-002"), term("ws1-003")])]);
+        assert_eq!(locked.swaps_to(&locked), Some(Vec::new()));
+
+        // Dragged around within the same structure: swapping puts it back
+        let mut moved = locked.clone();
+        moved.swap_terminals("ws1-001", "ws1-002");
+        moved.swap_terminals("ws1-001", "ws1-003");
+        let swaps = moved.swaps_to(&locked).unwrap();
+        for (a, b) in &swaps {
+            moved.swap_terminals(a, b);
+        }
+        assert_eq!(shape(&moved), shape(&locked));
+
+        // A different structure, or other terminals, can't be swapped back
+        let restructured = hsplit(vec![term("ws1-001"), term("ws1-002"), term("ws1-003")]);
+        assert_eq!(restructured.swaps_to(&locked), None);
+        let other = hsplit(vec![term("ws1-001"), vsplit(vec![term("ws1-002"), term("ws1-004")])]);
+        assert_eq!(other.swaps_to(&locked), None);
+    }
+
+    #[test]
+    fn test_merged_with() {
+        let ours = vsplit(vec![term("ws1-001"), term("ws1-002")]);
+        let theirs = hsplit(vec![term("ws1-002"), term("ws1-003"), term("ws1-004")]);
+        assert_eq!(shape(&ours.merged_with(&theirs)), "H[V[ws1-001 ws1-002] T[ws1-003 ws1-004]]");
+        assert_eq!(shape(&ours.merged_with(&vsplit(vec![term("ws1-005")]))), "H[V[ws1-001 ws1-002] ws1-005]");
+        assert_eq!(shape(&ours.merged_with(&term("ws1-001"))), "V[ws1-001 ws1-002]");
+    }
+
+    #[test]
+    fn test_evened() {
+        let sized = |socket, percent| Layout::Terminal {
+            socket: String::from(socket),
+            percent: Some(percent),
+            command: None,
+            host: None,
+            attach: None,
+            target: None,
+            title: None,
+        };
+        let layout = hsplit(vec![
+            sized("ws1-001", 0.7),
+            Layout::VSplit { children: vec![sized("ws1-002", 0.9), sized("ws1-003", 0.1)], percent: Some(0.2) },
+            sized("ws1-004", 0.1),
+        ]);
+        let plan = crate::compat::resize_plan(&layout.evened(), crate::wm::WmType::I3);
+        let commands: Vec<(&str, &str)> = plan.iter().map(|r| (r.socket.as_str(), r.command.as_str())).collect();
+        assert_eq!(
+            commands,
+            [
+                ("ws1-001", "resize set width 33 ppt"),
+                ("ws1-002", "resize set width 33 ppt"),
+                ("ws1-002", "resize set height 50 ppt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_restore_plan() {
+        let layout = vsplit(vec![term("ws1-001"), hsplit(vec![term("ws1-002"), term("ws1-003")]), term("ws1-004")]);
+        let launch = |socket| RestoreStep::Launch { socket, host: None, attach: None };
+        assert_eq!(
+            layout.restore_plan(),
+            [
+                launch("ws1-001"),
+                RestoreStep::Commands(vec!["split v"]),
+                launch("ws1-002"),
+                RestoreStep::Commands(vec!["split h"]),
+                launch("ws1-003"),
+                // Back up to the horizontal split, so the last terminal opens beside it
+                RestoreStep::Commands(vec!["focus parent"]),
+                launch("ws1-004"),
+            ]
+        );
+        assert_eq!(relative_command(Some(7), &["split h"]), r#"[con_id="7"] focus; split h"#);
+    }
+
+    #[test]
+    fn test_restore_simulated() {
+        let layouts = [
+            hsplit(vec![term("ws1-001"), term("ws1-002"), term("ws1-003")]),
+            vsplit(vec![term("ws1-001"), term("ws1-002")]),
+            hsplit(vec![term("ws1-001"), vsplit(vec![term("ws1-002"), term("ws1-003")])]),
+            vsplit(vec![term("ws1-001"), hsplit(vec![term("ws1-002"), term("ws1-003")]), term("ws1-004")]),
+            Layout::Tabbed { children: vec![term("ws1-001"), term("ws1-002"), term("ws1-003")] },
+            Layout::Stacked { children: vec![term("ws1-001"), term("ws1-002")] },
+            hsplit(vec![term("ws1-001"), Layout::Tabbed { children: vec![term("ws1-002"), term("ws1-003")] }]),
+            hsplit(vec![vsplit(vec![term("ws1-001"), term("ws1-002")]), term("ws1-003")]),
+            Layout::Tabbed { children: vec![hsplit(vec![term("ws1-001"), term("ws1-002")]), term("ws1-003")] },
+            Layout::Stacked {
+                children: vec![Layout::Tabbed { children: vec![term("ws1-001"), term("ws1-002")] }, term("ws1-003")],
+            },
+            hsplit(vec![
+                term("ws1-001"),
+                Layout::Tabbed {
+                    children: vec![vsplit(vec![term("ws1-002"), term("ws1-003")]), vsplit(vec![term("ws1-004"), term("ws1-005")])],
+                },
+                term("ws1-006"),
+            ]),
+            // Captured workspaces wrap everything in a split, often with a single child
+            hsplit(vec![Layout::Tabbed { children: vec![term("ws1-001"), term("ws1-002")] }]),
+        ];
+        for layout in &layouts {
+            let restored = restore_simulated(layout).unwrap();
+            assert_eq!(shape(&restored), shape(layout), "restoring {}", shape(layout));
+        }
+    }
+
+    #[test]
+    fn test_open_plan_simulated() {
+        // Opened beside a terminal already on the workspace
+        let layouts = [
+            vsplit(vec![term("ws1-002"), term("ws1-003")]),
+            vsplit(vec![hsplit(vec![term("ws1-002"), term("ws1-003")]), term("ws1-004")]),
+            Layout::Tabbed { children: vec![term("ws1-002"), term("ws1-003")] },
+            Layout::Tabbed { children: vec![vsplit(vec![term("ws1-002"), term("ws1-003")]), term("ws1-004")] },
+        ];
+        for layout in &layouts {
+            let mut wm = SimWm::new();
+            wm.open_window(&I3muxWindow::mark_from_parts("local", "ws1-001"));
+            run_simulated(&mut wm, layout.open_plan()).unwrap();
+            let opened = Layout::capture_from_node(&wm.workspace(), WmType::I3).unwrap().unwrap();
+            assert_eq!(shape(&opened), format!("H[ws1-001 {}]", shape(layout)), "opening {}", shape(layout));
+        }
+    }
 }
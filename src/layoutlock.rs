@@ -0,0 +1,114 @@
+//! Keeping a workspace's arrangement in place (`i3mux _watch-layout`)
+//!
+//! `i3mux lock-layout` records the workspace's layout in the local state and
+//! starts this in the background. It follows the WM's window events; whenever
+//! a terminal is moved, the workspace is captured again and compared with the
+//! locked layout. Terminals that only traded places are swapped back when the
+//! lock was taken with `--restore`; anything else gets a desktop notification.
+//! Opening or closing terminals is deliberate, so it re-records the lock
+//! instead. `i3mux unlock-layout`, unbinding the workspace or the WM exiting
+//! end the watch (`i3mux resume` starts it again).
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::process::Command;
+
+use crate::connection;
+use crate::ipc::WmEvent;
+use crate::layout::Layout;
+use crate::lockfile::{self, FileLock};
+use crate::window::I3muxWindow;
+use crate::wm::WmBackend;
+
+/// The arrangement `lock-layout` keeps a workspace in
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LockedLayout {
+    pub layout: Layout,
+    /// Swap moved terminals back rather than only warning
+    #[serde(default)]
+    pub restore: bool,
+}
+
+/// Window changes that can rearrange terminals
+const CHANGES: [&str; 4] = ["move", "floating", "mark", "close"];
+
+pub fn run(workspace: &str) -> Result<()> {
+    // One watch per workspace: a second lock-layout only updates the state
+    let path = connection::ensure_user_dir()?.join(format!("layout-watch-{}.lock", workspace));
+    let Some(_watching) = FileLock::try_acquire(&path)? else {
+        return Ok(());
+    };
+    let num: i32 = workspace.parse()?;
+    let backend = WmBackend::connect()?;
+    let events = backend.subscribe()?.into_channel();
+
+    let mut warned = false;
+    while let Ok(event) = events.recv() {
+        match event? {
+            WmEvent::Window { change, .. } if CHANGES.contains(&change.as_str()) => {}
+            WmEvent::Shutdown => break,
+            _ => continue,
+        }
+        let Some(locked) = locked_layout(workspace)? else {
+            break;
+        };
+        let Some(now) = Layout::capture_from_workspace_num(num, &backend)? else {
+            continue;
+        };
+
+        let sockets = |layout: &Layout| layout.get_sockets().into_iter().collect::<BTreeSet<_>>();
+        if sockets(&now) != sockets(&locked.layout) {
+            relock(workspace, now)?;
+            warned = false;
+            continue;
+        }
+        match now.swaps_to(&locked.layout) {
+            Some(swaps) if swaps.is_empty() => warned = false,
+            Some(swaps) if locked.restore => restore(&backend, &now, &locked.layout, &swaps),
+            _ if warned => {}
+            _ => {
+                warned = true;
+                let message = format!(
+                    "Terminals of workspace {} were rearranged. Run 'i3mux lock-layout' to keep the new \
+                     arrangement, or 'i3mux unlock-layout'.",
+                    workspace
+                );
+                let _ = Command::new("notify-send").args(["i3mux", &message]).status();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The workspace's locked layout, if it is still bound and locked
+fn locked_layout(workspace: &str) -> Result<Option<LockedLayout>> {
+    let state = crate::LocalState::load()?;
+    Ok(state.workspaces.get(workspace).and_then(|ws| ws.layout_lock.clone()))
+}
+
+/// Lock the workspace to `layout`, keeping the lock's settings
+fn relock(workspace: &str, layout: Layout) -> Result<()> {
+    let _binding = lockfile::lock_workspace(workspace)?;
+    let mut state = crate::LocalState::load()?;
+    if let Some(lock) = state.workspaces.get_mut(workspace).and_then(|ws| ws.layout_lock.as_mut()) {
+        lock.layout = layout;
+        state.save()?;
+    }
+    Ok(())
+}
+
+/// Swap terminals back into their locked places and put the sizes back
+fn restore(backend: &WmBackend, now: &Layout, locked: &Layout, swaps: &[(String, String)]) {
+    // Captured terminals always carry their host
+    let hosts = now.get_terminal_hosts("local");
+    for (a, b) in swaps {
+        let command = format!(
+            "[con_mark=\"^{}$\"] swap container with mark \"{}\"",
+            I3muxWindow::mark_from_parts(&hosts[a], a),
+            I3muxWindow::mark_from_parts(&hosts[b], b)
+        );
+        let _ = backend.run_command(&command);
+    }
+    crate::apply_sizes(backend, locked, "local");
+}
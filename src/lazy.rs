@@ -0,0 +1,77 @@
+//! Attaching terminals once they are looked at (`attach --lazy`)
+//!
+//! Restoring a large session starts an SSH attach per terminal at once, which
+//! can saturate a slow link. With `--lazy`, every window still opens in its
+//! place, but runs `i3mux _await-focus` as a placeholder in front of its attach
+//! command: it follows the WM's window events and returns once the window has
+//! focus, so each terminal connects only when first visited. Restoring the
+//! layout moves focus through every terminal, so focus only counts once the
+//! attach has bound the workspace (it holds the workspace lock until then);
+//! the terminal focused at that point attaches right away.
+
+use anyhow::{bail, Result};
+
+use crate::ipc::WmEvent;
+use crate::lockfile;
+use crate::window::{self, I3muxWindow};
+use crate::wm::WmBackend;
+
+/// Environment variable holding the i3mux binary for the placeholder
+pub const BIN_ENV: &str = "I3MUX_BIN";
+
+/// Environment variable holding the name of the workspace being attached
+pub const WORKSPACE_ENV: &str = "I3MUX_LAZY_WORKSPACE";
+
+/// Shell command waiting for the terminal of `socket` on `host` to get focus
+///
+/// The terminal needs [`BIN_ENV`] and [`WORKSPACE_ENV`] set.
+pub fn placeholder(host: &str, socket: &str) -> String {
+    format!(
+        r#"echo 'Waiting for focus to attach {socket} on {host}...'; "${bin}" _await-focus --workspace "${workspace}" --host {host} --socket {socket} && clear"#,
+        bin = BIN_ENV,
+        workspace = WORKSPACE_ENV,
+        host = host,
+        socket = socket
+    )
+}
+
+/// Block until the window marked for `socket` on `host` has focus, once
+/// `workspace` is no longer being attached
+pub fn await_focus(workspace: &str, host: &str, socket: &str) -> Result<()> {
+    drop(lockfile::lock_workspace(workspace)?);
+
+    let mark = I3muxWindow::mark_from_parts(host, socket);
+    let backend = WmBackend::connect()?;
+    // Subscribe before looking, so a focus change in between isn't missed
+    let events = backend.subscribe()?.into_channel();
+    if window::find_focused_i3mux_window(&backend)?.is_some_and(|w| w.mark() == mark) {
+        return Ok(());
+    }
+
+    while let Ok(event) = events.recv() {
+        match event? {
+            // Windows are marked after they open, possibly already focused
+            WmEvent::Window { change, container }
+                if (change == "focus" || change == "mark" && container.focused) && container.marks.contains(&mark) =>
+            {
+                return Ok(());
+            }
+            WmEvent::Shutdown => break,
+            _ => {}
+        }
+    }
+    bail!("The window manager exited before {} was focused", mark)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholder() {
+        assert_eq!(
+            placeholder("user@box", "ws2-003"),
+            r#"echo 'Waiting for focus to attach ws2-003 on user@box...'; "$I3MUX_BIN" _await-focus --workspace "$I3MUX_LAZY_WORKSPACE" --host user@box --socket ws2-003 && clear"#
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Advisory file locks serialising local i3mux commands
+//!
+//! Commands bind workspaces and hand out socket IDs by reading state.json,
+//! changing it and writing it back. Two of them running at once for the same
+//! workspace (a double keypress on the activate binding) would both see it
+//! unbound, or both take the same socket ID. Holding a [`FileLock`] across the
+//! read-modify-write makes the second wait for the first and see its result.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::connection;
+
+/// An exclusive `flock` on a file, released when dropped
+pub struct FileLock {
+    _file: fs::File,
+}
+
+impl FileLock {
+    /// Lock `path` (created if missing), waiting for any other holder
+    pub fn acquire(path: &Path) -> Result<Self> {
+        Self::lock(path, libc::LOCK_EX).map(|lock| lock.expect("blocking flock returned without the lock"))
+    }
+
+    /// Lock `path` unless another process holds it (None then)
+    pub fn try_acquire(path: &Path) -> Result<Option<Self>> {
+        Self::lock(path, libc::LOCK_EX | libc::LOCK_NB)
+    }
+
+    fn lock(path: &Path, operation: libc::c_int) -> Result<Option<Self>> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+        loop {
+            // SAFETY: flock only operates on the descriptor, which `file` keeps open
+            if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+                return Ok(Some(Self { _file: file }));
+            }
+            let err = std::io::Error::last_os_error();
+            match err.kind() {
+                std::io::ErrorKind::Interrupted => continue,
+                std::io::ErrorKind::WouldBlock => return Ok(None),
+                _ => return Err(err).with_context(|| format!("Failed to lock {}", path.display())),
+            }
+        }
+    }
+}
+
+/// Lock a workspace against other commands binding it or adding terminals to it
+pub fn lock_workspace(ws_name: &str) -> Result<FileLock> {
+    let path = connection::ensure_user_dir()?.join(format!("workspace-{}.lock", ws_name));
+    FileLock::acquire(&path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_concurrent_activation_binds_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("workspace-3.lock");
+        let state_path = dir.path().join("state");
+        let bound = Arc::new(AtomicU32::new(0));
+
+        // Each thread is one `activate`: check whether the workspace is bound, bind it
+        let activations: Vec<_> = (0..4)
+            .map(|_| {
+                let (lock_path, state_path, bound) = (lock_path.clone(), state_path.clone(), bound.clone());
+                std::thread::spawn(move || {
+                    let _lock = FileLock::acquire(&lock_path).unwrap();
+                    if !state_path.exists() {
+                        // Give the others time to get to the check
+                        std::thread::sleep(Duration::from_millis(50));
+                        fs::write(&state_path, "bound").unwrap();
+                        bound.fetch_add(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for activation in activations {
+            activation.join().unwrap();
+        }
+        assert_eq!(bound.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_try_acquire_does_not_wait() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("watch.lock");
+        let held = FileLock::try_acquire(&path).unwrap();
+        assert!(held.is_some());
+        assert!(FileLock::try_acquire(&path).unwrap().is_none());
+        drop(held);
+        assert!(FileLock::try_acquire(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_lock_is_released_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("workspace-1.lock");
+        drop(FileLock::acquire(&path).unwrap());
+        let _again = FileLock::acquire(&path).unwrap();
+    }
+}
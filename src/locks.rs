@@ -0,0 +1,141 @@
+//! Sessions locked by other machines, for a status bar (`i3mux watch-locks`)
+//!
+//! A session attached on another machine (the desktop at the office, say)
+//! stays locked until that machine detaches. `watch-locks` checks the hosts
+//! every `--interval` seconds and prints one line per check: nothing while no
+//! session is locked elsewhere, a glyph and the sessions otherwise. That suits
+//! i3blocks' `interval=persist`; with `--json` each line is an object for a
+//! waybar custom module, and `--once` prints a single line for bars that run
+//! the command on their own schedule.
+
+use anyhow::Result;
+use serde_json::json;
+use std::time::Duration;
+
+use crate::connection::{self, Connection};
+use crate::session::RemoteSession;
+
+/// A session held by a live lock of another machine
+#[derive(Debug, PartialEq, Eq)]
+pub struct ForeignLock {
+    pub host: String,
+    pub session: String,
+    pub locked_by: String,
+}
+
+/// What one check found
+#[derive(Debug, Default)]
+struct Check {
+    locks: Vec<ForeignLock>,
+    /// Hosts that could not be checked
+    unreachable: Vec<String>,
+}
+
+impl Check {
+    /// Bar text: empty, or the glyph with the session (or how many are locked)
+    fn text(&self, glyph: &str) -> String {
+        match self.locks.as_slice() {
+            [] => String::new(),
+            [lock] => format!("{} {} ({})", glyph, lock.session, lock.locked_by),
+            locks => format!("{} {} sessions", glyph, locks.len()),
+        }
+    }
+
+    /// One line per locked session and unreachable host
+    fn tooltip(&self) -> String {
+        let locks = self.locks.iter().map(|l| format!("{} on {}: locked by {}", l.session, l.host, l.locked_by));
+        let unreachable = self.unreachable.iter().map(|host| format!("{}: not checked (unreachable)", host));
+        locks.chain(unreachable).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Sessions on `host` locked by a live process of another machine
+///
+/// Stale locks are left out: nobody is attached behind them.
+pub fn foreign_locks(conn: &dyn Connection, host: &str) -> Result<Vec<ForeignLock>> {
+    let me = connection::hostname();
+    let mut locks = Vec::new();
+    for name in RemoteSession::list_remote_sessions(conn)? {
+        // One unreadable session shouldn't blank the bar (`health` reports it)
+        let Ok(session) = RemoteSession::load_from_remote(conn, &name) else {
+            continue;
+        };
+        let Some(lock) = session.lock.filter(|lock| lock.locked_by != me) else {
+            continue;
+        };
+        if conn.is_lock_valid(&lock)? {
+            locks.push(ForeignLock { host: host.to_string(), session: name, locked_by: lock.locked_by });
+        }
+    }
+    Ok(locks)
+}
+
+/// Check `hosts` every `interval` (just once with `once`), printing a line each time
+pub fn watch(hosts: &[String], interval: Duration, once: bool, glyph: &str, json: bool) -> Result<()> {
+    loop {
+        let results = crate::fan_out(hosts, |host| {
+            let conn = crate::host_connection(host)?;
+            foreign_locks(conn.as_ref(), host)
+        });
+        let mut check = Check::default();
+        for (host, result) in hosts.iter().zip(results) {
+            match result {
+                Ok(locks) => check.locks.extend(locks),
+                Err(_) => check.unreachable.push(host.clone()),
+            }
+        }
+
+        if json {
+            let class = if check.locks.is_empty() { "free" } else { "locked" };
+            println!("{}", json!({ "text": check.text(glyph), "tooltip": check.tooltip(), "class": class }));
+        } else {
+            println!("{}", check.text(glyph));
+        }
+        if once {
+            return Ok(());
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::MemoryConnection;
+
+    const FULL_SESSION: &str = include_str!("../tests/fixtures/sessions/full.json");
+
+    #[test]
+    fn test_foreign_locks() {
+        // full.json carries a lock taken by "laptop", held while the store has it
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("box.json");
+        let lock = serde_json::from_str::<serde_json::Value>(FULL_SESSION).unwrap()["lock"].clone();
+        let store = json!({ "sessions": { "dev": FULL_SESSION }, "locks": { "dev": lock } });
+        std::fs::write(&path, store.to_string()).unwrap();
+        let conn = MemoryConnection::open(path).unwrap();
+
+        assert_eq!(
+            foreign_locks(&conn, "box").unwrap(),
+            [ForeignLock { host: "box".into(), session: "dev".into(), locked_by: "laptop".into() }]
+        );
+        // Stale from here on
+        conn.release_lock("dev").unwrap();
+        assert!(foreign_locks(&conn, "box").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check_text() {
+        let lock = |session: &str| ForeignLock { host: "box".into(), session: session.into(), locked_by: "office".into() };
+        let mut check = Check { locks: Vec::new(), unreachable: vec!["db".into()] };
+        assert_eq!(check.text("L"), "");
+        check.locks.push(lock("dev"));
+        assert_eq!(check.text("L"), "L dev (office)");
+        check.locks.push(lock("web"));
+        assert_eq!(check.text("L"), "L 2 sessions");
+        assert_eq!(
+            check.tooltip(),
+            "dev on box: locked by office\nweb on box: locked by office\ndb: not checked (unreachable)"
+        );
+    }
+}
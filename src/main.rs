@@ -1,14 +1,42 @@
+mod commands;
+mod compat;
+mod config;
 mod connection;
+mod container;
+mod error;
+mod exec;
+mod health;
+mod hooks;
+mod instance;
+mod ipc;
+mod kube;
 mod layout;
+mod layoutlock;
+mod lazy;
+mod lockfile;
+mod locks;
+mod metrics;
+mod pending;
+mod query;
+mod reflow;
 mod session;
+mod spec;
+mod style;
+mod target;
+mod timings;
 mod types;
+mod watch;
 mod window;
 mod wm;
+#[cfg(test)]
+mod wmsim;
+mod wrap;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
@@ -26,23 +54,29 @@ macro_rules! debug {
     };
 }
 
+use commands::{AttachCommand, CommandSpec};
+use config::Config;
 use connection::create_connection;
-use layout::Layout;
-use session::RemoteSession;
-use types::{RemoteHost, SessionName};
+use container::{ContainerTarget, Runtime};
+use kube::PodTarget;
+use layout::{Layout, RestoreStep};
+use reflow::ReflowPolicy;
+use session::{ConflictPolicy, RemoteSession};
+use target::AttachTarget;
+use types::{Mount, PortForward, RemoteHost, SessionName};
 use window::{I3muxWindow, wait_for_window_and_mark};
 use wm::{WmBackend, WmType};
 
-const MARKER: &str = "i3mux:"; // Marker prefix for window titles (for initial window matching)
-const LOCAL_DISPLAY: &str = "\x1b[3mlocal\x1b[0m"; // Italicized "local"
-
 // Remote helper script - uploaded to remote hosts for reliable command execution
 const REMOTE_HELPER_SCRIPT: &str = include_str!("remote-helper.sh");
-const REMOTE_HELPER_PATH: &str = "/tmp/i3mux-helper.sh";
 
-// Wrapper script - runs locally to launch terminals with proper setup
-const WRAPPER_SCRIPT: &str = include_str!("wrapper.sh");
-const WRAPPER_PATH: &str = "/tmp/i3mux-wrapper.sh";
+/// Where the helper script is uploaded on remote hosts (one per instance)
+fn remote_helper_path() -> String {
+    format!("/tmp/i3mux{}-helper.sh", instance::suffix())
+}
+
+/// Time for terminals to pick up their final size after the layout is applied (milliseconds)
+const RESIZE_SETTLE_MS: u64 = 300;
 
 #[derive(Parser)]
 #[command(name = "i3mux")]
@@ -61,6 +95,32 @@ struct Cli {
     #[arg(short, long, global = true)]
     verbose: bool,
 
+    /// Report errors as a JSON object on stderr (kind, message, exit code)
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Report how long SSH, lock and window manager steps took
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Plain output without ANSI styling or symbols (also set by NO_COLOR, or when not on a terminal)
+    #[arg(long, global = true)]
+    no_ansi: bool,
+
+    /// Never contact remote hosts: their sessions and locks are kept locally and
+    /// their terminals run in local abduco sessions (for demos and development)
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Keep marks, local state and socket names apart from other i3mux
+    /// installations (e.g. "dev" for a development build next to a release)
+    #[arg(long, global = true, value_name = "NAME")]
+    instance: Option<String>,
+
+    /// Append readiness events (window marked, cleanup done, lock acquired) to FILE, for test automation
+    #[arg(long, global = true, hide = true, value_name = "FILE")]
+    test_hooks: Option<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -76,6 +136,84 @@ enum Commands {
         /// Session name (optional)
         #[arg(short, long)]
         session: Option<String>,
+
+        /// Open one terminal per host of a config group (e.g. @webservers) in this workspace
+        #[arg(long, value_name = "@GROUP", conflicts_with = "remote")]
+        cluster: Option<String>,
+
+        /// Arrange the --cluster terminals in a grid
+        #[arg(long, requires = "cluster")]
+        grid: bool,
+
+        /// Open terminals in this arrangement instead of a single one, e.g.
+        /// "h(60:v(editor,shell),40:logs)"; the names become window titles
+        #[arg(long, value_name = "EXPR", conflicts_with = "cluster")]
+        layout: Option<String>,
+    },
+
+    /// Bind the current workspace, replacing its plain terminals with i3mux terminals
+    AdoptWorkspace {
+        /// Remote host for the new terminals
+        #[arg(short, long)]
+        remote: Option<String>,
+
+        /// Session name (optional)
+        #[arg(short, long)]
+        session: Option<String>,
+
+        /// Window class or instance (app_id on Sway) of the terminals to replace;
+        /// repeatable (default: the name of $TERMINAL)
+        #[arg(long, value_name = "CLASS")]
+        class: Vec<String>,
+
+        /// Leave the replaced terminals open next to their replacements
+        #[arg(long)]
+        keep_originals: bool,
+
+        /// Only bind the workspace: existing windows are left alone, new terminals are managed
+        #[arg(long, conflicts_with_all = ["class", "keep_originals"])]
+        bind_only: bool,
+    },
+
+    /// Toggle the focused terminal between fullscreen and its place in the layout
+    Zoom,
+
+    /// Exchange the focused terminal with another one of the workspace
+    Swap {
+        /// Socket of the terminal to swap with (e.g. ws3-002, see `i3mux ps`)
+        #[arg(long, value_name = "SOCKET", required_unless_present = "direction", conflicts_with = "direction")]
+        with: Option<String>,
+
+        /// Swap with the neighbouring terminal in this direction
+        #[arg(long, value_enum)]
+        direction: Option<window::Direction>,
+    },
+
+    /// Keep the current workspace's arrangement, warning when terminals get moved around
+    LockLayout {
+        /// Swap moved terminals back (and restore sizes) instead of only warning
+        #[arg(long)]
+        restore: bool,
+    },
+
+    /// Stop keeping the arrangement recorded by lock-layout
+    UnlockLayout,
+
+    /// Give every pane of the current workspace's splits an equal share
+    Even,
+
+    /// Flip the current workspace's outermost split between side by side and stacked
+    Rotate,
+
+    /// Open N terminals in the current workspace's session, arranged in a grid
+    Grid {
+        /// Number of terminals
+        #[arg(value_parser = clap::value_parser!(u16).range(1..=64), required_unless_present = "layout")]
+        count: Option<u16>,
+
+        /// Open terminals in this arrangement instead, e.g. "h(60:editor,40:v(a,b))"
+        #[arg(long, value_name = "EXPR", conflicts_with = "count")]
+        layout: Option<String>,
     },
 
     /// Detach current workspace and save session to remote
@@ -83,6 +221,33 @@ enum Commands {
         /// Session name to save as
         #[arg(short, long)]
         session: Option<String>,
+
+        /// If the session was saved from elsewhere since it was attached here
+        /// (defaults to `on_conflict` from config)
+        #[arg(long, value_enum)]
+        on_conflict: Option<ConflictPolicy>,
+
+        /// Detach workspace N instead of the focused one
+        #[arg(long, value_name = "N")]
+        workspace: Option<i32>,
+    },
+
+    /// Move a workspace's binding to another workspace number (after renaming it in the WM)
+    Rebind {
+        /// Number the workspace is bound under
+        #[arg(long, value_name = "N")]
+        from: i32,
+
+        /// Number the workspace has now
+        #[arg(long, value_name = "N")]
+        to: i32,
+    },
+
+    /// Stop managing the current workspace, leaving its terminals open
+    Unbind {
+        /// Keep the windows' i3mux marks, so `resume` can bind the workspace again
+        #[arg(long)]
+        keep_marks: bool,
     },
 
     /// Attach to a saved session
@@ -98,13 +263,78 @@ enum Commands {
         /// Force attach (break existing lock)
         #[arg(long)]
         force: bool,
+
+        /// If another machine holds the session, have it detach (saving its
+        /// layout) and attach here once it has
+        #[arg(long, conflicts_with = "force")]
+        here_then_close_origin: bool,
+
+        /// Adapt the layout to this workspace's size (defaults to `reflow.policy` from config)
+        #[arg(long, value_enum)]
+        reflow: Option<ReflowPolicy>,
+
+        /// Give focus back to the window that had it once the terminals are up
+        #[arg(long)]
+        no_focus: bool,
+
+        /// Open every window right away, but only attach each terminal once it is first focused
+        #[arg(long)]
+        lazy: bool,
     },
 
     /// List available sessions on remote
     Sessions {
+        /// Remote host, or @group for every host of a group in config.toml
+        #[arg(short, long)]
+        remote: Option<String>,
+    },
+
+    /// Check a host's saved sessions, locks and terminals, and its helper and abduco versions
+    Health {
+        /// Remote host
+        #[arg(short, long)]
+        remote: Option<String>,
+    },
+
+    /// Create a session from a spec file (terminals, their commands and cwds, a layout expression) to attach later
+    Create {
+        /// Spec file (TOML)
+        #[arg(long, value_name = "FILE")]
+        spec: PathBuf,
+
+        /// Remote host, instead of the spec's `host`
+        #[arg(short, long)]
+        remote: Option<String>,
+
+        /// Replace a saved session of the same name
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Edit a saved session's JSON in $EDITOR; it is checked before being saved back
+    Edit {
         /// Remote host
         #[arg(short, long)]
         remote: Option<String>,
+
+        /// Session name
+        #[arg(short, long)]
+        session: String,
+    },
+
+    /// Check a session file, or a saved session, against the session format and layout rules
+    Validate {
+        /// Session file, e.g. one generated from a template
+        #[arg(required_unless_present = "session", conflicts_with_all = ["session", "remote"])]
+        file: Option<PathBuf>,
+
+        /// Saved session to check instead of a file
+        #[arg(short, long)]
+        session: Option<String>,
+
+        /// Remote host of the saved session
+        #[arg(short, long)]
+        remote: Option<String>,
     },
 
     /// Kill a saved session
@@ -116,6 +346,186 @@ enum Commands {
         /// Session name
         #[arg(short, long)]
         session: String,
+
+        /// Also terminate the session's abduco sessions and their processes
+        #[arg(long, conflicts_with = "keep_running")]
+        terminate: bool,
+
+        /// Leave abduco sessions running (overrides `kill_terminates` in config)
+        #[arg(long)]
+        keep_running: bool,
+    },
+
+    /// Upload sessions that detach queued while their host was unreachable
+    Sync {
+        /// Only sync sessions queued for this host
+        #[arg(short, long)]
+        remote: Option<String>,
+
+        /// Upload even sessions whose copy on the host was saved from elsewhere since
+        #[arg(long, conflicts_with = "discard")]
+        force: bool,
+
+        /// Drop the queued sessions instead of uploading them
+        #[arg(long)]
+        discard: bool,
+    },
+
+    /// Manage the SSH connection i3mux shares between its commands and terminals
+    Ssh {
+        #[command(subcommand)]
+        action: SshAction,
+    },
+
+    /// Manage the helper script i3mux installs on remote hosts
+    Helpers {
+        #[command(subcommand)]
+        action: HelpersAction,
+    },
+
+    /// Print a line for a status bar whenever sessions are locked by other machines
+    WatchLocks {
+        /// Host or @group to check (default: every remote host in the config)
+        #[arg(short, long)]
+        remote: Option<String>,
+
+        /// Seconds between checks
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Check once and exit (for bars that run the command themselves)
+        #[arg(long)]
+        once: bool,
+
+        /// Shown in front of the locked sessions
+        #[arg(long, default_value = "🔒")]
+        glyph: String,
+    },
+
+    /// Answer questions about i3mux terminals quickly, for scripts and window switchers
+    Query {
+        #[command(subcommand)]
+        action: QueryAction,
+    },
+
+    /// Show how the current workspace is bound (host, session, when and how it was bound)
+    Status,
+
+    /// Identify the focused window: host, socket, session and how to attach to it
+    Which,
+
+    /// Show the foreground process of each terminal in the current workspace
+    Ps,
+
+    /// Type a command into every terminal of the current workspace (e.g. source ~/.env)
+    ///
+    /// {index}, {name} and {socket} in the command are replaced by each terminal's
+    /// position (from 1), window title and socket. Terminals busy running
+    /// something other than their shell are skipped unless --include-busy.
+    ExecAll {
+        /// Command to run, as typed at a prompt
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+        /// Type into terminals running a program too (it gets the input, not a shell)
+        #[arg(long)]
+        include_busy: bool,
+    },
+
+    /// Detach other machines' abduco clients from a terminal (e.g. a dead connection)
+    Boot {
+        /// Socket to free (e.g. ws3-002; defaults to the focused terminal)
+        #[arg(long)]
+        socket: Option<String>,
+    },
+
+    /// Show attach/detach/activate latency per host (needs `metrics = true` in config)
+    Stats,
+
+    /// Detach, attach or activate the current workspace (for a single keybind)
+    Toggle {
+        /// Remote host (defaults to `default_remote` from config)
+        #[arg(short, long)]
+        remote: Option<String>,
+    },
+
+    /// Pin the current workspace to an output (first connected one wins)
+    PinOutput {
+        /// Output names in order of preference (e.g. DP-1 eDP-1)
+        #[arg(required_unless_present = "clear")]
+        outputs: Vec<String>,
+
+        /// Remove the pin
+        #[arg(long, conflicts_with = "outputs")]
+        clear: bool,
+    },
+
+    /// Add, remove or list SSH port forwards of the current workspace's session
+    Forward {
+        /// Local forward ([bind_address:]port:host:hostport)
+        #[arg(short = 'L')]
+        local: Vec<String>,
+
+        /// Remote forward ([bind_address:]port:host:hostport)
+        #[arg(short = 'R')]
+        remote_forward: Vec<String>,
+
+        /// Dynamic (SOCKS) forward ([bind_address:]port)
+        #[arg(short = 'D')]
+        dynamic: Vec<String>,
+
+        /// Remove the given forwards instead of adding them
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Add, remove or list sshfs mounts of the current workspace's session
+    Mount {
+        /// Remote directory (relative to the remote home unless absolute)
+        #[arg(requires = "local")]
+        remote: Option<String>,
+
+        /// Local mount point
+        local: Option<String>,
+
+        /// Unmount and forget the mount instead of adding it
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Re-establish i3mux bookkeeping after an i3 restart or Sway reload
+    Resume,
+
+    /// Attach the focused placeholder terminal, left by attach for a host that was down
+    Respawn,
+
+    /// Show or change how a terminal attaches: a custom command instead of abduco
+    EditTerminal {
+        /// Socket of the terminal (default: the focused terminal)
+        #[arg(long)]
+        socket: Option<String>,
+
+        /// Edit the terminal in this saved session instead of a bound workspace
+        #[arg(short, long, requires = "socket")]
+        session: Option<String>,
+
+        /// Host the session is saved on
+        #[arg(short, long)]
+        remote: Option<String>,
+
+        /// Attach with this command from the next attach on; runs locally, like
+        /// `terminal --attach` (e.g., 'ssh -t box sudo -u build abduco -a /tmp/build')
+        #[arg(short, long)]
+        attach: Option<String>,
+
+        /// Attach through abduco again
+        #[arg(long, conflicts_with = "attach")]
+        clear: bool,
+    },
+
+    /// Inspect or repair the local workspace state (state.json)
+    State {
+        #[command(subcommand)]
+        action: StateAction,
     },
 
     /// Launch terminal (called by i3 keybind)
@@ -123,6 +533,128 @@ enum Commands {
         /// Command to run instead of shell (e.g., -e '/path/to/script arg1 arg2')
         #[arg(short = 'e', long = "exec")]
         exec: Option<String>,
+
+        /// Run the terminal on another host than the workspace's (e.g., user@other)
+        #[arg(short, long)]
+        remote: Option<String>,
+
+        /// Run the terminal on this machine, even in a remote workspace
+        #[arg(short, long, conflicts_with = "remote")]
+        local: bool,
+
+        /// Attach with this command instead of abduco (e.g., 'picocom /dev/ttyUSB0');
+        /// runs locally and is restored by re-running it
+        #[arg(short, long, conflicts_with_all = ["exec", "remote"])]
+        attach: Option<String>,
+
+        /// Attach to the running pod matching this label selector via kubectl exec
+        #[arg(long, value_name = "SELECTOR", conflicts_with_all = ["exec", "remote", "attach"])]
+        pod: Option<String>,
+
+        /// Attach to a running Docker container via docker exec
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["exec", "remote", "attach", "pod"])]
+        docker: Option<String>,
+
+        /// Attach to a running Podman container via podman exec
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["exec", "remote", "attach", "pod", "docker"])]
+        podman: Option<String>,
+
+        /// Namespace of the pod
+        #[arg(short, long, requires = "pod")]
+        namespace: Option<String>,
+
+        /// Container within the pod
+        #[arg(short, long, requires = "pod")]
+        container: Option<String>,
+
+        /// Open the terminal in this i3mux workspace instead of the focused one,
+        /// without switching to it
+        #[arg(short, long, value_name = "N")]
+        workspace: Option<i32>,
+
+        /// Give focus back to the window that had it once the terminal is up
+        #[arg(long)]
+        no_focus: bool,
+
+        /// Split the focused window along its longer side first, keeping panes roughly square
+        #[arg(long, conflicts_with = "workspace")]
+        smart_split: bool,
+    },
+
+    /// Run a terminal's attach command and clean up after it (internal command, run by the terminal)
+    #[command(name = "_wrap", hide = true)]
+    Wrap {
+        /// Socket of the terminal
+        #[arg(long)]
+        socket: String,
+
+        /// Workspace the terminal belongs to
+        #[arg(long)]
+        workspace: String,
+
+        /// Shell command that attaches the terminal
+        #[arg(long, allow_hyphen_values = true)]
+        attach_cmd: String,
+
+        /// Window title (none: leave it to the shell)
+        #[arg(long)]
+        title: Option<String>,
+
+        /// Saved session whose files go away with the workspace's last socket
+        #[arg(long, requires = "host")]
+        session: Option<String>,
+
+        /// Host the session is saved on
+        #[arg(long)]
+        host: Option<String>,
+    },
+
+    /// Wait for a terminal's window to get focus (internal command, run by `attach --lazy` terminals)
+    #[command(name = "_await-focus", hide = true)]
+    AwaitFocus {
+        /// Workspace being attached
+        #[arg(long)]
+        workspace: String,
+
+        /// Host of the terminal's session
+        #[arg(long)]
+        host: String,
+
+        /// The terminal's socket
+        #[arg(long)]
+        socket: String,
+    },
+
+    /// Keep the cache of `query windows` current (internal command, run in the background)
+    #[command(name = "_watch-windows", hide = true)]
+    WatchWindows,
+
+    /// Keep a locked layout in place (internal command, run in the background)
+    #[command(name = "_watch-layout", hide = true)]
+    WatchLayout {
+        /// Workspace whose layout is locked
+        #[arg(long)]
+        workspace: String,
+    },
+
+    /// Notify when another machine takes over an attached session (internal command, run in the background)
+    #[command(name = "_watch-lock", hide = true)]
+    WatchLock {
+        /// Workspace attached to the session
+        #[arg(long)]
+        workspace: String,
+
+        /// Host the session is saved on
+        #[arg(long)]
+        host: String,
+
+        /// Attached session
+        #[arg(long)]
+        session: String,
+
+        /// Nonce of this machine's lock on the session
+        #[arg(long)]
+        nonce: String,
     },
 
     /// Clean up workspace state if no sessions remain (internal command)
@@ -130,6 +662,61 @@ enum Commands {
     CleanupWorkspace {
         /// Workspace name (e.g., "4" for workspace 4)
         workspace: String,
+
+        /// Socket of the exiting terminal (its window is ignored)
+        #[arg(long)]
+        socket: Option<String>,
+
+        /// The exiting terminal's window was closed externally (see kill_on_close)
+        #[arg(long, requires = "socket")]
+        window_closed: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum StateAction {
+    /// Print bound workspaces and their terminals, noting which windows and sessions are alive
+    Dump,
+
+    /// Go through bound workspaces and fix them (drop, change host, renumber sockets)
+    Repair,
+}
+
+#[derive(Subcommand)]
+enum SshAction {
+    /// Log in to a host in a terminal window, for password or 2FA prompts; later
+    /// commands and terminals reuse the connection until it is idle for 10 minutes
+    Connect {
+        /// Host to log in to (default: default_remote from the config)
+        #[arg(short, long)]
+        remote: Option<String>,
+
+        /// Log in from this terminal instead of opening a window
+        #[arg(long)]
+        here: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueryAction {
+    /// List every i3mux terminal as tab-separated id, workspace, host, socket,
+    /// session and title (a JSON array with --json); served from a cache kept
+    /// by a background watcher, so switchers can run it on every keypress
+    Windows,
+}
+
+#[derive(Subcommand)]
+enum HelpersAction {
+    /// Install this release's helper on hosts ahead of time, concurrently, and
+    /// report the version each host had
+    Update {
+        /// Every host named in the config (default remote, [hosts], [aliases], [groups])
+        #[arg(long, conflicts_with = "remote")]
+        all_hosts: bool,
+
+        /// Host or @group to update
+        #[arg(short, long)]
+        remote: Option<String>,
     },
 }
 
@@ -151,20 +738,178 @@ struct WorkspaceState {
     session_name: Option<String>,
     next_socket_id: u32,
     sockets: HashMap<String, SocketInfo>,
+    /// Outputs the workspace is pinned to, in order of preference
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    outputs: Vec<String>,
+    /// SSH port forwards of the session
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    forwards: Vec<PortForward>,
+    /// sshfs mounts of the session
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mounts: Vec<Mount>,
+    /// How and when the workspace was bound (absent in state from older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    activation: Option<Activation>,
+    /// Layout (with sizes) from before `i3mux zoom` made a terminal fullscreen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zoomed: Option<Layout>,
+    /// Arrangement kept by `i3mux lock-layout`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    layout_lock: Option<layoutlock::LockedLayout>,
+    /// Revision of the saved session as this workspace last saved it (see `detach --on-conflict`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    revision: Option<u64>,
+    /// The workspace's name before `workspace_labels` renamed it, restored when unbound
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    original_name: Option<String>,
+}
+
+/// The command that bound a workspace
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Activation {
+    /// "activate", "adopt-workspace", "attach" or "resume"
+    command: String,
+    /// Flags it was given (e.g. ["--remote", "user@host"])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    flags: Vec<String>,
+    /// When the workspace was bound (RFC 3339)
+    at: String,
+    /// Session name `detach` saves to when none is given
+    default_session: String,
+}
+
+impl Activation {
+    fn new(command: &str, flags: Vec<String>, default_session: String) -> Self {
+        Self {
+            command: command.to_string(),
+            flags,
+            at: chrono::Utc::now().to_rfc3339(),
+            default_session,
+        }
+    }
+
+    /// Flags for a host and session given on the command line
+    fn host_flags(remote: Option<&RemoteHost>, session: Option<&str>) -> Vec<String> {
+        let mut flags = Vec::new();
+        if let Some(remote) = remote {
+            flags.extend(["--remote".to_string(), remote.as_str().to_string()]);
+        }
+        if let Some(session) = session {
+            flags.extend(["--session".to_string(), session.to_string()]);
+        }
+        flags
+    }
+}
+
+impl std::fmt::Display for Activation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let at = chrono::DateTime::parse_from_rfc3339(&self.at)
+            .map(|at| at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| self.at.clone());
+        write!(f, "'i3mux {}", self.command)?;
+        for flag in &self.flags {
+            write!(f, " {}", flag)?;
+        }
+        write!(f, "' at {}", at)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct SocketInfo {
     socket_id: String,
+    /// Host the terminal runs on, if not the workspace's host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    /// Custom attach command the terminal runs instead of abduco
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attach: Option<String>,
+    /// Pod or container the terminal attaches to (its attach command is derived from it)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<AttachTarget>,
+    /// The terminal is a local placeholder: its host was down at attach (see `i3mux respawn`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    placeholder: bool,
+}
+
+impl WorkspaceState {
+    /// Custom attach commands of the workspace's terminals, keyed by socket
+    fn attach_commands(&self) -> HashMap<String, String> {
+        self.sockets
+            .iter()
+            .filter_map(|(socket, info)| Some((socket.clone(), info.attach.clone()?)))
+            .collect()
+    }
+
+    /// Pods and containers of the workspace's terminals, keyed by socket
+    fn targets(&self) -> HashMap<String, AttachTarget> {
+        self.sockets
+            .iter()
+            .filter_map(|(socket, info)| Some((socket.clone(), info.target.clone()?)))
+            .collect()
+    }
+
+    /// Host a socket's terminal runs on
+    fn socket_host<'a>(&'a self, socket: &str) -> &'a str {
+        self.sockets
+            .get(socket)
+            .and_then(|info| info.host.as_deref())
+            .unwrap_or(&self.host)
+    }
+
+    /// Sockets grouped by host; the workspace's own host is always included
+    fn sockets_by_host(&self) -> std::collections::BTreeMap<String, Vec<String>> {
+        let mut grouped = std::collections::BTreeMap::from([(self.host.clone(), Vec::new())]);
+        for socket in self.sockets.keys() {
+            grouped
+                .entry(self.socket_host(socket).to_string())
+                .or_insert_with(Vec::new)
+                .push(socket.clone());
+        }
+        for sockets in grouped.values_mut() {
+            sockets.sort();
+        }
+        grouped
+    }
+
+    /// Socket name prefixes (`ws<N>`) of the workspace's terminals and of new ones
+    ///
+    /// Sockets keep the number of the workspace they were created in, so a
+    /// workspace moved with `i3mux rebind` has more than one.
+    fn socket_prefixes(&self, ws_name: &str) -> Vec<String> {
+        let mut prefixes: Vec<String> = self
+            .sockets
+            .keys()
+            .filter_map(|s| Some(s.rsplit_once('-')?.0.to_string()))
+            .chain([instance::socket_prefix(ws_name)])
+            .collect();
+        prefixes.sort();
+        prefixes.dedup();
+        prefixes
+    }
+
+    /// Highest socket number in use (0 if there are no sockets)
+    fn highest_socket_id(&self) -> u32 {
+        self.sockets
+            .keys()
+            .filter_map(|s| s.rsplit('-').next()?.parse::<u32>().ok())
+            .max()
+            .unwrap_or(0)
+    }
 }
 
 impl LocalState {
+    /// Name of the workspace whose terminals include `socket`
+    fn workspace_of_socket(&self, socket: &str) -> Option<&str> {
+        self.workspaces
+            .iter()
+            .find(|(_, ws)| ws.sockets.contains_key(socket))
+            .map(|(name, _)| name.as_str())
+    }
+
     fn path() -> Result<PathBuf> {
-        let config_dir = dirs::config_dir()
-            .context("Could not find config directory")?
-            .join("i3mux");
-        fs::create_dir_all(&config_dir)?;
-        Ok(config_dir.join("state.json"))
+        let state_dir = instance::state_dir()?;
+        fs::create_dir_all(&state_dir)?;
+        Ok(state_dir.join("state.json"))
     }
 
     fn load() -> Result<Self> {
@@ -176,9 +921,27 @@ impl LocalState {
         Ok(serde_json::from_str(&contents)?)
     }
 
-    fn save(&self) -> Result<()> {
-        let path = Self::path()?;
-        let contents = serde_json::to_string_pretty(self)?;
+    /// Names of the bound workspaces, without parsing their state
+    ///
+    /// `i3mux terminal` runs on every terminal launch, bound or not; this keeps
+    /// the unbound case from paying for a full state load.
+    fn bound_workspaces() -> Result<HashSet<String>> {
+        #[derive(Deserialize)]
+        struct Bound {
+            workspaces: HashMap<String, serde::de::IgnoredAny>,
+        }
+
+        let contents = match fs::read_to_string(Self::path()?) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_str::<Bound>(&contents)?.workspaces.into_keys().collect())
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        let contents = serde_json::to_string_pretty(self)?;
         fs::write(&path, contents)?;
         Ok(())
     }
@@ -195,90 +958,281 @@ impl Drop for LocalState {
     }
 }
 
-fn main() -> Result<()> {
+fn main() {
     let cli = Cli::parse();
 
     // Set global verbose flag
     VERBOSE.store(cli.verbose, Ordering::Relaxed);
+    style::init(cli.no_ansi);
+    connection::set_offline(cli.offline);
+    if cli.timings {
+        timings::enable();
+    }
+    if let Some(path) = &cli.test_hooks {
+        hooks::enable(path);
+    }
+
+    let json = cli.json;
+    let result = run(cli);
+    timings::report();
+
+    if let Err(e) = result {
+        if json {
+            eprintln!("{}", error::to_json(&e));
+        } else {
+            eprintln!("Error: {:?}", e);
+        }
+        std::process::exit(error::exit_code(&e));
+    }
+}
 
+/// Dispatch the subcommand
+fn run(cli: Cli) -> Result<()> {
+    instance::init(cli.instance.as_deref())?;
     match cli.command {
         None => {
             // Default: activate current workspace
-            activate(cli.remote, cli.session)
+            activate(canonical_remote(cli.remote)?, cli.session, None)
         }
-        Some(Commands::Activate { remote, session }) => {
-            activate(remote.or(cli.remote), session.or(cli.session))
+        Some(Commands::Activate { cluster: Some(group), session, grid, .. }) => {
+            activate_cluster(&group, session.or(cli.session), grid)
         }
-        Some(Commands::Detach { session }) => detach(session),
+        Some(Commands::Activate { remote, session, layout, .. }) => {
+            activate(canonical_remote(remote.or(cli.remote))?, session.or(cli.session), layout)
+        }
+        Some(Commands::AdoptWorkspace { remote, session, class, keep_originals, bind_only }) => {
+            adopt_workspace(canonical_remote(remote.or(cli.remote))?, session, class, keep_originals, bind_only)
+        }
+        Some(Commands::Grid { layout: Some(expr), .. }) => grid_layout(&expr),
+        Some(Commands::Grid { count, layout: None }) => grid(count.unwrap_or(1).into()),
+        Some(Commands::Zoom) => zoom(),
+        Some(Commands::Swap { with, direction }) => swap(with, direction),
+        Some(Commands::LockLayout { restore }) => lock_layout(restore),
+        Some(Commands::UnlockLayout) => unlock_layout(),
+        Some(Commands::Even) => even(),
+        Some(Commands::Rotate) => rotate(),
+        Some(Commands::Detach { session, on_conflict, workspace }) => detach(session, on_conflict, workspace),
+        Some(Commands::Unbind { keep_marks }) => unbind(keep_marks),
+        Some(Commands::Rebind { from, to }) => rebind(from, to),
         Some(Commands::Attach {
             remote,
             session,
             force,
-        }) => attach(remote.or(cli.remote), session.or(cli.session), force),
-        Some(Commands::Sessions { remote }) => list_sessions(remote.or(cli.remote)),
-        Some(Commands::Kill { remote, session }) => kill_session(remote.or(cli.remote), session),
-        Some(Commands::Terminal { exec }) => terminal(exec.as_deref()),
-        Some(Commands::CleanupWorkspace { workspace }) => cleanup_workspace(&workspace),
+            here_then_close_origin,
+            reflow,
+            no_focus,
+            lazy,
+        }) => {
+            let remote = canonical_remote(remote.or(cli.remote))?;
+            keeping_focus(no_focus, || {
+                attach(remote, session.or(cli.session), force, here_then_close_origin, reflow, lazy)
+            })
+        }
+        Some(Commands::Sessions { remote }) => list_sessions(canonical_remote(remote.or(cli.remote))?),
+        Some(Commands::Health { remote }) => health(canonical_remote(remote.or(cli.remote))?),
+        Some(Commands::Create { spec, remote, force }) => create_session(spec, canonical_remote(remote.or(cli.remote))?, force),
+        Some(Commands::Edit { remote, session }) => edit_session(canonical_remote(remote.or(cli.remote))?, session),
+        Some(Commands::Validate { file, session, remote }) => {
+            validate(file, session, canonical_remote(remote.or(cli.remote))?)
+        }
+        Some(Commands::Kill {
+            remote,
+            session,
+            terminate,
+            keep_running,
+        }) => kill_session(canonical_remote(remote.or(cli.remote))?, session, terminate, keep_running),
+        Some(Commands::Sync { remote, force, discard }) => sync(canonical_remote(remote.or(cli.remote))?, force, discard),
+        Some(Commands::Ssh { action: SshAction::Connect { remote, here } }) => {
+            ssh_connect(canonical_remote(remote.or(cli.remote))?, here)
+        }
+        Some(Commands::Helpers { action: HelpersAction::Update { all_hosts, remote } }) => {
+            update_helpers(all_hosts, canonical_remote(remote.or(cli.remote))?)
+        }
+        Some(Commands::WatchLocks { remote, interval, once, glyph }) => {
+            watch_locks(canonical_remote(remote.or(cli.remote))?, interval, once, &glyph, cli.json)
+        }
+        Some(Commands::Query { action: QueryAction::Windows }) => query_windows(cli.json),
+        Some(Commands::WatchWindows) => query::watch(),
+        Some(Commands::Status) => status(),
+        Some(Commands::Which) => which(),
+        Some(Commands::Ps) => ps(),
+        Some(Commands::ExecAll { command, include_busy }) => exec_all(&command.join(" "), include_busy),
+        Some(Commands::Boot { socket }) => boot(socket),
+        Some(Commands::Stats) => stats(),
+        Some(Commands::Toggle { remote }) => toggle(remote.or(cli.remote)),
+        Some(Commands::PinOutput { outputs, clear }) => pin_output(if clear { Vec::new() } else { outputs }),
+        Some(Commands::Forward { local, remote_forward, dynamic, remove }) => {
+            // Validate at CLI boundary
+            let forwards = local
+                .into_iter()
+                .map(|spec| PortForward::new('L', spec))
+                .chain(remote_forward.into_iter().map(|spec| PortForward::new('R', spec)))
+                .chain(dynamic.into_iter().map(|spec| PortForward::new('D', spec)))
+                .collect::<Result<Vec<_>>>()?;
+            forward(forwards, remove)
+        }
+        Some(Commands::Mount { remote, local, remove }) => {
+            // Validate at CLI boundary
+            let mount = remote.zip(local).map(|(r, l)| Mount::new(r, l)).transpose()?;
+            mount_dir(mount, remove)
+        }
+        Some(Commands::Resume) => resume(),
+        Some(Commands::Respawn) => respawn(),
+        Some(Commands::EditTerminal { socket, session, remote, attach, clear }) => {
+            let change = if clear { Some(None) } else { attach.map(Some) };
+            edit_terminal(socket, session, canonical_remote(remote.or(cli.remote))?, change)
+        }
+        Some(Commands::State { action: StateAction::Dump }) => state_dump(),
+        Some(Commands::State { action: StateAction::Repair }) => state_repair(),
+        Some(Commands::Terminal { exec, remote, local, attach, pod, namespace, container, docker, podman, workspace, no_focus, smart_split }) => {
+            let target = match (pod, docker, podman) {
+                (Some(selector), _, _) => Some(AttachTarget::Pod(PodTarget::new(selector, namespace, container)?)),
+                (_, Some(name), _) => Some(AttachTarget::Container(ContainerTarget::new(Runtime::Docker, name)?)),
+                (_, _, Some(name)) => Some(AttachTarget::Container(ContainerTarget::new(Runtime::Podman, name)?)),
+                _ => None,
+            };
+            let remote = canonical_remote(remote)?;
+            let remote = if local || attach.is_some() || target.is_some() { Some("local") } else { remote.as_deref() };
+            if smart_split {
+                window::split_focused_along_longer_side(&WmBackend::connect()?)?;
+            }
+            keeping_focus(no_focus, || terminal(exec.as_deref(), remote, attach.as_deref(), target, workspace))
+        }
+        Some(Commands::Wrap { socket, workspace, attach_cmd, title, session, host }) => {
+            wrap::run(wrap::Wrap {
+                socket,
+                workspace,
+                attach_cmd,
+                title,
+                session: session.zip(host),
+            })
+        }
+        Some(Commands::AwaitFocus { workspace, host, socket }) => lazy::await_focus(&workspace, &host, &socket),
+        Some(Commands::WatchLayout { workspace }) => layoutlock::run(&workspace),
+        Some(Commands::WatchLock { workspace, host, session, nonce }) => {
+            watch::run(watch::Watch { workspace, host, session, nonce })
+        }
+        Some(Commands::CleanupWorkspace { workspace, socket, window_closed }) => {
+            cleanup_workspace(&workspace, socket.as_deref(), window_closed)
+        }
+    }
+}
+
+/// Resolve a host given on the command line to its canonical name (`[aliases]` in config)
+///
+/// Marks, saved sessions, locks and SSH control sockets are all keyed by the
+/// host string, so every spelling of a machine has to become the same one
+/// before anything is looked up. Groups (`@name`) resolve their own entries.
+fn canonical_remote(remote: Option<String>) -> Result<Option<String>> {
+    match remote {
+        Some(host) if !host.starts_with('@') && host != "local" => Ok(Some(Config::load()?.canonical_host(&host))),
+        other => Ok(other),
     }
 }
 
 /// Check if abduco is available locally
 fn check_abduco_local() -> Result<()> {
-    match Command::new("which").arg("abduco").output() {
+    match exec::current().output(Command::new("which").arg("abduco")) {
         Ok(output) if output.status.success() => Ok(()),
-        _ => anyhow::bail!(
-            "abduco not found. Please install it:\n\
-            - Arch Linux: sudo pacman -S abduco\n\
-            - Debian/Ubuntu: sudo apt install abduco\n\
-            - macOS: brew install abduco\n\
-            - Or build from source: https://github.com/martanne/abduco"
-        ),
+        _ => Err(error::Error::AbducoMissing {
+            host: "this machine".to_string(),
+            hint: "Please install it:\n\
+                - Arch Linux: sudo pacman -S abduco\n\
+                - Debian/Ubuntu: sudo apt install abduco\n\
+                - macOS: brew install abduco\n\
+                - Or build from source: https://github.com/martanne/abduco"
+                .to_string(),
+        }
+        .into()),
     }
 }
 
 /// Check if abduco is available on remote host using helper script
 fn check_abduco_remote(remote_host: &str) -> Result<()> {
+    if connection::is_offline() {
+        return check_abduco_local();
+    }
+
     // Ensure helper script is uploaded
-    ensure_remote_helper(remote_host)?;
+    timings::time("ssh: helper version check", || ensure_remote_helper(remote_host))
+        .map_err(|e| diagnose_platform(remote_host, e))?;
 
     // Use helper script to check dependencies
-    let output = Command::new("ssh")
-        .arg(remote_host)
-        .arg(format!("bash -lc '{} check-deps'", REMOTE_HELPER_PATH))
-        .output()
-        .context("Failed to check for abduco on remote host")?;
+    let output = timings::time("ssh: abduco check", || {
+        exec::current().output(
+            connection::ssh_command(remote_host)
+                .arg(format!("bash -lc '{} check-deps'", remote_helper_path())),
+        )
+    })
+    .context("Failed to check for abduco on remote host")?;
 
-    if !output.status.success() {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("{}", error_msg.trim());
+    if output.status.code() == Some(connection::SSH_CONNECTION_FAILED) {
+        let error = connection::ssh_unreachable(remote_host, &String::from_utf8_lossy(&output.stderr));
+        return Err(diagnose_platform(remote_host, error.into()));
     }
 
-    debug!("abduco found at: {}", String::from_utf8_lossy(&output.stdout).trim());
+    let records = connection::helper_response("check-deps", &output).map_err(|e| {
+        let error = match e.downcast_ref::<connection::HelperError>() {
+            Some(helper) if helper.code == "abduco_missing" => error::Error::AbducoMissing {
+                host: remote_host.to_string(),
+                hint: helper.hint.clone().unwrap_or_default(),
+            }
+            .into(),
+            _ => e,
+        };
+        diagnose_platform(remote_host, error)
+    })?;
+
+    debug!("abduco found at: {}", connection::find_record(&records, "deps")?.field("abduco")?);
     Ok(())
 }
 
-/// Ensure the wrapper script exists locally
-fn ensure_wrapper_script() -> Result<()> {
-    use std::io::Write;
+/// Explain a failed remote setup when the host is a platform the helper can't run on
+///
+/// Only probed after a failure, so healthy hosts don't pay for the extra round trip.
+fn diagnose_platform(remote_host: &str, error: anyhow::Error) -> anyhow::Error {
+    match connection::probe_platform(remote_host) {
+        Ok(connection::Platform::Unsupported(platform)) => error.context(format!(
+            "{} runs {}, which i3mux does not support: it needs a POSIX shell with abduco. \
+             For WSL, run sshd inside the WSL distribution and use that as the remote.",
+            remote_host, platform
+        )),
+        Ok(connection::Platform::Wsl) => {
+            debug!("{} is a WSL distribution", remote_host);
+            error
+        }
+        Ok(connection::Platform::Unix) | Err(_) => error,
+    }
+}
 
-    let path = std::path::Path::new(WRAPPER_PATH);
+/// Version of the helper script this binary uploads
+fn helper_script_version() -> &'static str {
+    // Extract version from script (look for VERSION="x.x.x")
+    REMOTE_HELPER_SCRIPT
+        .lines()
+        .find(|line| line.contains("VERSION="))
+        .and_then(|line| line.split('"').nth(1))
+        .unwrap_or("unknown")
+}
 
-    // Always write the script (it's cheap and ensures we have latest version)
-    let mut file = std::fs::File::create(path)
-        .context("Failed to create wrapper script")?;
-    file.write_all(WRAPPER_SCRIPT.as_bytes())
-        .context("Failed to write wrapper script")?;
+/// Version of the helper script on a remote host (empty if it has none)
+fn installed_helper_version(remote_host: &str) -> Result<String> {
+    let version_check = exec::current()
+        .output(
+            connection::ssh_command(remote_host)
+                .arg(format!("{} version 2>/dev/null || echo ''", remote_helper_path())),
+        )
+        .context("Failed to check remote helper version")?;
 
-    // Make executable
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = std::fs::metadata(path)?.permissions();
-        perms.set_mode(0o755);
-        std::fs::set_permissions(path, perms)?;
+    if version_check.status.code() == Some(connection::SSH_CONNECTION_FAILED) {
+        return Err(connection::ssh_unreachable(remote_host, &String::from_utf8_lossy(&version_check.stderr)).into());
     }
 
-    Ok(())
+    // Older helpers answer with a bare version number, which never matches
+    Ok(connection::parse_helper_response(&String::from_utf8_lossy(&version_check.stdout))
+        .ok()
+        .and_then(|records| Some(connection::find_record(&records, "version").ok()?.get("version")?.to_string()))
+        .unwrap_or_default())
 }
 
 /// Ensure the helper script is uploaded and executable on a remote host
@@ -286,20 +1240,9 @@ fn ensure_remote_helper(remote_host: &str) -> Result<()> {
     debug!("Ensuring helper script is present on {}", remote_host);
 
     // Check if script exists and has correct version
-    let version_check = Command::new("ssh")
-        .arg(remote_host)
-        .arg(format!("{} version 2>/dev/null || echo ''", REMOTE_HELPER_PATH))
-        .output()
-        .context("Failed to check remote helper version")?;
-
-    let remote_version = String::from_utf8_lossy(&version_check.stdout).trim().to_string();
-
-    // Extract version from script (look for VERSION="x.x.x")
-    let local_version = REMOTE_HELPER_SCRIPT
-        .lines()
-        .find(|line| line.contains("VERSION="))
-        .and_then(|line| line.split('"').nth(1))
-        .unwrap_or("unknown");
+    let exec = exec::current();
+    let remote_version = installed_helper_version(remote_host)?;
+    let local_version = helper_script_version();
 
     if remote_version == local_version {
         debug!("Remote helper already at version {}", local_version);
@@ -308,12 +1251,25 @@ fn ensure_remote_helper(remote_host: &str) -> Result<()> {
 
     debug!("Uploading helper script to remote (version {})", local_version);
 
+    // A full or read-only /tmp would otherwise only show up as a failed upload
+    let helper_path = remote_helper_path();
+    let helper_dir = std::path::Path::new(&helper_path).parent().and_then(|p| p.to_str()).unwrap_or("/tmp");
+    let preflight = exec
+        .output(connection::ssh_command(remote_host).arg(connection::remote_preflight(helper_dir)))
+        .context("Failed to check the remote helper's directory")?;
+    if !preflight.status.success() {
+        if let Some(err) = connection::preflight_error(remote_host, &String::from_utf8_lossy(&preflight.stderr)) {
+            return Err(err.into());
+        }
+    }
+
     // Upload script via stdin
-    let mut upload = Command::new("ssh")
-        .arg(remote_host)
-        .arg(format!("cat > {}", REMOTE_HELPER_PATH))
-        .stdin(std::process::Stdio::piped())
-        .spawn()
+    let mut upload = exec
+        .spawn(
+            connection::ssh_command(remote_host)
+                .arg(format!("cat > {}", helper_path))
+                .stdin(std::process::Stdio::piped()),
+        )
         .context("Failed to start SSH upload")?;
 
     if let Some(mut stdin) = upload.stdin.take() {
@@ -328,10 +1284,8 @@ fn ensure_remote_helper(remote_host: &str) -> Result<()> {
     }
 
     // Make script executable
-    let chmod = Command::new("ssh")
-        .arg(remote_host)
-        .arg(format!("chmod +x {}", REMOTE_HELPER_PATH))
-        .status()
+    let chmod = exec
+        .run(connection::ssh_command(remote_host).arg(format!("chmod +x {}", helper_path)))
         .context("Failed to make helper script executable")?;
 
     if !chmod.success() {
@@ -343,14 +1297,14 @@ fn ensure_remote_helper(remote_host: &str) -> Result<()> {
 }
 
 /// Activate i3mux for current workspace
-fn activate(remote: Option<String>, session_name: Option<String>) -> Result<()> {
+fn activate(remote: Option<String>, session_name: Option<String>, layout: Option<String>) -> Result<()> {
     let backend = WmBackend::connect()?;
     let (ws_name, ws_num) = get_focused_workspace(&backend)?;
 
-    let mut state = LocalState::load()?;
-
     // Validate inputs at CLI boundary
     let remote_host = remote.map(|r| RemoteHost::new(r)).transpose()?;
+    let layout = layout.map(|expr| spec::LayoutExpr::parse(&expr)).transpose().context("Invalid --layout")?;
+    let operation = metrics::Operation::start("activate", remote_host.as_ref().map_or("local", |h| h.as_str()));
 
     let validated_session_name = session_name.map(|name| SessionName::new(name)).transpose()?;
 
@@ -370,79 +1324,476 @@ fn activate(remote: Option<String>, session_name: Option<String>) -> Result<()>
         Some(h) => ("remote", Some(h.as_str().to_string())),
     };
 
+    // A second activate (e.g. a double keypress) waits here until the first
+    // has bound the workspace, then only opens a terminal
+    let binding = lockfile::lock_workspace(&ws_name)?;
+    let mut state = LocalState::load()?;
+    if already_bound(&state, &ws_name, ws_num, host_str.as_deref().unwrap_or("local"))? {
+        drop(binding);
+        match &layout {
+            Some(layout) => open_layout(&backend, &ws_name, ws_num, layout, false)?,
+            None => terminal(None, None, None, None, None)?,
+        }
+        operation.succeeded();
+        return Ok(());
+    }
+
+    let session_name = validated_session_name.map(|n| n.as_str().to_string());
+    let activation = Activation::new(
+        "activate",
+        Activation::host_flags(remote_host.as_ref(), session_name.as_deref()),
+        session_name.clone().unwrap_or_else(|| format!("ws{}", ws_num)),
+    );
+
     state.workspaces.insert(
         ws_name.clone(),
         WorkspaceState {
             session_type: session_type.to_string(),
             host: host_str.clone().unwrap_or_else(|| "local".to_string()),
-            session_name: validated_session_name.map(|n| n.as_str().to_string()),
+            session_name,
             next_socket_id: 1,
             sockets: HashMap::new(),
+            outputs: Vec::new(),
+            forwards: Vec::new(),
+            mounts: Vec::new(),
+            activation: Some(activation),
+            zoomed: None,
+            layout_lock: None,
+            revision: None,
+            original_name: None,
         },
     );
+    label_workspace(&backend, &mut state, &ws_name, ws_num);
 
     state.save()?;
+    drop(binding);
 
-    println!("✓ Workspace {} activated", ws_num);
+    println!("{} Workspace {} activated", style::ok(), ws_num);
     if let Some(host) = &host_str {
         println!("  Remote: {}", host);
     }
 
-    // Launch first terminal
-    terminal(None)?;
+    // Launch first terminal (or the --layout ones)
+    match &layout {
+        Some(layout) => open_layout(&backend, &ws_name, ws_num, layout, true)?,
+        None => terminal(None, None, None, None, None)?,
+    }
+
+    operation.succeeded();
+    Ok(())
+}
+
+/// Activate the current workspace with one terminal per host of a group
+///
+/// The workspace is bound to the group's first host, which is where detach
+/// saves the session; the other terminals are recorded with their own host, so
+/// attach brings the whole cluster back. With `grid`, the terminals are laid
+/// out row by row, in group order, in a roughly square grid.
+fn activate_cluster(group: &str, session_name: Option<String>, grid: bool) -> Result<()> {
+    let group = group.strip_prefix('@').unwrap_or(group);
+    let hosts = Config::load()?.group_hosts(group)?;
+    let first_host = hosts.first().with_context(|| format!("Host group '@{}' is empty", group))?.clone();
+
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+    let operation = metrics::Operation::start("activate", &format!("@{}", group));
+
+    let validated_session_name = session_name.map(SessionName::new).transpose()?;
+
+    // Check every host before binding, so one dead host doesn't leave half a cluster
+    std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+    let checks = fan_out(&hosts, |host| if host == "local" { check_abduco_local() } else { check_abduco_remote(host) });
+    let failures: Vec<String> = hosts
+        .iter()
+        .zip(checks)
+        .filter_map(|(host, check)| check.err().map(|e| format!("  {}: {}", host, format!("{:#}", e).trim_end())))
+        .collect();
+    if !failures.is_empty() {
+        anyhow::bail!("{} of {} hosts in @{} are not usable:\n{}", failures.len(), hosts.len(), group, failures.join("\n"));
+    }
+
+    let session_name = validated_session_name.map(|n| n.as_str().to_string());
+    let mut flags = vec!["--cluster".to_string(), format!("@{}", group)];
+    if grid {
+        flags.push("--grid".to_string());
+    }
+    flags.extend(Activation::host_flags(None, session_name.as_deref()));
+    let activation = Activation::new(
+        "activate",
+        flags,
+        session_name.clone().unwrap_or_else(|| format!("ws{}", ws_num)),
+    );
+
+    let binding = lockfile::lock_workspace(&ws_name)?;
+    let mut state = LocalState::load()?;
+    if already_bound(&state, &ws_name, ws_num, &first_host)? {
+        drop(binding);
+        terminal(None, None, None, None, None)?;
+        operation.succeeded();
+        return Ok(());
+    }
+    state.workspaces.insert(
+        ws_name.clone(),
+        WorkspaceState {
+            session_type: if first_host == "local" { "local" } else { "remote" }.to_string(),
+            host: first_host,
+            session_name,
+            next_socket_id: 1,
+            sockets: HashMap::new(),
+            outputs: Vec::new(),
+            forwards: Vec::new(),
+            mounts: Vec::new(),
+            activation: Some(activation),
+            zoomed: None,
+            layout_lock: None,
+            revision: None,
+            original_name: None,
+        },
+    );
+    label_workspace(&backend, &mut state, &ws_name, ws_num);
+    state.save()?;
+    drop(binding);
+
+    println!("{} Workspace {} activated", style::ok(), ws_num);
+    println!("  Cluster: @{} ({} hosts)", group, hosts.len());
+
+    let launch = |i: usize| launch_i3mux_terminal(&ws_name, ws_num, backend.wm_type(), None, Some(&hosts[i]), None, None);
+    if grid {
+        open_grid(&backend, hosts.len(), launch)?;
+    } else {
+        for i in 0..hosts.len() {
+            launch(i)?;
+        }
+    }
+
+    operation.succeeded();
+    Ok(())
+}
+
+/// Open `count` terminals in the current workspace's session, in a grid
+fn grid(count: usize) -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+    if !LocalState::bound_workspaces()?.contains(&ws_name) {
+        return Err(error::Error::NotBound.into());
+    }
+    open_grid(&backend, count, |_| launch_i3mux_terminal(&ws_name, ws_num, backend.wm_type(), None, None, None, None))
+}
+
+/// Open the terminals of a layout expression in the current workspace's session
+fn grid_layout(expr: &str) -> Result<()> {
+    let layout = spec::LayoutExpr::parse(expr).context("Invalid --layout")?;
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+    if !LocalState::bound_workspaces()?.contains(&ws_name) {
+        return Err(error::Error::NotBound.into());
+    }
+    open_layout(&backend, &ws_name, ws_num, &layout, false)
+}
+
+/// Open new terminals of a bound workspace arranged as `expr`, split sizes included
+///
+/// The terminals get the workspace's next sockets and are put in place the
+/// way attach restores a session: `alone` when the workspace has no other
+/// windows, next to the focused one otherwise. Their names in the
+/// expression become the window titles: in the title bar (in place of the
+/// marker) or, with `title_mode = "hidden"`, as the terminals' first title.
+fn open_layout(backend: &WmBackend, ws_name: &str, ws_num: i32, expr: &spec::LayoutExpr, alone: bool) -> Result<()> {
+    let config = Config::load()?;
+    let binding = lockfile::lock_workspace(ws_name)?;
+    let mut state = LocalState::load()?;
+    let ws_state = state.workspaces.get_mut(ws_name).ok_or(error::Error::NotBound)?;
+    let mut titles = HashMap::new();
+    let layout = expr.to_layout(&mut |name| {
+        let socket = format!("{}-{:03}", instance::socket_prefix(ws_name), ws_state.next_socket_id);
+        ws_state.next_socket_id += 1;
+        ws_state.sockets.insert(
+            socket.clone(),
+            SocketInfo { socket_id: socket.clone(), host: None, attach: None, target: None, placeholder: false },
+        );
+        titles.insert(socket.clone(), name.to_string());
+        Layout::Terminal { socket, percent: None, command: None, host: None, attach: None, target: None, title: None }
+    });
+    layout.check().context("Invalid --layout")?;
+    let host = ws_state.host.clone();
+    let session_name = ws_state.session_name.clone();
+    state.save()?;
+    drop(binding);
+
+    if host != "local" {
+        std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+        check_abduco_remote(&host)?;
+    }
+    let ctx = RestoreContext {
+        backend,
+        config: &config,
+        host: &host,
+        session_name: session_name.as_deref(),
+        workspace_name: ws_name,
+        workspace_num: ws_num,
+        restore_commands: HashMap::new(),
+        titles,
+        lazy: false,
+        unreachable: HashMap::new(),
+        last_container: Cell::new(None),
+    };
+    restore_layout_structure(&ctx, if alone { layout.restore_plan() } else { layout.open_plan() })?;
+    apply_sizes(backend, &layout, &host);
+    Ok(())
+}
 
+/// Launch `count` terminals (the i-th by `launch(i)`) row by row into a
+/// roughly square grid
+///
+/// The first row opens side by side; each column is then split vertically
+/// under its top terminal and filled from there.
+fn open_grid(backend: &WmBackend, count: usize, mut launch: impl FnMut(usize) -> Result<u64>) -> Result<()> {
+    let columns = grid_columns(count);
+    backend.run_command("split h")?;
+    let mut tops = Vec::new();
+    for i in 0..columns.min(count) {
+        tops.push(launch(i)?);
+    }
+    for (column, top) in tops.into_iter().enumerate() {
+        let below: Vec<usize> = (columns + column..count).step_by(columns).collect();
+        if below.is_empty() {
+            continue;
+        }
+        backend.run_command_on_container(top, "focus; split v")?;
+        for i in below {
+            launch(i)?;
+        }
+    }
     Ok(())
 }
 
-/// Detach current workspace and save session
-fn detach(session_name: Option<String>) -> Result<()> {
+/// Columns of a grid of `count` cells: the smallest square that fits them
+fn grid_columns(count: usize) -> usize {
+    (1..).find(|c| c * c >= count).unwrap_or(1)
+}
+
+/// Bind a workspace of ordinary terminals, putting an i3mux terminal in place of each
+///
+/// Each replacement opens next to the terminal it replaces (which is focused
+/// first), so closing the original leaves it in the same spot of the layout.
+/// Whatever ran in the original is not carried over.
+fn adopt_workspace(
+    remote: Option<String>,
+    session_name: Option<String>,
+    classes: Vec<String>,
+    keep_originals: bool,
+    bind_only: bool,
+) -> Result<()> {
     let backend = WmBackend::connect()?;
     let (ws_name, ws_num) = get_focused_workspace(&backend)?;
 
+    let remote_host = remote.map(RemoteHost::new).transpose()?;
+    let session_name = session_name.map(SessionName::new).transpose()?.map(|n| n.as_str().to_string());
+    let host = remote_host.as_ref().map_or("local", |h| h.as_str()).to_string();
+
+    // Pick the terminals before binding, so a workspace with none stays as it was
+    let windows = if bind_only {
+        Vec::new()
+    } else {
+        let classes = if classes.is_empty() {
+            let terminal = get_terminal_command(backend.wm_type());
+            vec![std::path::Path::new(&terminal).file_name().and_then(|n| n.to_str()).unwrap_or(&terminal).to_string()]
+        } else {
+            classes
+        };
+        let plain = window::find_plain_windows_in_workspace(ws_num, &backend)?;
+        let (terminals, others): (Vec<_>, Vec<_>) = plain.into_iter().partition(|w| w.matches(&classes));
+        if terminals.is_empty() {
+            let mut seen: Vec<&str> = others.iter().filter_map(|w| w.class.as_deref()).collect();
+            seen.sort_unstable();
+            seen.dedup();
+            anyhow::bail!(
+                "No {} windows in workspace {} (classes here: {}). Pick them with --class, or use --bind-only",
+                classes.join("/"),
+                ws_num,
+                if seen.is_empty() { "none".to_string() } else { seen.join(", ") }
+            );
+        }
+        terminals
+    };
+
+    match &remote_host {
+        None => check_abduco_local()?,
+        Some(host) => {
+            std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+            check_abduco_remote(host.as_str())?;
+        }
+    }
+
+    let mut flags = Activation::host_flags(remote_host.as_ref(), session_name.as_deref());
+    if bind_only {
+        flags.push("--bind-only".to_string());
+    }
+    let activation = Activation::new(
+        "adopt-workspace",
+        flags,
+        session_name.clone().unwrap_or_else(|| format!("ws{}", ws_num)),
+    );
+
+    let binding = lockfile::lock_workspace(&ws_name)?;
+    let mut state = LocalState::load()?;
+    if let Some(existing) = state.workspaces.get(&ws_name) {
+        anyhow::bail!("Workspace {} is already bound to {}", ws_num, existing.host);
+    }
+    state.workspaces.insert(
+        ws_name.clone(),
+        WorkspaceState {
+            session_type: if remote_host.is_some() { "remote" } else { "local" }.to_string(),
+            host: host.clone(),
+            session_name,
+            next_socket_id: 1,
+            sockets: HashMap::new(),
+            outputs: Vec::new(),
+            forwards: Vec::new(),
+            mounts: Vec::new(),
+            activation: Some(activation),
+            zoomed: None,
+            layout_lock: None,
+            revision: None,
+            original_name: None,
+        },
+    );
+    label_workspace(&backend, &mut state, &ws_name, ws_num);
+    state.save()?;
+    drop(binding);
+
+    println!("{} Workspace {} bound to {}", style::ok(), ws_num, host);
+
+    for original in &windows {
+        backend.run_command_on_container(original.window_id, "focus")?;
+        launch_i3mux_terminal(&ws_name, ws_num, backend.wm_type(), None, None, None, None)?;
+        if !keep_originals {
+            backend.run_command_on_container(original.window_id, "kill")?;
+        }
+    }
+    if !windows.is_empty() {
+        println!("  Replaced {} terminals", windows.len());
+    }
+    Ok(())
+}
+
+/// Whether an activate finds the workspace already bound to `host` (and should
+/// only open a terminal); bound to another host, it is an error
+fn already_bound(state: &LocalState, ws_name: &str, ws_num: i32, host: &str) -> Result<bool> {
+    match state.workspaces.get(ws_name) {
+        None => Ok(false),
+        Some(ws_state) if ws_state.host == host => {
+            println!("Workspace {} is already activated on {}", ws_num, style::host(host));
+            Ok(true)
+        }
+        Some(ws_state) => anyhow::bail!(
+            "Workspace {} is already bound to {}. Detach it first.",
+            ws_num,
+            ws_state.host
+        ),
+    }
+}
+
+/// Detach the current workspace (or workspace `workspace`) and save its session
+fn detach(session_name: Option<String>, on_conflict: Option<ConflictPolicy>, workspace: Option<i32>) -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = match workspace {
+        Some(num) => (num.to_string(), num),
+        None => get_focused_workspace(&backend)?,
+    };
+
     let mut state = LocalState::load()?;
 
     let ws_state = state
         .workspaces
         .get(&ws_name)
-        .context("Workspace not i3mux-bound")?
+        .ok_or(error::Error::NotBound)?
         .clone();
-
-    if ws_state.session_type == "local" {
-        anyhow::bail!("Cannot detach local sessions (use remote sessions for detach/attach)");
-    }
+    let operation = metrics::Operation::start("detach", &ws_state.host);
 
     // Capture layout using marks (most reliable identification method)
-    let layout = Layout::capture_from_workspace_num(ws_num, &backend)?
+    let mut layout = Layout::capture_from_workspace_num(ws_num, &backend)?
         .context("No i3mux terminals found in workspace")?;
+    layout.set_attach_commands(&ws_state.attach_commands());
+    layout.set_targets(&ws_state.targets());
 
-    // Determine session name and validate at boundary
+    // Determine session name and validate at boundary; the name intended at
+    // activation wins over the current workspace number (it may have moved)
     let final_session_name_str = session_name
-        .or(ws_state.session_name)
+        .or(ws_state.session_name.clone())
+        .or(ws_state.activation.as_ref().map(|a| a.default_session.clone()))
         .unwrap_or_else(|| format!("ws{}", ws_num));
     let final_session_name = SessionName::new(final_session_name_str)?;
 
-    // Parse remote host (if "local", use None)
-    let remote_host = if ws_state.host == "local" {
-        None
-    } else {
-        Some(RemoteHost::new(ws_state.host.clone())?)
-    };
+    // The session is stored on the workspace's host, even if terminals run elsewhere
+    let host_conn = host_connection(&ws_state.host)?;
+
+    // Record what each terminal is running, so attach can restart it if the session died
+    let mut commands = HashMap::new();
+    for (host, sockets) in layout.get_sockets_by_host(&ws_state.host) {
+        let processes = timings::time("ssh: process info", || {
+            host_connection(&host).and_then(|conn| conn.process_info(&sockets))
+        });
+        match processes {
+            Ok(processes) => commands.extend(
+                processes
+                    .into_iter()
+                    .filter(|p| p.busy)
+                    .map(|p| (p.socket, p.command)),
+            ),
+            Err(e) => eprintln!("Warning: could not record running commands on {}: {:#}", host, e),
+        }
+    }
+    layout.set_commands(&commands);
 
     // Create remote session (internal code uses validated inputs)
-    let remote_session = RemoteSession::new(
+    let mut remote_session = RemoteSession::new(
         final_session_name.as_str().to_string(),
         ws_name.clone(),
         ws_state.host.clone(),
         layout,
     )?;
-
-    // Save to remote
-    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
-    remote_session.save_to_remote(host_conn.as_ref())?;
-
-    println!("✓ Session '{}' saved to {}", final_session_name, ws_state.host);
+    remote_session.outputs = ws_state.outputs.clone();
+    remote_session.forwards = ws_state.forwards.clone();
+    remote_session.mounts = ws_state.mounts.clone();
+
+    // Save to remote; an unreachable host gets the session once it's back
+    let on_conflict = match on_conflict {
+        Some(policy) => policy,
+        None => Config::load()?.on_conflict,
+    };
+    let saved = timings::time("session: save", || {
+        save_detached(host_conn.as_ref(), &mut remote_session, &ws_state, on_conflict)
+    });
+    match saved {
+        Ok(()) => {
+            println!("{} Session '{}' saved to {}", style::ok(), final_session_name, ws_state.host);
+            let queue = pending::Queue::open()?;
+            queue.remove(&ws_state.host, final_session_name.as_str())?;
+            sync_pending(&ws_state.host, host_conn.as_ref());
+        }
+        Err(e) if ws_state.host != "local" && !matches!(e.downcast_ref::<error::Error>(), Some(error::Error::SessionChanged { .. })) => {
+            eprintln!("Warning: could not save session to {}: {:#}", ws_state.host, e);
+            remote_session.revision = ws_state.revision.unwrap_or(0);
+            let path = pending::Queue::open()?.push(&remote_session)?;
+            println!(
+                "{} Session '{}' queued in {} until {} is reachable again",
+                style::ok(),
+                final_session_name,
+                path.display(),
+                ws_state.host
+            );
+        }
+        Err(e) => return Err(e),
+    }
     println!("  Layout captured: {} terminals", remote_session.layout.get_sockets().len());
 
+    // Unbind the workspace before closing terminals, so their cleanup hooks
+    // don't prune the layout that was just saved
+    state.workspaces.remove(&ws_name);
+    state.save()?;
+    unlabel_workspace(&backend, ws_num, &ws_state);
+
     // Close all i3mux terminals (identified by marks)
     window::kill_i3mux_windows_in_workspace(&backend, ws_num)?;
 
@@ -457,44 +1808,255 @@ fn detach(session_name: Option<String>) -> Result<()> {
     // Explicitly release lock on remote
     let _ = host_conn.release_lock(final_session_name.as_str());
 
-    // Remove from local state
-    state.workspaces.remove(&ws_name);
+    for forward in &ws_state.forwards {
+        if let Err(e) = host_conn.cancel_forward(forward) {
+            debug!("Could not tear down {}: {:#}", forward, e);
+        }
+    }
+
+    for mount in &ws_state.mounts {
+        if let Err(e) = host_conn.unmount(mount) {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
+
     state.save()?;
 
-    println!("✓ Workspace {} detached", ws_num);
+    println!("{} Workspace {} detached", style::ok(), ws_num);
 
+    operation.succeeded();
     Ok(())
 }
 
-/// Attach to a saved session
-fn attach(
-    remote: Option<String>,
-    session_name: Option<String>,
-    force: bool,
+/// Save a detached workspace's session over the host's copy
+///
+/// The copy must still be at the revision this workspace last saved; if it
+/// was saved from elsewhere since, `on_conflict` decides. Workspaces that
+/// weren't attached from a saved session have nothing to compare with and
+/// just continue the copy's revisions.
+fn save_detached(
+    host_conn: &dyn connection::Connection,
+    session: &mut RemoteSession,
+    ws_state: &WorkspaceState,
+    on_conflict: ConflictPolicy,
 ) -> Result<()> {
-    // Validate remote host at CLI boundary
-    let remote_host = remote.map(|r| RemoteHost::new(r)).transpose()?;
+    use std::io::IsTerminal;
 
-    // Check abduco availability
-    match &remote_host {
-        None => check_abduco_local()?,
-        Some(host) => check_abduco_remote(host.as_str())?,
+    let current = RemoteSession::find_on_remote(host_conn, &session.name)?;
+    session.revision = current.as_ref().map_or(0, |c| c.revision);
+    let Some(current) = current.filter(|c| ws_state.revision.is_some_and(|r| r != c.revision)) else {
+        return session.save_to_remote(host_conn);
+    };
+
+    let saved_by = current.saved_by.clone().unwrap_or_else(|| "another machine".to_string());
+    let policy = match on_conflict {
+        ConflictPolicy::Ask if std::io::stdin().is_terminal() => {
+            println!(
+                "Session '{}' on {} was saved from {} since this workspace attached it ({} terminal(s) there, {} here).",
+                session.name,
+                ws_state.host,
+                saved_by,
+                current.layout.get_sockets().len(),
+                session.layout.get_sockets().len()
+            );
+            loop {
+                match prompt("[m]erge, [o]verwrite or [a]bort?")?.as_str() {
+                    "m" | "merge" => break ConflictPolicy::Merge,
+                    "o" | "overwrite" => break ConflictPolicy::Overwrite,
+                    "a" | "abort" | "" => break ConflictPolicy::Abort,
+                    _ => continue,
+                }
+            }
+        }
+        ConflictPolicy::Ask => ConflictPolicy::Abort,
+        policy => policy,
+    };
+
+    match policy {
+        ConflictPolicy::Merge => session.merge_from(&current),
+        ConflictPolicy::Overwrite => {}
+        ConflictPolicy::Ask | ConflictPolicy::Abort => {
+            return Err(error::Error::SessionChanged {
+                session: session.name.clone(),
+                host: ws_state.host.clone(),
+                saved_by,
+            }
+            .into())
+        }
     }
+    session.save_to_remote(host_conn)
+}
 
-    // Ensure SSH control socket directory exists
-    if remote_host.is_some() {
-        std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+/// Move the binding of workspace `from` to workspace `to`
+///
+/// Running terminals keep their sockets (named after `from`) and are found
+/// by socket when they exit; new terminals are named after `to`. Background
+/// watches started for `from` end and are started again for `to`.
+fn rebind(from: i32, to: i32) -> Result<()> {
+    let (from_name, to_name) = (from.to_string(), to.to_string());
+    if from == to {
+        anyhow::bail!("--from and --to are the same workspace");
     }
+    // Always lock in the same order, so two rebinds can't wait on each other
+    let _bindings = if from < to {
+        (lockfile::lock_workspace(&from_name)?, lockfile::lock_workspace(&to_name)?)
+    } else {
+        (lockfile::lock_workspace(&to_name)?, lockfile::lock_workspace(&from_name)?)
+    };
 
-    // Create connection (None = local, Some = remote)
-    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
+    let mut state = LocalState::load()?;
+    if let Some(existing) = state.workspaces.get(&to_name) {
+        anyhow::bail!("Workspace {} is already bound to {}", to, existing.host);
+    }
+    let ws_state = state
+        .workspaces
+        .remove(&from_name)
+        .with_context(|| format!("Workspace {} is not bound to i3mux", from))?;
+    state.workspaces.insert(to_name.clone(), ws_state.clone());
+    // Label the workspace under its new number; original_name keeps the name
+    // from before any label. Without a WM only state.json changes.
+    if let Ok(backend) = WmBackend::connect() {
+        label_workspace(&backend, &mut state, &to_name, to);
+    }
+    state.save()?;
 
-    // List available sessions
-    let sessions = RemoteSession::list_remote_sessions(host_conn.as_ref())?;
+    if ws_state.layout_lock.is_some() {
+        start_layout_watch(&to_name);
+    }
+    if let (true, Some(session_name)) = (ws_state.host != "local", &ws_state.session_name) {
+        let lock = workspace_connection(&ws_state)
+            .and_then(|conn| RemoteSession::load_from_remote(conn.as_ref(), session_name))
+            .ok()
+            .and_then(|session| session.lock);
+        if let Some(lock) = lock.filter(|lock| lock.locked_by == connection::hostname()) {
+            start_lock_watch(&to_name, &ws_state.host, session_name, &lock.nonce);
+        }
+    }
 
-    let host_display = remote_host.as_ref()
-        .map(|h| h.as_str().to_string())
-        .unwrap_or_else(|| LOCAL_DISPLAY.to_string());
+    println!("{} Workspace {} is now bound as workspace {}", style::ok(), from, to);
+    Ok(())
+}
+
+/// Forget the current workspace's binding without closing anything
+///
+/// Terminals keep running their sessions; they just stop being i3mux
+/// terminals. The saved session (if any) stays on its host, unlocked.
+fn unbind(keep_marks: bool) -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let binding = lockfile::lock_workspace(&ws_name)?;
+    let mut state = LocalState::load()?;
+    let ws_state = state.workspaces.remove(&ws_name).ok_or(error::Error::NotBound)?;
+    state.save()?;
+    drop(binding);
+    unlabel_workspace(&backend, ws_num, &ws_state);
+
+    if let Some(session) = &ws_state.session_name {
+        match host_connection(&ws_state.host) {
+            Ok(conn) => {
+                if let Err(e) = conn.release_lock(session) {
+                    eprintln!("Warning: could not release the lock on '{}': {:#}", session, e);
+                }
+            }
+            Err(e) => eprintln!("Warning: could not release the lock on '{}': {:#}", session, e),
+        }
+    }
+
+    let windows = window::find_i3mux_windows_in_workspace(ws_num, &backend)?;
+    if !keep_marks {
+        for window in &windows {
+            if let Err(e) = window.remove_mark(&backend) {
+                debug!("Could not unmark {}: {:#}", window.mark(), e);
+            }
+        }
+    }
+
+    println!("{} Workspace {} unbound ({} terminals left open)", style::ok(), ws_num, windows.len());
+    if !ws_state.forwards.is_empty() || !ws_state.mounts.is_empty() {
+        println!("  Port forwards and mounts stay up until their connections end");
+    }
+    Ok(())
+}
+
+/// How long `attach --here-then-close-origin` waits for the other machine to detach
+const PULL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Have the machine holding `session`'s lock detach it, then reload what it saved
+///
+/// Its lock watch (see watch.rs) notices the request and detaches the
+/// workspace; the lock is free once its lock holder process is gone. Sessions
+/// that aren't held by another machine are left as they are.
+fn pull_session(conn: &dyn connection::Connection, session: &mut RemoteSession) -> Result<()> {
+    let Some(lock) = session.lock.clone() else {
+        return Ok(());
+    };
+    if lock.locked_by == connection::hostname() || !conn.is_lock_valid(&lock)? {
+        return Ok(());
+    }
+
+    println!("Asking {} to detach session '{}'...", lock.locked_by, session.name);
+    let revision = session.revision;
+    session.request_detach(conn)?;
+    let started = std::time::Instant::now();
+    while conn.is_lock_valid(&lock)? {
+        if started.elapsed() > PULL_TIMEOUT {
+            anyhow::bail!(
+                "{} did not detach session '{}' within {}s (is it asleep, or running an i3mux without \
+                 --here-then-close-origin support?). attach --force takes the session over without its latest layout",
+                lock.locked_by,
+                session.name,
+                PULL_TIMEOUT.as_secs()
+            );
+        }
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+
+    *session = RemoteSession::load_from_remote(conn, &session.name)?;
+    if session.revision == revision {
+        eprintln!("Warning: {} released the lock without saving; attaching the last saved layout", lock.locked_by);
+    } else {
+        println!("{} {} detached session '{}'", style::ok(), lock.locked_by, session.name);
+    }
+    Ok(())
+}
+
+/// Attach to a saved session
+fn attach(
+    remote: Option<String>,
+    session_name: Option<String>,
+    force: bool,
+    here_then_close_origin: bool,
+    reflow_policy: Option<ReflowPolicy>,
+    lazy: bool,
+) -> Result<()> {
+    // Validate remote host at CLI boundary
+    let remote_host = remote.map(|r| RemoteHost::new(r)).transpose()?;
+    let operation = metrics::Operation::start("attach", remote_host.as_ref().map_or("local", |h| h.as_str()));
+
+    // Check abduco availability
+    match &remote_host {
+        None => check_abduco_local()?,
+        Some(host) => check_abduco_remote(host.as_str())?,
+    }
+
+    // Ensure SSH control socket directory exists
+    if remote_host.is_some() {
+        std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+    }
+
+    // Create connection (None = local, Some = remote)
+    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
+    if let Some(host) = &remote_host {
+        sync_pending(host.as_str(), host_conn.as_ref());
+    }
+
+    // List available sessions
+    let sessions = RemoteSession::list_remote_sessions(host_conn.as_ref())?;
+
+    let host_display = remote_host.as_ref()
+        .map(|h| h.as_str().to_string())
+        .unwrap_or_else(|| style::local().to_string());
 
     if sessions.is_empty() {
         anyhow::bail!("No sessions found on {}", host_display);
@@ -503,47 +2065,139 @@ fn attach(
     // Determine which session to attach
     let final_session_name_str = if let Some(name) = session_name {
         if !sessions.contains(&name) {
-            anyhow::bail!("Session '{}' not found on {}", name, host_display);
+            let host = remote_host.as_ref().map_or("local", |h| h.as_str()).to_string();
+            return Err(error::Error::SessionNotFound { session: name, host }.into());
         }
         name
     } else if sessions.len() == 1 {
         sessions[0].clone()
     } else {
-        // Multiple sessions, return exit code 2 for rofi integration
-        eprintln!("Multiple sessions available:");
-        for s in &sessions {
-            eprintln!("  - {}", s);
-        }
-        eprintln!("\nSpecify session with -s/--session");
-        std::process::exit(2);
+        // Multiple sessions: a distinct exit code for rofi integration
+        let host = remote_host.as_ref().map_or("local", |h| h.as_str()).to_string();
+        return Err(error::Error::AmbiguousSession { host, sessions }.into());
     };
 
     // Validate session name at CLI boundary
     let final_session_name = SessionName::new(final_session_name_str)?;
 
     // Load session
-    let mut session = RemoteSession::load_from_remote(host_conn.as_ref(), final_session_name.as_str())?;
+    let mut session = timings::time("session: load", || {
+        RemoteSession::load_from_remote(host_conn.as_ref(), final_session_name.as_str())
+    })?;
+    if here_then_close_origin {
+        pull_session(host_conn.as_ref(), &mut session)?;
+    }
 
     // Acquire lock
-    let (lock, lock_holder) = host_conn.acquire_lock(final_session_name.as_str(), force)?;
+    let (lock, lock_holder) =
+        timings::time("lock: acquire", || host_conn.acquire_lock(final_session_name.as_str(), force))?;
     session.lock = Some(lock.clone());
-    session.save_to_remote(host_conn.as_ref())?;
+    timings::time("session: save", || session.save_to_remote(host_conn.as_ref()))?;
+    hooks::emit(hooks::Event::LockAcquired, final_session_name.as_str());
 
-    println!("✓ Lock acquired for session '{}'", final_session_name);
+    println!("{} Lock acquired for session '{}'", style::ok(), final_session_name);
 
     // Check workspace doesn't have existing i3mux terminals (non-i3mux windows are fine)
     let backend = WmBackend::connect()?;
     let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+    // Held until the workspace is bound (`attach --lazy` terminals wait for it)
+    let _binding = lockfile::lock_workspace(&ws_name)?;
 
     if window::workspace_has_i3mux_windows(ws_num, &backend)? {
         anyhow::bail!("Workspace {} already has i3mux terminals. Detach or clear them first.", ws_num);
     }
 
+    if !session.outputs.is_empty() {
+        move_to_pinned_output(&backend, &ws_name, &session.outputs)?;
+    }
+
     // Restore layout and launch terminals
-    restore_layout(&backend, &session, &ws_name, &host_display)?;
+    let config = Config::load()?;
+
+    let reflow_policy = reflow_policy.unwrap_or(config.reflow.policy);
+    if reflow_policy != ReflowPolicy::Keep {
+        let rect = focused_workspace_rect(&backend)?;
+        session.layout = reflow::reflow(
+            &session.layout,
+            reflow_policy,
+            &config.reflow,
+            rect.width as f64,
+            rect.height as f64,
+            backend.get_gaps(),
+        );
+    }
+
+    // Terminals on other hosts than the session's need abduco and the helper there too
+    let primary_host = remote_host.as_ref().map(|h| h.as_str()).unwrap_or("local");
+    let terminal_hosts = session.layout.get_terminal_hosts(primary_host);
+    let mut other_hosts: Vec<&String> = terminal_hosts
+        .values()
+        .filter(|h| h.as_str() != primary_host && h.as_str() != "local")
+        .collect();
+    other_hosts.sort();
+    other_hosts.dedup();
+    // A host that is down gets placeholders, so the rest of the layout keeps its shape
+    let mut unreachable = HashMap::new();
+    for host in other_hosts {
+        RemoteHost::new(host.as_str())?;
+        std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+        if let Err(e) = check_abduco_remote(host) {
+            eprintln!("Warning: {:#}", e);
+            unreachable.insert(host.clone(), format!("{:#}", e));
+        }
+    }
+
+    // Pods may have been replaced and containers restarted since detach
+    let mut targets = session.layout.get_targets();
+    if !targets.is_empty() {
+        let mut attach_commands = session.layout.get_attach_commands();
+        for (socket, target) in targets.iter_mut() {
+            match target.resolve() {
+                Ok(command) => {
+                    attach_commands.insert(socket.clone(), command);
+                }
+                Err(e) => eprintln!("Warning: {:#}", e),
+            }
+        }
+        session.layout.set_attach_commands(&attach_commands);
+        session.layout.set_targets(&targets);
+    }
+
+    let restore_commands = timings::time("ssh: process info", || {
+        restorable_commands(&config, primary_host, &session.layout, &unreachable, session.fresh)
+    });
+    let restore_ctx = RestoreContext {
+        backend: &backend,
+        config: &config,
+        host: primary_host,
+        session_name: Some(final_session_name.as_str()),
+        workspace_name: &ws_name,
+        workspace_num: ws_num,
+        restore_commands,
+        titles: session.layout.get_titles(),
+        lazy,
+        unreachable: unreachable.clone(),
+        last_container: Cell::new(None),
+    };
+    restore_layout(&restore_ctx, &session)?;
+
+    for forward in &session.forwards {
+        match host_conn.add_forward(forward) {
+            Ok(()) => println!("  Forwarding {}", forward),
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+
+    for mount in &session.mounts {
+        match host_conn.mount(mount) {
+            Ok(()) => println!("  Mounted {}", mount),
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
 
     // Update local state
     let mut state = LocalState::load()?;
+    let attach_commands = session.layout.get_attach_commands();
     let (session_type, host_str) = match &remote_host {
         None => ("local", "local".to_string()),
         Some(h) => ("remote", h.as_str().to_string()),
@@ -556,14 +2210,31 @@ fn attach(
             host: host_str.clone(),
             session_name: Some(final_session_name.as_str().to_string()),
             next_socket_id: session.layout.get_sockets().len() as u32 + 1,
-            sockets: session
-                .layout
-                .get_sockets()
+            sockets: terminal_hosts
                 .into_iter()
-                .map(|s| (s.clone(), SocketInfo { socket_id: s }))
+                .map(|(s, host)| {
+                    let host = (host != host_str).then_some(host);
+                    let attach = attach_commands.get(&s).cloned();
+                    let target = targets.get(&s).cloned();
+                    let placeholder = unreachable.contains_key(host.as_deref().unwrap_or(&host_str));
+                    (s.clone(), SocketInfo { socket_id: s, host, attach, target, placeholder })
+                })
                 .collect(),
+            outputs: session.outputs.clone(),
+            forwards: session.forwards.clone(),
+            mounts: session.mounts.clone(),
+            activation: Some(Activation::new(
+                "attach",
+                Activation::host_flags(remote_host.as_ref(), Some(final_session_name.as_str())),
+                final_session_name.as_str().to_string(),
+            )),
+            zoomed: None,
+            layout_lock: None,
+            revision: Some(session.revision),
+            original_name: None,
         },
     );
+    label_workspace(&backend, &mut state, &ws_name, ws_num);
 
     // Store lock holder process if present
     if let Some(lock_process) = lock_holder {
@@ -572,29 +2243,104 @@ fn attach(
     }
 
     state.save()?;
+    start_lock_watch(&ws_name, &host_str, final_session_name.as_str(), &lock.nonce);
 
-    println!("✓ Attached to session '{}' in workspace {}", final_session_name, ws_num);
+    println!("{} Attached to session '{}' in workspace {}", style::ok(), final_session_name, ws_num);
 
+    operation.succeeded();
     Ok(())
 }
 
+/// Commands to re-run for terminals whose abduco session no longer exists
+///
+/// Only commands recorded at detach whose program is listed in `restore_programs`
+/// qualify, except in a `fresh` session created from a spec, whose commands
+/// were all asked for. Failing to query the host just means nothing is restarted.
+fn restorable_commands(
+    config: &Config,
+    default_host: &str,
+    layout: &Layout,
+    unreachable: &HashMap<String, String>,
+    fresh: bool,
+) -> HashMap<String, String> {
+    let mut commands = layout.get_commands();
+    if !fresh {
+        commands.retain(|_, command| config.may_restore(command));
+    }
+    if commands.is_empty() {
+        return commands;
+    }
+
+    let mut gone = Vec::new();
+    for (host, sockets) in layout.get_sockets_by_host(default_host) {
+        let sockets: Vec<String> = sockets.into_iter().filter(|s| commands.contains_key(s)).collect();
+        if sockets.is_empty() || unreachable.contains_key(&host) {
+            continue;
+        }
+        match host_connection(&host).and_then(|conn| conn.list_sockets(None)) {
+            Ok(listed) => {
+                let running: Vec<String> = listed.into_iter().filter(|s| s.is_running()).map(|s| s.name).collect();
+                gone.extend(sockets.into_iter().filter(|s| !running.contains(s)));
+            }
+            Err(e) => debug!("Could not check terminal sessions on {}, not restarting commands: {:#}", host, e),
+        }
+    }
+
+    commands.retain(|socket, _| gone.contains(socket));
+    for (socket, command) in &commands {
+        println!("  {} '{}' in {}", if fresh { "Starting" } else { "Restarting" }, command, socket);
+    }
+    commands
+}
+
 /// List sessions on remote
 fn list_sessions(remote: Option<String>) -> Result<()> {
+    if let Some(group) = remote.as_deref().and_then(|r| r.strip_prefix('@')) {
+        return list_group_sessions(group);
+    }
+
     // Validate remote host at CLI boundary
     let remote_host = remote.map(|r| RemoteHost::new(r)).transpose()?;
     let host_display = remote_host.as_ref()
         .map(|h| h.as_str().to_string())
-        .unwrap_or_else(|| LOCAL_DISPLAY.to_string());
+        .unwrap_or_else(|| style::local().to_string());
 
-    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
-    let sessions = RemoteSession::list_remote_sessions(host_conn.as_ref())?;
-
-    if sessions.is_empty() {
+    let lines = session_lines(remote_host.as_ref().map(|h| h.as_str()))?;
+    if lines.is_empty() {
         println!("No sessions on {}", host_display);
         return Ok(());
     }
 
     println!("Sessions on {}:\n", host_display);
+    for line in lines {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+/// Report on a host's sessions, failing if anything needs attention
+fn health(remote: Option<String>) -> Result<()> {
+    let remote_host = remote.map(RemoteHost::new).transpose()?;
+    let report = health::run(remote_host.as_ref().map(|h| h.as_str()))?;
+    print!("{}", report);
+
+    match report.problems() {
+        0 => println!("\nNo problems found"),
+        problems => anyhow::bail!("{} problem(s) found on {}", problems, report.host),
+    }
+    Ok(())
+}
+
+/// One line per saved session on a host (None = local): name, terminal count, lock
+fn session_lines(remote: Option<&str>) -> Result<Vec<String>> {
+    let host_conn = create_connection(remote)?;
+    if let Some(host) = remote {
+        sync_pending(host, host_conn.as_ref());
+    }
+    let sessions = RemoteSession::list_remote_sessions(host_conn.as_ref())?;
+
+    let mut lines = Vec::new();
     for name in &sessions {
         let session = RemoteSession::load_from_remote(host_conn.as_ref(), name)?;
         let locked = if let Some(lock) = &session.lock {
@@ -607,51 +2353,1679 @@ fn list_sessions(remote: Option<String>) -> Result<()> {
             "".to_string()
         };
 
-        println!("  {} - {} terminals{}", name, session.layout.get_sockets().len(), locked);
+        lines.push(format!("  {} - {} terminals{}", name, session.layout.get_sockets().len(), locked));
+    }
+
+    Ok(lines)
+}
+
+/// Install the current helper on several hosts at once (`i3mux helpers update`)
+///
+/// Otherwise each host gets it on the first command after an upgrade, one
+/// upload at a time. Unreachable hosts are reported and fail the command once
+/// the others are done.
+fn update_helpers(all_hosts: bool, remote: Option<String>) -> Result<()> {
+    if connection::is_offline() {
+        anyhow::bail!("Helpers can't be updated --offline");
+    }
+    let config = Config::load()?;
+    let hosts = match remote {
+        _ if all_hosts => config.configured_hosts()?,
+        Some(group) if group.starts_with('@') => config.group_hosts(&group[1..])?,
+        Some(host) if host != "local" => vec![host],
+        Some(_) => anyhow::bail!("The local helper is built into i3mux and needs no update"),
+        None => anyhow::bail!("No hosts to update: pass --remote HOST or @group, or --all-hosts"),
+    };
+    if hosts.is_empty() {
+        println!("No remote hosts in the config");
+        return Ok(());
+    }
+
+    let shipped = helper_script_version();
+    let results = fan_out(&hosts, |host| {
+        RemoteHost::new(host)?;
+        let installed = installed_helper_version(host)?;
+        if installed != shipped {
+            ensure_remote_helper(host)?;
+        }
+        Ok(installed)
+    });
+
+    let mut failed = 0;
+    for (host, result) in hosts.iter().zip(results) {
+        match result {
+            Ok(installed) if installed == shipped => println!("{} {}: {} (up to date)", style::ok(), host, shipped),
+            Ok(installed) if installed.is_empty() => println!("{} {}: {} (installed)", style::ok(), host, shipped),
+            Ok(installed) => println!("{} {}: {} -> {}", style::ok(), host, installed, shipped),
+            Err(e) => {
+                failed += 1;
+                println!("{} {}: {}", style::problem(), host, format!("{:#}", e).trim_end());
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} hosts could not be updated", failed, hosts.len());
+    }
+    Ok(())
+}
+
+/// List sessions on every host of a config-defined group, connecting concurrently
+///
+/// Hosts are reported in group order; unreachable hosts are listed with their
+/// error and make the command fail once the report is printed.
+fn list_group_sessions(group: &str) -> Result<()> {
+    let hosts = Config::load()?.group_hosts(group)?;
+    let results = fan_out(&hosts, |host| session_lines(Some(host)));
+
+    println!("Sessions on @{} ({} hosts):\n", group, hosts.len());
+    let mut total = 0;
+    let mut failed = 0;
+    for (host, result) in hosts.iter().zip(results) {
+        match result {
+            Ok(lines) if lines.is_empty() => println!("{}: no sessions", host),
+            Ok(lines) => {
+                println!("{}:", host);
+                total += lines.len();
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{}: unreachable: {}", host, format!("{:#}", e).trim_end());
+            }
+        }
+    }
+
+    println!("\n{} sessions on {} hosts", total, hosts.len() - failed);
+    if failed > 0 {
+        anyhow::bail!("{} of {} hosts in @{} could not be listed", failed, hosts.len(), group);
+    }
+    Ok(())
+}
+
+/// Run `f` for each host on its own thread; results come back in host order
+fn fan_out<T: Send>(hosts: &[String], f: impl Fn(&str) -> Result<T> + Sync) -> Vec<Result<T>> {
+    let executor = exec::current();
+    std::thread::scope(|scope| {
+        let f = &f;
+        let handles: Vec<_> = hosts
+            .iter()
+            .map(|host| {
+                let executor = executor.clone();
+                scope.spawn(move || exec::scoped(executor, || f(host)))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(anyhow::anyhow!("Panicked while connecting"))))
+            .collect()
+    })
+}
+
+/// Upload sessions detach queued for unreachable hosts (all hosts, or `remote`)
+///
+/// Sessions saved from elsewhere since they were attached are left queued,
+/// unless `force` uploads them anyway; `discard` drops them without connecting.
+fn sync(remote: Option<String>, force: bool, discard: bool) -> Result<()> {
+    let queue = pending::Queue::open()?;
+    let hosts = match remote {
+        Some(host) => vec![RemoteHost::new(host)?.as_str().to_string()],
+        None => queue.hosts()?,
+    };
+
+    let mut conflicts = 0;
+    let mut any = false;
+    for host in hosts {
+        for session in queue.sessions(&host)? {
+            any = true;
+            if discard {
+                queue.remove(&host, &session.name)?;
+                println!("{} Dropped queued session '{}' for {}", style::ok(), session.name, host);
+            }
+        }
+        if discard {
+            continue;
+        }
+        let host_conn = host_connection(&host)?;
+        for (name, synced) in queue.sync(&host, host_conn.as_ref(), force)? {
+            match synced {
+                pending::Synced::Saved => println!("{} Session '{}' saved to {}", style::ok(), name, host),
+                pending::Synced::Conflict => {
+                    conflicts += 1;
+                    eprintln!("Session '{}' on {} was saved from elsewhere after it was attached here", name, host);
+                }
+            }
+        }
+    }
+
+    if !any {
+        println!("No sessions are queued");
+    } else if conflicts > 0 {
+        anyhow::bail!(
+            "{} queued session(s) left in place; 'i3mux sync --force' uploads them anyway, --discard drops them",
+            conflicts
+        );
+    }
+    Ok(())
+}
+
+/// How long `ssh connect` waits for the login in its window
+const SSH_LOGIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(180);
+
+/// Start the shared master connection to a host interactively
+///
+/// Everything else runs ssh with its output captured, where a password or
+/// one-time code prompt can't be answered. Once the master is up, those
+/// commands ride on it without authenticating again.
+fn ssh_connect(remote: Option<String>, here: bool) -> Result<()> {
+    let config = Config::load()?;
+    let host = match remote.or(config.default_remote.clone()).map(|r| config.canonical_host(&r)) {
+        Some(host) => RemoteHost::new(host)?,
+        None => anyhow::bail!("No host to connect to: pass --remote or set default_remote in the config"),
+    };
+    if connection::is_offline() {
+        anyhow::bail!("--offline never connects to {}", host);
+    }
+    if connection::master_running(host.as_str()) {
+        println!("{} Already connected to {}", style::ok(), host);
+        return Ok(());
+    }
+
+    if here {
+        let status = exec::current()
+            .run(connection::ssh_command(host.as_str()).arg("true"))
+            .context("Failed to run ssh")?;
+        if !status.success() {
+            anyhow::bail!("Could not log in to {}", host);
+        }
+    } else {
+        let backend = WmBackend::connect()?;
+        std::fs::create_dir_all(connection::MASTER_SOCKET_DIR)?;
+        let line = format!(
+            "ssh {options} {host} true && echo 'Connected to {host}.' && sleep 1 || {{ echo 'Could not log in to {host}. Press Enter to close.'; read -r _; }}",
+            options = commands::SSH_MASTER_OPTIONS,
+            host = host
+        );
+        exec::current()
+            .spawn(Command::new(get_terminal_command(backend.wm_type())).arg("-e").arg("bash").arg("-c").arg(line))
+            .context("Failed to launch terminal")?;
+
+        let deadline = std::time::Instant::now() + SSH_LOGIN_TIMEOUT;
+        while !connection::master_running(host.as_str()) {
+            if std::time::Instant::now() > deadline {
+                anyhow::bail!("Not connected to {} after {} seconds; check the login window", host, SSH_LOGIN_TIMEOUT.as_secs());
+            }
+            std::thread::sleep(std::time::Duration::from_millis(500));
+        }
+    }
+
+    if !connection::master_running(host.as_str()) {
+        anyhow::bail!("Logged in to {}, but ssh did not keep the connection open (ControlPersist)", host);
+    }
+    println!("{} Connected to {}", style::ok(), host);
+    Ok(())
+}
+
+/// Upload sessions queued for `host`, warning instead of failing
+///
+/// Runs whenever a command reaches a host, so a detach made while it was
+/// unreachable lands there before anything reads its sessions.
+fn sync_pending(host: &str, conn: &dyn connection::Connection) {
+    let results = pending::Queue::open().and_then(|queue| queue.sync(host, conn, false));
+    match results {
+        Ok(results) => {
+            for (name, synced) in results {
+                match synced {
+                    pending::Synced::Saved => eprintln!("Uploaded session '{}' queued by an earlier detach", name),
+                    pending::Synced::Conflict => eprintln!(
+                        "Warning: session '{}' queued by an earlier detach conflicts with the copy on {} (see 'i3mux sync')",
+                        name, host
+                    ),
+                }
+            }
+        }
+        Err(e) => debug!("Could not upload queued sessions to {}: {:#}", host, e),
+    }
+}
+
+/// Edit a saved session's JSON in $EDITOR (`i3mux edit`)
+///
+/// The edited JSON must still be a session of the same name with a layout
+/// attach can restore (see `Layout::check`); otherwise the editor opens again
+/// on request. Files that no longer parse can be edited too, which makes this
+/// the way to repair them. Attached sessions are refused, since their next
+/// detach would overwrite the edit.
+fn edit_session(remote: Option<String>, session: String) -> Result<()> {
+    use std::io::IsTerminal;
+
+    let remote_host = remote.map(RemoteHost::new).transpose()?;
+    let session_name = SessionName::new(session)?;
+    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
+
+    let original = host_conn.load_session_data(session_name.as_str())?;
+    let loaded = serde_json::from_str::<RemoteSession>(&original).ok();
+    if let Some(lock) = loaded.as_ref().and_then(|s| s.lock.as_ref()) {
+        if host_conn.is_lock_valid(lock)? {
+            anyhow::bail!("Session '{}' is attached on {}; detach it first", session_name, lock.locked_by);
+        }
+    }
+
+    // Sessions are saved pretty-printed, so the file is edited as it is
+    let path = connection::ensure_user_dir()?.join(format!("edit-{}.json", session_name));
+    std::fs::write(&path, &original).with_context(|| format!("Failed to write {}", path.display()))?;
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+
+    let mut edited = loop {
+        // Through sh, so EDITOR may carry arguments ("code --wait")
+        let status = exec::current()
+            .run(Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(&path))
+            .with_context(|| format!("Failed to run editor '{}'", editor))?;
+        if !status.success() {
+            anyhow::bail!("Editor '{}' failed; your edit is in {}", editor, path.display());
+        }
+        let edited = std::fs::read_to_string(&path)?;
+        if edited == original {
+            let _ = std::fs::remove_file(&path);
+            println!("No changes");
+            return Ok(());
+        }
+        match RemoteSession::from_edited(&edited, session_name.as_str()) {
+            Ok(session) => break session,
+            Err(e) => {
+                eprintln!("{} {:#}", style::problem(), e);
+                if !std::io::stdin().is_terminal() || !matches!(prompt("[e]dit again or [a]bort?")?.as_str(), "e" | "edit") {
+                    anyhow::bail!("Session not saved; your edit is in {}", path.display());
+                }
+            }
+        }
+    };
+
+    // Saved from elsewhere meanwhile: don't drop that save silently
+    let current = host_conn.load_session_data(session_name.as_str())?;
+    if current != original {
+        anyhow::bail!(
+            "Session '{}' was saved from elsewhere while you edited it; your edit is in {}",
+            session_name,
+            path.display()
+        );
+    }
+    edited.revision = loaded.map_or(edited.revision, |s| s.revision);
+    edited.save_to_remote(host_conn.as_ref())?;
+    let _ = std::fs::remove_file(&path);
+    println!("{} Session '{}' saved (revision {})", style::ok(), session_name, edited.revision);
+    Ok(())
+}
+
+/// Save the session a spec file declares, for a later `i3mux attach`
+///
+/// `--remote` wins over the spec's `host`. An existing session of the same
+/// name is only replaced with `force`, and never while it is attached.
+fn create_session(spec_path: PathBuf, remote: Option<String>, force: bool) -> Result<()> {
+    let mut spec = spec::SessionSpec::load(&spec_path)?;
+    let config = Config::load()?;
+    spec.host = match remote.or(spec.host) {
+        Some(host) if host != "local" => Some(config.canonical_host(&host)),
+        _ => None,
+    };
+    let mut session = RemoteSession::from_spec(&spec)
+        .with_context(|| format!("Invalid spec {}", spec_path.display()))?;
+
+    let remote_host = spec.host.map(RemoteHost::new).transpose()?;
+    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
+    let host_display = remote_host.as_ref().map_or_else(|| style::local().to_string(), |h| h.as_str().to_string());
+    if host_conn.list_session_names()?.contains(&session.name) {
+        if !force {
+            anyhow::bail!("Session '{}' already exists on {}; use --force to replace it", session.name, host_display);
+        }
+        if let Some(lock) = RemoteSession::load_from_remote(host_conn.as_ref(), &session.name).ok().and_then(|s| s.lock) {
+            if host_conn.is_lock_valid(&lock)? {
+                anyhow::bail!("Session '{}' is attached on {}; detach it first", session.name, lock.locked_by);
+            }
+        }
+    }
+    session.save_to_remote(host_conn.as_ref())?;
+
+    let remote_flag = remote_host.as_ref().map(|h| format!(" --remote {}", h)).unwrap_or_default();
+    println!(
+        "{} Session '{}' created on {} ({} terminals); attach it with: i3mux attach{} --session {}",
+        style::ok(),
+        session.name,
+        host_display,
+        session.layout.get_sockets().len(),
+        remote_flag,
+        session.name
+    );
+    Ok(())
+}
+
+/// Check a session document without restoring anything (`i3mux validate`)
+///
+/// JSON and format errors stop at the first one, with its line and column;
+/// past that every problem with names, hosts, mounts and the layout is
+/// listed, so a generator can be fixed in one go.
+fn validate(file: Option<PathBuf>, session: Option<String>, remote: Option<String>) -> Result<()> {
+    let (json, source) = match (file, session) {
+        (Some(path), _) => {
+            let json = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            (json, path.display().to_string())
+        }
+        (None, Some(session)) => {
+            let remote_host = remote.map(RemoteHost::new).transpose()?;
+            let session_name = SessionName::new(session)?;
+            let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
+            let host_display = remote_host.as_ref().map_or_else(|| style::local().to_string(), |h| h.as_str().to_string());
+            (host_conn.load_session_data(session_name.as_str())?, format!("Session '{}' on {}", session_name, host_display))
+        }
+        (None, None) => anyhow::bail!("Give a session file or --session"),
+    };
+
+    let session: RemoteSession =
+        serde_json::from_str(&json).with_context(|| format!("{} is not a valid session", source))?;
+    let mut problems = session.problems();
+    if json.len() > connection::MAX_SESSION_BYTES {
+        problems.insert(0, format!("over the {} MiB limit for session files", connection::MAX_SESSION_BYTES >> 20));
+    }
+    if problems.is_empty() {
+        println!("{} {}: valid session '{}' ({} terminals)", style::ok(), source, session.name, session.layout.get_sockets().len());
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{} {}", style::problem(), problem);
+    }
+    anyhow::bail!("{} problem(s) found in {}", problems.len(), source)
+}
+
+/// Kill a saved session
+///
+/// With `terminate` (or `kill_terminates` in config, unless `keep_running`),
+/// the abduco sessions of the session's terminals are terminated as well.
+fn kill_session(remote: Option<String>, session: String, terminate: bool, keep_running: bool) -> Result<()> {
+    // Validate inputs at CLI boundary
+    let remote_host = remote.map(|r| RemoteHost::new(r)).transpose()?;
+    let session_name = SessionName::new(session)?;
+    let host_display = remote_host.as_ref()
+        .map(|h| h.as_str().to_string())
+        .unwrap_or_else(|| style::local().to_string());
+
+    let terminate = terminate || (Config::load()?.kill_terminates && !keep_running);
+
+    // Create connection and delete session (None = local, Some = remote)
+    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
+
+    if terminate {
+        match RemoteSession::load_from_remote(host_conn.as_ref(), session_name.as_str()) {
+            Ok(session) => {
+                let default_host = remote_host.as_ref().map_or("local", |h| h.as_str());
+                let mut terminated = 0;
+                for (host, sockets) in session.layout.get_sockets_by_host(default_host) {
+                    host_connection(&host)?.kill_sockets(&sockets)?;
+                    terminated += sockets.len();
+                }
+                println!("{} Terminated {} terminals", style::ok(), terminated);
+            }
+            Err(e) => eprintln!("Warning: could not read session to terminate its terminals: {:#}", e),
+        }
+    }
+
+    host_conn.delete_session(session_name.as_str())?;
+
+    println!("{} Session '{}' deleted from {}", style::ok(), session_name, host_display);
+    Ok(())
+}
+
+/// Show the current workspace's binding
+fn status() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let state = LocalState::load()?;
+    let Some(ws_state) = state.workspaces.get(&ws_name) else {
+        println!("Workspace {} is not bound", ws_num);
+        return Ok(());
+    };
+
+    println!("Workspace {}: bound to {}", ws_num, style::host(&ws_state.host));
+    match (&ws_state.session_name, &ws_state.activation) {
+        (Some(name), _) => println!("  Session:   {}", name),
+        (None, Some(activation)) => println!("  Session:   not saved yet (detach saves it as '{}')", activation.default_session),
+        (None, None) => println!("  Session:   not saved yet"),
+    }
+    println!("  Terminals: {}", ws_state.sockets.len());
+    if let Some(activation) = &ws_state.activation {
+        println!("  Bound by:  {}", activation);
+    }
+
+    // abduco allows several clients per session; more than one means someone else is watching
+    let attach_commands = ws_state.attach_commands();
+    for (host, sockets) in ws_state.sockets_by_host() {
+        let sockets: Vec<String> = sockets.into_iter().filter(|s| !attach_commands.contains_key(s)).collect();
+        if sockets.is_empty() {
+            continue;
+        }
+        match host_connection(&host).and_then(|conn| conn.process_info(&sockets)) {
+            Ok(processes) => {
+                for p in processes.iter().filter(|p| p.clients > 1) {
+                    println!("  {} is also attached elsewhere ({} clients)", p.socket, p.clients);
+                }
+            }
+            Err(e) => debug!("status: could not check clients on {}: {:#}", host, e),
+        }
+    }
+    Ok(())
+}
+
+/// Describe the focused window as `key: value` lines (for debugging and scripts)
+fn which() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let window = window::find_focused_i3mux_window(&backend)?
+        .context("The focused window is not an i3mux terminal")?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let state = LocalState::load()?;
+    let ws_state = state.workspaces.get(&ws_name);
+    let info = ws_state.and_then(|ws| ws.sockets.get(&window.socket));
+
+    println!("window: {}", window.window_id);
+    println!("workspace: {}", ws_num);
+    println!("host: {}", window.host);
+    println!("socket: {}", window.socket);
+    if let Some(session) = ws_state.and_then(|ws| ws.session_name.as_deref()) {
+        println!("session: {}", session);
+    }
+    if let Some(target) = info.and_then(|info| info.target.as_ref()) {
+        println!("target: {}", target);
+    }
+    let attach = info
+        .and_then(|info| info.attach.clone())
+        .unwrap_or_else(|| commands::manual_attach(&window.host, &window.socket));
+    println!("attach: {}", attach);
+
+    if info.is_none() {
+        eprintln!("Warning: {} is not in the local state (run 'i3mux resume' to adopt it)", window.socket);
+    }
+    Ok(())
+}
+
+/// Type a command, filled in per terminal, into each terminal of the current workspace
+fn exec_all(template: &str, include_busy: bool) -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let state = LocalState::load()?;
+    let ws_state = state
+        .workspaces
+        .get(&ws_name)
+        .ok_or(error::Error::NotBound)?;
+
+    // Positions and titles come from the workspace as arranged now; terminals
+    // not on screen (e.g. in the scratchpad) follow in socket order
+    let layout = Layout::capture_from_workspace_num(ws_num, &backend)?;
+    let titles = layout.as_ref().map(|l| l.get_titles()).unwrap_or_default();
+    let mut order = layout.map(|l| l.get_sockets()).unwrap_or_default();
+    order.retain(|s| ws_state.sockets.contains_key(s));
+    let mut rest: Vec<&String> = ws_state.sockets.keys().filter(|s| !order.contains(s)).collect();
+    rest.sort();
+    order.extend(rest.into_iter().cloned());
+    if order.is_empty() {
+        println!("No terminals in workspace {}", ws_num);
+        return Ok(());
+    }
+
+    // Custom attach terminals have no abduco session to type into
+    let attach_commands = ws_state.attach_commands();
+    let mut sent = 0;
+    let mut skipped = 0;
+    for (host, sockets) in ws_state.sockets_by_host() {
+        let sockets: Vec<String> = sockets.into_iter().filter(|s| !attach_commands.contains_key(s)).collect();
+        if sockets.is_empty() {
+            continue;
+        }
+        let conn = host_connection(&host)?;
+        let busy: HashMap<String, String> = if include_busy {
+            HashMap::new()
+        } else {
+            conn.process_info(&sockets)?.into_iter().filter(|p| p.busy).map(|p| (p.socket, p.command)).collect()
+        };
+
+        let mut inputs = Vec::new();
+        for socket in &sockets {
+            if let Some(command) = busy.get(socket) {
+                println!("Skipped {}: busy running {} (--include-busy to type into it anyway)", socket, command);
+                skipped += 1;
+                continue;
+            }
+            let index = order.iter().position(|s| s == socket).map_or(order.len(), |i| i + 1);
+            let name = titles.get(socket).map_or(socket.as_str(), |t| t.as_str());
+            inputs.push((socket.clone(), format!("{}\n", commands::fill_template(template, index, name, socket))));
+        }
+        if !inputs.is_empty() {
+            sent += conn.send_input(&inputs)?;
+        }
+    }
+    for socket in order.iter().filter(|s| attach_commands.contains_key(*s)) {
+        println!("Skipped {}: custom attach terminal, no session to type into", socket);
+        skipped += 1;
+    }
+
+    let skipped = if skipped > 0 { format!(" ({} skipped)", skipped) } else { String::new() };
+    println!("{} Ran in {} terminal(s) of workspace {}{}", style::ok(), sent, ws_num, skipped);
+    Ok(())
+}
+
+/// Show the foreground process, PID, CPU and memory of each terminal's shell
+fn ps() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let state = LocalState::load()?;
+    let ws_state = state
+        .workspaces
+        .get(&ws_name)
+        .ok_or(error::Error::NotBound)?;
+
+    if ws_state.sockets.is_empty() {
+        println!("No terminals in workspace {}", ws_num);
+        return Ok(());
+    }
+
+    // Custom attach terminals have no abduco session to inspect
+    let attach_commands = ws_state.attach_commands();
+    let targets = ws_state.targets();
+
+    let mut first = true;
+    for (host, sockets) in ws_state.sockets_by_host() {
+        if sockets.is_empty() {
+            continue;
+        }
+        let host_display = style::host(&host);
+        let (custom, sockets): (Vec<String>, Vec<String>) =
+            sockets.into_iter().partition(|s| attach_commands.contains_key(s));
+        let (processes, strays) = if sockets.is_empty() {
+            (Vec::new(), Vec::new())
+        } else {
+            // Sessions named after this workspace that it has no terminal for
+            // (left running by a crash, or another machine's workspace of the same number)
+            let conn = host_connection(&host)?;
+            let strays: Vec<connection::AbducoSocket> = conn
+                .list_sockets(Some(&instance::socket_prefix(&ws_num.to_string())))?
+                .into_iter()
+                .filter(|s| s.is_running() && !ws_state.sockets.contains_key(&s.name))
+                .collect();
+            (conn.process_info(&sockets)?, strays)
+        };
+
+        if !first {
+            println!();
+        }
+        first = false;
+        println!("Terminals on {} (workspace {}):\n", host_display, ws_num);
+        println!("  {:<10} {:>8} {:>6} {:>6}  COMMAND", "SOCKET", "PID", "%CPU", "%MEM");
+        for p in &processes {
+            let shared = if p.clients > 1 { format!("  [{} clients attached]", p.clients) } else { String::new() };
+            match p.pid {
+                Some(pid) => println!("  {:<10} {:>8} {:>6} {:>6}  {}{}", p.socket, pid, p.cpu, p.mem, p.command, shared),
+                None => println!("  {:<10} {:>8} {:>6} {:>6}  (not running)", p.socket, "-", "-", "-"),
+            }
+        }
+        for socket in &custom {
+            let attached_to = targets.get(socket).map_or_else(|| attach_commands[socket].clone(), |t| t.to_string());
+            println!("  {:<10} {:>8} {:>6} {:>6}  (attach) {}", socket, "-", "-", "-", attached_to);
+        }
+        for stray in &strays {
+            let started = stray
+                .created
+                .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                .map(|at| format!(", started {}", at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")))
+                .unwrap_or_default();
+            let pid = stray.shell_pid.map_or_else(|| "-".to_string(), |pid| pid.to_string());
+            println!("  {:<10} {:>8} {:>6} {:>6}  (not in this workspace{})", stray.name, pid, "-", "-", started);
+        }
+    }
+
+    Ok(())
+}
+
+/// Detach the abduco clients of other machines from a terminal's session
+///
+/// Clients attached from this machine are kept, so the terminal's own window
+/// stays attached; abduco then sizes the session for the remaining clients.
+fn boot(socket: Option<String>) -> Result<()> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => {
+            let backend = WmBackend::connect()?;
+            window::find_focused_i3mux_window(&backend)?
+                .context("No --socket given and the focused window is not an i3mux terminal")?
+                .socket
+        }
+    };
+
+    let state = LocalState::load()?;
+    let (ws_state, info) = state
+        .workspaces
+        .values()
+        .find_map(|ws| Some((ws, ws.sockets.get(&socket)?)))
+        .with_context(|| format!("Socket {} is not in any bound workspace", socket))?;
+    if info.attach.is_some() {
+        anyhow::bail!("{} is a custom attach terminal; it has no abduco session", socket);
+    }
+
+    let host = ws_state.socket_host(&socket);
+    let booted = host_connection(host)?.boot_clients(&socket, &connection::client_id())?;
+    if booted == 0 {
+        println!("No other clients attached to {} on {}", socket, style::host(host));
+    } else {
+        println!("{} Detached {} client(s) from {} on {}", style::ok(), booted, socket, style::host(host));
+    }
+    Ok(())
+}
+
+/// Show recorded operation latencies
+fn stats() -> Result<()> {
+    let metrics = metrics::Metrics::load()?;
+    let rows = metrics.report();
+    if rows.len() == 1 {
+        if !Config::load()?.metrics {
+            println!("No metrics recorded. Set `metrics = true` in config.toml to record them.");
+        } else {
+            println!("No metrics recorded yet");
+        }
+        return Ok(());
+    }
+
+    for row in rows {
+        println!("{}", row);
+    }
+    Ok(())
+}
+
+/// Whether a terminal's window and abduco session are still around
+struct TerminalStatus {
+    /// None if the window manager could not be asked
+    window: Option<bool>,
+    /// None if the terminal's host could not be reached
+    session: Option<bool>,
+    /// Custom attach terminal (no abduco session to check)
+    attach: bool,
+}
+
+impl TerminalStatus {
+    /// Neither a window nor a running session is left
+    fn is_gone(&self) -> bool {
+        self.window == Some(false) && (self.session == Some(false) || self.attach)
+    }
+}
+
+impl std::fmt::Display for TerminalStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let window = match self.window {
+            Some(true) => "window",
+            Some(false) => "no window",
+            None => "window unknown",
+        };
+        let session = match (self.attach, self.session) {
+            (true, _) => "attach",
+            (false, Some(true)) => "running",
+            (false, Some(false)) => "dead",
+            (false, None) => "host unreachable",
+        };
+        write!(f, "{}, {}", window, session)
+    }
+}
+
+/// Print a workspace's state with the liveness of each terminal
+///
+/// Returns the terminal statuses (keyed by socket) for repairs to act on.
+fn print_workspace_state(
+    ws_name: &str,
+    ws_state: &WorkspaceState,
+    backend: Option<&WmBackend>,
+) -> HashMap<String, TerminalStatus> {
+    let windows: Option<Vec<I3muxWindow>> = backend
+        .zip(ws_name.parse::<i32>().ok())
+        .and_then(|(backend, ws_num)| window::find_i3mux_windows_in_workspace(ws_num, backend).ok());
+
+    let attach_commands = ws_state.attach_commands();
+    let mut running = HashMap::new();
+    for (host, sockets) in ws_state.sockets_by_host() {
+        let sockets: Vec<String> = sockets.into_iter().filter(|s| !attach_commands.contains_key(s)).collect();
+        if sockets.is_empty() {
+            continue;
+        }
+        match host_connection(&host).and_then(|conn| conn.process_info(&sockets)) {
+            Ok(processes) => running.extend(processes.into_iter().map(|p| (p.socket, p.pid.is_some()))),
+            Err(e) => debug!("state: could not check sockets on {}: {:#}", host, e),
+        }
+    }
+
+    let session = ws_state
+        .session_name
+        .as_deref()
+        .map_or_else(|| "no saved session".to_string(), |name| format!("session '{}'", name));
+    println!(
+        "Workspace {}: {} ({}), next socket {:03}",
+        ws_name,
+        style::host(&ws_state.host),
+        session,
+        ws_state.next_socket_id
+    );
+    if let Some(activation) = &ws_state.activation {
+        println!("  bound by {}", activation);
+    }
+
+    let mut sockets: Vec<&String> = ws_state.sockets.keys().collect();
+    sockets.sort();
+    let mut statuses = HashMap::new();
+    for socket in sockets {
+        let status = TerminalStatus {
+            window: windows.as_ref().map(|w| w.iter().any(|w| &w.socket == socket)),
+            session: running.get(socket).copied(),
+            attach: attach_commands.contains_key(socket),
+        };
+        let host = ws_state.sockets[socket].host.as_deref().map(|h| format!("on {}", style::host(h))).unwrap_or_default();
+        println!("  {:<10} {:<24} {}", socket, host, status);
+        statuses.insert(socket.clone(), status);
+    }
+
+    for window in windows.iter().flatten().filter(|w| !ws_state.sockets.contains_key(&w.socket)) {
+        println!("  {} on {}: window not in state (run 'i3mux resume' to adopt it)", window.socket, style::host(&window.host));
+    }
+    for forward in &ws_state.forwards {
+        println!("  forward {}", forward);
+    }
+    for mount in &ws_state.mounts {
+        println!("  mount {}", mount);
+    }
+    if !ws_state.outputs.is_empty() {
+        println!("  pinned to {}", ws_state.outputs.join(", "));
+    }
+
+    statuses
+}
+
+/// Bound workspace names, in workspace order
+fn sorted_workspaces(state: &LocalState) -> Vec<String> {
+    let mut names: Vec<String> = state.workspaces.keys().cloned().collect();
+    names.sort_by_key(|name| (name.parse::<i32>().unwrap_or(i32::MAX), name.clone()));
+    names
+}
+
+/// Pretty-print the local state with liveness annotations
+fn state_dump() -> Result<()> {
+    let state = LocalState::load()?;
+    println!("State file: {}\n", LocalState::path()?.display());
+    if state.workspaces.is_empty() {
+        println!("No workspaces are bound");
+        return Ok(());
+    }
+
+    // Liveness is best effort; the state is still worth seeing without a WM
+    let backend = WmBackend::connect().ok();
+    for (i, ws_name) in sorted_workspaces(&state).iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_workspace_state(ws_name, &state.workspaces[ws_name], backend.as_ref());
+    }
+    Ok(())
+}
+
+/// Read one answer from stdin
+fn prompt(question: &str) -> Result<String> {
+    use std::io::Write;
+
+    print!("{} ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        anyhow::bail!("Aborted (no input); no changes saved");
+    }
+    Ok(answer.trim().to_string())
+}
+
+/// Walk through bound workspaces and fix their state interactively
+///
+/// Changes are saved once every workspace has been handled; quitting early
+/// discards them.
+fn state_repair() -> Result<()> {
+    let mut state = LocalState::load()?;
+    if state.workspaces.is_empty() {
+        println!("No workspaces are bound");
+        return Ok(());
+    }
+
+    let backend = WmBackend::connect().ok();
+    let mut changed = false;
+    for ws_name in sorted_workspaces(&state) {
+        let statuses = print_workspace_state(&ws_name, &state.workspaces[&ws_name], backend.as_ref());
+
+        loop {
+            let answer = prompt("[k]eep, [d]rop workspace, change [h]ost, [r]enumber sockets, [p]rune gone terminals, [q]uit?")?;
+            let Some(ws_state) = state.workspaces.get_mut(&ws_name) else {
+                break;
+            };
+            match answer.as_str() {
+                "" | "k" => break,
+                "d" => {
+                    state.workspaces.remove(&ws_name);
+                    println!("  Dropped workspace {}", ws_name);
+                    changed = true;
+                    break;
+                }
+                "h" => {
+                    let host = prompt("  New host (user@host, or 'local'):")?;
+                    if host != "local" {
+                        RemoteHost::new(host.as_str())?;
+                    }
+                    // Terminals recorded on the new host now simply run on the workspace's host
+                    for info in ws_state.sockets.values_mut() {
+                        if info.host.as_deref() == Some(host.as_str()) {
+                            info.host = None;
+                        }
+                    }
+                    ws_state.session_type = if host == "local" { "local" } else { "remote" }.to_string();
+                    println!("  Workspace {} now bound to {}", ws_name, style::host(&host));
+                    ws_state.host = host;
+                    changed = true;
+                }
+                "r" => {
+                    ws_state.next_socket_id = ws_state.highest_socket_id() + 1;
+                    println!("  New terminals will use socket {:03}", ws_state.next_socket_id);
+                    changed = true;
+                }
+                "p" => {
+                    let gone: Vec<String> =
+                        statuses.iter().filter(|(_, status)| status.is_gone()).map(|(socket, _)| socket.clone()).collect();
+                    for socket in &gone {
+                        ws_state.sockets.remove(socket);
+                    }
+                    println!("  Removed {} terminal(s) with neither a window nor a session", gone.len());
+                    changed |= !gone.is_empty();
+                }
+                "q" => {
+                    println!("No changes saved");
+                    return Ok(());
+                }
+                _ => println!("  Unknown choice '{}'", answer),
+            }
+        }
+        println!();
+    }
+
+    if changed {
+        state.save()?;
+        println!("{} Saved {}", style::ok(), LocalState::path()?.display());
+    } else {
+        println!("Nothing changed");
+    }
+    Ok(())
+}
+
+/// Toggle the current workspace between detached and attached/activated
+///
+/// - Bound to a session: detach it
+/// - Unbound, and the default session (`ws{N}`) exists on the host: attach it
+/// - Otherwise: activate the workspace against the remote (or locally)
+fn toggle(remote: Option<String>) -> Result<()> {
+    let config = Config::load()?;
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let state = LocalState::load()?;
+    if let Some(ws_state) = state.workspaces.get(&ws_name) {
+        debug!("toggle: workspace {} is bound to {}, detaching", ws_num, ws_state.host);
+        return detach(None, None, None);
+    }
+    let remote = remote.or(config.default_remote.clone()).map(|r| config.canonical_host(&r));
+    let remote_host = remote.clone().map(RemoteHost::new).transpose()?;
+    let default_session = format!("ws{}", ws_num);
+
+    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
+    let sessions = RemoteSession::list_remote_sessions(host_conn.as_ref())?;
+
+    if sessions.contains(&default_session) {
+        debug!("toggle: found session '{}', attaching", default_session);
+        attach(remote, Some(default_session), false, false, None, false)
+    } else {
+        debug!("toggle: no session '{}', activating", default_session);
+        activate(remote, None, None)
+    }
+}
+
+/// Connection to a host as recorded in state, marks and layouts ("local" or a remote)
+fn host_connection(host: &str) -> Result<Box<dyn connection::Connection>> {
+    let remote_host = if host == "local" {
+        None
+    } else {
+        Some(RemoteHost::new(host)?)
+    };
+    create_connection(remote_host.as_ref().map(|h| h.as_str()))
+}
+
+/// Connection to the host a workspace is bound to
+fn workspace_connection(ws_state: &WorkspaceState) -> Result<Box<dyn connection::Connection>> {
+    host_connection(&ws_state.host)
+}
+
+/// Apply a change to the workspace's saved session, if one exists on the host
+///
+/// The workspace follows the new revision only if it had saved the one
+/// changed; a session saved from elsewhere stays a conflict for `detach`.
+fn update_saved_session(
+    host_conn: &dyn connection::Connection,
+    ws_name: &str,
+    ws_state: &WorkspaceState,
+    update: impl FnOnce(&mut RemoteSession),
+) -> Result<()> {
+    if let Some(session_name) = &ws_state.session_name {
+        if let Ok(mut session) = RemoteSession::load_from_remote(host_conn, session_name) {
+            let ours = ws_state.revision == Some(session.revision);
+            update(&mut session);
+            session.save_to_remote(host_conn)?;
+            if ours {
+                let _binding = lockfile::lock_workspace(ws_name)?;
+                let mut state = LocalState::load()?;
+                if let Some(ws_state) = state.workspaces.get_mut(ws_name) {
+                    ws_state.revision = Some(session.revision);
+                    state.save()?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add or remove port forwards of the current workspace's session
+///
+/// Forwards are established on the SSH control connection right away, saved
+/// with the session on detach (which tears them down) and re-established on
+/// attach. Without any forwards given, the current ones are listed.
+fn forward(forwards: Vec<PortForward>, remove: bool) -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let mut state = LocalState::load()?;
+    let ws_state = state
+        .workspaces
+        .get_mut(&ws_name)
+        .ok_or(error::Error::NotBound)?;
+
+    if forwards.is_empty() {
+        if ws_state.forwards.is_empty() {
+            println!("No port forwards for workspace {}", ws_num);
+        }
+        for forward in &ws_state.forwards {
+            println!("{}", forward);
+        }
+        return Ok(());
+    }
+
+    if ws_state.session_type != "remote" {
+        anyhow::bail!("Port forwards are only available for remote sessions");
+    }
+    let host_conn = workspace_connection(ws_state)?;
+
+    for forward in forwards {
+        if remove {
+            if let Err(e) = host_conn.cancel_forward(&forward) {
+                eprintln!("Warning: {:#}", e);
+            }
+            ws_state.forwards.retain(|f| f != &forward);
+            println!("{} Removed {}", style::ok(), forward);
+        } else if !ws_state.forwards.contains(&forward) {
+            host_conn.add_forward(&forward)?;
+            println!("{} Forwarding {}", style::ok(), forward);
+            ws_state.forwards.push(forward);
+        }
+    }
+
+    let ws_state = ws_state.clone();
+    state.save()?;
+    update_saved_session(host_conn.as_ref(), &ws_name, &ws_state, |session| session.forwards = ws_state.forwards.clone())
+}
+
+/// Add or remove an sshfs mount of the current workspace's session
+///
+/// Mounts follow the session like port forwards: mounted right away, unmounted
+/// on detach and mounted again on attach. Without a mount given, the current
+/// ones are listed.
+fn mount_dir(mount: Option<Mount>, remove: bool) -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let mut state = LocalState::load()?;
+    let ws_state = state
+        .workspaces
+        .get_mut(&ws_name)
+        .ok_or(error::Error::NotBound)?;
+
+    let Some(mount) = mount else {
+        if ws_state.mounts.is_empty() {
+            println!("No mounts for workspace {}", ws_num);
+        }
+        for mount in &ws_state.mounts {
+            println!("{}", mount);
+        }
+        return Ok(());
+    };
+
+    if ws_state.session_type != "remote" {
+        anyhow::bail!("Mounts are only available for remote sessions");
+    }
+    let host_conn = workspace_connection(ws_state)?;
+
+    if remove {
+        host_conn.unmount(&mount)?;
+        ws_state.mounts.retain(|m| m != &mount);
+        println!("{} Unmounted {}", style::ok(), mount.local);
+    } else if !ws_state.mounts.contains(&mount) {
+        host_conn.mount(&mount)?;
+        println!("{} Mounted {}", style::ok(), mount);
+        ws_state.mounts.push(mount);
+    }
+
+    let ws_state = ws_state.clone();
+    state.save()?;
+    update_saved_session(host_conn.as_ref(), &ws_name, &ws_state, |session| session.mounts = ws_state.mounts.clone())
+}
+
+/// Pin the current workspace to outputs, or unpin it if `outputs` is empty
+///
+/// The pin is kept in workspace state, carried into the session on detach and
+/// honored on attach. A saved session of the same name is updated right away.
+fn pin_output(outputs: Vec<String>) -> Result<()> {
+    for output in &outputs {
+        if output.is_empty() || output.chars().any(|c| c.is_control() || "\"\\".contains(c)) {
+            anyhow::bail!("Invalid output name '{}'", output);
+        }
+    }
+
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+
+    let mut state = LocalState::load()?;
+    let ws_state = state
+        .workspaces
+        .get_mut(&ws_name)
+        .ok_or(error::Error::NotBound)?;
+    ws_state.outputs = outputs.clone();
+    let ws_state = ws_state.clone();
+    state.save()?;
+
+    let host_conn = workspace_connection(&ws_state)?;
+    update_saved_session(host_conn.as_ref(), &ws_name, &ws_state, |session| session.outputs = outputs.clone())?;
+
+    if outputs.is_empty() {
+        println!("{} Workspace {} unpinned", style::ok(), ws_num);
+        return Ok(());
+    }
+
+    move_to_pinned_output(&backend, &ws_name, &outputs)?;
+    println!("{} Workspace {} pinned to {}", style::ok(), ws_num, outputs.join(", "));
+    Ok(())
+}
+
+/// Move the focused workspace to the first connected pinned output
+///
+/// Falls back to the primary output if none of them is connected, and leaves
+/// the workspace where it is if there is no primary either.
+fn move_to_pinned_output(backend: &WmBackend, ws_name: &str, outputs: &[String]) -> Result<()> {
+    let active: Vec<wm::OutputInfo> = backend.get_outputs()?.into_iter().filter(|o| o.active).collect();
+
+    let target = outputs
+        .iter()
+        .find_map(|name| active.iter().find(|o| &o.name == name))
+        .or_else(|| {
+            let primary = active.iter().find(|o| o.primary);
+            let connected: Vec<String> = active
+                .iter()
+                .map(|o| {
+                    let (width, height) = o.resolution();
+                    format!("{} ({}x{})", o.name, width, height)
+                })
+                .collect();
+            eprintln!(
+                "Warning: pinned output(s) {} not connected (connected: {})",
+                outputs.join(", "),
+                connected.join(", ")
+            );
+            primary
+        });
+
+    match target {
+        Some(output) if output.current_workspace.as_deref() == Some(ws_name) => Ok(()),
+        Some(output) => {
+            debug!("Moving workspace to output {}", output.name);
+            backend.run_command(&format!("move workspace to output \"{}\"", output.name))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Replace the focused placeholder terminal with one attached to its session
+///
+/// The new terminal opens next to the placeholder, takes over its mark, and
+/// the placeholder is closed.
+fn respawn() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+    let placeholder = window::find_focused_i3mux_window(&backend)?.context("The focused window is not an i3mux terminal")?;
+    let state = LocalState::load()?;
+    let ws_state = state.workspaces.get(&ws_name).ok_or(error::Error::NotBound)?;
+    let info = ws_state
+        .sockets
+        .get(&placeholder.socket)
+        .filter(|info| info.placeholder)
+        .with_context(|| format!("{} is not a placeholder; only terminals attach couldn't reach are respawned", placeholder.socket))?;
+
+    if placeholder.host == "local" {
+        check_abduco_local()?;
+    } else {
+        std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+        check_abduco_remote(&placeholder.host)?;
+    }
+
+    let config = Config::load()?;
+    let ctx = RestoreContext {
+        backend: &backend,
+        config: &config,
+        host: &ws_state.host,
+        session_name: ws_state.session_name.as_deref(),
+        workspace_name: &ws_name,
+        workspace_num: ws_num,
+        restore_commands: HashMap::new(),
+        titles: HashMap::new(),
+        lazy: false,
+        unreachable: HashMap::new(),
+        last_container: Cell::new(None),
+    };
+    launch_terminal_for_socket(&ctx, &placeholder.host, &placeholder.socket, info.attach.as_deref())?;
+
+    {
+        let _binding = lockfile::lock_workspace(&ws_name)?;
+        let mut state = LocalState::load()?;
+        if let Some(info) = state.workspaces.get_mut(&ws_name).and_then(|ws| ws.sockets.get_mut(&placeholder.socket)) {
+            info.placeholder = false;
+            state.save()?;
+        }
+    }
+
+    // Last, since the placeholder may be the terminal this runs in
+    println!("{} Attached {} on {}", style::ok(), placeholder.socket, placeholder.host);
+    backend.run_command_on_container(placeholder.window_id, "kill")
+}
+
+/// Show or change a terminal's custom attach command (`i3mux edit-terminal`)
+///
+/// `change` is the new command, `Some(None)` to attach through abduco again,
+/// or None to only show the current one. Terminals of bound workspaces are
+/// changed in the local state, which the next detach saves; with `session`,
+/// the terminal is changed in the saved session, which must not be attached.
+/// A pod or container terminal given a command of its own stops following its
+/// target.
+fn edit_terminal(
+    socket: Option<String>,
+    session: Option<String>,
+    remote: Option<String>,
+    change: Option<Option<String>>,
+) -> Result<()> {
+    if change.as_ref().is_some_and(|c| c.as_deref().is_some_and(|c| c.trim().is_empty())) {
+        anyhow::bail!("The attach command is empty (use --clear to attach through abduco)");
+    }
+
+    let Some(session_name) = session else {
+        if remote.is_some() {
+            anyhow::bail!("--remote needs --session: terminals of bound workspaces are edited where they are");
+        }
+        return edit_bound_terminal(socket, change);
+    };
+    let socket = socket.context("--session needs --socket")?;
+    let remote_host = remote.map(RemoteHost::new).transpose()?;
+    let conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
+    let mut session = RemoteSession::load_from_remote(conn.as_ref(), &session_name)?;
+    if !session.layout.get_sockets().contains(&socket) {
+        anyhow::bail!("Session '{}' has no terminal {}", session_name, socket);
+    }
+
+    let mut attach_commands = session.layout.get_attach_commands();
+    let mut targets = session.layout.get_targets();
+    let Some(change) = change else {
+        print_terminal_attach(&socket, attach_commands.get(&socket).map(String::as_str), targets.get(&socket));
+        return Ok(());
+    };
+    if let Some(lock) = session.lock.as_ref().filter(|lock| conn.is_lock_valid(lock).unwrap_or(true)) {
+        anyhow::bail!(
+            "Session '{}' is attached on {}; edit the terminal there, or once it is detached",
+            session_name,
+            lock.locked_by
+        );
+    }
+    targets.remove(&socket);
+    match &change {
+        Some(command) => attach_commands.insert(socket.clone(), command.clone()),
+        None => attach_commands.remove(&socket),
+    };
+    session.layout.set_attach_commands(&attach_commands);
+    session.layout.set_targets(&targets);
+    session.save_to_remote(conn.as_ref())?;
+
+    print_terminal_attach(&socket, change.as_deref(), None);
+    Ok(())
+}
+
+/// [`edit_terminal`] for a terminal of a bound workspace (the focused one by default)
+fn edit_bound_terminal(socket: Option<String>, change: Option<Option<String>>) -> Result<()> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => {
+            let backend = WmBackend::connect()?;
+            window::find_focused_i3mux_window(&backend)?.context("The focused window is not an i3mux terminal")?.socket
+        }
+    };
+    let ws_name = LocalState::load()?
+        .workspace_of_socket(&socket)
+        .map(String::from)
+        .with_context(|| format!("No bound workspace has terminal {}", socket))?;
+
+    let _binding = lockfile::lock_workspace(&ws_name)?;
+    let mut state = LocalState::load()?;
+    let info = state
+        .workspaces
+        .get_mut(&ws_name)
+        .and_then(|ws| ws.sockets.get_mut(&socket))
+        .with_context(|| format!("No bound workspace has terminal {}", socket))?;
+    let Some(change) = change else {
+        print_terminal_attach(&socket, info.attach.as_deref(), info.target.as_ref());
+        return Ok(());
+    };
+    info.attach = change;
+    info.target = None;
+    print_terminal_attach(&socket, info.attach.as_deref(), None);
+    state.save()?;
+    println!("  The terminal keeps running as it is; detach and attach to use the change");
+    Ok(())
+}
+
+fn print_terminal_attach(socket: &str, attach: Option<&str>, target: Option<&AttachTarget>) {
+    match (attach, target) {
+        (_, Some(target)) => println!("{} attaches to {}", socket, target),
+        (Some(attach), None) => println!("{} attaches with: {}", socket, attach),
+        (None, None) => println!("{} attaches through abduco", socket),
+    }
+}
+
+/// Re-establish bookkeeping after the window manager restarted
+///
+/// Marks survive `i3 restart` and `swaymsg reload`, so the marked windows are
+/// the source of truth: workspace state is rebuilt from them, sockets are
+/// checked on their host, and locks of attached remote sessions are re-acquired.
+fn resume() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let mut state = LocalState::load()?;
+
+    // Every workspace that is bound or may still hold marked windows
+    let mut ws_nums: Vec<i32> = backend.get_workspaces()?.iter().map(|w| w.num).collect();
+    ws_nums.extend(state.workspaces.keys().filter_map(|k| k.parse::<i32>().ok()));
+    ws_nums.sort_unstable();
+    ws_nums.dedup();
+
+    for ws_num in ws_nums {
+        let ws_name = ws_num.to_string();
+        let windows = window::find_i3mux_windows_in_workspace(ws_num, &backend)?;
+
+        let ws_state = match state.workspaces.get(&ws_name) {
+            Some(ws_state) => ws_state.clone(),
+            None => {
+                // Marked windows but no state (e.g. state.json was lost): rebind from the marks,
+                // preferring a remote host since local terminals may be mixed into remote sessions
+                let Some(first) = windows.iter().find(|w| w.host != "local").or(windows.first()) else {
+                    continue;
+                };
+                println!("Workspace {}: rebinding to {} from window marks", ws_num, first.host);
+                WorkspaceState {
+                    session_type: if first.host == "local" { "local" } else { "remote" }.to_string(),
+                    host: first.host.clone(),
+                    session_name: None,
+                    next_socket_id: 1,
+                    sockets: HashMap::new(),
+                    outputs: Vec::new(),
+                    forwards: Vec::new(),
+                    mounts: Vec::new(),
+                    activation: Some(Activation::new("resume", Vec::new(), format!("ws{}", ws_num))),
+                    zoomed: None,
+                    layout_lock: None,
+                    revision: None,
+                    original_name: None,
+                }
+            }
+        };
+
+        match resume_workspace(ws_num, ws_state, &windows) {
+            Ok(Some(ws_state)) => {
+                state.workspaces.insert(ws_name, ws_state);
+            }
+            Ok(None) => {
+                println!("Workspace {}: no terminals or sessions left, unbinding", ws_num);
+                state.workspaces.remove(&ws_name);
+            }
+            Err(e) => eprintln!("Warning: could not resume workspace {}: {:#}", ws_num, e),
+        }
+    }
+
+    state.save()?;
+    println!("{} Resumed {} workspace(s)", style::ok(), state.workspaces.len());
+    Ok(())
+}
+
+/// Revalidate one workspace against its marked windows and host-side sockets
+///
+/// Returns the updated state, or None if nothing of the workspace survives.
+fn resume_workspace(
+    ws_num: i32,
+    mut ws_state: WorkspaceState,
+    windows: &[I3muxWindow],
+) -> Result<Option<WorkspaceState>> {
+    // Terminals may run on several hosts; check each one's sockets where they live
+    let attach_commands = ws_state.attach_commands();
+    let targets = ws_state.targets();
+    let mut by_host = ws_state.sockets_by_host();
+    for window in windows {
+        by_host.entry(window.host.clone()).or_default().push(window.socket.clone());
+    }
+
+    let mut sockets = HashMap::new();
+    for (host, mut candidates) in by_host {
+        candidates.sort();
+        candidates.dedup();
+
+        if host != "local" {
+            RemoteHost::new(host.as_str())?;
+            std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+            ensure_remote_helper(&host)?;
+        }
+        let host_conn = host_connection(&host)?;
+
+        let marked: Vec<&String> = windows.iter().filter(|w| w.host == host).map(|w| &w.socket).collect();
+        // Sockets keep the workspace number they were created in, so list them all
+        let alive: Vec<String> = host_conn
+            .list_sockets(None)?
+            .into_iter()
+            .filter(|s| s.is_running() && candidates.contains(&s.name))
+            .map(|s| s.name)
+            .collect();
+
+        // Custom attach terminals have no abduco session to find
+        for socket in marked.iter().filter(|s| !alive.contains(s) && !attach_commands.contains_key(s.as_str())) {
+            eprintln!("Warning: window for {} has no running session on {}", socket, host);
+        }
+
+        // Keep sockets that still have a window or are still running detached
+        for socket in candidates {
+            if marked.contains(&&socket) || alive.contains(&socket) {
+                let info_host = (host != ws_state.host).then(|| host.clone());
+                let attach = attach_commands.get(&socket).cloned();
+                let target = targets.get(&socket).cloned();
+                sockets.insert(socket.clone(), SocketInfo { socket_id: socket, host: info_host, attach, target, placeholder: false });
+            }
+        }
+    }
+    ws_state.sockets = sockets;
+
+    if ws_state.sockets.is_empty() {
+        return Ok(None);
+    }
+
+    // Never hand out a socket ID that is already in use
+    ws_state.next_socket_id = ws_state.next_socket_id.max(ws_state.highest_socket_id() + 1);
+
+    if let (true, Some(session_name)) = (ws_state.host != "local", &ws_state.session_name) {
+        let host_conn = workspace_connection(&ws_state)?;
+        if let Some(nonce) = resume_lock(host_conn.as_ref(), session_name, &mut ws_state.revision)? {
+            start_lock_watch(&ws_num.to_string(), &ws_state.host, session_name, &nonce);
+        }
+    }
+    if ws_state.layout_lock.is_some() {
+        start_layout_watch(&ws_num.to_string());
+    }
+
+    println!(
+        "Workspace {}: {} terminal(s) on {}",
+        ws_num,
+        ws_state.sockets.len(),
+        ws_state.sockets_by_host().keys().cloned().collect::<Vec<_>>().join(", ")
+    );
+    Ok(Some(ws_state))
+}
+
+/// Re-acquire the lock of an attached session if its holder is gone
+///
+/// Returns the nonce of the new lock, if one was taken. `revision` follows
+/// the save if it was the revision saved over.
+fn resume_lock(
+    host_conn: &dyn connection::Connection,
+    session_name: &str,
+    revision: &mut Option<u64>,
+) -> Result<Option<String>> {
+    let mut session = match RemoteSession::load_from_remote(host_conn, session_name) {
+        Ok(session) => session,
+        Err(e) => {
+            debug!("No saved session '{}' to lock: {:#}", session_name, e);
+            return Ok(None);
+        }
+    };
+
+    if let Some(lock) = &session.lock {
+        if host_conn.is_lock_valid(lock)? {
+            debug!("Lock for '{}' still held", session_name);
+            return Ok(None);
+        }
     }
 
-    Ok(())
+    let (lock, _lock_holder) = host_conn
+        .acquire_lock(session_name, false)
+        .with_context(|| format!("Failed to re-acquire lock for session '{}'", session_name))?;
+    // The holder is deliberately not tracked in lock_holders (those are
+    // killed on exit); it has to keep running after this command returns
+    let nonce = lock.nonce.clone();
+    let ours = *revision == Some(session.revision);
+    session.lock = Some(lock);
+    session.save_to_remote(host_conn)?;
+    if ours {
+        *revision = Some(session.revision);
+    }
+    hooks::emit(hooks::Event::LockAcquired, session_name);
+
+    println!("{} Lock re-acquired for session '{}'", style::ok(), session_name);
+    Ok(Some(nonce))
 }
 
-/// Kill a saved session
-fn kill_session(remote: Option<String>, session: String) -> Result<()> {
-    // Validate inputs at CLI boundary
-    let remote_host = remote.map(|r| RemoteHost::new(r)).transpose()?;
-    let session_name = SessionName::new(session)?;
-    let host_display = remote_host.as_ref()
-        .map(|h| h.as_str().to_string())
-        .unwrap_or_else(|| LOCAL_DISPLAY.to_string());
+/// Start watching an attached remote session for other machines taking it over (see watch.rs)
+fn start_lock_watch(workspace: &str, host: &str, session: &str, nonce: &str) {
+    if host == "local" || connection::is_offline() {
+        return;
+    }
+    let args = ["_watch-lock", "--workspace", workspace, "--host", host, "--session", session, "--nonce", nonce];
+    if let Err(e) = spawn_background(&args) {
+        debug!("Could not start watching session '{}': {:#}", session, e);
+    }
+}
 
-    // Create connection and delete session (None = local, Some = remote)
-    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
-    host_conn.delete_session(session_name.as_str())?;
+/// Report sessions locked by other machines, for a status bar (`i3mux watch-locks`)
+fn watch_locks(remote: Option<String>, interval: u64, once: bool, glyph: &str, json: bool) -> Result<()> {
+    let config = Config::load()?;
+    let hosts = match remote {
+        Some(group) if group.starts_with('@') => config.group_hosts(&group[1..])?,
+        Some(host) => vec![host],
+        None => config.configured_hosts()?,
+    };
+    if hosts.is_empty() {
+        anyhow::bail!("No hosts to watch: pass --remote HOST or @group, or add hosts to the config");
+    }
+    for host in &hosts {
+        if host != "local" {
+            RemoteHost::new(host.as_str())?;
+        }
+    }
+    locks::watch(&hosts, std::time::Duration::from_secs(interval), once, glyph, json)
+}
+
+/// List i3mux terminals for window switchers (`i3mux query windows`)
+fn query_windows(json: bool) -> Result<()> {
+    let (windows, watched) = query::windows()?;
+    // Later queries read the cache the watcher keeps
+    if !watched {
+        if let Err(e) = spawn_background(&["_watch-windows"]) {
+            debug!("Could not start watching windows: {:#}", e);
+        }
+    }
 
-    println!("✓ Session '{}' deleted from {}", session_name, host_display);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&windows)?);
+    } else {
+        for window in &windows {
+            println!("{}", window.line());
+        }
+    }
     Ok(())
 }
 
+/// Start `_watch-layout` for a workspace with a locked layout
+fn start_layout_watch(workspace: &str) {
+    if let Err(e) = spawn_background(&["_watch-layout", "--workspace", workspace]) {
+        debug!("Could not start watching the layout of workspace {}: {:#}", workspace, e);
+    }
+}
+
+/// Run this binary with `args`, detached from this command and its terminal
+fn spawn_background(args: &[&str]) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let mut cmd = Command::new(i3mux_binary());
+    cmd.args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        // Outlives this command and the terminal it was run from
+        .process_group(0);
+    exec::current().spawn(&mut cmd).map(drop)
+}
+
 /// Launch terminal (smart detection)
-fn terminal(exec: Option<&str>) -> Result<()> {
+fn terminal(
+    exec: Option<&str>,
+    remote: Option<&str>,
+    attach: Option<&str>,
+    target: Option<AttachTarget>,
+    workspace: Option<i32>,
+) -> Result<()> {
     let backend = WmBackend::connect()?;
-    let (ws_name, _) = get_focused_workspace(&backend)?;
+    let bound = LocalState::bound_workspaces()?;
+    let i3mux_only = remote.is_some() || attach.is_some() || target.is_some() || workspace.is_some();
 
-    let state = LocalState::load()?;
+    // Fast path: with nothing bound, the focused workspace doesn't matter
+    if bound.is_empty() && !i3mux_only {
+        return launch_normal_terminal(backend.wm_type(), exec);
+    }
+
+    let (ws_name, ws_num) = match workspace {
+        Some(num) => (num.to_string(), num),
+        None => get_focused_workspace(&backend)?,
+    };
 
     // Check if workspace is i3mux-bound
-    if state.workspaces.get(&ws_name).is_none() {
+    if !bound.contains(&ws_name) {
+        if i3mux_only {
+            anyhow::bail!("--remote/--local/--attach/--pod/--docker/--podman/--workspace require an i3mux-bound workspace (run 'i3mux activate' first)");
+        }
         return launch_normal_terminal(backend.wm_type(), exec);
     }
 
     // Workspace is i3mux-bound - always launch i3mux terminal
     // (The old logic checked focused window type, but that doesn't make sense:
     //  if the workspace is bound to i3mux, ALL terminals should be i3mux terminals)
-    launch_i3mux_terminal(&ws_name, backend.wm_type(), exec)?;
+    launch_i3mux_terminal(&ws_name, ws_num, backend.wm_type(), exec, remote, attach, target)?;
 
     Ok(())
 }
 
 // Helper functions
 
+/// Run `f`, then focus whatever had focus before it (`--no-focus`)
+///
+/// New windows take focus when they appear; this hands it back once they
+/// are marked, so a terminal opened from a script doesn't keep the keyboard.
+fn keeping_focus(enabled: bool, f: impl FnOnce() -> Result<()>) -> Result<()> {
+    if !enabled {
+        return f();
+    }
+    let backend = WmBackend::connect()?;
+    let focused = window::find_focused_container(&backend)?;
+    let result = f();
+    if let Some(container_id) = focused {
+        if let Err(e) = backend.run_command_on_container(container_id, "focus") {
+            debug!("Could not restore focus: {:#}", e);
+        }
+    }
+    result
+}
+
+/// Dimensions of the focused workspace
+fn focused_workspace_rect(backend: &WmBackend) -> Result<wm::Rect> {
+    backend
+        .get_workspaces()?
+        .into_iter()
+        .find(|ws| ws.focused)
+        .map(|ws| ws.rect)
+        .context("No focused workspace found")
+}
+
+/// Rename bound workspace `ws_name` after its host, if `workspace_labels` is set
+///
+/// The name it had is kept in its state, for [`unlabel_workspace`]. Failing to
+/// rename only warns: the binding works the same either way.
+fn label_workspace(backend: &WmBackend, state: &mut LocalState, ws_name: &str, ws_num: i32) {
+    let Some(ws_state) = state.workspaces.get_mut(ws_name) else {
+        return;
+    };
+    let Some(label) = Config::load().ok().and_then(|config| config.workspace_label(ws_num, &ws_state.host)) else {
+        return;
+    };
+    match rename_workspace(backend, ws_num, &label) {
+        Ok(Some(name)) => {
+            ws_state.original_name.get_or_insert(name);
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Warning: could not label workspace {}: {:#}", ws_num, e),
+    }
+}
+
+/// Give an unbound workspace back the name it had before [`label_workspace`]
+fn unlabel_workspace(backend: &WmBackend, ws_num: i32, ws_state: &WorkspaceState) {
+    if let Some(name) = &ws_state.original_name {
+        if let Err(e) = rename_workspace(backend, ws_num, name) {
+            eprintln!("Warning: could not rename workspace {} back to '{}': {:#}", ws_num, name, e);
+        }
+    }
+}
+
+/// Rename workspace `ws_num` to `name`, returning the name it had (None if it
+/// already had that one)
+fn rename_workspace(backend: &WmBackend, ws_num: i32, name: &str) -> Result<Option<String>> {
+    let current = backend
+        .get_workspaces()?
+        .into_iter()
+        .find(|ws| ws.num == ws_num)
+        .with_context(|| format!("Workspace {} not found", ws_num))?
+        .name;
+    if current == name {
+        return Ok(None);
+    }
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    backend.run_command(&format!("rename workspace {} to {}", quote(&current), quote(name)))?;
+    Ok(Some(current))
+}
+
 fn get_focused_workspace(backend: &WmBackend) -> Result<(String, i32)> {
     let workspaces = backend.get_workspaces()?;
     for ws in workspaces {
@@ -697,6 +4071,19 @@ fn build_terminal_instance_args(terminal: &str, instance: &str, wm_type: WmType)
     }
 }
 
+/// How spawned terminals run this binary
+///
+/// The running executable, unless it was removed or replaced since it started
+/// (`cargo install` over it, a new Nix store path); Linux then reports the old
+/// path with " (deleted)" appended. `i3mux` is looked up on PATH in that case.
+fn i3mux_binary() -> String {
+    std::env::current_exe()
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| path.to_str().map(String::from))
+        .unwrap_or_else(|| "i3mux".to_string())
+}
+
 fn get_terminal_command(wm_type: WmType) -> String {
     std::env::var("TERMINAL").unwrap_or_else(|_| match wm_type {
         WmType::Sway => "foot".to_string(),
@@ -717,150 +4104,118 @@ fn launch_normal_terminal(wm_type: WmType, exec: Option<&str>) -> Result<()> {
         cmd.arg("-e").arg("sh").arg("-c").arg(exec_cmd);
     }
 
-    cmd.spawn().context("Failed to launch terminal")?;
+    exec::current().spawn(&mut cmd).context("Failed to launch terminal")?;
     Ok(())
 }
 
-fn launch_i3mux_terminal(ws_name: &str, wm_type: WmType, exec: Option<&str>) -> Result<()> {
+fn launch_i3mux_terminal(
+    ws_name: &str,
+    ws_num: i32,
+    wm_type: WmType,
+    exec: Option<&str>,
+    remote: Option<&str>,
+    attach: Option<&str>,
+    mut target: Option<AttachTarget>,
+) -> Result<u64> {
     debug!("launch_i3mux_terminal called for workspace: {}", ws_name);
 
-    // Ensure wrapper script exists
-    ensure_wrapper_script()?;
+    // A broken config costs the theme, not the terminal
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Warning: {:#}; using the default settings", e);
+        Config::default()
+    });
+    let state = LocalState::load()?;
 
-    let mut state = LocalState::load()?;
+    // The terminal runs on the workspace's host unless --remote names another
+    let host = match remote {
+        Some(remote) => remote.to_string(),
+        None => state.workspaces.get(ws_name).ok_or(error::Error::NotBound)?.host.clone(),
+    };
+    let is_local = host == "local";
+    if !is_local {
+        RemoteHost::new(host.as_str())?;
+        std::fs::create_dir_all("/tmp/i3mux/sockets")?;
+        check_abduco_remote(&host)?;
+    }
+
+    // Pod and container terminals attach through kubectl/docker/podman exec
+    let target_attach = target.as_mut().map(AttachTarget::resolve).transpose()?;
+    let attach = target_attach.as_deref().or(attach);
 
+    // Allocate the socket ID under the workspace lock, so terminals launched at
+    // the same time (e.g. by a repeated activate) never share one
+    let binding = lockfile::lock_workspace(ws_name)?;
+    let mut state = LocalState::load()?;
     let socket = {
         let ws_state = state
             .workspaces
             .get_mut(ws_name)
-            .context("Workspace not i3mux-bound")?;
+            .ok_or(error::Error::NotBound)?;
 
-        let socket = format!("ws{}-{:03}", ws_name, ws_state.next_socket_id);
+        let socket = format!("{}-{:03}", instance::socket_prefix(ws_name), ws_state.next_socket_id);
         debug!("Generated socket ID: {}", socket);
         ws_state.next_socket_id += 1;
-        ws_state.sockets.insert(socket.clone(), SocketInfo { socket_id: socket.clone() });
+        let socket_host = (host != ws_state.host).then(|| host.clone());
+        ws_state.sockets.insert(
+            socket.clone(),
+            SocketInfo { socket_id: socket.clone(), host: socket_host, attach: attach.map(String::from), target, placeholder: false },
+        );
         socket
     };
 
-    let (title, attach_cmd, cleanup_cmd) = {
-        let ws_state = state
-            .workspaces
-            .get(ws_name)
-            .context("Workspace not i3mux-bound")?;
-
-        let title = if ws_state.session_type == "local" {
-            format!("{}local:{}", MARKER, socket)
-        } else {
-            format!("{}{}:{}", MARKER, ws_state.host, socket)
-        };
-
-        // Escape the title for use in PROMPT_COMMAND (needs extra escaping for SSH)
-        let title_for_prompt = title.replace("\\", "\\\\").replace("\"", "\\\"").replace("$", "\\$");
-
-        // Use exec command if provided, otherwise use user's shell
-        let cmd_to_run = exec.map(String::from).unwrap_or_else(get_user_shell);
-        debug!("Command to run: {}", cmd_to_run);
-
-        let attach_cmd = if ws_state.session_type == "local" {
-            // Local: Direct abduco attach
-            let prompt_cmd_val = format!("echo -ne \\\"\\\\033]0;{}\\\\007\\\"", title_for_prompt);
-            format!(
-                r#"bash -c "export PROMPT_COMMAND='{}'; exec abduco -A /tmp/{} {}""#,
-                prompt_cmd_val, socket, cmd_to_run
-            )
-        } else {
-            // Remote: Use helper script to attach (ensures PATH is set correctly)
-            // When exec is provided, pass it to the attach command
-            if exec.is_some() {
-                format!(
-                    r#"TERM=xterm-256color ssh -o ControlPath=/tmp/i3mux/sockets/%r@%h:%p -o ControlMaster=auto -o ControlPersist=10m -tt {} 'bash -l -c "exec {} attach {} -- {}"'"#,
-                    ws_state.host, REMOTE_HELPER_PATH, socket, cmd_to_run
-                )
-            } else {
-                format!(
-                    r#"TERM=xterm-256color ssh -o ControlPath=/tmp/i3mux/sockets/%r@%h:%p -o ControlMaster=auto -o ControlPersist=10m -tt {} 'bash -l -c "exec {} attach {}"'"#,
-                    ws_state.host, REMOTE_HELPER_PATH, socket
-                )
-            }
-        };
-
-        // Cleanup script to run after terminal exits
-        // This cleans up remote session files AND local workspace state
-        let cleanup_cmd = {
-            let ws_prefix = format!("ws{}", ws_name);
-            let session_cleanup = if let Some(session_name) = &ws_state.session_name {
-                if ws_state.session_type == "local" {
-                    // Local cleanup: Remove session files if no sockets remain
-                    format!(
-                        r#"if ! ls /tmp/{ws_prefix}-* &>/dev/null; then rm -f /tmp/i3mux/sessions/{session}.json /tmp/i3mux/locks/{session}.lock; fi"#,
-                        ws_prefix = ws_prefix,
-                        session = session_name
-                    )
-                } else {
-                    // Remote cleanup: Use helper script to check and clean up remote session files
-                    format!(
-                        r#"ssh -o ControlPath=/tmp/i3mux/sockets/%r@%h:%p {host} 'bash -lc "{helper} cleanup-check {ws_prefix} {session}"' 2>/dev/null || true"#,
-                        host = ws_state.host,
-                        helper = REMOTE_HELPER_PATH,
-                        ws_prefix = ws_prefix,
-                        session = session_name
-                    )
-                }
-            } else {
-                String::new()
-            };
-
-            // Always clean up workspace state if no sockets remain (for both local and remote)
-            // Get the binary path for i3mux
-            let i3mux_bin = std::env::current_exe()
-                .ok()
-                .and_then(|p| p.to_str().map(String::from))
-                .unwrap_or_else(|| "i3mux".to_string());
-
-            format!(
-                r#"{session_cleanup}; {bin} cleanup-workspace {ws} 2>/dev/null || true"#,
-                session_cleanup = session_cleanup,
-                bin = i3mux_bin,
-                ws = ws_name
-            )
-        };
-
-        (title, attach_cmd, cleanup_cmd)
+    let session_name = state.workspaces.get(ws_name).ok_or(error::Error::NotBound)?.session_name.clone();
+
+    // host is "local" for local terminals
+    let title = config.title_for(&host, &socket);
+
+    let attach_spec = match attach {
+        // Custom attach command: run as-is, there is no abduco session to keep
+        Some(attach) => CommandSpec { line: attach.to_string(), env: Vec::new() },
+        None => AttachCommand {
+            config: &config,
+            host: &host,
+            socket: &socket,
+            session: session_name.as_deref(),
+            user_shell: &get_user_shell(),
+            client: &connection::client_id(),
+        }
+        .launch(exec),
     };
+    let attach_cmd = attach_spec.line;
 
     state.save()?;
+    drop(binding);
 
     let ws_state = state.workspaces.get(ws_name).unwrap();
 
     debug!("Session type: {}", ws_state.session_type);
-    debug!("Host: {}", ws_state.host);
-    debug!("Title: {}", title);
+    debug!("Host: {}", host);
+    debug!("Title: {:?}", title);
     debug!("Attach command: {}", attach_cmd);
 
-    // Build wrapper script invocation
-    // Pass PROMPT_COMMAND for remote sessions to maintain title
-    let prompt_cmd = if ws_state.session_type == "remote" {
-        format!("echo -ne \"\\033]0;{}\\007\"", title.replace("\\", "\\\\").replace("\"", "\\\"").replace("$", "\\$"))
-    } else {
-        String::new()
-    };
-
-    let wrapper_args = vec![
-        socket.as_str(),
-        &title,
-        &attach_cmd,
-        &cleanup_cmd,
-        &prompt_cmd,
+    // The terminal runs `i3mux _wrap`, which attaches and cleans up afterwards
+    let mut wrap_args: Vec<String> = vec![
+        "_wrap".into(),
+        "--socket".into(),
+        socket.clone(),
+        "--workspace".into(),
+        ws_name.to_string(),
+        "--attach-cmd".into(),
+        attach_cmd.clone(),
     ];
+    if let Some(title) = &title {
+        wrap_args.extend(["--title".into(), title.clone()]);
+    }
+    if let Some(session_name) = &ws_state.session_name {
+        wrap_args.extend(["--session".into(), session_name.clone(), "--host".into(), ws_state.host.clone()]);
+    }
 
-    debug!("Wrapper script: {} with args: {:?}", WRAPPER_PATH, wrapper_args);
+    let i3mux_bin = i3mux_binary();
+    debug!("Wrapper: {} {:?}", i3mux_bin, wrap_args);
     debug!("Terminal command: {}", get_terminal_command(wm_type));
 
-    // Get the host for creating the I3muxWindow identity
-    let host = ws_state.host.clone();
-
-    // Generate instance name (same format as marks)
-    let instance = I3muxWindow::mark_from_parts(&host, &socket);
+    // Generate instance name (mark format plus a per-launch nonce)
+    let instance = I3muxWindow::launch_instance(&host, &socket);
 
     // Build terminal command with instance-specific args
     let terminal = get_terminal_command(wm_type);
@@ -871,235 +4226,530 @@ fn launch_i3mux_terminal(ws_name: &str, wm_type: WmType, exec: Option<&str>) ->
 
     // Spawn the terminal with instance set via terminal-specific CLI args
     let mut cmd = Command::new(&terminal);
-    cmd.args(&instance_args)
-        .arg("-T")
-        .arg(&title)
-        .arg("-e")
-        .arg(WRAPPER_PATH)
-        .args(&wrapper_args);
+    cmd.env(connection::CLIENT_ENV, connection::client_id());
+    cmd.envs(attach_spec.env);
+    cmd.args(&instance_args);
+    if let Some(title) = &title {
+        cmd.arg("-T").arg(title);
+    }
+    cmd.arg("-e")
+        .arg(&i3mux_bin)
+        .args(&wrap_args);
+
+    // A terminal for a workspace in the background opens on the focused one;
+    // once marked, it is moved next to the workspace's last i3mux terminal
+    let backend = WmBackend::connect()?;
+    let anchor = if get_focused_workspace(&backend)?.1 != ws_num {
+        window::find_i3mux_windows_in_workspace(ws_num, &backend)?.last().map(I3muxWindow::mark)
+    } else {
+        None
+    };
 
-    cmd.spawn().context("Failed to launch i3mux terminal")?;
+    exec::current().spawn(&mut cmd).context("Failed to launch i3mux terminal")?;
 
     // Wait for window to appear and apply i3mux mark
-    let backend = WmBackend::connect()?;
-    wait_for_window_and_mark(&backend, &instance, &host, &socket)?;
+    let container_id =
+        timings::time("wm: wait for window", || wait_for_window_and_mark(&backend, &instance, &host, &socket, ws_num))?;
+    if let Some(anchor) = anchor {
+        backend.run_command_on_container(container_id, &format!("move container to mark \"{}\"", anchor))?;
+    }
+
+    // The title bar keeps the i3mux title whatever the shell sets
+    let mut theme = config.theme_for(&host, ws_state.session_name.as_deref());
+    theme.title = title;
+    window::apply_theme(&backend, container_id, &theme)?;
 
     debug!("launch_i3mux_terminal completed successfully");
-    Ok(())
+    Ok(container_id)
 }
 
-/// Clean up workspace state if no active sessions remain
-fn cleanup_workspace(ws_name: &str) -> Result<()> {
+/// Clean up after a terminal exits, and the workspace state if no active sessions remain
+///
+/// Runs from `i3mux _wrap` when a terminal's attach command ends, including when
+/// the window was closed externally (`window_closed`).
+/// The exiting socket is dropped from the workspace state, and with
+/// `kill_on_close` its abduco session is terminated; once the session is gone
+/// the socket is dropped from the saved layout too.
+///
+/// The workspace stays bound while any i3mux window remains in it (other than
+/// `exiting_socket`, whose terminal is running this cleanup) or while abduco
+/// sockets for it survive on the session's host.
+fn cleanup_workspace(ws_name: &str, exiting_socket: Option<&str>, window_closed: bool) -> Result<()> {
     debug!("cleanup_workspace called for workspace: {}", ws_name);
 
     let mut state = LocalState::load()?;
 
+    // The terminal's workspace may have been renumbered since it started
+    let ws_name = &exiting_socket
+        .and_then(|socket| state.workspace_of_socket(socket))
+        .unwrap_or(ws_name)
+        .to_string();
+
     // Check if workspace exists in state
-    if !state.workspaces.contains_key(ws_name) {
+    let Some(ws_state) = state.workspaces.get_mut(ws_name) else {
         debug!("Workspace {} not in state, nothing to clean up", ws_name);
         return Ok(());
-    }
+    };
+
+    let remote_host = if ws_state.host == "local" {
+        None
+    } else {
+        Some(RemoteHost::new(ws_state.host.clone())?)
+    };
+    let host_conn = create_connection(remote_host.as_ref().map(|h| h.as_str()))?;
 
-    // Check if any socket files exist for this workspace
-    let ws_prefix = format!("ws{}", ws_name);
-    let socket_pattern = format!("/tmp/{}-*", ws_prefix);
+    // Hosts with terminals, including the exiting one's, for the live-socket check below
+    let hosts: Vec<String> = ws_state.sockets_by_host().into_keys().collect();
+    let prefixes = ws_state.socket_prefixes(ws_name);
+
+    if let Some(socket) = exiting_socket {
+        cleanup_socket(ws_state, host_conn.as_ref(), socket, window_closed)?;
+    }
+    state.save()?;
 
-    debug!("Checking for socket files: {}", socket_pattern);
+    let ws_num: i32 = ws_name
+        .parse()
+        .with_context(|| format!("Invalid workspace number '{}'", ws_name))?;
 
-    // Use glob to check for socket files
-    let has_sockets = std::process::Command::new("sh")
-        .arg("-c")
-        .arg(format!("ls {} 2>/dev/null", socket_pattern))
-        .output()?
-        .status
-        .success();
+    // Check for remaining marked windows in the workspace
+    let backend = WmBackend::connect()?;
+    let remaining = window::find_i3mux_windows_in_workspace(ws_num, &backend)?
+        .into_iter()
+        .filter(|w| Some(w.socket.as_str()) != exiting_socket)
+        .count();
 
-    if has_sockets {
-        debug!("Socket files still exist, not cleaning up workspace state");
+    if remaining > 0 {
+        debug!("{} i3mux windows remain, not cleaning up workspace state", remaining);
         return Ok(());
     }
 
-    // No sockets remain, remove workspace state
-    debug!("No socket files found, removing workspace state for {}", ws_name);
-    state.workspaces.remove(ws_name);
+    // Check for surviving sockets on every host the workspace has terminals on
+    for host in &hosts {
+        let conn = host_connection(host)?;
+        for prefix in &prefixes {
+            if conn.has_live_sockets(prefix)? {
+                debug!("Sockets for {} still exist on {}, not cleaning up workspace state", prefix, host);
+                return Ok(());
+            }
+        }
+    }
+
+    // Nothing remains, remove workspace state
+    debug!("No windows or sockets remain, removing workspace state for {}", ws_name);
+    if let Some(ws_state) = state.workspaces.remove(ws_name) {
+        unlabel_workspace(&backend, ws_num, &ws_state);
+    }
     state.save()?;
 
     debug!("Workspace {} state cleaned up successfully", ws_name);
     Ok(())
 }
 
-fn restore_layout(
-    backend: &WmBackend,
-    session: &RemoteSession,
-    _ws_name: &str,
-    remote_host: &str,
+/// Socket prefixes of the bound workspace `socket` belongs to (None if none does)
+fn socket_owner_prefixes(socket: &str) -> Result<Option<Vec<String>>> {
+    let state = LocalState::load()?;
+    Ok(state.workspace_of_socket(socket).map(|name| state.workspaces[name].socket_prefixes(name)))
+}
+
+/// Per-socket cleanup for a terminal that went away
+///
+/// `host_conn` is the workspace's host, where the session is saved; the
+/// terminal itself may have run on another host. `window_closed` is set when
+/// the window was closed externally rather than the attach command ending.
+/// The terminal stays in the saved layout while its abduco session runs on
+/// (closed without `kill_on_close`, or detached), so a later attach brings it
+/// back.
+fn cleanup_socket(
+    ws_state: &mut WorkspaceState,
+    host_conn: &dyn connection::Connection,
+    socket: &str,
+    window_closed: bool,
 ) -> Result<()> {
+    let socket_host = ws_state.socket_host(socket).to_string();
+    ws_state.sockets.remove(socket);
+
+    // The session runs on the terminal's own host; one that can't be reached
+    // can't be checked, so the terminal keeps its place in the saved layout
+    let remote_conn = match (socket_host != ws_state.host).then(|| host_connection(&socket_host)).transpose() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[i3mux] Could not reach {} to clean up {}: {:#}", socket_host, socket, e);
+            return Ok(());
+        }
+    };
+    let socket_conn = remote_conn.as_deref().unwrap_or(host_conn);
+
+    let mut killed = false;
+    if window_closed && Config::load()?.kill_on_close {
+        debug!("Window for {} closed externally, killing its abduco session on {}", socket, socket_host);
+        match socket_conn.kill_sockets(&[socket.to_string()]) {
+            Ok(()) => killed = true,
+            Err(e) => eprintln!("[i3mux] Failed to kill abduco session {}: {:#}", socket, e),
+        }
+    }
+
+    let running = !killed
+        && match socket_conn.list_sockets(None) {
+            Ok(listed) => listed.iter().any(|s| s.name == socket && s.is_running()),
+            Err(e) => {
+                debug!("Could not check {} on {}, keeping it in the saved layout: {:#}", socket, socket_host, e);
+                true
+            }
+        };
+    if running {
+        debug!("Session {} is still running, keeping it in the saved layout", socket);
+        return Ok(());
+    }
+
+    // Drop the terminal from the saved layout so a later attach doesn't resurrect it
+    if let Some(session_name) = &ws_state.session_name {
+        match RemoteSession::load_from_remote(host_conn, session_name) {
+            Ok(mut session) => {
+                if let Some(layout) = session.layout.without_socket(socket) {
+                    let ours = ws_state.revision == Some(session.revision);
+                    session.layout = layout;
+                    session.save_to_remote(host_conn)?;
+                    if ours {
+                        ws_state.revision = Some(session.revision);
+                    }
+                }
+            }
+            Err(e) => debug!("No saved layout to update for {}: {:#}", session_name, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Settings shared by every terminal launched while restoring a layout
+struct RestoreContext<'a> {
+    backend: &'a WmBackend,
+    config: &'a Config,
+    /// Host of the session ("local" for local sessions); terminals run here
+    /// unless their layout entry names another host
+    host: &'a str,
+    /// Session the workspace is saved as (None for workspaces bound without one)
+    session_name: Option<&'a str>,
+    /// Workspace the layout is restored into
+    workspace_name: &'a str,
+    workspace_num: i32,
+    /// Commands to start in terminals whose session is gone, keyed by socket
+    restore_commands: HashMap<String, String>,
+    /// Window titles captured at detach, keyed by socket
+    titles: HashMap<String, String>,
+    /// Hold each terminal's attach until its window is focused (`attach --lazy`)
+    lazy: bool,
+    /// Hosts that couldn't be reached, with the error: their terminals are placeholders
+    unreachable: HashMap<String, String>,
+    /// Container of the most recently launched terminal
+    last_container: Cell<Option<u64>>,
+}
+
+impl RestoreContext<'_> {
+    /// Run layout commands relative to the most recently launched terminal
+    ///
+    /// Focus is re-established on that terminal in the same WM command, so the
+    /// commands are unaffected by the user switching workspaces mid-attach.
+    fn run_relative(&self, commands: &[&str]) -> Result<()> {
+        let chain = layout::relative_command(self.last_container.get(), commands);
+        timings::time("wm: layout commands", || self.backend.run_command(&chain))
+    }
+}
+
+fn restore_layout(ctx: &RestoreContext, session: &RemoteSession) -> Result<()> {
     let sockets = session.layout.get_sockets();
     println!("Restoring layout with {} terminals...", sockets.len());
 
-    // Use recursive restore that properly handles nested layouts
-    restore_layout_recursive(backend, &session.layout, remote_host)?;
+    restore_layout_structure(ctx, session.layout.restore_plan())?;
+
+    // Then restore split sizes, translated for the WM we're restoring into
+    apply_sizes(ctx.backend, &session.layout, ctx.host);
 
+    resync_terminal_sizes(ctx, &session.layout);
     Ok(())
 }
 
-/// Recursively restore a layout by walking the tree and creating the proper structure
-fn restore_layout_recursive(
-    backend: &WmBackend,
-    layout: &Layout,
-    remote_host: &str,
-) -> Result<()> {
-    match layout {
-        Layout::Terminal { socket, .. } => {
-            // Launch and wait for this terminal
-            launch_terminal_for_socket(backend, remote_host, socket)?;
-        }
-        Layout::HSplit { children, .. } => {
-            // Restore first child
-            if let Some(first) = children.first() {
-                restore_layout_recursive(backend, first, remote_host)?;
-            }
-            // Set split mode ONCE, then create all remaining children
-            // They will join the same horizontal split container as equal siblings
-            if children.len() > 1 {
-                backend.run_command("split h")?;
-                for child in children.iter().skip(1) {
-                    restore_layout_recursive(backend, child, remote_host)?;
-                }
-            }
+/// Resize the terminals of a restored or live layout to its recorded split sizes
+fn apply_sizes(backend: &WmBackend, layout: &Layout, default_host: &str) {
+    let hosts = layout.get_terminal_hosts(default_host);
+    for resize in compat::resize_plan(layout, backend.wm_type()) {
+        let mark = I3muxWindow::mark_from_parts(&hosts[&resize.socket], &resize.socket);
+        let cmd = format!("[con_mark=\"^{}$\"] {}", mark, resize.command);
+        if let Err(e) = timings::time("wm: resize", || backend.run_command(&cmd)) {
+            debug!("Resize of {} failed: {:#}", resize.socket, e);
         }
-        Layout::VSplit { children, .. } => {
-            // Restore first child
-            if let Some(first) = children.first() {
-                restore_layout_recursive(backend, first, remote_host)?;
-            }
-            // Set split mode ONCE, then create all remaining children
-            if children.len() > 1 {
-                backend.run_command("split v")?;
-                for child in children.iter().skip(1) {
-                    restore_layout_recursive(backend, child, remote_host)?;
-                }
+    }
+}
+
+/// Make the focused terminal fullscreen, or put the zoomed one back
+///
+/// The layout is recorded on zoom, so un-zooming can put the split sizes back
+/// as they were even if something resized panes in the meantime. They are
+/// left alone if terminals came or went while zoomed.
+fn zoom() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+    let focused = window::find_focused_i3mux_window(&backend)?.context("The focused window is not an i3mux terminal")?;
+
+    let binding = lockfile::lock_workspace(&ws_name)?;
+    let mut state = LocalState::load()?;
+    let ws_state = state.workspaces.get_mut(&ws_name).ok_or(error::Error::NotBound)?;
+    match ws_state.zoomed.take() {
+        Some(before) => {
+            backend.run_command_on_container(focused.window_id, "fullscreen disable")?;
+            let now = Layout::capture_from_workspace_num(ws_num, &backend)?;
+            let sockets = |layout: &Layout| layout.get_sockets().into_iter().collect::<HashSet<_>>();
+            if now.is_some_and(|now| sockets(&now) == sockets(&before)) {
+                apply_sizes(&backend, &before, &ws_state.host);
             }
         }
-        Layout::Tabbed { children } => {
-            // Restore first child
-            if let Some(first) = children.first() {
-                restore_layout_recursive(backend, first, remote_host)?;
-            }
+        None => {
+            ws_state.zoomed = Layout::capture_from_workspace_num(ws_num, &backend)?;
+            backend.run_command_on_container(focused.window_id, "fullscreen enable")?;
+        }
+    }
+    state.save()?;
+    drop(binding);
+    Ok(())
+}
 
-            if children.len() > 1 {
-                // For nested containers, go up to workspace/parent container level
-                // We need to go past the split container to set tabbed on its parent
-                let first_is_container = matches!(
-                    children.first(),
-                    Some(Layout::HSplit { .. } | Layout::VSplit { .. } | Layout::Tabbed { .. } | Layout::Stacked { .. })
-                );
-                if first_is_container {
-                    // Go from leaf to split container, then to workspace
-                    backend.run_command("focus parent")?;
-                    backend.run_command("focus parent")?;
-                }
+/// Exchange the focused terminal with another, in the WM and in the saved session
+fn swap(with: Option<String>, direction: Option<window::Direction>) -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+    let focused = window::find_focused_i3mux_window(&backend)?.context("The focused window is not an i3mux terminal")?;
+    let terminals = window::find_i3mux_windows_in_workspace(ws_num, &backend)?;
+
+    let other = match (with, direction) {
+        (Some(socket), _) => terminals
+            .into_iter()
+            .find(|w| w.socket == socket)
+            .with_context(|| format!("No terminal {} in workspace {}", socket, ws_num))?,
+        (None, direction) => {
+            // Let the WM find the neighbour, then put focus back
+            let direction = direction.context("--with or --direction is required")?;
+            backend.run_command(&format!("focus {}", direction))?;
+            let neighbour = window::find_focused_i3mux_window(&backend)?;
+            backend.run_command_on_container(focused.window_id, "focus")?;
+            neighbour
+                .filter(|n| n.window_id != focused.window_id && terminals.iter().any(|w| w.window_id == n.window_id))
+                .with_context(|| format!("No i3mux terminal {} of this one", direction))?
+        }
+    };
+    if other.window_id == focused.window_id {
+        return Ok(());
+    }
 
-                // Set tabbed layout
-                backend.run_command("layout tabbed")?;
+    backend.run_command_on_container(focused.window_id, &format!("swap container with mark \"{}\"", other.mark()))?;
 
-                // For nested containers, ensure we're at the tabbed level before adding children
-                // The new children should be tabs, not nested inside the first child
-                if first_is_container {
-                    // "layout tabbed" keeps focus at workspace, but new windows might
-                    // still join the last focused child. Force focus back to workspace.
-                    backend.run_command("focus parent")?;
-                }
+    let state = LocalState::load()?;
+    if let Some(ws_state) = state.workspaces.get(&ws_name) {
+        let updated = workspace_connection(ws_state).and_then(|conn| {
+            update_saved_session(conn.as_ref(), &ws_name, ws_state, |session| {
+                session.layout.swap_terminals(&focused.socket, &other.socket)
+            })
+        });
+        if let Err(e) = updated {
+            eprintln!("Warning: could not update the saved session: {:#}", e);
+        }
+    }
+    Ok(())
+}
 
-                for child in children.iter().skip(1) {
-                    restore_layout_recursive(backend, child, remote_host)?;
-                }
+/// Record the current workspace's arrangement and start keeping it
+fn lock_layout(restore: bool) -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
+    let layout = Layout::capture_from_workspace_num(ws_num, &backend)?
+        .context("No i3mux terminals found in workspace")?;
+    let terminals = layout.get_sockets().len();
 
-                // For nested containers, focus the first tab for consistency
-                if first_is_container {
-                    // Go up to tabbed container level, then left to first tab
-                    backend.run_command("focus parent")?;
-                    backend.run_command("focus parent")?;
-                    backend.run_command("focus left")?;
-                }
-            }
-        }
-        Layout::Stacked { children } => {
-            // Restore first child
-            if let Some(first) = children.first() {
-                restore_layout_recursive(backend, first, remote_host)?;
-            }
+    let binding = lockfile::lock_workspace(&ws_name)?;
+    let mut state = LocalState::load()?;
+    let ws_state = state.workspaces.get_mut(&ws_name).ok_or(error::Error::NotBound)?;
+    ws_state.layout_lock = Some(layoutlock::LockedLayout { layout, restore });
+    state.save()?;
+    drop(binding);
 
-            if children.len() > 1 {
-                // For nested containers, go up to workspace/parent container level
-                let first_is_container = matches!(
-                    children.first(),
-                    Some(Layout::HSplit { .. } | Layout::VSplit { .. } | Layout::Tabbed { .. } | Layout::Stacked { .. })
-                );
-                if first_is_container {
-                    backend.run_command("focus parent")?;
-                    backend.run_command("focus parent")?;
-                }
+    start_layout_watch(&ws_name);
+    let action = if restore { "moved back" } else { "reported" };
+    println!("{} Layout of workspace {} locked ({} terminals; moves are {})", style::ok(), ws_num, terminals, action);
+    Ok(())
+}
 
-                // Set stacked layout
-                backend.run_command("layout stacking")?;
+/// Stop keeping the current workspace's arrangement
+fn unlock_layout() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (ws_name, ws_num) = get_focused_workspace(&backend)?;
 
-                // For nested containers, ensure we're at the stacked level before adding children
-                if first_is_container {
-                    backend.run_command("focus parent")?;
-                }
+    let binding = lockfile::lock_workspace(&ws_name)?;
+    let mut state = LocalState::load()?;
+    let ws_state = state.workspaces.get_mut(&ws_name).ok_or(error::Error::NotBound)?;
+    if ws_state.layout_lock.take().is_none() {
+        println!("Layout of workspace {} is not locked", ws_num);
+        return Ok(());
+    }
+    state.save()?;
+    drop(binding);
 
-                for child in children.iter().skip(1) {
-                    restore_layout_recursive(backend, child, remote_host)?;
-                }
+    println!("{} Layout of workspace {} unlocked", style::ok(), ws_num);
+    Ok(())
+}
 
-                // For nested containers, focus the first item for consistency
-                if first_is_container {
-                    backend.run_command("focus parent")?;
-                    backend.run_command("focus parent")?;
-                    backend.run_command("focus up")?;
-                }
+/// Give every pane of the current workspace's splits an equal share
+fn even() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (_, ws_num) = get_focused_workspace(&backend)?;
+    let layout = Layout::capture_from_workspace_num(ws_num, &backend)?
+        .context("No i3mux terminals found in workspace")?;
+    // Captured terminals always carry their host
+    apply_sizes(&backend, &layout.evened(), "local");
+    Ok(())
+}
+
+/// Flip the orientation of the current workspace's outermost split
+///
+/// Containers with a single child are looked through, since that is where
+/// the first real split sits. Sizes carry over to the new axis.
+fn rotate() -> Result<()> {
+    let backend = WmBackend::connect()?;
+    let (_, ws_num) = get_focused_workspace(&backend)?;
+    let tree = backend.get_tree()?;
+    let mut node = window::find_workspace_node(&tree, ws_num).context("No focused workspace found")?;
+    while let [only] = node.nodes.as_slice() {
+        node = only;
+    }
+    let Some(child) = node.nodes.first() else {
+        anyhow::bail!("Nothing to rotate: the workspace has fewer than two windows");
+    };
+    if !matches!(node.layout.as_str(), "splith" | "splitv") {
+        anyhow::bail!("The outermost container is {}, not a split", node.layout);
+    }
+    // A layout command on a container applies to its parent
+    backend.run_command_on_container(child.id, "layout toggle split")
+}
+
+/// Have this machine's abduco clients resend their window size
+///
+/// abduco sizes a session to whichever client last reported its size, which
+/// may be a client on a differently sized screen; terminals resized by the
+/// layout only report once the WM is done with them.
+fn resync_terminal_sizes(ctx: &RestoreContext, layout: &Layout) {
+    std::thread::sleep(std::time::Duration::from_millis(RESIZE_SETTLE_MS));
+
+    let attach_commands = layout.get_attach_commands();
+    for (host, sockets) in layout.get_sockets_by_host(ctx.host) {
+        // Custom attach terminals have no abduco client
+        let sockets: Vec<String> = sockets.into_iter().filter(|s| !attach_commands.contains_key(s)).collect();
+        if sockets.is_empty() || ctx.unreachable.contains_key(&host) {
+            continue;
+        }
+        let resized = timings::time("ssh: resize", || {
+            host_connection(&host).and_then(|conn| conn.resize_clients(&sockets, &connection::client_id()))
+        });
+        if let Err(e) = resized {
+            debug!("Could not resync terminal sizes on {}: {:#}", host, e);
+        }
+    }
+}
+
+/// Restore a layout's structure by launching its terminals and arranging them
+///
+/// Structural commands are always issued relative to the most recently launched
+/// terminal (see `RestoreContext::run_relative`), never the current focus.
+fn restore_layout_structure(ctx: &RestoreContext, plan: Vec<RestoreStep>) -> Result<()> {
+    for step in plan {
+        match step {
+            RestoreStep::Launch { socket, host, attach } => {
+                let host = host.unwrap_or(ctx.host);
+                let container_id = launch_terminal_for_socket(ctx, host, socket, attach)?;
+                ctx.last_container.set(Some(container_id));
             }
+            RestoreStep::Commands(commands) => ctx.run_relative(&commands)?,
         }
     }
     Ok(())
 }
 
-/// Launch a terminal for a specific socket and wait for it to appear
-fn launch_terminal_for_socket(
-    backend: &WmBackend,
-    remote_host: &str,
-    socket_id: &str,
-) -> Result<()> {
-    let title = format!("{}{}:{}", MARKER, remote_host, socket_id);
-    let instance = I3muxWindow::mark_from_parts(remote_host, socket_id);
+/// Environment variable holding the error a placeholder terminal shows
+const PLACEHOLDER_ENV: &str = "I3MUX_PLACEHOLDER";
 
-    let attach_cmd = format!(
-        r#"TERM=xterm-256color ssh -o ControlPath=/tmp/i3mux/sockets/%r@%h:%p -o ControlMaster=auto -o ControlPersist=10m -t {} 'exec bash -lc "{} attach {}"'"#,
-        remote_host, REMOTE_HELPER_PATH, socket_id
-    );
+/// Stand-in for a terminal whose host is down: a local shell explaining why
+fn placeholder_command(host: &str) -> String {
+    format!(
+        r#"printf '%s\n\n' "${env}"; echo 'Once {host} is back, run "i3mux respawn" here (or bind it to a key) to attach this terminal.'; exec "${{SHELL:-bash}}""#,
+        env = PLACEHOLDER_ENV,
+        host = host
+    )
+}
 
-    let wrapper = format!(
-        r#"echo -ne '\033]0;{}\007'; {}; echo 'Session ended.'"#,
-        title, attach_cmd
-    );
+/// Launch a terminal for a specific socket and wait for it to appear
+///
+/// Terminals with a custom `attach` command re-run it instead of attaching to abduco.
+fn launch_terminal_for_socket(ctx: &RestoreContext, host: &str, socket_id: &str, attach: Option<&str>) -> Result<u64> {
+    let backend = ctx.backend;
+    let title = ctx.config.title_for(host, socket_id);
+    let instance = I3muxWindow::launch_instance(host, socket_id);
+    let restore_command = ctx.restore_commands.get(socket_id);
 
     let terminal = get_terminal_command(backend.wm_type());
     let instance_args = build_terminal_instance_args(&terminal, &instance, backend.wm_type());
-
     let mut cmd = Command::new(&terminal);
-    cmd.args(&instance_args)
-        .arg("-T")
-        .arg(&title)
-        .arg("-e")
+    cmd.env(connection::CLIENT_ENV, connection::client_id());
+
+    let attach_spec = match attach {
+        Some(attach) => CommandSpec { line: attach.to_string(), env: Vec::new() },
+        None => AttachCommand {
+            config: ctx.config,
+            host,
+            socket: socket_id,
+            session: ctx.session_name,
+            user_shell: &get_user_shell(),
+            client: &connection::client_id(),
+        }
+        .restore(restore_command.map(String::as_str)),
+    };
+    cmd.envs(attach_spec.env);
+    let attach_cmd = if let Some(reason) = ctx.unreachable.get(host) {
+        cmd.env(PLACEHOLDER_ENV, reason);
+        placeholder_command(host)
+    } else if ctx.lazy {
+        cmd.env(lazy::BIN_ENV, i3mux_binary()).env(lazy::WORKSPACE_ENV, ctx.workspace_name);
+        format!("{} && {}", lazy::placeholder(host, socket_id), attach_spec.line)
+    } else {
+        attach_spec.line
+    };
+
+    // Without marker titles the title bar follows the terminal's own title, so
+    // it starts at the one captured at detach until the shell sets another
+    let captured = ctx.titles.get(socket_id).map(|t| t.replace(char::is_control, " "));
+    let initial_title = title.clone().or_else(|| captured.clone());
+    let set_title = initial_title
+        .as_ref()
+        .map(|t| format!(r#"printf '\033]0;%s\007' '{}'; "#, t.replace('\'', r"'\''")))
+        .unwrap_or_default();
+    let wrapper = format!("{}{}; echo 'Session ended.'", set_title, attach_cmd);
+
+    cmd.args(&instance_args);
+    if let Some(title) = &initial_title {
+        cmd.arg("-T").arg(title);
+    }
+    cmd.arg("-e")
         .arg("bash")
         .arg("-c")
         .arg(&wrapper);
 
-    cmd.spawn().context("Failed to spawn terminal for layout restore")?;
+    exec::current().spawn(&mut cmd).context("Failed to spawn terminal for layout restore")?;
 
     // Wait for window to appear and apply i3mux mark
-    wait_for_window_and_mark(backend, &instance, remote_host, socket_id)?;
-
-    Ok(())
+    let container_id = timings::time("wm: wait for window", || {
+        wait_for_window_and_mark(backend, &instance, host, socket_id, ctx.workspace_num)
+    })?;
+    // Marker titles give way to the captured title (or the name a spec or
+    // layout expression gave the terminal), so tab bars read well right away
+    let mut theme = ctx.config.theme_for(host, ctx.session_name);
+    theme.title = title.map(|marker| captured.unwrap_or(marker));
+    window::apply_theme(backend, container_id, &theme)?;
+
+    Ok(container_id)
 }
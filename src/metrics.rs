@@ -0,0 +1,172 @@
+//! Local operation metrics (opt-in via `metrics = true`)
+//!
+//! Durations and failures of attach, detach and activate are kept per host in
+//! `~/.local/state/i3mux/metrics.json`. Nothing leaves the machine; the file
+//! only feeds `i3mux stats`, which shows which hosts are slow to attach.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// Durations kept per operation and host (older ones are dropped)
+const MAX_SAMPLES: usize = 500;
+
+/// Recorded durations and failures, keyed by operation, then host
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Metrics {
+    operations: BTreeMap<String, BTreeMap<String, OperationStats>>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OperationStats {
+    /// Durations of successful runs in milliseconds, oldest first
+    durations_ms: Vec<u64>,
+    failures: u32,
+}
+
+impl OperationStats {
+    fn record(&mut self, duration: Duration, succeeded: bool) {
+        if !succeeded {
+            self.failures += 1;
+            return;
+        }
+        self.durations_ms.push(duration.as_millis() as u64);
+        if self.durations_ms.len() > MAX_SAMPLES {
+            self.durations_ms.drain(..self.durations_ms.len() - MAX_SAMPLES);
+        }
+    }
+
+    /// Duration below which `p` percent of successful runs finished
+    fn percentile(&self, p: f64) -> Option<u64> {
+        let mut sorted = self.durations_ms.clone();
+        sorted.sort_unstable();
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+impl Metrics {
+    fn path() -> Result<PathBuf> {
+        let state_dir = dirs::state_dir()
+            .or_else(|| dirs::home_dir().map(|home| home.join(".local/state")))
+            .context("Could not find state directory")?
+            .join("i3mux");
+        fs::create_dir_all(&state_dir)?;
+        Ok(state_dir.join("metrics.json"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path)?;
+        serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::write(Self::path()?, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn record(&mut self, operation: &str, host: &str, duration: Duration, succeeded: bool) {
+        self.operations
+            .entry(operation.to_string())
+            .or_default()
+            .entry(host.to_string())
+            .or_default()
+            .record(duration, succeeded);
+    }
+
+    /// Table rows for `i3mux stats`
+    pub fn report(&self) -> Vec<String> {
+        let mut rows = vec![format!(
+            "{:<10} {:<24} {:>5} {:>6} {:>8} {:>8} {:>8}",
+            "OPERATION", "HOST", "RUNS", "FAILED", "P50", "P90", "P99"
+        )];
+        for (operation, hosts) in &self.operations {
+            for (host, stats) in hosts {
+                let ms = |p| stats.percentile(p).map_or_else(|| "-".to_string(), |v| format!("{}ms", v));
+                rows.push(format!(
+                    "{:<10} {:<24} {:>5} {:>6} {:>8} {:>8} {:>8}",
+                    operation,
+                    host,
+                    stats.durations_ms.len() as u32 + stats.failures,
+                    stats.failures,
+                    ms(50.0),
+                    ms(90.0),
+                    ms(99.0)
+                ));
+            }
+        }
+        rows
+    }
+}
+
+/// A running operation; recorded as failed unless `succeeded` is called
+///
+/// Dropping the guard (including on early return via `?`) records the
+/// outcome, if metrics are enabled in the config.
+pub struct Operation {
+    name: &'static str,
+    host: String,
+    started: Instant,
+    succeeded: bool,
+}
+
+impl Operation {
+    pub fn start(name: &'static str, host: &str) -> Self {
+        Self { name, host: host.to_string(), started: Instant::now(), succeeded: false }
+    }
+
+    pub fn succeeded(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        if !Config::load().map(|c| c.metrics).unwrap_or(false) {
+            return;
+        }
+        let mut metrics = Metrics::load().unwrap_or_default();
+        metrics.record(self.name, &self.host, self.started.elapsed(), self.succeeded);
+        // Metrics are best effort; never fail the operation over them
+        let _ = metrics.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut stats = OperationStats::default();
+        for ms in (10..=1000).step_by(10) {
+            stats.record(Duration::from_millis(ms), true);
+        }
+        stats.record(Duration::from_secs(30), false);
+
+        assert_eq!(stats.percentile(50.0), Some(500));
+        assert_eq!(stats.percentile(90.0), Some(900));
+        assert_eq!(stats.percentile(100.0), Some(1000));
+        assert_eq!(stats.failures, 1);
+        assert_eq!(OperationStats::default().percentile(50.0), None);
+    }
+
+    #[test]
+    fn test_samples_are_capped() {
+        let mut stats = OperationStats::default();
+        for ms in 0..(MAX_SAMPLES as u64 + 10) {
+            stats.record(Duration::from_millis(ms), true);
+        }
+        assert_eq!(stats.durations_ms.len(), MAX_SAMPLES);
+        assert_eq!(stats.durations_ms[0], 10);
+    }
+}
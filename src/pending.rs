@@ -0,0 +1,179 @@
+//! Detaches waiting for an unreachable host
+//!
+//! When `detach` can't save a session because its host is unreachable, the
+//! captured session is queued in `~/.config/i3mux/pending/<host>/<session>.json`
+//! and the workspace is detached anyway. The next command that reaches the
+//! host (`attach`, `sessions`, `detach` or `i3mux sync`) uploads it.
+//!
+//! A queued session carries the revision its workspace last saved, and only
+//! replaces the host's copy if that copy is still at this revision (or
+//! missing). Otherwise it was saved from elsewhere in the meantime, and the
+//! queued session stays put as a conflict until `i3mux sync --force` uploads
+//! it or `i3mux sync --discard` drops it. Sessions queued with revision 0
+//! (workspaces not attached from a saved session) have nothing to compare.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::connection::Connection;
+use crate::session::RemoteSession;
+
+/// What syncing did with a queued session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Synced {
+    /// Uploaded to the host and dropped from the queue
+    Saved,
+    /// Left queued: the host's copy was saved from elsewhere after the attach
+    Conflict,
+}
+
+/// The queue of sessions waiting for their hosts
+pub struct Queue {
+    dir: PathBuf,
+}
+
+impl Queue {
+    /// The queue in the config directory
+    pub fn open() -> Result<Self> {
+        let dir = crate::instance::state_dir()?.join("pending");
+        Ok(Self { dir })
+    }
+
+    #[cfg(test)]
+    fn at(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Queue `session` for its host, replacing an older copy
+    ///
+    /// Its revision should be the one its workspace last saved.
+    pub fn push(&self, session: &RemoteSession) -> Result<PathBuf> {
+        let dir = self.dir.join(&session.host);
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(format!("{}.json", session.name));
+        fs::write(&path, serde_json::to_string_pretty(session)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Hosts with queued sessions
+    pub fn hosts(&self) -> Result<Vec<String>> {
+        let mut hosts: Vec<String> = read_dir(&self.dir)?
+            .into_iter()
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        hosts.sort();
+        Ok(hosts)
+    }
+
+    /// Sessions queued for `host`, by name
+    pub fn sessions(&self, host: &str) -> Result<Vec<RemoteSession>> {
+        let mut sessions = Vec::new();
+        for entry in read_dir(&self.dir.join(host))? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let contents = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let session: RemoteSession =
+                serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))?;
+            sessions.push(session);
+        }
+        sessions.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(sessions)
+    }
+
+    /// Drop the queued copy of session `name` on `host`, if any
+    pub fn remove(&self, host: &str, name: &str) -> Result<()> {
+        let dir = self.dir.join(host);
+        match fs::remove_file(dir.join(format!("{}.json", name))) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).context("Failed to remove queued session"),
+        }
+        // The host's directory goes with its last session
+        let _ = fs::remove_dir(&dir);
+        Ok(())
+    }
+
+    /// Upload the sessions queued for `host` over `conn`
+    ///
+    /// With `force`, sessions are uploaded even if the host's copy changed.
+    pub fn sync(&self, host: &str, conn: &dyn Connection, force: bool) -> Result<Vec<(String, Synced)>> {
+        let queued = self.sessions(host)?;
+        if queued.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut results = Vec::new();
+        for mut session in queued {
+            if let Some(current) = RemoteSession::find_on_remote(conn, &session.name)? {
+                if !force && session.revision != 0 && current.revision != session.revision {
+                    results.push((session.name, Synced::Conflict));
+                    continue;
+                }
+                session.revision = current.revision;
+            }
+            session.save_to_remote(conn)?;
+            // Detach couldn't release the lock it was queued instead of saving under
+            let _ = conn.release_lock(&session.name);
+            self.remove(host, &session.name)?;
+            results.push((session.name, Synced::Saved));
+        }
+        Ok(results)
+    }
+}
+
+/// Entries of `dir` (none if it doesn't exist)
+fn read_dir(dir: &std::path::Path) -> Result<Vec<fs::DirEntry>> {
+    match fs::read_dir(dir) {
+        Ok(entries) => Ok(entries.collect::<std::io::Result<_>>()?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::MemoryConnection;
+    use crate::layout::Layout;
+
+    fn session(name: &str, socket: &str, revision: u64) -> RemoteSession {
+        let layout = Layout::Terminal { socket: socket.into(), percent: None, command: None, host: None, attach: None, target: None, title: None };
+        RemoteSession { revision, ..RemoteSession::new(name.into(), "2".into(), "user@box".into(), layout).unwrap() }
+    }
+
+    #[test]
+    fn test_sync_uploads_unless_changed_remotely() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue = Queue::at(dir.path().to_path_buf());
+        let conn = MemoryConnection::default();
+
+        // "dev" is still at the revision queued, "ops" was saved from elsewhere since
+        session("dev", "ws2-001", 2).save_to_remote(&conn).unwrap();
+        session("ops", "ws2-009", 3).save_to_remote(&conn).unwrap();
+
+        for queued in [session("dev", "ws2-002", 3), session("ops", "ws2-003", 3), session("new", "ws2-004", 0)] {
+            queue.push(&queued).unwrap();
+        }
+        assert_eq!(queue.hosts().unwrap(), ["user@box"]);
+
+        let results = queue.sync("user@box", &conn, false).unwrap();
+        assert_eq!(
+            results,
+            [("dev".into(), Synced::Saved), ("new".into(), Synced::Saved), ("ops".into(), Synced::Conflict)]
+        );
+        let dev = RemoteSession::load_from_remote(&conn, "dev").unwrap();
+        assert_eq!((dev.layout.get_sockets(), dev.revision), (vec!["ws2-002".to_string()], 4));
+        assert_eq!(RemoteSession::load_from_remote(&conn, "ops").unwrap().layout.get_sockets(), ["ws2-009"]);
+        assert_eq!(queue.sessions("user@box").unwrap().len(), 1);
+
+        // Forcing replaces the conflicting copy and empties the queue
+        assert_eq!(queue.sync("user@box", &conn, true).unwrap(), [("ops".into(), Synced::Saved)]);
+        assert_eq!(RemoteSession::load_from_remote(&conn, "ops").unwrap().layout.get_sockets(), ["ws2-003"]);
+        assert!(queue.hosts().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+//! i3mux terminals for window switchers (`i3mux query windows`)
+//!
+//! A switcher (a rofi or fuzzel script) runs its query on every keypress that
+//! opens it, so the answer comes from a cache rather than the WM's tree. The
+//! first query starts `i3mux _watch-windows` in the background, which keeps
+//! `windows.json` in the per-user directory current from the WM's window and
+//! workspace events. While no watcher holds its lock (before the first query,
+//! or after the WM restarted) queries read the tree themselves.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::connection;
+use crate::ipc::WmEvent;
+use crate::lockfile::FileLock;
+use crate::window::I3muxWindow;
+use crate::wm::{WmBackend, WmNode};
+
+/// An i3mux terminal, with what a switcher shows about it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowEntry {
+    /// Container ID (`[con_id=...] focus` switches to it)
+    pub id: u64,
+    /// Name of the workspace the window is on
+    pub workspace: String,
+    pub host: String,
+    pub socket: String,
+    /// Session the workspace is attached to, if any
+    pub session: Option<String>,
+    /// Current window title
+    pub title: Option<String>,
+    pub focused: bool,
+}
+
+impl WindowEntry {
+    /// One tab-separated line: id, workspace, host, socket, session and title
+    pub fn line(&self) -> String {
+        let clean = |s: &str| s.replace(['\t', '\n'], " ");
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.id,
+            clean(&self.workspace),
+            self.host,
+            self.socket,
+            self.session.as_deref().unwrap_or("-"),
+            clean(self.title.as_deref().unwrap_or(""))
+        )
+    }
+
+    /// The workspace's key in the local state: its number ("4" for "4:web")
+    fn workspace_key(&self) -> String {
+        self.workspace.split(':').next().unwrap_or_default().to_string()
+    }
+}
+
+fn cache_path() -> Result<PathBuf> {
+    Ok(connection::ensure_user_dir()?.join("windows.json"))
+}
+
+fn watch_lock_path() -> Result<PathBuf> {
+    Ok(connection::ensure_user_dir()?.join("windows-watch.lock"))
+}
+
+/// The i3mux terminals, and whether a watcher is keeping the cache current
+pub fn windows() -> Result<(Vec<WindowEntry>, bool)> {
+    // The watcher holds its lock for as long as it runs
+    if FileLock::try_acquire(&watch_lock_path()?)?.is_none() {
+        if let Some(entries) = fs::read_to_string(cache_path()?).ok().and_then(|json| serde_json::from_str(&json).ok()) {
+            return Ok((entries, true));
+        }
+    }
+    let backend = WmBackend::connect()?;
+    Ok((snapshot(&backend)?, false))
+}
+
+/// Keep the cache current until the WM exits (`i3mux _watch-windows`)
+pub fn watch() -> Result<()> {
+    let Some(_watching) = FileLock::try_acquire(&watch_lock_path()?)? else {
+        return Ok(());
+    };
+    let backend = WmBackend::connect()?;
+    // Subscribe before the first snapshot, so no change falls in between
+    let events = backend.subscribe()?.into_channel();
+    write_cache(&snapshot(&backend)?)?;
+
+    while let Ok(event) = events.recv() {
+        match event? {
+            WmEvent::Window { .. } | WmEvent::Workspace => write_cache(&snapshot(&backend)?)?,
+            WmEvent::Shutdown => break,
+            _ => {}
+        }
+    }
+    // Stale from here on: queries read the tree until a watcher runs again
+    let _ = fs::remove_file(cache_path()?);
+    Ok(())
+}
+
+fn snapshot(backend: &WmBackend) -> Result<Vec<WindowEntry>> {
+    let tree = backend.get_tree().context("Failed to get window manager tree")?;
+    let state = crate::LocalState::load()?;
+    let mut entries = Vec::new();
+    collect(&tree, None, &mut entries);
+    for entry in &mut entries {
+        if let Some(ws) = state.workspaces.get(&entry.workspace_key()) {
+            entry.session = ws.session_name.clone();
+        }
+    }
+    Ok(entries)
+}
+
+/// Collect the i3mux windows under `node`, which is on `workspace`
+fn collect(node: &WmNode, workspace: Option<&str>, entries: &mut Vec<WindowEntry>) {
+    let workspace = if node.node_type == "workspace" { node.name.as_deref() } else { workspace };
+    if let Some(identity) = node.marks.iter().find_map(|mark| I3muxWindow::from_mark(mark)) {
+        entries.push(WindowEntry {
+            id: node.id,
+            workspace: workspace.unwrap_or_default().to_string(),
+            host: identity.host,
+            socket: identity.socket,
+            session: None,
+            title: node.name.clone(),
+            focused: node.focused,
+        });
+        return;
+    }
+    for child in node.children() {
+        collect(child, workspace, entries);
+    }
+}
+
+/// Replace the cache in one step, so queries never read half of it
+fn write_cache(entries: &[WindowEntry]) -> Result<()> {
+    let path = cache_path()?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(entries)?).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let mark = I3muxWindow::mark_from_parts("user@box", "ws4-001");
+        let tree: WmNode = serde_json::from_value(serde_json::json!({
+            "type": "root",
+            "nodes": [{
+                "type": "output",
+                "nodes": [{
+                    "type": "workspace", "name": "4:web", "num": 4,
+                    "nodes": [
+                        { "id": 11, "type": "con", "marks": [mark], "name": "vim\tmain.rs", "focused": true },
+                        { "id": 12, "type": "con", "name": "firefox" },
+                    ],
+                }],
+            }],
+        }))
+        .unwrap();
+
+        let mut entries = Vec::new();
+        collect(&tree, None, &mut entries);
+        assert_eq!(entries.len(), 1);
+        let entry = WindowEntry { session: Some("web".into()), ..entries.remove(0) };
+        assert_eq!(entry.workspace_key(), "4");
+        assert_eq!(entry.line(), "11\t4:web\tuser@box\tws4-001\tweb\tvim main.rs");
+    }
+}
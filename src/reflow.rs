@@ -0,0 +1,235 @@
+//! Reflowing saved layouts for a workspace of a different size
+//!
+//! A layout arranged on a large monitor can leave unusable slivers when
+//! attached on a small screen. Before restoring, the layout is checked against
+//! the target workspace's dimensions, and splits whose panes would fall below
+//! the configured minimum size are rewritten according to the reflow policy.
+//! Pane sizes account for the WM's inner gaps, which take their pixels off
+//! first (the workspace size already excludes the outer ones).
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::layout::Layout;
+use crate::wm::Gaps;
+
+/// What to do with splits whose panes would be too small
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReflowPolicy {
+    /// Restore the saved proportions unchanged
+    #[default]
+    Keep,
+    /// Turn undersized splits into tabbed containers
+    CollapseToTabs,
+    /// Rewrite undersized splits using the `[reflow.map]` config table
+    Map,
+}
+
+/// Container kinds a split can be rewritten to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerKind {
+    Hsplit,
+    Vsplit,
+    Tabbed,
+    Stacked,
+}
+
+/// Reflow settings from the `[reflow]` config table
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReflowConfig {
+    /// Policy used when `attach` is run without --reflow
+    pub policy: ReflowPolicy,
+    /// Narrowest acceptable pane in a horizontal split (pixels)
+    pub min_width: u32,
+    /// Shortest acceptable pane in a vertical split (pixels)
+    pub min_height: u32,
+    /// Replacement for each kind of undersized split (policy "map");
+    /// splits without an entry are collapsed to tabs
+    pub map: HashMap<ContainerKind, ContainerKind>,
+}
+
+impl Default for ReflowConfig {
+    fn default() -> Self {
+        Self {
+            policy: ReflowPolicy::Keep,
+            min_width: 400,
+            min_height: 150,
+            map: HashMap::new(),
+        }
+    }
+}
+
+/// Rewrite `layout` for a workspace of `width` x `height` pixels with `gaps`
+pub fn reflow(layout: &Layout, policy: ReflowPolicy, config: &ReflowConfig, width: f64, height: f64, gaps: Gaps) -> Layout {
+    if policy == ReflowPolicy::Keep {
+        return layout.clone();
+    }
+    let sizing = Sizing { config, gaps };
+    reflow_node(layout, policy, &sizing, width, height)
+}
+
+/// What pane sizes are checked against
+struct Sizing<'a> {
+    config: &'a ReflowConfig,
+    gaps: Gaps,
+}
+
+fn reflow_node(layout: &Layout, policy: ReflowPolicy, sizing: &Sizing, width: f64, height: f64) -> Layout {
+    let config = sizing.config;
+    let (kind, children, percent) = match layout {
+        Layout::Terminal { .. } => return layout.clone(),
+        Layout::HSplit { children, percent } => (ContainerKind::Hsplit, children, *percent),
+        Layout::VSplit { children, percent } => (ContainerKind::Vsplit, children, *percent),
+        Layout::Tabbed { children } => {
+            let children = children.iter().map(|c| reflow_node(c, policy, sizing, width, height)).collect();
+            return Layout::Tabbed { children };
+        }
+        Layout::Stacked { children } => {
+            let children = children.iter().map(|c| reflow_node(c, policy, sizing, width, height)).collect();
+            return Layout::Stacked { children };
+        }
+    };
+
+    let sizes = pane_sizes(children, kind, width, height, sizing.gaps);
+    let undersized = sizes.iter().any(|(w, h)| match kind {
+        ContainerKind::Hsplit => *w < config.min_width as f64,
+        _ => *h < config.min_height as f64,
+    });
+
+    let target = match (undersized, policy) {
+        (false, _) | (_, ReflowPolicy::Keep) => kind,
+        (true, ReflowPolicy::CollapseToTabs) => ContainerKind::Tabbed,
+        (true, ReflowPolicy::Map) => config.map.get(&kind).copied().unwrap_or(ContainerKind::Tabbed),
+    };
+
+    // Re-check the children against the space they get in the rewritten container
+    let sizes = if target == kind { sizes } else { pane_sizes(children, target, width, height, sizing.gaps) };
+    let children: Vec<Layout> = children
+        .iter()
+        .zip(sizes)
+        .map(|(c, (w, h))| reflow_node(c, policy, sizing, w, h))
+        .collect();
+
+    match target {
+        ContainerKind::Hsplit => Layout::HSplit { children, percent },
+        ContainerKind::Vsplit => Layout::VSplit { children, percent },
+        ContainerKind::Tabbed => Layout::Tabbed { children },
+        ContainerKind::Stacked => Layout::Stacked { children },
+    }
+}
+
+/// Pixel size of each child when laid out in a container of `kind`
+fn pane_sizes(children: &[Layout], kind: ContainerKind, width: f64, height: f64, gaps: Gaps) -> Vec<(f64, f64)> {
+    let equal = 1.0 / children.len().max(1) as f64;
+    children
+        .iter()
+        .map(|child| {
+            let fraction = match child {
+                Layout::Terminal { percent, .. } | Layout::HSplit { percent, .. } | Layout::VSplit { percent, .. } => {
+                    percent.unwrap_or(equal)
+                }
+                Layout::Tabbed { .. } | Layout::Stacked { .. } => equal,
+            };
+            match kind {
+                ContainerKind::Hsplit => (gaps.pane_size(width, children.len(), fraction), height),
+                ContainerKind::Vsplit => (width, gaps.pane_size(height, children.len(), fraction)),
+                ContainerKind::Tabbed | ContainerKind::Stacked => (width, height),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(socket: &str, percent: f64) -> Layout {
+        Layout::Terminal {
+            socket: socket.to_string(),
+            percent: Some(percent),
+            command: None,
+            host: None,
+            attach: None,
+            target: None,
+            title: None,
+        }
+    }
+
+    fn four_columns() -> Layout {
+        Layout::HSplit {
+            children: (1..=4).map(|i| term(&format!("ws1-00{}", i), 0.25)).collect(),
+            percent: None,
+        }
+    }
+
+    #[test]
+    fn test_keep_leaves_layout_alone() {
+        let layout = reflow(&four_columns(), ReflowPolicy::Keep, &ReflowConfig::default(), 1366.0, 768.0, Gaps::default());
+        assert!(matches!(layout, Layout::HSplit { .. }));
+    }
+
+    #[test]
+    fn test_collapse_only_when_undersized() {
+        let config = ReflowConfig::default();
+
+        // 4 x 960px columns on 4K are fine
+        let layout = reflow(&four_columns(), ReflowPolicy::CollapseToTabs, &config, 3840.0, 2160.0, Gaps::default());
+        assert!(matches!(layout, Layout::HSplit { .. }));
+
+        // 4 x 341px columns on a laptop are not
+        let layout = reflow(&four_columns(), ReflowPolicy::CollapseToTabs, &config, 1366.0, 768.0, Gaps::default());
+        assert!(matches!(&layout, Layout::Tabbed { children } if children.len() == 4));
+        assert_eq!(layout.get_sockets(), four_columns().get_sockets());
+    }
+
+    #[test]
+    fn test_gaps_count() {
+        let config = ReflowConfig::default();
+        let two_columns = Layout::HSplit { children: vec![term("ws1-001", 0.5), term("ws1-002", 0.5)], percent: None };
+
+        // 2 x 410px columns are fine without gaps, under 400px once gaps come off
+        let layout = reflow(&two_columns, ReflowPolicy::CollapseToTabs, &config, 820.0, 768.0, Gaps::default());
+        assert!(matches!(layout, Layout::HSplit { .. }));
+        let layout = reflow(&two_columns, ReflowPolicy::CollapseToTabs, &config, 820.0, 768.0, Gaps { inner: 30 });
+        assert!(matches!(layout, Layout::Tabbed { .. }));
+    }
+
+    #[test]
+    fn test_map_policy() {
+        let config = ReflowConfig {
+            map: HashMap::from([(ContainerKind::Hsplit, ContainerKind::Vsplit)]),
+            ..ReflowConfig::default()
+        };
+
+        // Columns become rows, which fit in 768px height (4 x 192px)
+        let layout = reflow(&four_columns(), ReflowPolicy::Map, &config, 1366.0, 768.0, Gaps::default());
+        assert!(matches!(layout, Layout::VSplit { .. }));
+
+        // Unmapped kinds collapse to tabs
+        let rows = Layout::VSplit {
+            children: (1..=4).map(|i| term(&format!("ws1-00{}", i), 0.25)).collect(),
+            percent: None,
+        };
+        let layout = reflow(&rows, ReflowPolicy::Map, &config, 1366.0, 400.0, Gaps::default());
+        assert!(matches!(layout, Layout::Tabbed { .. }));
+    }
+
+    #[test]
+    fn test_config_parsing() {
+        let config: ReflowConfig = toml::from_str(
+            r#"
+            policy = "collapse-to-tabs"
+            min_width = 500
+            map = { hsplit = "stacked" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.policy, ReflowPolicy::CollapseToTabs);
+        assert_eq!(config.min_width, 500);
+        assert_eq!(config.min_height, 150);
+        assert_eq!(config.map.get(&ContainerKind::Hsplit), Some(&ContainerKind::Stacked));
+    }
+}
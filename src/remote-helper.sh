@@ -5,40 +5,117 @@
 
 set -euo pipefail
 
-VERSION="1.0.4"
+VERSION="1.7.0"
+
+# Responses are one record per line on stdout: "@<type>", then tab-separated
+# key=value fields, with backslash, tab and newline in fields escaped as \\,
+# \t and \n. i3mux skips any other output (login banners, motd). Failures are
+# an "@error" record (code, message, optional hint) and exit status 1.
+PROTOCOL=1
+
+# Per-user directory for session files and locks (private to the user); i3mux
+# passes other locations for hosts with a storage_dir
+USER_DIR="/tmp/i3mux-$(id -u)"
+SESSIONS_DIR="${I3MUX_SESSIONS_DIR:-$USER_DIR/sessions}"
+LOCKS_DIR="${I3MUX_LOCKS_DIR:-$USER_DIR/locks}"
+
+# Print a response record
+# Usage: respond <type> [key=value]...
+respond() {
+    local record="@$1" field
+    shift
+
+    for field in "$@"; do
+        field="${field//\\/\\\\}"
+        field="${field//$'\t'/\\t}"
+        field="${field//$'\n'/\\n}"
+        record+=$'\t'"$field"
+    done
+    printf '%s\n' "$record"
+}
+
+# Report a failure and exit
+# Usage: fail <code> <message> [key=value]...
+fail() {
+    local code="$1" message="$2"
+    shift 2
+
+    respond error "code=$code" "message=$message" "$@"
+    exit 1
+}
+
+# Fail unless abduco is available
+require_abduco() {
+    command -v abduco &>/dev/null || fail abduco_missing "abduco not found" \
+        "hint=Install abduco on this host:
+  - Arch Linux: sudo pacman -S abduco
+  - Debian/Ubuntu: sudo apt install abduco
+  - Or build from source: https://github.com/martanne/abduco"
+}
 
 # Check if abduco is available (sources login profile for PATH)
+# Output: @deps abduco=<path>
 cmd_check_deps() {
-    if ! command -v abduco &>/dev/null; then
-        echo "ERROR: abduco not found" >&2
-        echo "Install abduco on this host:" >&2
-        echo "  - Arch Linux: sudo pacman -S abduco" >&2
-        echo "  - Debian/Ubuntu: sudo apt install abduco" >&2
-        echo "  - Or build from source: https://github.com/martanne/abduco" >&2
-        exit 1
-    fi
-    # Output path to abduco for verification
-    command -v abduco
+    require_abduco
+    respond deps "abduco=$(command -v abduco)"
 }
 
 # Attach to an abduco session (runs specified command or user's shell)
 # Usage: attach <socket> [-- <cmd>]
+#        attach <socket> --run <hex-cmd>
+#
+# --run restarts a command recorded at detach: if the session no longer exists,
+# the new shell runs the (hex-encoded) command first, then stays interactive.
 cmd_attach() {
     local socket="$1"
     shift
+    require_abduco
+
+    # Shell for a new session: I3MUX_SHELL (default $SHELL), a login shell with
+    # I3MUX_LOGIN=1, after running the hex-encoded I3MUX_RC
+    local shell="${I3MUX_SHELL:-$SHELL}"
+    local login=""
+    if [ "${I3MUX_LOGIN:-}" = 1 ]; then
+        login=" -l"
+    fi
+    local rc=""
+    if [ -n "${I3MUX_RC:-}" ]; then
+        rc="$(unhex "$I3MUX_RC"); "
+    fi
+    unset I3MUX_SHELL I3MUX_LOGIN I3MUX_RC
+
+    # A configured TERM (e.g. tmux-256color) may have no terminfo entry on this host
+    if command -v infocmp &>/dev/null && ! infocmp "${TERM:-dumb}" &>/dev/null; then
+        echo "i3mux: no terminfo entry for TERM=${TERM:-}, using xterm-256color" >&2
+        export TERM=xterm-256color
+    fi
 
     # Check for -- separator
     if [[ "${1:-}" == "--" ]]; then
         shift
         # Run the specified command in abduco
         exec abduco -A "/tmp/$socket" "$@"
+    elif [[ "${1:-}" == "--run" ]] && [ ! -e "/tmp/$socket" ]; then
+        local cmd
+        cmd=$(unhex "$2")
+        exec abduco -A "/tmp/$socket" "$shell" -c "${rc}${cmd}; exec ${shell}${login}"
+    elif [ -n "$rc" ] && [ ! -e "/tmp/$socket" ]; then
+        exec abduco -A "/tmp/$socket" "$shell" -c "${rc}exec ${shell}${login}"
+    elif [ -n "$login" ]; then
+        exec abduco -A "/tmp/$socket" "$shell" -l
     else
         # Default: run user's shell
-        exec abduco -A "/tmp/$socket" "$SHELL"
+        exec abduco -A "/tmp/$socket" "$shell"
     fi
 }
 
+# Decode a hex-encoded argument (used for commands that must survive nested quoting)
+unhex() {
+    printf '%b' "$(printf '%s' "$1" | sed 's/../\\x&/g')"
+}
+
 # Check if any abduco sessions exist for a workspace prefix, clean up if none
+# Output: @cleanup removed=<1 if the session files were removed, else 0>
 cmd_cleanup_check() {
     local ws_prefix="$1"
     local session="$2"
@@ -47,18 +124,232 @@ cmd_cleanup_check() {
     # (abduco sessions create socket files in /tmp/)
     if ls /tmp/${ws_prefix}-* &>/dev/null; then
         # Sessions still exist, don't clean up
-        exit 0
+        respond cleanup removed=0
     else
         # No sessions exist, safe to clean up session files
-        rm -f "/tmp/i3mux/sessions/${session}.json"
-        rm -f "/tmp/i3mux/locks/${session}.lock"
-        exit 0
+        rm -f "$SESSIONS_DIR/${session}.json"
+        rm -f "$LOCKS_DIR/${session}.lock"
+        respond cleanup removed=1
+    fi
+}
+
+# Find the abduco processes of a socket
+# Sets: shell (PID of the session's shell, empty if no session is running) and
+# clients (attached abduco clients)
+socket_processes() {
+    local pid pids
+
+    # Server and clients share the command line; the server is the one with a child (the shell)
+    shell=""
+    clients=0
+    pids=$(pgrep -f "^abduco -[aA] /tmp/${1}( |\$)" || true)
+    for pid in $pids; do
+        shell=$(pgrep -P "$pid" | head -n1 || true)
+        [ -n "$shell" ] && break
+    done
+    if [ -n "$shell" ]; then
+        clients=$(( $(echo "$pids" | wc -w) - 1 ))
+    fi
+}
+
+# List the abduco sockets of a workspace prefix (every workspace's without one)
+# Usage: list-sockets [prefix]
+# Output: @socket name= created= clients= shell= for each socket (created is the
+# socket's modification time in seconds since the epoch; shell is the PID of
+# the session's shell, or - for a socket whose session is gone)
+cmd_list_sockets() {
+    local socket_path name created shell clients
+
+    for socket_path in /tmp/${1:-ws*}-*; do
+        [ -S "$socket_path" ] || continue
+        name="${socket_path#/tmp/}"
+        created=$(stat -c %Y "$socket_path" 2>/dev/null || stat -f %m "$socket_path" 2>/dev/null || echo -)
+        socket_processes "$name"
+        respond socket "name=$name" "created=$created" "clients=$clients" "shell=${shell:--}"
+    done
+}
+
+# Terminate the abduco sessions behind sockets (and the processes inside them)
+# Usage: kill-sockets <socket>...   (globs like 'ws3-*' are expanded in /tmp)
+# Output: @killed socket=<socket> for each socket that existed
+cmd_kill_sockets() {
+    local pattern socket_path socket
+
+    for pattern in "$@"; do
+        for socket_path in /tmp/$pattern; do
+            socket="${socket_path#/tmp/}"
+            # The abduco server keeps the client's command line; killing it hangs up the shell
+            pkill -f "^abduco -A /tmp/${socket}( |\$)" || true
+            if [ -e "$socket_path" ]; then
+                rm -f "$socket_path"
+                respond killed "socket=$socket"
+            fi
+        done
+    done
+}
+
+# Report the foreground process of each socket's shell
+# Usage: ps <socket>...
+# Output: @process socket= pid= cpu= mem= busy= clients= command= for each socket
+# (pid is "-" when no abduco session is running for the socket; busy is 1 when
+# something other than the shell itself is in the foreground; clients counts the
+# attached abduco clients, so more than one means it is also viewed elsewhere)
+cmd_ps() {
+    local socket pid shell fg info busy clients cpu mem args
+
+    for socket in "$@"; do
+        socket_processes "$socket"
+        if [ -z "$shell" ]; then
+            respond process "socket=$socket" pid=- cpu=- mem=- busy=0 clients=0 command=-
+            continue
+        fi
+
+        # The tty's foreground process group is whatever the user is running
+        fg=$(ps -o tpgid= -p "$shell" | tr -d ' ' || true)
+        if [ -z "$fg" ] || [ "$fg" -le 0 ]; then
+            fg="$shell"
+        fi
+
+        info=$(ps -o pid=,pcpu=,pmem=,args= -p "$fg" || ps -o pid=,pcpu=,pmem=,args= -p "$shell" || true)
+        if [ -z "$info" ]; then
+            respond process "socket=$socket" pid=- cpu=- mem=- busy=0 clients=0 command=-
+            continue
+        fi
+        busy=0
+        [ "$fg" != "$shell" ] && busy=1
+        read -r pid cpu mem args <<< "$info"
+        respond process "socket=$socket" "pid=$pid" "cpu=$cpu" "mem=$mem" "busy=$busy" "clients=$clients" "command=$args"
+    done
+}
+
+# PIDs of the abduco clients attached to a socket
+client_pids() {
+    local pid
+
+    for pid in $(pgrep -f "^abduco -[aA] /tmp/${1}( |\$)" || true); do
+        # The server is the one with a child (the shell)
+        if [ -z "$(pgrep -P "$pid" || true)" ]; then
+            echo "$pid"
+        fi
+    done
+}
+
+# Exit 0 if an abduco client was attached with the given client ID
+# (clients carry I3MUX_CLIENT in their environment)
+is_client_of() {
+    local pid="$1" id="$2"
+
+    if [ -r "/proc/$pid/environ" ]; then
+        tr '\0' '\n' < "/proc/$pid/environ" | grep -qx "I3MUX_CLIENT=$id"
+    else
+        ps eww -o command= -p "$pid" | grep -qE "(^| )I3MUX_CLIENT=$id( |\$)"
+    fi
+}
+
+# Detach the abduco clients of a socket, except those attached from this client ID
+# Usage: boot <socket> [keep-client-id]
+# Output: @boot booted=<clients detached> (ones without a client ID are detached too)
+cmd_boot() {
+    local socket="$1" keep="${2:-}" pid booted=0
+
+    for pid in $(client_pids "$socket"); do
+        if [ -n "$keep" ] && is_client_of "$pid" "$keep"; then
+            continue
+        fi
+        kill "$pid" 2>/dev/null && booted=$((booted + 1))
+    done
+    respond boot "booted=$booted"
+}
+
+# Make a client ID's abduco clients resend their window size (abduco sizes the
+# session to the last client that did)
+# Usage: resize <client-id> <socket>...
+# Output: @resize signalled=<clients signalled>
+cmd_resize() {
+    local id="$1" socket pid signalled=0
+    shift
+
+    for socket in "$@"; do
+        for pid in $(client_pids "$socket"); do
+            if is_client_of "$pid" "$id"; then
+                kill -WINCH "$pid" 2>/dev/null && signalled=$((signalled + 1))
+            fi
+        done
+    done
+    respond resize "signalled=$signalled"
+}
+
+# Type input into abduco sessions as if it was entered at their terminals
+# (abduco -p); the input is hex-encoded to survive the ssh command line
+# Usage: send <socket> <hex-input> [<socket> <hex-input>...]
+# Output: @send sent=<sessions written to> (sockets without a session are skipped)
+cmd_send() {
+    local sent=0
+    require_abduco
+
+    while [ "$#" -ge 2 ]; do
+        if [ -e "/tmp/$1" ] && unhex "$2" | abduco -p "/tmp/$1" >/dev/null 2>&1; then
+            sent=$((sent + 1))
+        fi
+        shift 2
+    done
+    respond send "sent=$sent"
+}
+
+# State of a session's lock: held (its holder process is running), stale or free
+# Usage: lock-status <session>
+# Output: @lock state=<held|stale|free> pid=<holder pid, or - when free>
+cmd_lock_status() {
+    local pid state=free
+
+    pid=$(cat "$LOCKS_DIR/$1.lock.pid" 2>/dev/null || true)
+    if [ -n "$pid" ]; then
+        state=stale
+        if kill -0 "$pid" 2>/dev/null; then
+            state=held
+        fi
+    fi
+    respond lock "state=$state" "pid=${pid:--}"
+}
+
+# Stop a session's lock holder and remove its lock files
+# Usage: unlock <session>
+# Output: @unlock session=<session>
+cmd_unlock() {
+    local lock_file="$LOCKS_DIR/$1.lock" pid_file="$LOCKS_DIR/$1.lock.pid"
+
+    if [ -f "$pid_file" ]; then
+        kill "$(cat "$pid_file")" 2>/dev/null || true
+    fi
+    rm -f "$lock_file" "$pid_file"
+    respond unlock "session=$1"
+}
+
+# Wait until a session is no longer locked with a nonce: it was locked again
+# (by another client, or this one), detached or deleted, or another client
+# asked for it to be detached
+# Session files are gzip-compressed JSON, or plain JSON from older versions
+# Usage: watch-session <session> <nonce> [interval-seconds]
+# Output: @session state=<changed|deleted>
+cmd_watch_session() {
+    local file="$SESSIONS_DIR/$1.json" nonce="$2" interval="${3:-5}"
+
+    # No grep -q: gzip killed by SIGPIPE would fail the pipeline under pipefail
+    session_has() { gzip -cdf "$file" 2>/dev/null | grep "$1" >/dev/null; }
+    while session_has "\"nonce\": \"$nonce\"" && ! session_has '"detach_request"'; do
+        sleep "$interval"
+    done
+    if [ -e "$file" ]; then
+        respond session state=changed
+    else
+        respond session state=deleted
     fi
 }
 
 # Output version for script update detection
+# Output: @version version=<script version> protocol=<response protocol version>
 cmd_version() {
-    echo "$VERSION"
+    respond version "version=$VERSION" "protocol=$PROTOCOL"
 }
 
 # Main command dispatcher
@@ -74,11 +365,46 @@ case "${1:-}" in
         shift
         cmd_cleanup_check "$@"
         ;;
+    list-sockets)
+        shift
+        cmd_list_sockets "$@"
+        ;;
+    kill-sockets)
+        shift
+        cmd_kill_sockets "$@"
+        ;;
+    ps)
+        shift
+        cmd_ps "$@"
+        ;;
+    boot)
+        shift
+        cmd_boot "$@"
+        ;;
+    resize)
+        shift
+        cmd_resize "$@"
+        ;;
+    send)
+        shift
+        cmd_send "$@"
+        ;;
+    lock-status)
+        shift
+        cmd_lock_status "$@"
+        ;;
+    unlock)
+        shift
+        cmd_unlock "$@"
+        ;;
+    watch-session)
+        shift
+        cmd_watch_session "$@"
+        ;;
     version)
         cmd_version
         ;;
     *)
-        echo "Usage: $0 {check-deps|attach|cleanup-check|version}" >&2
-        exit 1
+        fail usage "Usage: $0 {check-deps|attach|cleanup-check|list-sockets|kill-sockets|ps|boot|resize|send|lock-status|unlock|watch-session|version}"
         ;;
 esac
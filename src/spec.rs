@@ -0,0 +1,352 @@
+//! Sessions declared in a spec file (`i3mux create --spec dev.toml`)
+//!
+//! A spec names the terminals, what each one runs and where, and arranges
+//! them with a layout expression: `h(v(editor,shell),logs)` puts `editor`
+//! above `shell` on the left and `logs` on the right. `h` and `v` are
+//! horizontal and vertical splits, `t` and `s` tabbed and stacked containers.
+//! A child of `h` or `v` may be given its share in percent,
+//! `h(60:v(editor,shell),40:logs)`; children without one split what is left.
+//! The same expressions open terminals directly (`activate --layout`,
+//! `grid --layout`).
+//!
+//! ```toml
+//! name = "dev"
+//! host = "user@box"
+//! layout = "h(v(editor,shell),logs)"
+//!
+//! [terminals.editor]
+//! command = "nvim"
+//! cwd = "~/src/app"
+//!
+//! [terminals.logs]
+//! command = "journalctl -f"
+//! ```
+//!
+//! Terminals the layout names without a `[terminals]` entry get a plain
+//! shell. The session is saved as if it had been detached; its commands start
+//! on the first attach.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::layout::Layout;
+
+/// A session as declared in a spec file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionSpec {
+    pub name: String,
+    /// `[user@]host` the terminals run on (None: local)
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Workspace recorded with the session (attach uses the focused one)
+    #[serde(default)]
+    pub workspace: Option<String>,
+    /// Layout expression over the terminal names
+    pub layout: String,
+    #[serde(default)]
+    pub terminals: BTreeMap<String, TerminalSpec>,
+}
+
+/// What one terminal of a spec starts with
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TerminalSpec {
+    /// Command run in the terminal's shell on the first attach
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Directory the shell starts in (`~/` is the home of the session's host)
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Window title until the terminal sets its own (default: the terminal's name)
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+impl SessionSpec {
+    /// Read a spec from a TOML file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid spec {}", path.display()))
+    }
+}
+
+impl TerminalSpec {
+    /// Shell command the terminal starts with: `command`, run in `cwd`
+    pub fn start_command(&self) -> Option<String> {
+        let quote = |s: &str| format!("'{}'", s.replace('\'', r"'\''"));
+        let cd = self.cwd.as_deref().map(|cwd| match cwd.strip_prefix("~/") {
+            Some(rest) => format!("cd ~/{}", quote(rest)),
+            None if cwd == "~" => "cd ~".to_string(),
+            None => format!("cd {}", quote(cwd)),
+        });
+        match (cd, self.command.as_deref()) {
+            (Some(cd), Some(command)) => Some(format!("{} && {}", cd, command)),
+            (cd, command) => cd.or(command.map(str::to_string)),
+        }
+    }
+}
+
+/// A parsed layout expression: a terminal name, or a container of expressions
+///
+/// `percent` is the share of the parent split (0.6 for `60:`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutExpr {
+    Terminal { name: String, percent: Option<f64> },
+    /// `kind` is one of `h`, `v`, `t`, `s`
+    Container { kind: char, percent: Option<f64>, children: Vec<LayoutExpr> },
+}
+
+impl LayoutExpr {
+    /// Parse e.g. `h(60:v(editor,shell),40:logs)`; errors give the column
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser { chars: text.chars().collect(), pos: 0 };
+        parser.skip_whitespace();
+        if parser.percent()?.is_some() {
+            anyhow::bail!("Size at column 1: only the children of h and v have sizes");
+        }
+        parser.pos = 0;
+        let expr = parser.expr()?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.peek() {
+            anyhow::bail!("Unexpected '{}' at column {}", c, parser.pos + 1);
+        }
+        Ok(expr)
+    }
+
+    /// Terminal names, left to right
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            LayoutExpr::Terminal { name, .. } => vec![name.as_str()],
+            LayoutExpr::Container { children, .. } => children.iter().flat_map(LayoutExpr::names).collect(),
+        }
+    }
+
+    fn percent(&self) -> Option<f64> {
+        match self {
+            LayoutExpr::Terminal { percent, .. } | LayoutExpr::Container { percent, .. } => *percent,
+        }
+    }
+
+    fn set_percent(&mut self, share: f64) {
+        match self {
+            LayoutExpr::Terminal { percent, .. } | LayoutExpr::Container { percent, .. } => *percent = Some(share),
+        }
+    }
+
+    /// The layout tree, with `terminal(name)` for each terminal (sizes are
+    /// filled in afterwards)
+    pub fn to_layout(&self, terminal: &mut impl FnMut(&str) -> Layout) -> Layout {
+        let (kind, share, children) = match self {
+            LayoutExpr::Terminal { name, percent: share } => {
+                let mut layout = terminal(name);
+                if let Layout::Terminal { percent, .. } = &mut layout {
+                    *percent = *share;
+                }
+                return layout;
+            }
+            LayoutExpr::Container { kind, percent, children } => (kind, *percent, children),
+        };
+        let children: Vec<Layout> = children.iter().map(|c| c.to_layout(terminal)).collect();
+        match kind {
+            'h' => Layout::HSplit { children, percent: share },
+            'v' => Layout::VSplit { children, percent: share },
+            't' => Layout::Tabbed { children },
+            _ => Layout::Stacked { children },
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// A `60:` size prefix, as a share; the position is left alone if there is none
+    fn percent(&mut self) -> Result<Option<f64>> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.pos += 1;
+        }
+        if self.pos == start || self.peek() != Some(':') {
+            self.pos = start;
+            return Ok(None);
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        match number.parse::<f64>() {
+            Ok(percent) if percent > 0.0 && percent <= 100.0 => {
+                self.pos += 1;
+                Ok(Some(percent / 100.0))
+            }
+            _ => anyhow::bail!("Invalid size '{}' at column {}: give a percentage above 0 and at most 100", number, start + 1),
+        }
+    }
+
+    fn expr(&mut self) -> Result<LayoutExpr> {
+        self.skip_whitespace();
+        let sized_at = self.pos + 1;
+        let percent = self.percent()?;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            self.pos += 1;
+        }
+        let word: String = self.chars[start..self.pos].iter().collect();
+        if word.is_empty() {
+            match self.peek() {
+                Some(c) => anyhow::bail!("Expected a terminal name at column {}, found '{}'", start + 1, c),
+                None => anyhow::bail!("Expected a terminal name at column {}, found the end", start + 1),
+            }
+        }
+
+        self.skip_whitespace();
+        if self.peek() != Some('(') {
+            return Ok(LayoutExpr::Terminal { name: word, percent });
+        }
+        let kind = match word.as_str() {
+            "h" | "hsplit" => 'h',
+            "v" | "vsplit" => 'v',
+            "t" | "tabbed" => 't',
+            "s" | "stacked" => 's',
+            _ => anyhow::bail!("Unknown container '{}' at column {}: use h, v, t or s", word, start + 1),
+        };
+        if percent.is_some() && matches!(kind, 't' | 's') {
+            anyhow::bail!("Size at column {}: tabbed and stacked containers can't be sized", sized_at);
+        }
+        self.pos += 1;
+
+        let mut children = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let child_at = self.pos + 1;
+            let child = self.expr()?;
+            if child.percent().is_some() && matches!(kind, 't' | 's') {
+                anyhow::bail!("Size at column {}: only the children of h and v have sizes", child_at);
+            }
+            children.push(child);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(')') => {
+                    self.pos += 1;
+                    share_remainder(&mut children, start + 1)?;
+                    return Ok(LayoutExpr::Container { kind, percent, children });
+                }
+                Some(c) => anyhow::bail!("Expected ',' or ')' at column {}, found '{}'", self.pos + 1, c),
+                None => anyhow::bail!("Missing ')' for the container at column {}", start + 1),
+            }
+        }
+    }
+}
+
+/// Give children without a size equal parts of what the sized ones leave
+fn share_remainder(children: &mut [LayoutExpr], column: usize) -> Result<()> {
+    let sized: f64 = children.iter().filter_map(LayoutExpr::percent).sum();
+    let without_size = children.iter().filter(|c| c.percent().is_none()).count();
+    if without_size == children.len() || without_size == 0 {
+        return Ok(());
+    }
+    let remainder = 1.0 - sized;
+    if remainder <= 0.0 {
+        anyhow::bail!("The sizes in the container at column {} leave nothing for its other children", column);
+    }
+    for child in children.iter_mut().filter(|c| c.percent().is_none()) {
+        child.set_percent(remainder / without_size as f64);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(n: &str) -> LayoutExpr {
+        LayoutExpr::Terminal { name: n.into(), percent: None }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(LayoutExpr::parse("editor").unwrap(), name("editor"));
+        assert_eq!(
+            LayoutExpr::parse(" h( v(editor, shell) ,logs)").unwrap(),
+            LayoutExpr::Container {
+                kind: 'h',
+                percent: None,
+                children: vec![
+                    LayoutExpr::Container { kind: 'v', percent: None, children: vec![name("editor"), name("shell")] },
+                    name("logs")
+                ],
+            }
+        );
+        assert_eq!(LayoutExpr::parse("tabbed(a,b)").unwrap().names(), ["a", "b"]);
+
+        let error = |text: &str| LayoutExpr::parse(text).unwrap_err().to_string();
+        assert_eq!(error("h()"), "Expected a terminal name at column 3, found ')'");
+        assert_eq!(error("x(a)"), "Unknown container 'x' at column 1: use h, v, t or s");
+        assert_eq!(error("h(a b)"), "Expected ',' or ')' at column 5, found 'b'");
+        assert_eq!(error("h(a,v(b"), "Missing ')' for the container at column 5");
+        assert_eq!(error("a)"), "Unexpected ')' at column 2");
+    }
+
+    #[test]
+    fn test_parse_sizes() {
+        let sizes = |text: &str| match LayoutExpr::parse(text).unwrap() {
+            LayoutExpr::Container { children, .. } => children.iter().map(|c| c.percent()).collect::<Vec<_>>(),
+            LayoutExpr::Terminal { .. } => unreachable!(),
+        };
+        assert_eq!(sizes("h(60:v(editor,shell),40:logs)"), [Some(0.6), Some(0.4)]);
+        assert_eq!(sizes("v(50:a,b,c)"), [Some(0.5), Some(0.25), Some(0.25)]);
+        assert_eq!(sizes("h(a,b)"), [None, None]);
+        // Digits alone are a name
+        assert_eq!(LayoutExpr::parse("h(1,2)").unwrap().names(), ["1", "2"]);
+
+        let error = |text: &str| LayoutExpr::parse(text).unwrap_err().to_string();
+        assert_eq!(error("60:a"), "Size at column 1: only the children of h and v have sizes");
+        assert_eq!(error("t(50:a,b)"), "Size at column 3: only the children of h and v have sizes");
+        assert_eq!(error("h(50:t(a,b),c)"), "Size at column 3: tabbed and stacked containers can't be sized");
+        assert!(error("h(150:a,b)").starts_with("Invalid size '150' at column 3"));
+        assert_eq!(error("h(70:a,30:b,c)"), "The sizes in the container at column 1 leave nothing for its other children");
+
+        // Sizes carry over to the layout tree
+        let mut socket = 0;
+        let layout = LayoutExpr::parse("h(60:a,40:b)").unwrap().to_layout(&mut |_| {
+            socket += 1;
+            Layout::Terminal {
+                socket: format!("ws1-00{}", socket),
+                percent: None,
+                command: None,
+                host: None,
+                attach: None,
+                target: None,
+                title: None,
+            }
+        });
+        assert!(layout.check().is_ok());
+        assert!(matches!(&layout, Layout::HSplit { children, .. } if matches!(children[0], Layout::Terminal { percent: Some(p), .. } if p == 0.6)));
+    }
+
+    #[test]
+    fn test_start_command() {
+        let terminal = |command: Option<&str>, cwd: Option<&str>| TerminalSpec {
+            command: command.map(str::to_string),
+            cwd: cwd.map(str::to_string),
+            title: None,
+        };
+        assert_eq!(terminal(None, None).start_command(), None);
+        assert_eq!(terminal(Some("make"), None).start_command().unwrap(), "make");
+        assert_eq!(terminal(None, Some("/srv/it's")).start_command().unwrap(), r"cd '/srv/it'\''s'");
+        assert_eq!(terminal(Some("nvim"), Some("~/src/app")).start_command().unwrap(), "cd ~/'src/app' && nvim");
+    }
+}
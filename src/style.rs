@@ -0,0 +1,58 @@
+//! Styled terminal output
+//!
+//! Everything i3mux prints with ANSI styling or symbols goes through here, so
+//! plain output can be chosen with --no-ansi or the NO_COLOR convention, and is
+//! used automatically when stdout is not a terminal (pipes, rofi). Plain output
+//! also reads better on braille displays and screen readers.
+
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+static PLAIN: OnceLock<bool> = OnceLock::new();
+
+/// Choose between plain and styled output; called once at startup
+pub fn init(no_ansi: bool) {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let _ = PLAIN.set(is_plain(no_ansi, no_color, std::io::stdout().is_terminal()));
+}
+
+fn is_plain(no_ansi: bool, no_color: bool, is_terminal: bool) -> bool {
+    no_ansi || no_color || !is_terminal
+}
+
+fn plain() -> bool {
+    PLAIN.get().copied().unwrap_or(true)
+}
+
+/// Marker in front of a completed step
+pub fn ok() -> &'static str {
+    if plain() { "OK:" } else { "✓" }
+}
+
+/// Marker in front of something that needs attention
+pub fn problem() -> &'static str {
+    if plain() { "PROBLEM:" } else { "✗" }
+}
+
+/// How the local machine is shown in place of a host name
+pub fn local() -> &'static str {
+    if plain() { "local" } else { "\x1b[3mlocal\x1b[0m" }
+}
+
+/// Display form of a host as recorded in state ("local" or user@host)
+pub fn host(host: &str) -> &str {
+    if host == "local" { local() } else { host }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_plain() {
+        assert!(!is_plain(false, false, true));
+        assert!(is_plain(true, false, true));
+        assert!(is_plain(false, true, true));
+        assert!(is_plain(false, false, false));
+    }
+}
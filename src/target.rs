@@ -0,0 +1,61 @@
+//! Terminals that attach through another program instead of abduco
+//!
+//! Pods and containers can't host an abduco session that outlives them, so
+//! their terminals record where to attach and re-derive the attach command
+//! each time the session is attached.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::container::ContainerTarget;
+use crate::kube::PodTarget;
+
+/// Shell started by `exec`: bash if the image has it, sh otherwise
+pub const EXEC_SHELL: &str = "sh -c 'if command -v bash >/dev/null; then exec bash; else exec sh; fi'";
+
+/// Where a terminal attaches to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachTarget {
+    Pod(PodTarget),
+    Container(ContainerTarget),
+}
+
+impl AttachTarget {
+    /// Check the target is reachable now and return the command that attaches to it
+    pub fn resolve(&mut self) -> Result<String> {
+        match self {
+            AttachTarget::Pod(target) => {
+                let pod = target.resolve()?;
+                Ok(target.attach_command(&pod))
+            }
+            AttachTarget::Container(target) => {
+                target.check()?;
+                Ok(target.attach_command())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for AttachTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttachTarget::Pod(target) => write!(f, "pod {}", target),
+            AttachTarget::Container(target) => write!(f, "container {}", target),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Runtime;
+
+    #[test]
+    fn test_serialization() {
+        let target = AttachTarget::Container(ContainerTarget::new(Runtime::Docker, "web").unwrap());
+        let json = serde_json::to_string(&target).unwrap();
+        assert_eq!(json, r#"{"container":{"runtime":"docker","name":"web"}}"#);
+        assert_eq!(serde_json::from_str::<AttachTarget>(&json).unwrap(), target);
+    }
+}
@@ -0,0 +1,94 @@
+//! Timing instrumentation (--timings)
+//!
+//! Steps worth knowing about when an attach feels slow (SSH round trips,
+//! helper checks, lock acquisition, waiting for windows, WM commands) are
+//! wrapped in `time`. With --timings a summary is printed to stderr on exit,
+//! which shows whether time goes to the network or the window manager.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Time spent per step, in order of first occurrence: (step, count, total)
+static STEPS: Mutex<Vec<(String, u32, Duration)>> = Mutex::new(Vec::new());
+
+/// Start recording
+pub fn enable() {
+    START.get_or_init(Instant::now);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Run `f`, recording its duration under `step`
+///
+/// Repeated steps with the same name are added up.
+pub fn time<T>(step: &str, f: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    record(step, start.elapsed());
+    result
+}
+
+fn record(step: &str, elapsed: Duration) {
+    let mut steps = STEPS.lock().unwrap_or_else(|e| e.into_inner());
+    match steps.iter_mut().find(|(name, _, _)| name == step) {
+        Some((_, count, total)) => {
+            *count += 1;
+            *total += elapsed;
+        }
+        None => steps.push((step.to_string(), 1, elapsed)),
+    }
+}
+
+/// Print the recorded steps to stderr
+pub fn report() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut steps = STEPS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(start) = START.get() {
+        steps.push(("total".to_string(), 1, start.elapsed()));
+    }
+    eprintln!("\nTimings:");
+    for line in format_steps(&steps) {
+        eprintln!("{}", line);
+    }
+}
+
+fn format_steps(steps: &[(String, u32, Duration)]) -> Vec<String> {
+    let width = steps.iter().map(|(name, _, _)| name.len()).max().unwrap_or(0);
+    steps
+        .iter()
+        .map(|(name, count, total)| {
+            let repeats = if *count > 1 { format!(" ({}x)", count) } else { String::new() };
+            format!("  {:<width$} {:>8.1} ms{}", name, total.as_secs_f64() * 1000.0, repeats, width = width)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_steps() {
+        let steps = vec![
+            ("ssh: helper check".to_string(), 1, Duration::from_millis(412)),
+            ("wm: wait for window".to_string(), 3, Duration::from_micros(1_250_500)),
+        ];
+        assert_eq!(
+            format_steps(&steps),
+            vec![
+                "  ssh: helper check      412.0 ms",
+                "  wm: wait for window   1250.5 ms (3x)",
+            ]
+        );
+    }
+}
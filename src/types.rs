@@ -3,7 +3,7 @@
 //! All user input is validated at the CLI boundary and wrapped in these types.
 //! Internal code can trust that these values are safe to use in shell commands.
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 /// A validated session name.
 ///
@@ -128,9 +128,161 @@ impl AsRef<str> for RemoteHost {
     }
 }
 
+/// A validated SSH port forward (`-L`, `-R` or `-D` plus its specification).
+///
+/// Serialized as e.g. `"L 8080:localhost:8080"`, and validated again when
+/// loaded from a session file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortForward {
+    kind: char,
+    spec: String,
+}
+
+impl PortForward {
+    /// Creates a new PortForward after validation.
+    ///
+    /// # Errors
+    /// Returns error if the kind isn't one of L, R, D or the specification
+    /// contains characters that can't appear in a forward.
+    pub fn new(kind: char, spec: impl Into<String>) -> Result<Self> {
+        let spec = spec.into();
+
+        if !matches!(kind, 'L' | 'R' | 'D') {
+            anyhow::bail!("Invalid forward type '{}': expected L, R or D", kind);
+        }
+
+        if spec.is_empty()
+            || !spec
+                .chars()
+                .all(|c| c.is_alphanumeric() || ":.-_[]/".contains(c))
+        {
+            anyhow::bail!(
+                "Invalid forward '{}': expected [bind_address:]port[:host:hostport] or a socket path",
+                spec
+            );
+        }
+
+        Ok(Self { kind, spec })
+    }
+
+    /// The ssh arguments for this forward (e.g. `["-L", "8080:localhost:8080"]`).
+    pub fn ssh_args(&self) -> [String; 2] {
+        [format!("-{}", self.kind), self.spec.clone()]
+    }
+}
+
+impl std::fmt::Display for PortForward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "-{} {}", self.kind, self.spec)
+    }
+}
+
+impl TryFrom<String> for PortForward {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        let (kind, spec) = value
+            .split_once(' ')
+            .with_context(|| format!("Invalid forward '{}'", value))?;
+        let mut chars = kind.chars();
+        match (chars.next(), chars.next()) {
+            (Some(kind), None) => Self::new(kind, spec),
+            _ => anyhow::bail!("Invalid forward '{}'", value),
+        }
+    }
+}
+
+impl From<PortForward> for String {
+    fn from(forward: PortForward) -> String {
+        format!("{} {}", forward.kind, forward.spec)
+    }
+}
+
+/// A validated sshfs mount of a remote directory.
+///
+/// `remote` is a path on the session's host (relative paths and `~/` are
+/// relative to the remote home); `local` is the mount point (`~/` expands to
+/// the local home). Both are passed as plain arguments, never through a shell.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Mount {
+    pub remote: String,
+    pub local: String,
+}
+
+impl Mount {
+    /// Creates a new Mount after validation.
+    ///
+    /// # Errors
+    /// Returns error if either path is empty or contains control characters.
+    pub fn new(remote: impl Into<String>, local: impl Into<String>) -> Result<Self> {
+        let (remote, local) = (remote.into(), local.into());
+
+        for path in [&remote, &local] {
+            if path.is_empty() || path.chars().any(|c| c.is_control()) {
+                anyhow::bail!("Invalid mount path '{}'", path.escape_debug());
+            }
+        }
+
+        Ok(Self { remote, local })
+    }
+
+    /// Remote path as sshfs expects it (relative to the remote home unless absolute).
+    pub fn remote_path(&self) -> &str {
+        match self.remote.as_str() {
+            "~" => "",
+            path => path.strip_prefix("~/").unwrap_or(path),
+        }
+    }
+
+    /// Local mount point with `~/` expanded.
+    pub fn local_path(&self) -> std::path::PathBuf {
+        match (self.local.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => std::path::PathBuf::from(&self.local),
+        }
+    }
+}
+
+impl std::fmt::Display for Mount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.remote, self.local)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    /// Characters a shell treats specially, which validated names must never contain
+    fn is_shell_special(c: char) -> bool {
+        c.is_whitespace() || c.is_control() || "\"'`$\\;&|<>(){}[]*?!~#%^=,:/+".contains(c)
+    }
+
+    proptest! {
+        #[test]
+        fn test_session_name_any_string(name in any::<String>()) {
+            if let Ok(session) = SessionName::new(name.clone()) {
+                prop_assert_eq!(session.as_str(), name.as_str());
+                prop_assert!(!name.chars().any(is_shell_special));
+            }
+        }
+
+        #[test]
+        fn test_remote_host_any_string(host in any::<String>()) {
+            if let Ok(remote) = RemoteHost::new(host.clone()) {
+                prop_assert_eq!(remote.as_str(), host.as_str());
+                prop_assert!(!host.replacen('@', "", 1).chars().any(|c| c == '@' || is_shell_special(c)));
+            }
+        }
+
+        #[test]
+        fn test_remote_host_shaped_strings(host in "[a-z@._;$ -]{0,12}") {
+            // Near-misses of valid hosts exercise each rejection path
+            let _ = RemoteHost::new(host);
+        }
+    }
 
     #[test]
     fn test_valid_session_names() {
@@ -164,6 +316,33 @@ mod tests {
         assert!(RemoteHost::new("user name@server").is_err()); // Space in username
     }
 
+    #[test]
+    fn test_port_forwards() {
+        let forward = PortForward::new('L', "8080:localhost:8080").unwrap();
+        assert_eq!(forward.ssh_args(), ["-L".to_string(), "8080:localhost:8080".to_string()]);
+        assert_eq!(forward.to_string(), "-L 8080:localhost:8080");
+
+        let json = serde_json::to_string(&forward).unwrap();
+        assert_eq!(json, r#""L 8080:localhost:8080""#);
+        assert_eq!(serde_json::from_str::<PortForward>(&json).unwrap(), forward);
+
+        assert!(PortForward::new('D', "1080").is_ok());
+        assert!(PortForward::new('X', "1080").is_err());
+        assert!(PortForward::new('L', "8080; rm -rf ~").is_err());
+        assert!(serde_json::from_str::<PortForward>(r#""L 80 80""#).is_err());
+    }
+
+    #[test]
+    fn test_mounts() {
+        let mount = Mount::new("~/project", "~/mnt/project").unwrap();
+        assert_eq!(mount.remote_path(), "project");
+        assert!(mount.local_path().ends_with("mnt/project"));
+        assert_eq!(Mount::new("/srv/www", "/mnt/www").unwrap().remote_path(), "/srv/www");
+
+        assert!(Mount::new("", "/mnt/x").is_err());
+        assert!(Mount::new("src", "/mnt/\nx").is_err());
+    }
+
     // TODO: Implement is_local() method
     // #[test]
     // fn test_remote_host_is_local() {
@@ -0,0 +1,110 @@
+//! Watching an attached session for other machines (`i3mux _watch-lock`)
+//!
+//! `attach` (and `resume`, when it re-acquires a lock) starts this in the
+//! background for sessions on remote hosts. It waits in the helper's
+//! `watch-session` until the session's lock changes. If another machine took
+//! the session over, the workspace's windows get a "[lock lost]" title and a
+//! desktop notification says so, rather than leaving the user to find out
+//! from terminals that no longer match the session. Detaching, or locking the
+//! session again from this machine, ends the watch quietly.
+//!
+//! It also wakes when another machine asks for the session (`attach
+//! --here-then-close-origin` writes a detach request into the session file):
+//! the workspace is then detached as by `i3mux detach`, which saves the layout
+//! and releases the lock for the machine waiting on it.
+
+use anyhow::Result;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::connection;
+use crate::session::RemoteSession;
+use crate::window;
+use crate::wm::WmBackend;
+
+/// How long to wait before watching again after the connection dropped
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// An attached session and the lock this machine holds on it
+pub struct Watch {
+    pub workspace: String,
+    pub host: String,
+    pub session: String,
+    pub nonce: String,
+}
+
+impl Watch {
+    /// Whether the workspace is still attached to the session
+    fn is_bound(&self) -> Result<bool> {
+        let state = crate::LocalState::load()?;
+        Ok(state
+            .workspaces
+            .get(&self.workspace)
+            .is_some_and(|ws| ws.host == self.host && ws.session_name.as_deref() == Some(self.session.as_str())))
+    }
+
+    /// The machine that took the session over, if one did
+    fn taken_by(&self, conn: &dyn connection::Connection) -> Option<String> {
+        let lock = RemoteSession::load_from_remote(conn, &self.session).ok()?.lock?;
+        (lock.nonce != self.nonce && lock.locked_by != connection::hostname()).then_some(lock.locked_by)
+    }
+
+    /// The machine asking for the session while this one still holds it, if one is
+    fn detach_requested_by(&self, conn: &dyn connection::Connection) -> Option<String> {
+        let session = RemoteSession::load_from_remote(conn, &self.session).ok()?;
+        let request = session.detach_request.filter(|_| session.lock.is_some_and(|lock| lock.nonce == self.nonce))?;
+        Some(request.requested_by)
+    }
+
+    /// Detach the workspace for `requester`, and tell the user
+    fn hand_over(&self, requester: &str) -> Result<()> {
+        let num = self.workspace.parse()?;
+        let (urgency, message) = match crate::detach(Some(self.session.clone()), None, Some(num)) {
+            Ok(()) => ("--urgency=normal", format!("Session '{}' was detached for {}", self.session, requester)),
+            Err(e) => (
+                "--urgency=critical",
+                format!("Could not detach session '{}' for {}: {:#}", self.session, requester, e),
+            ),
+        };
+        let _ = Command::new("notify-send").args([urgency, "i3mux", &message]).status();
+        Ok(())
+    }
+
+    /// Mark the workspace's windows and tell the user
+    fn report_lost(&self, holder: &str) {
+        let message = format!(
+            "Session '{}' was attached on {}; workspace {} no longer holds its lock",
+            self.session, holder, self.workspace
+        );
+        if let (Ok(backend), Ok(num)) = (WmBackend::connect(), self.workspace.parse()) {
+            for window in window::find_i3mux_windows_in_workspace(num, &backend).unwrap_or_default() {
+                let _ = backend.run_command_on_container(window.window_id, "title_format \"[lock lost] %title\"");
+            }
+        }
+        let _ = Command::new("notify-send")
+            .args(["--urgency=critical", "i3mux", &message])
+            .status();
+    }
+}
+
+pub fn run(watch: Watch) -> Result<()> {
+    let conn = crate::host_connection(&watch.host)?;
+    while watch.is_bound()? {
+        if conn.wait_for_lock_change(&watch.session, &watch.nonce).is_err() {
+            // The host went away for a while; the lock may still be ours
+            std::thread::sleep(RETRY_INTERVAL);
+            continue;
+        }
+        if let Some(requester) = watch.detach_requested_by(conn.as_ref()) {
+            if watch.is_bound()? {
+                watch.hand_over(&requester)?;
+            }
+        } else if let Some(holder) = watch.taken_by(conn.as_ref()) {
+            if watch.is_bound()? {
+                watch.report_lost(&holder);
+            }
+        }
+        break;
+    }
+    Ok(())
+}
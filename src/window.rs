@@ -6,7 +6,8 @@
 //!
 //! ## Mark Format
 //!
-//! i3mux windows are marked with: `_i3mux:{host}:{socket}`
+//! i3mux windows are marked with: `_i3mux:{host}:{socket}` (`_i3mux-{name}:...`
+//! under `--instance name`, see [`crate::instance`])
 //!
 //! - The underscore prefix makes the mark hidden (not shown in title bar)
 //! - `host` is either "local" or the remote host identifier
@@ -18,14 +19,29 @@
 //! _i3mux:local:ws1-001
 //! _i3mux:user@server:ws2-003
 //! ```
+//!
+//! Terminals are launched with an instance name (WM_CLASS instance / app_id) of
+//! `{mark}#{nonce}`, where the nonce is unique per launch. This is only used to
+//! find the freshly spawned window; the nonce never appears in the mark.
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::wm::WmBackend;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::config::Theme;
+use crate::hooks;
+use crate::ipc::{EventStream, WmEvent};
+use crate::wm::{Rect, WmBackend, WmNode};
 
 /// Prefix for hidden i3 marks (underscore = hidden from title bar)
-pub const MARK_PREFIX: &str = "_i3mux:";
+pub fn mark_prefix() -> String {
+    format!("_i3mux{}:", crate::instance::suffix())
+}
+
+/// Separator between the mark and the per-launch nonce in instance names
+const INSTANCE_NONCE_SEPARATOR: char = '#';
 
 /// Maximum attempts when waiting for a window to appear
 pub const WINDOW_WAIT_MAX_ATTEMPTS: u32 = 30;
@@ -59,22 +75,32 @@ impl I3muxWindow {
         Self::mark_from_parts(&self.host, &self.socket)
     }
 
-    /// Generate a mark/instance string from host and socket components
+    /// Generate a mark string from host and socket components
     ///
-    /// The instance name and mark use the same format: `_i3mux:{host}:{socket}`
+    /// Format: `_i3mux:{host}:{socket}` (launch instances append a nonce, see `launch_instance`)
     pub fn mark_from_parts(host: &str, socket: &str) -> String {
-        format!("{}{}:{}", MARK_PREFIX, host, socket)
+        format!("{}{}:{}", mark_prefix(), host, socket)
+    }
+
+    /// Generate a unique instance name for launching a terminal
+    ///
+    /// Two terminals for the same host/socket spawning concurrently get different
+    /// instances, so each launch marks its own window.
+    pub fn launch_instance(host: &str, socket: &str) -> String {
+        let nonce = uuid::Uuid::new_v4().simple().to_string();
+        format!(
+            "{}{}{}",
+            Self::mark_from_parts(host, socket),
+            INSTANCE_NONCE_SEPARATOR,
+            &nonce[..8]
+        )
     }
 
     /// Parse an i3mux identity from a mark string
     ///
     /// Returns None if the mark doesn't match the i3mux format
     pub fn from_mark(mark: &str) -> Option<Self> {
-        if !mark.starts_with(MARK_PREFIX) {
-            return None;
-        }
-
-        let data = mark.trim_start_matches(MARK_PREFIX);
+        let data = mark.strip_prefix(mark_prefix().as_str())?;
         let parts: Vec<&str> = data.splitn(2, ':').collect();
 
         if parts.len() != 2 {
@@ -98,74 +124,141 @@ impl I3muxWindow {
         backend.run_command(&cmd)?;
         Ok(())
     }
+
+    /// Remove the i3mux mark, leaving a plain window with the title its program sets
+    ///
+    /// The commands are joined with `,`: after `;` the criteria no longer
+    /// apply, and the title reset would hit the focused window instead.
+    pub fn remove_mark(&self, backend: &WmBackend) -> Result<()> {
+        backend.run_command_on_container(self.window_id, &format!("unmark {}, title_format \"%title\"", self.mark()))
+    }
 }
 
-/// Find a window by its instance name (WM_CLASS instance on X11, app_id on Wayland)
-///
-/// Searches the window manager tree for a window with the specified instance.
-/// Returns the container ID if found.
-pub fn find_window_by_instance(backend: &WmBackend, instance: &str) -> Option<u64> {
-    let tree = backend.get_tree().ok()?;
-    find_window_by_instance_in_tree(&tree, instance)
+/// Direction to a neighbouring window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
 }
 
-fn find_window_by_instance_in_tree(node: &serde_json::Value, target_instance: &str) -> Option<u64> {
-    // Get container ID - works for both i3 and Sway
-    // Sway uses "id" for container ID, i3 uses "id" as well (but also has "window" for X11 window ID)
-    let container_id = node.get("id").and_then(|w| w.as_u64());
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Direction::Left => "left",
+            Direction::Right => "right",
+            Direction::Up => "up",
+            Direction::Down => "down",
+        })
+    }
+}
 
-    // Check app_id first (Wayland/Sway)
-    if let Some(app_id) = node.get("app_id").and_then(|a| a.as_str()) {
-        if app_id == target_instance {
-            return container_id;
-        }
+/// A tiled window that i3mux doesn't manage (no i3mux mark)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlainWindow {
+    pub window_id: u64,
+    pub class: Option<String>,
+    pub instance: Option<String>,
+}
+
+impl PlainWindow {
+    /// Whether the class or instance is one of `names` (ignoring case)
+    pub fn matches(&self, names: &[String]) -> bool {
+        [&self.class, &self.instance]
+            .into_iter()
+            .flatten()
+            .any(|id| names.iter().any(|name| name.eq_ignore_ascii_case(id)))
     }
+}
 
-    // Fall back to window_properties.instance (X11/i3)
-    if let Some(props) = node.get("window_properties") {
-        if let Some(instance) = props.get("instance").and_then(|i| i.as_str()) {
-            if instance == target_instance {
-                return container_id;
-            }
-        }
+/// Unmarked tiled windows of a workspace, in tree order
+pub fn find_plain_windows_in_workspace(workspace_num: i32, backend: &WmBackend) -> Result<Vec<PlainWindow>> {
+    let tree = backend.get_tree()
+        .context("Failed to get window manager tree")?;
+    let mut windows = Vec::new();
+    if let Some(node) = find_workspace_node(&tree, workspace_num) {
+        collect_plain_windows(node, &mut windows);
     }
+    Ok(windows)
+}
 
-    // Recurse into children
-    if let Some(nodes) = node.get("nodes").and_then(|n| n.as_array()) {
-        for child in nodes {
-            if let Some(found) = find_window_by_instance_in_tree(child, target_instance) {
-                return Some(found);
-            }
-        }
+fn collect_plain_windows(node: &WmNode, windows: &mut Vec<PlainWindow>) {
+    if node.is_window() && !has_i3mux_mark(node) {
+        windows.push(PlainWindow {
+            window_id: node.id,
+            class: node.class().map(String::from),
+            instance: node.instance().map(String::from),
+        });
+    }
+    for child in &node.nodes {
+        collect_plain_windows(child, windows);
     }
+}
 
-    if let Some(nodes) = node.get("floating_nodes").and_then(|n| n.as_array()) {
-        for child in nodes {
-            if let Some(found) = find_window_by_instance_in_tree(child, target_instance) {
-                return Some(found);
-            }
-        }
+/// Find a window by its instance name (WM_CLASS instance on X11, app_id on Wayland)
+///
+/// Searches the window manager tree for a window with the specified instance.
+/// Windows that already carry an i3mux mark are skipped, so a window claimed by
+/// an earlier launch is never re-marked. Returns the container ID and the number
+/// of the workspace the window landed on, if found.
+pub fn find_window_by_instance(backend: &WmBackend, instance: &str) -> Option<(u64, Option<i32>)> {
+    let tree = backend.get_tree().ok()?;
+    find_window_by_instance_in_tree(&tree, instance, None)
+}
+
+fn find_window_by_instance_in_tree(
+    node: &WmNode,
+    target_instance: &str,
+    workspace_num: Option<i32>,
+) -> Option<(u64, Option<i32>)> {
+    // Track the workspace we're descending through
+    let workspace_num = if node.node_type == "workspace" { node.num } else { workspace_num };
+
+    // Container ID ("id") works with con_id on both i3 and Sway
+    if !has_i3mux_mark(node) && node.instance() == Some(target_instance) {
+        return Some((node.id, workspace_num));
     }
 
-    None
+    node.children()
+        .find_map(|child| find_window_by_instance_in_tree(child, target_instance, workspace_num))
 }
 
 /// Wait for a window to appear by instance name, then apply i3mux mark
 ///
-/// Polls until the window appears or max_attempts is reached.
+/// Polls until the window appears or max_attempts is reached; a window event
+/// for the instance ends the current poll interval early. If the window
+/// landed on a different workspace (e.g. the user switched workspaces while it
+/// was spawning), it is moved back to `workspace_num` before being marked.
 /// Returns the container ID on success.
 pub fn wait_for_window_and_mark(
     backend: &WmBackend,
     instance: &str,
     host: &str,
     socket: &str,
+    workspace_num: i32,
 ) -> Result<u64> {
+    // Without an event subscription this is plain polling
+    let events = backend.subscribe().ok().map(EventStream::into_channel);
+
     for attempt in 0..WINDOW_WAIT_MAX_ATTEMPTS {
-        std::thread::sleep(std::time::Duration::from_millis(WINDOW_WAIT_INTERVAL_MS));
+        wait_for_window_event(events.as_ref(), instance);
+
+        if let Some((container_id, found_ws)) = find_window_by_instance(backend, instance) {
+            if found_ws != Some(workspace_num) {
+                eprintln!(
+                    "[i3mux] Window for {} appeared on workspace {:?}, moving it to workspace {}",
+                    socket, found_ws, workspace_num
+                );
+                backend.run_command_on_container(
+                    container_id,
+                    &format!("move container to workspace number {}", workspace_num),
+                )?;
+            }
 
-        if let Some(container_id) = find_window_by_instance(backend, instance) {
             let i3mux_window = I3muxWindow::new(container_id, host, socket);
             i3mux_window.apply_mark(backend)?;
+            hooks::emit(hooks::Event::WindowMarked, &i3mux_window.mark());
             return Ok(container_id);
         }
 
@@ -185,6 +278,57 @@ pub fn wait_for_window_and_mark(
     )
 }
 
+/// Sleep for one poll interval, or less if a window event for `instance` arrives
+fn wait_for_window_event(events: Option<&Receiver<Result<WmEvent>>>, instance: &str) {
+    let deadline = Instant::now() + Duration::from_millis(WINDOW_WAIT_INTERVAL_MS);
+    let Some(events) = events else {
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        return;
+    };
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match events.recv_timeout(remaining) {
+            Ok(Ok(WmEvent::Window { change, container }))
+                if change != "close" && container.instance() == Some(instance) => return,
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => return,
+            Err(RecvTimeoutError::Disconnected) => {
+                std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                return;
+            }
+        }
+    }
+}
+
+/// Apply per-host/per-session decoration (border, title color, fixed title) to a marked window
+pub fn apply_theme(backend: &WmBackend, container_id: u64, theme: &Theme) -> Result<()> {
+    for cmd in theme.commands() {
+        backend.run_command_on_container(container_id, &cmd)?;
+    }
+    Ok(())
+}
+
+/// Split the focused container along its longer side (`--smart-split`)
+///
+/// The next window then opens beside a wide container and below a tall one,
+/// which keeps panes closer to square than a fixed direction does.
+pub fn split_focused_along_longer_side(backend: &WmBackend) -> Result<()> {
+    let tree = backend.get_tree()
+        .context("Failed to get window manager tree")?;
+    if let Some(node) = find_focused_node(&tree) {
+        backend.run_command(split_for(node.rect))?;
+    }
+    Ok(())
+}
+
+/// `split h` for containers at least as wide as tall, `split v` otherwise
+fn split_for(rect: Rect) -> &'static str {
+    if rect.width >= rect.height {
+        "split h"
+    } else {
+        "split v"
+    }
+}
+
 /// Find all i3mux windows in a specific workspace
 pub fn find_i3mux_windows_in_workspace(workspace_num: i32, backend: &WmBackend) -> Result<Vec<I3muxWindow>> {
     let tree = backend.get_tree()
@@ -216,6 +360,20 @@ pub fn kill_i3mux_windows_in_workspace(backend: &WmBackend, workspace_num: i32)
     Ok(())
 }
 
+/// Container ID of whatever has focus (a window, or an empty workspace)
+pub fn find_focused_container(backend: &WmBackend) -> Result<Option<u64>> {
+    let tree = backend.get_tree()
+        .context("Failed to get window manager tree")?;
+    Ok(find_focused_node(&tree).map(|node| node.id))
+}
+
+/// The focused window's i3mux identity (None if it is not an i3mux terminal)
+pub fn find_focused_i3mux_window(backend: &WmBackend) -> Result<Option<I3muxWindow>> {
+    let tree = backend.get_tree()
+        .context("Failed to get window manager tree")?;
+    Ok(find_focused_node(&tree).and_then(window_identity))
+}
+
 /// Check if a workspace has any i3mux windows
 pub fn workspace_has_i3mux_windows(workspace_num: i32, backend: &WmBackend) -> Result<bool> {
     let windows = find_i3mux_windows_in_workspace(workspace_num, backend)?;
@@ -224,64 +382,64 @@ pub fn workspace_has_i3mux_windows(workspace_num: i32, backend: &WmBackend) -> R
 
 // ============ Internal helpers ============
 
-fn collect_i3mux_windows(node: &serde_json::Value, windows: &mut Vec<I3muxWindow>) {
-    // Check if this node has marks
-    // Use "id" for container ID which works for both i3 and Sway
-    if let Some(marks) = node.get("marks").and_then(|m| m.as_array()) {
-        if let Some(container_id) = node.get("id").and_then(|w| w.as_u64()) {
-            for mark in marks {
-                if let Some(mark_str) = mark.as_str() {
-                    if let Some(mut identity) = I3muxWindow::from_mark(mark_str) {
-                        identity.window_id = container_id;
-                        windows.push(identity);
-                        break; // Only count once per window
-                    }
-                }
-            }
-        }
-    }
+fn has_i3mux_mark(node: &WmNode) -> bool {
+    node.marks.iter().any(|m| I3muxWindow::from_mark(m).is_some())
+}
 
-    // Recurse into children
-    if let Some(nodes) = node.get("nodes").and_then(|n| n.as_array()) {
-        for child in nodes {
-            collect_i3mux_windows(child, windows);
-        }
+/// i3mux identity of a node from its first i3mux mark
+fn window_identity(node: &WmNode) -> Option<I3muxWindow> {
+    let mut identity = node.marks.iter().find_map(|m| I3muxWindow::from_mark(m))?;
+    identity.window_id = node.id;
+    Some(identity)
+}
+
+fn collect_i3mux_windows(node: &WmNode, windows: &mut Vec<I3muxWindow>) {
+    // Only count once per window
+    if let Some(identity) = window_identity(node) {
+        windows.push(identity);
     }
 
-    if let Some(nodes) = node.get("floating_nodes").and_then(|n| n.as_array()) {
-        for child in nodes {
-            collect_i3mux_windows(child, windows);
-        }
+    for child in node.children() {
+        collect_i3mux_windows(child, windows);
     }
 }
 
-fn find_workspace_node<'a>(node: &'a serde_json::Value, workspace_num: i32) -> Option<&'a serde_json::Value> {
-    // Check if this is the workspace we're looking for
-    if let Some(node_type) = node.get("type").and_then(|t| t.as_str()) {
-        if node_type == "workspace" {
-            if let Some(num) = node.get("num").and_then(|n| n.as_i64()) {
-                if num == workspace_num as i64 {
-                    return Some(node);
-                }
-            }
-        }
+fn find_focused_node(node: &WmNode) -> Option<&WmNode> {
+    if node.focused {
+        return Some(node);
     }
+    node.children().find_map(find_focused_node)
+}
 
-    // Recurse into children
-    if let Some(nodes) = node.get("nodes").and_then(|n| n.as_array()) {
-        for child in nodes {
-            if let Some(found) = find_workspace_node(child, workspace_num) {
-                return Some(found);
-            }
-        }
+/// The workspace node with a given number in a WM tree (i3 and Sway alike)
+pub fn find_workspace_node(node: &WmNode, workspace_num: i32) -> Option<&WmNode> {
+    if node.is_workspace(workspace_num) {
+        return Some(node);
     }
-
-    None
+    node.nodes.iter().find_map(|child| find_workspace_node(child, workspace_num))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        // Marks come from the WM tree, where anything can set them
+        #[test]
+        fn test_from_mark_any_string(mark in any::<String>()) {
+            if let Some(window) = I3muxWindow::from_mark(&mark) {
+                prop_assert_eq!(window.mark(), mark);
+            }
+        }
+
+        #[test]
+        fn test_mark_roundtrip(host in "local|([a-z_][a-z0-9_-]{0,8}@)?[a-zA-Z0-9._-]{1,20}", socket in "ws[0-9]{1,3}-[0-9]{3}") {
+            let window = I3muxWindow::from_mark(&I3muxWindow::mark_from_parts(&host, &socket)).unwrap();
+            prop_assert_eq!(window.host, host);
+            prop_assert_eq!(window.socket, socket);
+        }
+    }
 
     #[test]
     fn test_mark_format() {
@@ -314,6 +472,142 @@ mod tests {
         assert!(I3muxWindow::from_mark("random-mark").is_none());
         assert!(I3muxWindow::from_mark("i3mux:local:ws1").is_none()); // Missing underscore
         assert!(I3muxWindow::from_mark("_i3mux:nocolon").is_none());
+        // Only one prefix is stripped, whatever the host is called
+        assert_eq!(I3muxWindow::from_mark("_i3mux:_i3mux:ws1-001").unwrap().host, "_i3mux");
+    }
+
+    #[test]
+    fn test_launch_instance_is_unique() {
+        let a = I3muxWindow::launch_instance("local", "ws1-001");
+        let b = I3muxWindow::launch_instance("local", "ws1-001");
+        assert_ne!(a, b);
+        assert!(a.starts_with("_i3mux:local:ws1-001#"));
+    }
+
+    fn window_node(id: u64, instance: &str, marks: &[&str]) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "marks": marks,
+            "window_properties": { "instance": instance },
+            "nodes": [],
+            "floating_nodes": [],
+        })
+    }
+
+    fn parse_tree(json: serde_json::Value) -> WmNode {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_find_instance_skips_marked_windows() {
+        // Two windows with the same instance: the first was already claimed
+        let tree = parse_tree(serde_json::json!({
+            "id": 1,
+            "nodes": [
+                window_node(10, "_i3mux:local:ws1-001", &["_i3mux:local:ws1-001"]),
+                window_node(11, "_i3mux:local:ws1-001", &[]),
+            ],
+        }));
+        assert_eq!(
+            find_window_by_instance_in_tree(&tree, "_i3mux:local:ws1-001", None),
+            Some((11, None))
+        );
+    }
+
+    #[test]
+    fn test_concurrent_launches_find_their_own_window() {
+        // Restore spawning two terminals at once: each launch only sees its own window
+        let first = I3muxWindow::launch_instance("local", "ws1-001");
+        let second = I3muxWindow::launch_instance("local", "ws1-002");
+        let tree = parse_tree(serde_json::json!({
+            "id": 1,
+            "nodes": [
+                { "id": 2, "app_id": second, "marks": [], "nodes": [] },
+                { "id": 3, "app_id": first, "marks": [], "nodes": [] },
+            ],
+        }));
+        assert_eq!(find_window_by_instance_in_tree(&tree, &first, None), Some((3, None)));
+        assert_eq!(find_window_by_instance_in_tree(&tree, &second, None), Some((2, None)));
+    }
+
+    #[test]
+    fn test_find_instance_reports_workspace() {
+        // Window spawned after the user switched from workspace 1 to workspace 2
+        let tree = parse_tree(serde_json::json!({
+            "id": 1,
+            "type": "root",
+            "nodes": [{
+                "id": 2,
+                "type": "output",
+                "nodes": [
+                    { "id": 3, "type": "workspace", "num": 1, "nodes": [] },
+                    {
+                        "id": 4,
+                        "type": "workspace",
+                        "num": 2,
+                        "nodes": [window_node(5, "_i3mux:local:ws1-001#abcd1234", &[])],
+                    },
+                ],
+            }],
+        }));
+        assert_eq!(
+            find_window_by_instance_in_tree(&tree, "_i3mux:local:ws1-001#abcd1234", None),
+            Some((5, Some(2)))
+        );
+    }
+
+    #[test]
+    fn test_focused_window_identity() {
+        let mut focused = window_node(7, "", &["other", "_i3mux:user@host:ws2-003"]);
+        focused["focused"] = serde_json::json!(true);
+        let tree = parse_tree(serde_json::json!({
+            "id": 1,
+            "nodes": [window_node(5, "", &["_i3mux:local:ws2-001"])],
+            "floating_nodes": [focused],
+        }));
+
+        let identity = find_focused_node(&tree).and_then(window_identity).unwrap();
+        assert_eq!(identity, I3muxWindow::new(7, "user@host", "ws2-003"));
+
+        let unmarked = parse_tree(serde_json::json!({ "id": 1, "focused": true, "nodes": [] }));
+        assert_eq!(find_focused_node(&unmarked).and_then(window_identity), None);
+    }
+
+    #[test]
+    fn test_plain_windows() {
+        let tree = parse_tree(serde_json::json!({
+            "id": 1,
+            "type": "workspace",
+            "num": 3,
+            "nodes": [
+                window_node(5, "_i3mux:local:ws3-001", &["_i3mux:local:ws3-001"]),
+                {
+                    "id": 6,
+                    "nodes": [
+                        { "id": 7, "app_id": "foot", "marks": ["todo"], "nodes": [] },
+                        { "id": 8, "window_properties": { "class": "Alacritty", "instance": "Alacritty" }, "nodes": [] },
+                    ],
+                },
+            ],
+            "floating_nodes": [{ "id": 9, "app_id": "foot", "nodes": [] }],
+        }));
+        let mut windows = Vec::new();
+        collect_plain_windows(&tree, &mut windows);
+
+        // Marked i3mux windows, split containers and floating windows are left out
+        let ids: Vec<u64> = windows.iter().map(|w| w.window_id).collect();
+        assert_eq!(ids, [7, 8]);
+        assert!(windows[0].matches(&["foot".to_string()]));
+        assert!(windows[1].matches(&["alacritty".to_string()]));
+        assert!(!windows[1].matches(&["foot".to_string()]));
+    }
+
+    #[test]
+    fn test_split_for() {
+        let rect = |width, height| Rect { x: 0, y: 0, width, height };
+        assert_eq!(split_for(rect(1920, 1080)), "split h");
+        assert_eq!(split_for(rect(960, 1080)), "split v");
+        assert_eq!(split_for(rect(800, 800)), "split h");
     }
 
     #[test]
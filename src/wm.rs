@@ -5,8 +5,12 @@
 
 use anyhow::{Context, Result};
 use serde::Deserialize;
-use serde_json::Value;
-use std::process::Command;
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Output};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ipc::{EventKind, EventStream};
 
 /// Detected window manager type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -18,7 +22,8 @@ pub enum WmType {
 /// Window manager backend abstraction
 pub struct WmBackend {
     wm_type: WmType,
-    socket_path: String,
+    /// Replaced when the WM turns out to have restarted under a new path
+    socket_path: Mutex<String>,
 }
 
 /// Workspace information from the window manager
@@ -27,6 +32,191 @@ pub struct WorkspaceInfo {
     pub num: i32,
     pub name: String,
     pub focused: bool,
+    #[serde(default)]
+    pub rect: Rect,
+}
+
+/// Output (monitor) information from the window manager
+///
+/// i3 and Sway report the same core fields; Sway additionally reports the
+/// current mode, whose size is the physical resolution when the output is scaled.
+#[derive(Debug, Deserialize)]
+pub struct OutputInfo {
+    pub name: String,
+    pub active: bool,
+    #[serde(default)]
+    pub primary: bool,
+    #[serde(default)]
+    pub rect: Rect,
+    /// Name of the workspace currently visible on this output
+    pub current_workspace: Option<String>,
+    /// Sway only
+    #[serde(default)]
+    current_mode: Option<OutputMode>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OutputMode {
+    width: u32,
+    height: u32,
+}
+
+impl OutputInfo {
+    /// Resolution in physical pixels (width, height)
+    pub fn resolution(&self) -> (u32, u32) {
+        match &self.current_mode {
+            Some(mode) => (mode.width, mode.height),
+            None => (self.rect.width, self.rect.height),
+        }
+    }
+}
+
+/// Position and size in pixels, as reported by i3 and Sway
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Gaps between windows (`gaps inner` in the WM config), in pixels
+///
+/// Panes are smaller than their share of a split by the gaps, which matters
+/// for reflow's minimum sizes and for shares worked out from rects. Outer
+/// gaps need no accounting: i3 (4.22+) and Sway report workspace rects with
+/// them already taken off.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Gaps {
+    /// Between neighbouring windows
+    pub inner: u32,
+}
+
+impl Gaps {
+    /// Global inner gaps set in a WM config file; per-workspace gaps and
+    /// smart_gaps are ignored
+    pub fn from_config(config: &str) -> Self {
+        let mut gaps = Self::default();
+        for line in config.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let &["gaps", "inner", amount] = words.as_slice() else {
+                continue;
+            };
+            if let Ok(amount) = amount.trim_end_matches("px").parse() {
+                gaps.inner = amount;
+            }
+        }
+        gaps
+    }
+
+    /// Pixels a split of `children` along an axis of `size` leaves each of them,
+    /// at `fraction` of it: the inner gaps between them come off first
+    pub fn pane_size(&self, size: f64, children: usize, fraction: f64) -> f64 {
+        let gaps = self.inner as f64 * children.saturating_sub(1) as f64;
+        ((size - gaps) * fraction).max(0.0)
+    }
+}
+
+/// A node of the layout tree (`get_tree`): root, output, workspace, container or window
+///
+/// i3 and Sway share this shape. Windows are told apart by `window_properties`
+/// on i3 (X11) and by `app_id` on Sway (Wayland); fields that only one of them
+/// reports are optional.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct WmNode {
+    /// Container ID, usable with `[con_id=...]` on both WMs
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub node_type: String,
+    pub name: Option<String>,
+    /// Workspace number (workspaces only)
+    pub num: Option<i32>,
+    pub layout: String,
+    /// Share of the parent container (None when the WM doesn't report one)
+    pub percent: Option<f64>,
+    pub rect: Rect,
+    pub focused: bool,
+    pub marks: Vec<String>,
+    /// Sway only (null for Xwayland windows)
+    pub app_id: Option<String>,
+    /// i3 and Xwayland windows on Sway
+    pub window_properties: Option<WmWindowProps>,
+    pub nodes: Vec<WmNode>,
+    pub floating_nodes: Vec<WmNode>,
+}
+
+/// X11 properties of a window (`window_properties`)
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct WmWindowProps {
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    pub title: Option<String>,
+}
+
+impl WmNode {
+    /// Tiling and floating children
+    pub fn children(&self) -> impl Iterator<Item = &WmNode> {
+        self.nodes.iter().chain(&self.floating_nodes)
+    }
+
+    /// Whether this node is the workspace with the given number
+    pub fn is_workspace(&self, num: i32) -> bool {
+        self.node_type == "workspace" && self.num == Some(num)
+    }
+
+    /// Instance the window was launched with: app_id on Sway, WM_CLASS instance on X11
+    pub fn instance(&self) -> Option<&str> {
+        self.app_id
+            .as_deref()
+            .or_else(|| self.window_properties.as_ref()?.instance.as_deref())
+    }
+
+    /// Application the window belongs to: app_id on Sway, WM_CLASS class on X11
+    pub fn class(&self) -> Option<&str> {
+        self.app_id
+            .as_deref()
+            .or_else(|| self.window_properties.as_ref()?.class.as_deref())
+    }
+
+    /// Whether this node is a window rather than a container
+    pub fn is_window(&self) -> bool {
+        self.nodes.is_empty() && (self.app_id.is_some() || self.window_properties.is_some())
+    }
+}
+
+/// Attempts to find the WM socket again after it went stale
+const RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Pause between reconnect attempts (an in-place i3 restart takes a moment)
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Socket path reported by `i3 --get-socketpath` / `sway --get-socketpath`
+fn query_socket_path(wm_type: WmType) -> Option<String> {
+    let binary = match wm_type {
+        WmType::I3 => "i3",
+        WmType::Sway => "sway",
+    };
+    let output = Command::new(binary).arg("--get-socketpath").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let socket = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!socket.is_empty()).then_some(socket)
+}
+
+fn socket_accepts(path: &str) -> bool {
+    UnixStream::connect(path).is_ok()
+}
+
+/// Whether msg command stderr reports a lost or refused IPC connection
+/// (as opposed to the WM rejecting the command)
+fn is_connection_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    ["connection reset", "could not connect", "unable to connect", "connection refused", "broken pipe", "unexpected eof", "ipc: read()", "unable to receive ipc response"]
+        .iter()
+        .any(|pattern| stderr.contains(pattern))
 }
 
 impl WmBackend {
@@ -37,47 +227,30 @@ impl WmBackend {
     pub fn connect() -> Result<Self> {
         // Try Sway first (SWAYSOCK)
         if let Ok(socket) = std::env::var("SWAYSOCK") {
-            return Ok(Self {
-                wm_type: WmType::Sway,
-                socket_path: socket,
-            });
+            return Ok(Self::new(WmType::Sway, socket));
         }
 
         // Then try i3 (I3SOCK)
         if let Ok(socket) = std::env::var("I3SOCK") {
-            return Ok(Self {
-                wm_type: WmType::I3,
-                socket_path: socket,
-            });
+            return Ok(Self::new(WmType::I3, socket));
         }
 
-        // Fallback: try to get socket path from sway
-        if let Ok(output) = Command::new("sway").arg("--get-socketpath").output() {
-            if output.status.success() {
-                let socket = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                if !socket.is_empty() {
-                    return Ok(Self {
-                        wm_type: WmType::Sway,
-                        socket_path: socket,
-                    });
-                }
+        // Fallback: ask the WM binaries for their socket path
+        for wm_type in [WmType::Sway, WmType::I3] {
+            if let Some(socket) = query_socket_path(wm_type) {
+                return Ok(Self::new(wm_type, socket));
             }
         }
 
-        // Fallback: try to get socket path from i3
-        if let Ok(output) = Command::new("i3").arg("--get-socketpath").output() {
-            if output.status.success() {
-                let socket = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                if !socket.is_empty() {
-                    return Ok(Self {
-                        wm_type: WmType::I3,
-                        socket_path: socket,
-                    });
-                }
-            }
-        }
+        Err(crate::error::Error::WmUnavailable.into())
+    }
 
-        anyhow::bail!("No running window manager (i3 or Sway) detected. Ensure I3SOCK or SWAYSOCK is set.")
+    fn new(wm_type: WmType, socket_path: String) -> Self {
+        Self { wm_type, socket_path: Mutex::new(socket_path) }
+    }
+
+    fn socket_path(&self) -> String {
+        self.socket_path.lock().unwrap().clone()
     }
 
     /// Get the window manager type
@@ -93,15 +266,56 @@ impl WmBackend {
         }
     }
 
+    /// Run the msg command against the current socket
+    ///
+    /// A failure caused by a stale socket (the WM restarted or was restarted
+    /// under a new path since we connected) is retried once against the
+    /// re-resolved socket; if the WM can't be reached at all the error is
+    /// `WmUnavailable` rather than whatever the msg command printed.
+    fn msg(&self, args: &[&str]) -> Result<Output> {
+        let output = self.msg_once(args)?;
+        if output.status.success() {
+            return Ok(output);
+        }
+
+        let stale = !socket_accepts(&self.socket_path())
+            || is_connection_error(&String::from_utf8_lossy(&output.stderr));
+        if !stale {
+            return Ok(output);
+        }
+
+        let socket = self.wait_for_socket().ok_or(crate::error::Error::WmUnavailable)?;
+        *self.socket_path.lock().unwrap() = socket;
+        self.msg_once(args)
+    }
+
+    fn msg_once(&self, args: &[&str]) -> Result<Output> {
+        Command::new(self.msg_command())
+            .arg("-s")
+            .arg(self.socket_path())
+            .args(args)
+            .output()
+            .with_context(|| format!("Failed to run {}", self.msg_command()))
+    }
+
+    /// A socket path the WM accepts connections on, waiting out an in-place restart
+    fn wait_for_socket(&self) -> Option<String> {
+        for _ in 0..RECONNECT_ATTEMPTS {
+            let socket = query_socket_path(self.wm_type).unwrap_or_else(|| self.socket_path());
+            if socket_accepts(&socket) {
+                return Some(socket);
+            }
+            std::thread::sleep(RECONNECT_INTERVAL);
+        }
+        None
+    }
+
     /// Run a WM command (like "split h", "kill", etc.)
     ///
     /// Returns Ok(()) if the command was executed. Note that some commands
     /// may "succeed" from the WM's perspective even if they don't match any windows.
     pub fn run_command(&self, cmd: &str) -> Result<()> {
-        let output = Command::new(self.msg_command())
-            .args(["-s", &self.socket_path, cmd])
-            .output()
-            .with_context(|| format!("Failed to run {} command", self.msg_command()))?;
+        let output = self.msg(&[cmd])?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -112,11 +326,8 @@ impl WmBackend {
     }
 
     /// Get the i3/sway tree as JSON
-    pub fn get_tree(&self) -> Result<Value> {
-        let output = Command::new(self.msg_command())
-            .args(["-s", &self.socket_path, "-t", "get_tree"])
-            .output()
-            .with_context(|| format!("Failed to get {} tree", self.msg_command()))?;
+    pub fn get_tree(&self) -> Result<WmNode> {
+        let output = self.msg(&["-t", "get_tree"])?;
 
         if !output.status.success() {
             anyhow::bail!("{} get_tree failed", self.msg_command());
@@ -126,12 +337,26 @@ impl WmBackend {
         serde_json::from_str(&json_str).context("Failed to parse WM tree JSON")
     }
 
+    /// Gaps set in the running WM's config (none if it can't be read)
+    ///
+    /// Only the main config text `get_config` returns is read: gaps set in an
+    /// `include`d file or through a `$variable` count as none.
+    pub fn get_gaps(&self) -> Gaps {
+        #[derive(Deserialize)]
+        struct ConfigReply {
+            config: String,
+        }
+        let reply = self
+            .msg(&["-t", "get_config"])
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| serde_json::from_slice::<ConfigReply>(&output.stdout).ok());
+        reply.map(|reply| Gaps::from_config(&reply.config)).unwrap_or_default()
+    }
+
     /// Get list of workspaces
     pub fn get_workspaces(&self) -> Result<Vec<WorkspaceInfo>> {
-        let output = Command::new(self.msg_command())
-            .args(["-s", &self.socket_path, "-t", "get_workspaces"])
-            .output()
-            .with_context(|| format!("Failed to get {} workspaces", self.msg_command()))?;
+        let output = self.msg(&["-t", "get_workspaces"])?;
 
         if !output.status.success() {
             anyhow::bail!("{} get_workspaces failed", self.msg_command());
@@ -141,6 +366,29 @@ impl WmBackend {
         serde_json::from_str(&json_str).context("Failed to parse workspaces JSON")
     }
 
+    /// Get list of outputs
+    ///
+    /// Includes inactive outputs (and i3's pseudo-outputs like `xroot-0`);
+    /// filter on `active` for connected monitors.
+    pub fn get_outputs(&self) -> Result<Vec<OutputInfo>> {
+        let output = self.msg(&["-t", "get_outputs"])?;
+
+        if !output.status.success() {
+            anyhow::bail!("{} get_outputs failed", self.msg_command());
+        }
+
+        let json_str = String::from_utf8_lossy(&output.stdout);
+        serde_json::from_str(&json_str).context("Failed to parse outputs JSON")
+    }
+
+    /// Subscribe to window, workspace, binding and shutdown events
+    ///
+    /// Opens a dedicated connection to the IPC socket; iterate the stream, or
+    /// use `into_channel()` to receive events alongside other work.
+    pub fn subscribe(&self) -> Result<EventStream> {
+        EventStream::subscribe(&self.socket_path(), &EventKind::ALL)
+    }
+
     /// Run a command targeting a specific window by container ID
     ///
     /// Uses the `[con_id="..."]` selector which works for both i3 and Sway.
@@ -154,9 +402,122 @@ impl WmBackend {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_gaps_from_config() {
+        let config = "# gaps inner 99\ngaps inner 10px\n  gaps outer 4\nworkspace 3 gaps inner 20\ngaps top 30\nsmart_gaps on\n";
+        assert_eq!(Gaps::from_config(config), Gaps { inner: 10 });
+        assert_eq!(Gaps::from_config("bindsym $mod+g gaps inner current plus 5"), Gaps::default());
+        assert_eq!(Gaps::from_config("set $gap 8\ngaps inner $gap"), Gaps::default());
+
+        let gaps = Gaps { inner: 10 };
+        assert_eq!(gaps.pane_size(1930.0, 2, 0.5), 960.0);
+        assert_eq!(Gaps::default().pane_size(1920.0, 2, 0.5), 960.0);
+    }
+
     #[test]
     fn test_wm_type_debug() {
         assert_eq!(format!("{:?}", WmType::I3), "I3");
         assert_eq!(format!("{:?}", WmType::Sway), "Sway");
     }
+
+    const I3_TREE: &str = include_str!("../tests/fixtures/i3-tree.json");
+    const SWAY_TREE: &str = include_str!("../tests/fixtures/sway-tree.json");
+
+    fn find(node: &WmNode, id: u64) -> &WmNode {
+        fn walk(node: &WmNode, id: u64) -> Option<&WmNode> {
+            if node.id == id {
+                return Some(node);
+            }
+            node.children().find_map(|child| walk(child, id))
+        }
+        walk(node, id).unwrap()
+    }
+
+    #[test]
+    fn test_parse_i3_tree() {
+        let tree: WmNode = serde_json::from_str(I3_TREE).unwrap();
+        assert_eq!(tree.node_type, "root");
+
+        // i3 nests workspaces in a "content" container below the output
+        let ws = find(&tree, 94229504470608);
+        assert!(ws.is_workspace(2));
+        assert_eq!(ws.rect, Rect { x: 0, y: 0, width: 1920, height: 1080 });
+
+        let term = find(&tree, 94229504489168);
+        assert!(term.focused);
+        assert_eq!(term.percent, Some(0.5));
+        assert_eq!(term.marks, ["_i3mux:user@server:ws2-001"]);
+        assert_eq!(term.instance(), Some("_i3mux:user@server:ws2-001#3f9a1c2e"));
+        assert_eq!(term.window_properties.as_ref().unwrap().class.as_deref(), Some("Alacritty"));
+
+        // Containers without a window report null names and no properties
+        let split = find(&tree, 94229504497392);
+        assert_eq!(split.layout, "splitv");
+        assert_eq!(split.name, None);
+        assert_eq!(split.instance(), None);
+
+        // Floating windows sit in floating_nodes of the workspace
+        assert_eq!(find(&tree, 94229504529456).marks, ["_i3mux:local:ws2-003"]);
+    }
+
+    #[test]
+    fn test_parse_sway_tree() {
+        let tree: WmNode = serde_json::from_str(SWAY_TREE).unwrap();
+
+        // Sway puts workspaces directly below the output, and omits num for the scratchpad
+        let ws = find(&tree, 4);
+        assert!(ws.is_workspace(3));
+        assert_eq!(ws.layout, "tabbed");
+        assert_eq!(find(&tree, 2147483646).num, None);
+
+        let term = find(&tree, 8);
+        assert!(term.focused);
+        assert!(term.window_properties.is_none());
+        assert_eq!(term.instance(), Some("_i3mux:local:ws3-002#04cf61d3"));
+
+        // Xwayland windows have a null app_id and X11 properties
+        let xterm = find(&tree, 9);
+        assert_eq!(xterm.app_id, None);
+        assert_eq!(xterm.instance(), Some("_i3mux:local:ws3-004#e81f2b6c"));
+    }
+
+    #[test]
+    fn test_connection_errors() {
+        assert!(is_connection_error("IPC: read(): Connection reset by peer"));
+        assert!(is_connection_error("Could not connect to i3 on socket /run/user/1000/i3/ipc-socket.1234: No such file or directory"));
+        assert!(is_connection_error("[common/ipc-client.c:87] Unable to connect to /run/user/1000/sway-ipc.1000.42.sock"));
+        // The WM answered; retrying wouldn't help
+        assert!(!is_connection_error("ERROR: Expected one of these tokens: <end>, '[', 'move'"));
+        assert!(!is_connection_error("Error: No matching node."));
+    }
+
+    #[test]
+    fn test_parse_i3_outputs() {
+        let json = r#"[
+            {"name": "xroot-0", "active": false, "primary": false, "current_workspace": null,
+             "rect": {"x": 0, "y": 0, "width": 3840, "height": 1080}},
+            {"name": "DP-1", "active": true, "primary": true, "current_workspace": "1",
+             "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080}}
+        ]"#;
+        let outputs: Vec<OutputInfo> = serde_json::from_str(json).unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert!(!outputs[0].active);
+        assert_eq!(outputs[1].current_workspace.as_deref(), Some("1"));
+        assert_eq!(outputs[1].resolution(), (1920, 1080));
+    }
+
+    #[test]
+    fn test_parse_sway_outputs() {
+        // Scaled 4K output: rect is logical, current_mode is physical
+        let json = r#"[
+            {"id": 3, "name": "eDP-1", "active": true, "primary": false, "make": "BOE",
+             "scale": 2.0, "current_workspace": "2",
+             "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+             "current_mode": {"width": 3840, "height": 2160, "refresh": 60000}}
+        ]"#;
+        let outputs: Vec<OutputInfo> = serde_json::from_str(json).unwrap();
+        assert_eq!(outputs[0].name, "eDP-1");
+        assert_eq!(outputs[0].rect.width, 1920);
+        assert_eq!(outputs[0].resolution(), (3840, 2160));
+    }
 }
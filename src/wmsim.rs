@@ -0,0 +1,295 @@
+//! Simulated i3 container tree for headless layout tests
+//!
+//! Models the parts of i3's tree semantics that layout restores depend on:
+//! where new windows open, `split`, `layout`, `focus parent|child|<direction>`
+//! and `[con_id="N"]` criteria. Restore plans can then be checked against the
+//! tree they produce in `cargo test`, without Xvfb containers. The rules follow
+//! i3's tree.c and con.c; only a single workspace on a single output is
+//! modelled, and i3's flattening of redundant split containers is not.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::wm::WmNode;
+
+/// Con id of the workspace
+const WORKSPACE: usize = 0;
+
+struct Con {
+    parent: usize,
+    /// Children in tree order
+    nodes: Vec<usize>,
+    /// Children, most recently focused first
+    focus: Vec<usize>,
+    layout: &'static str,
+    mark: Option<String>,
+}
+
+/// One empty workspace, numbered 1
+pub struct SimWm {
+    cons: Vec<Con>,
+    focused: usize,
+}
+
+impl SimWm {
+    /// Workspaces start out horizontal, as on a landscape output
+    pub fn new() -> Self {
+        let workspace = Con { parent: WORKSPACE, nodes: Vec::new(), focus: Vec::new(), layout: "splith", mark: None };
+        Self { cons: vec![workspace], focused: WORKSPACE }
+    }
+
+    /// Open a window marked `mark` where i3 would, and focus it
+    ///
+    /// New windows open next to the focused container, or inside the
+    /// workspace when the workspace itself is focused.
+    pub fn open_window(&mut self, mark: &str) -> u64 {
+        let parent = if self.focused == WORKSPACE { WORKSPACE } else { self.cons[self.focused].parent };
+        let window = self.new_con(parent, "splith");
+        self.cons[window].mark = Some(mark.to_string());
+        self.attach(window, parent);
+        self.focus(window);
+        window as u64
+    }
+
+    /// Run a `;`-separated command chain, failing like `i3-msg` on the first error
+    pub fn run(&mut self, chain: &str) -> Result<()> {
+        for command in chain.split(';').map(str::trim) {
+            self.run_one(command).with_context(|| format!("'{}' failed", command))?;
+        }
+        Ok(())
+    }
+
+    /// The workspace, as the WM's tree reports it
+    pub fn workspace(&self) -> WmNode {
+        serde_json::from_value(self.node_json(WORKSPACE)).unwrap()
+    }
+
+    fn run_one(&mut self, command: &str) -> Result<()> {
+        if let Some(rest) = command.strip_prefix("[con_id=\"") {
+            let (id, command) = rest.split_once("\"]").context("unterminated criteria")?;
+            let id: usize = id.parse()?;
+            if id >= self.cons.len() || command.trim() != "focus" {
+                bail!("unsupported criteria command");
+            }
+            self.focus(id);
+            return Ok(());
+        }
+
+        match command {
+            "split h" | "split horizontal" => self.split("splith"),
+            "split v" | "split vertical" => self.split("splitv"),
+            "layout splith" | "layout splitv" | "layout tabbed" | "layout stacking" => {
+                self.set_layout(match &command[7..] {
+                    "stacking" => "stacked",
+                    "tabbed" => "tabbed",
+                    "splith" => "splith",
+                    _ => "splitv",
+                });
+            }
+            "focus parent" => {
+                // Focus can go up to the workspace, but not any higher
+                if self.focused == WORKSPACE {
+                    bail!("focus is already on the workspace");
+                }
+                self.focus(self.cons[self.focused].parent);
+            }
+            "focus child" => match self.cons[self.focused].focus.first() {
+                Some(&child) => self.focus(child),
+                None => bail!("nothing to focus below"),
+            },
+            "focus left" => self.focus_sibling(true, false),
+            "focus right" => self.focus_sibling(true, true),
+            "focus up" => self.focus_sibling(false, false),
+            "focus down" => self.focus_sibling(false, true),
+            _ => bail!("unsupported command"),
+        }
+        Ok(())
+    }
+
+    fn new_con(&mut self, parent: usize, layout: &'static str) -> usize {
+        self.cons.push(Con { parent, nodes: Vec::new(), focus: Vec::new(), layout, mark: None });
+        self.cons.len() - 1
+    }
+
+    /// Insert `con` into `parent` after its focused child (con_attach)
+    fn attach(&mut self, con: usize, parent: usize) {
+        self.cons[con].parent = parent;
+        let at = self.cons[parent].focus.first().and_then(|f| self.cons[parent].nodes.iter().position(|n| n == f));
+        let parent = &mut self.cons[parent];
+        match at {
+            Some(i) => parent.nodes.insert(i + 1, con),
+            None => parent.nodes.push(con),
+        }
+        parent.focus.push(con);
+    }
+
+    /// Focus `con`, raising it in the focus order of every ancestor
+    fn focus(&mut self, con: usize) {
+        self.focused = con;
+        let mut child = con;
+        while child != WORKSPACE {
+            let parent = self.cons[child].parent;
+            let focus = &mut self.cons[parent].focus;
+            focus.retain(|&c| c != child);
+            focus.insert(0, child);
+            child = parent;
+        }
+    }
+
+    /// Move all children of the workspace into a new container with `layout`
+    fn encapsulate_workspace(&mut self, layout: &'static str) -> usize {
+        let wrapper = self.new_con(WORKSPACE, layout);
+        let workspace = &mut self.cons[WORKSPACE];
+        let nodes = std::mem::take(&mut workspace.nodes);
+        let focus = std::mem::take(&mut workspace.focus);
+        for &child in &nodes {
+            self.cons[child].parent = wrapper;
+        }
+        self.cons[wrapper].nodes = nodes;
+        self.cons[wrapper].focus = focus;
+        self.attach(wrapper, WORKSPACE);
+        wrapper
+    }
+
+    /// tree_split
+    fn split(&mut self, layout: &'static str) {
+        let mut con = self.focused;
+        if con == WORKSPACE {
+            if self.cons[WORKSPACE].nodes.len() < 2 {
+                self.cons[WORKSPACE].layout = layout;
+                return;
+            }
+            con = self.encapsulate_workspace(self.cons[WORKSPACE].layout);
+        }
+
+        // A split container with a single child just changes orientation
+        let parent = self.cons[con].parent;
+        if self.cons[parent].nodes.len() == 1 && self.cons[parent].layout.starts_with("split") {
+            self.cons[parent].layout = layout;
+            return;
+        }
+
+        // Otherwise the container takes the place of a new split container
+        let split = self.new_con(parent, layout);
+        let Con { nodes, focus, .. } = &mut self.cons[parent];
+        for list in [nodes, focus] {
+            let i = list.iter().position(|&c| c == con).unwrap();
+            list[i] = split;
+        }
+        self.cons[con].parent = split;
+        self.cons[split].nodes.push(con);
+        self.cons[split].focus.push(con);
+    }
+
+    /// con_set_layout: applies to the focused container's parent, unless the
+    /// workspace itself is focused
+    fn set_layout(&mut self, layout: &'static str) {
+        let con = if self.focused == WORKSPACE { WORKSPACE } else { self.cons[self.focused].parent };
+        if con == WORKSPACE && !self.cons[WORKSPACE].nodes.is_empty() && matches!(layout, "tabbed" | "stacked") {
+            // The workspace's children move into a new tabbed/stacked container
+            self.encapsulate_workspace(layout);
+            return;
+        }
+        self.cons[con].layout = layout;
+    }
+
+    /// Focus the neighbour in a direction, going up the tree until a container
+    /// of that orientation has one, then down to its most recently focused window
+    fn focus_sibling(&mut self, horizontal: bool, forward: bool) {
+        let mut con = self.focused;
+        while con != WORKSPACE {
+            let parent = self.cons[con].parent;
+            let oriented = match self.cons[parent].layout {
+                "splith" | "tabbed" => horizontal,
+                _ => !horizontal,
+            };
+            let nodes = &self.cons[parent].nodes;
+            let i = nodes.iter().position(|&c| c == con).unwrap();
+            let next = if forward { nodes.get(i + 1) } else { i.checked_sub(1).map(|i| &nodes[i]) };
+            if let (true, Some(&next)) = (oriented, next) {
+                let mut target = next;
+                while let Some(&child) = self.cons[target].focus.first() {
+                    target = child;
+                }
+                self.focus(target);
+                return;
+            }
+            con = parent;
+        }
+    }
+
+    fn node_json(&self, id: usize) -> Value {
+        let con = &self.cons[id];
+        json!({
+            "id": id,
+            "type": if id == WORKSPACE { "workspace" } else { "con" },
+            "num": if id == WORKSPACE { Some(1) } else { None },
+            "layout": con.layout,
+            "focused": id == self.focused,
+            "marks": con.mark.iter().collect::<Vec<_>>(),
+            "nodes": con.nodes.iter().map(|&child| self.node_json(child)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(wm: &SimWm) -> String {
+        fn walk(node: &WmNode) -> String {
+            match node.marks.first() {
+                Some(mark) => mark.clone(),
+                None => format!("{}[{}]", node.layout, node.children().map(walk).collect::<Vec<_>>().join(" ")),
+            }
+        }
+        walk(&wm.workspace())
+    }
+
+    #[test]
+    fn test_windows_open_next_to_focus() {
+        let mut wm = SimWm::new();
+        let a = wm.open_window("a");
+        wm.open_window("b");
+        wm.run(&format!("[con_id=\"{}\"] focus", a)).unwrap();
+        wm.open_window("c");
+        assert_eq!(shape(&wm), "splith[a c b]");
+        assert!(wm.workspace().children().any(|c| c.focused && c.marks == ["c"]));
+    }
+
+    #[test]
+    fn test_split_and_layout() {
+        let mut wm = SimWm::new();
+        // A lone window changes the workspace's orientation instead of nesting
+        wm.open_window("a");
+        wm.run("split v").unwrap();
+        wm.open_window("b");
+        assert_eq!(shape(&wm), "splitv[a b]");
+
+        // With siblings, the window gets a container of its own
+        wm.run("split h").unwrap();
+        wm.open_window("c");
+        assert_eq!(shape(&wm), "splitv[a splith[b c]]");
+
+        // Tabbing the workspace's children wraps them
+        wm.run("focus parent; focus parent; layout tabbed").unwrap();
+        assert_eq!(shape(&wm), "splitv[tabbed[a splith[b c]]]");
+
+        assert!(wm.run("focus parent; focus parent").is_err());
+    }
+
+    #[test]
+    fn test_focus_directions() {
+        let mut wm = SimWm::new();
+        wm.open_window("a");
+        wm.run("split v").unwrap();
+        wm.open_window("b");
+        wm.run("split h").unwrap();
+        wm.open_window("c");
+        // Left of c is b; up from there leaves the horizontal split for a
+        wm.run("focus left").unwrap();
+        assert!(wm.workspace().children().nth(1).unwrap().children().next().unwrap().focused);
+        wm.run("focus up").unwrap();
+        assert!(wm.workspace().children().next().unwrap().focused);
+    }
+}
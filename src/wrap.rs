@@ -0,0 +1,161 @@
+//! What runs inside an i3mux terminal (`i3mux _wrap`)
+//!
+//! The terminal emulator starts `i3mux _wrap` instead of a shell. It sets the
+//! title, runs the attach command and, once that ends or the window goes away,
+//! cleans up the socket, the workspace state and (with the workspace's last
+//! socket) the saved session. Progress is logged to `<socket>.log` in the
+//! per-user directory, since the terminal is usually gone by the time cleanup
+//! could report anything.
+//!
+//! Cleanup runs in this process rather than by invoking the binary again, so it
+//! still works when the binary is upgraded or moved while the terminal is open.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::connection;
+use crate::hooks;
+
+/// Exit status when the terminal hung up on us (128 + SIGHUP)
+const EXIT_HANGUP: i32 = 129;
+
+/// How often the attach command is checked while waiting for it
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Set by the signal handler when the window is closed or the terminal dies
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+/// A terminal to attach and clean up after
+pub struct Wrap {
+    pub socket: String,
+    pub workspace: String,
+    pub attach_cmd: String,
+    /// Window title (None: left to the shell)
+    pub title: Option<String>,
+    /// Saved session and the host it is saved on
+    pub session: Option<(String, String)>,
+}
+
+extern "C" fn on_hangup(_signal: libc::c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+pub fn run(wrap: Wrap) -> Result<()> {
+    let log = Log::open(&wrap.socket);
+    let result = attach_and_clean_up(&wrap, &log);
+    if let Err(e) = &result {
+        log.write(&format!("Failed: {:#}", e));
+    }
+    result
+}
+
+fn attach_and_clean_up(wrap: &Wrap, log: &Log) -> Result<()> {
+    // Set the title before anything else writes to the terminal
+    if let Some(title) = &wrap.title {
+        print!("\x1b]0;{}\x07", title);
+        let _ = std::io::stdout().flush();
+    }
+
+    log.write(&format!("Starting at {}", chrono::Local::now().to_rfc2822()));
+    log.write(&format!("Socket: {}", wrap.socket));
+    log.write(&format!("Attach command: {}", wrap.attach_cmd));
+
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGHUP, on_hangup as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_hangup as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+
+    let mut cmd = Command::new("bash");
+    cmd.arg("-c").arg(&wrap.attach_cmd);
+    let mut child = cmd.spawn().context("Failed to run attach command")?;
+
+    let status = loop {
+        if HANGUP.load(Ordering::SeqCst) {
+            // Window closed externally (i3-msg kill, terminal crash). The
+            // attach command goes first, so cleanup never sees it still attached
+            log.write("Window closed, cleaning up");
+            let _ = child.kill();
+            let _ = child.wait();
+            cleanup(wrap, log, true);
+            std::process::exit(EXIT_HANGUP);
+        }
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    log.write(&format!("Attach command exited with {}", status));
+    cleanup(wrap, log, false);
+    log.write(&format!("Session ended at {}", chrono::Local::now().to_rfc2822()));
+
+    // Keep the window open on failure so the error can be read
+    if !status.success() {
+        print!("Press Enter to close terminal...");
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stdin().read_line(&mut String::new());
+    }
+    Ok(())
+}
+
+/// Clean up after the terminal (`window_closed`: its window went away, rather
+/// than the attach command ending); failures are reported but never fatal
+fn cleanup(wrap: &Wrap, log: &Log, window_closed: bool) {
+    if let Some((session, host)) = &wrap.session {
+        // A terminal no bound workspace lists (after detach or `i3mux unbind`)
+        // no longer owns the session; the workspace may have been renumbered
+        let owner = crate::socket_owner_prefixes(&wrap.socket).unwrap_or_else(|e| {
+            log.write(&format!("Could not read the workspace state: {:#}", e));
+            Some(vec![crate::instance::socket_prefix(&wrap.workspace)])
+        });
+        if let Some(prefixes) = owner {
+            if let Err(e) = cleanup_session(&prefixes, session, host) {
+                log.write(&format!("Session cleanup failed: {:#}", e));
+            }
+        }
+    }
+    if let Err(e) = crate::cleanup_workspace(&wrap.workspace, Some(&wrap.socket), window_closed) {
+        log.write(&format!("Cleanup failed: {:#}", e));
+    }
+    hooks::emit(hooks::Event::CleanupDone, &wrap.socket);
+}
+
+/// Remove the saved session and its lock once no socket of the workspace
+/// (under any of its socket `prefixes`) survives
+fn cleanup_session(prefixes: &[String], session: &str, host: &str) -> Result<()> {
+    let conn = crate::host_connection(host)?;
+    for prefix in prefixes {
+        if conn.has_live_sockets(prefix)? {
+            return Ok(());
+        }
+    }
+    conn.delete_session(session)?;
+    conn.release_lock(session)
+}
+
+/// Append-only log of one terminal (best effort: a missing log never stops the terminal)
+struct Log(Option<fs::File>);
+
+impl Log {
+    fn open(socket: &str) -> Self {
+        let file = connection::ensure_user_dir().ok().and_then(|dir| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(format!("{}.log", socket)))
+                .ok()
+        });
+        Self(file)
+    }
+
+    fn write(&self, message: &str) {
+        if let Some(mut file) = self.0.as_ref() {
+            let _ = writeln!(file, "[i3mux wrapper] {}", message);
+        }
+    }
+}
@@ -0,0 +1,374 @@
+// CLI tests: argument parsing, error messages, exit codes and --json errors
+// Run with: cargo test --test cli
+//
+// These run the real binary without a window manager, against a throwaway
+// config directory and `--offline` hosts, so they need no Docker containers.
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use serde_json::Value;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Exit codes from src/error.rs
+const EXIT_FAILURE: i32 = 1;
+const EXIT_WM_UNAVAILABLE: i32 = 6;
+/// clap's exit code for usage errors
+const EXIT_USAGE: i32 = 2;
+
+/// i3mux with its own config directory and no window manager in reach
+fn i3mux(home: &TempDir) -> Command {
+    let mut cmd = Command::cargo_bin("i3mux").unwrap();
+    cmd.env("HOME", home.path())
+        .env("XDG_CONFIG_HOME", home.path().join("config"))
+        .env("NO_COLOR", "1")
+        .env("RUST_BACKTRACE", "0")
+        .env_remove("I3MUX_INSTANCE")
+        .env_remove("I3SOCK")
+        .env_remove("SWAYSOCK")
+        .env_remove("DISPLAY")
+        .env_remove("WAYLAND_DISPLAY");
+    cmd
+}
+
+/// The `{"error": {...}}` object printed on stderr by --json
+fn json_error(output: &std::process::Output) -> Value {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let value: Value = serde_json::from_str(stderr.trim()).unwrap_or_else(|e| panic!("not JSON ({}): {}", e, stderr));
+    value["error"].clone()
+}
+
+/// An `--offline` host whose sessions live in a file removed on drop
+struct OfflineHost {
+    host: String,
+    path: PathBuf,
+}
+
+impl OfflineHost {
+    fn new(name: &str) -> Self {
+        // Same location as connection::user_dir(), unique per test process
+        let uid = unsafe { libc::getuid() };
+        let host = format!("tester@cli{}-{}", std::process::id(), name);
+        let path = PathBuf::from(format!("/tmp/i3mux-{}/offline/{}.json", uid, host));
+        Self { host, path }
+    }
+
+    /// Store `session_json` as the session `name`
+    fn save(&self, name: &str, session_json: &str) {
+        std::fs::create_dir_all(self.path.parent().unwrap()).unwrap();
+        let store = serde_json::json!({ "sessions": { name: session_json }, "locks": {} });
+        std::fs::write(&self.path, store.to_string()).unwrap();
+    }
+}
+
+impl Drop for OfflineHost {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn test_help_lists_subcommands() {
+    let home = TempDir::new().unwrap();
+    let assert = i3mux(&home).arg("--help").assert().success();
+    let help = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+    for subcommand in [
+        "activate", "adopt-workspace", "detach", "sync", "ssh", "helpers", "rebind", "unbind", "attach", "sessions", "health", "watch-locks", "query", "create", "edit", "validate", "kill", "status", "which",
+        "ps", "exec-all", "grid", "zoom", "swap", "even", "rotate", "lock-layout", "unlock-layout", "boot", "stats", "toggle",
+        "pin-output", "forward", "mount", "resume", "respawn", "edit-terminal", "state", "terminal",
+    ] {
+        assert!(help.contains(&format!("\n  {} ", subcommand)), "--help is missing {}", subcommand);
+    }
+    // Internal commands stay out of the listing
+    assert!(!help.contains("_wrap"));
+}
+
+#[test]
+fn test_usage_errors() {
+    let home = TempDir::new().unwrap();
+    i3mux(&home)
+        .arg("bogus")
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("unrecognized subcommand 'bogus'"));
+    i3mux(&home)
+        .args(["kill", "--remote", "user@box"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("--session <SESSION>"));
+    i3mux(&home)
+        .args(["grid", "0"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("0 is not in 1..=64"));
+    i3mux(&home).arg("grid").assert().code(EXIT_USAGE).stderr(predicate::str::contains("<COUNT>"));
+    i3mux(&home)
+        .args(["attach", "--force", "--here-then-close-origin"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("cannot be used with"));
+    i3mux(&home)
+        .args(["terminal", "--pod", "app=api", "--docker", "web"])
+        .assert()
+        .code(EXIT_USAGE)
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_invalid_input_is_rejected() {
+    let home = TempDir::new().unwrap();
+    i3mux(&home)
+        .args(["grid", "--layout", "h(60:editor,v(a,b)"])
+        .assert()
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::contains("Missing ')' for the container at column 1"));
+    i3mux(&home)
+        .args(["sessions", "--remote", "user@box; rm -rf ~"])
+        .assert()
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::starts_with("Error: Invalid hostname"));
+    i3mux(&home)
+        .args(["ssh", "connect"])
+        .assert()
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::contains("No host to connect to"));
+    i3mux(&home)
+        .args(["helpers", "update"])
+        .assert()
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::contains("No hosts to update"));
+    i3mux(&home)
+        .args(["helpers", "update", "--all-hosts"])
+        .assert()
+        .success()
+        .stdout("No remote hosts in the config\n");
+    i3mux(&home)
+        .args(["watch-locks", "--once"])
+        .assert()
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::contains("No hosts to watch"));
+
+    let output = i3mux(&home).args(["--json", "forward", "-L", "8080;reboot"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_FAILURE));
+    let error = json_error(&output);
+    assert_eq!(error["kind"], "other");
+    assert_eq!(error["exit_code"], EXIT_FAILURE);
+    assert!(error["message"].as_str().unwrap().starts_with("Invalid forward '8080;reboot'"));
+}
+
+#[test]
+fn test_commands_need_a_window_manager() {
+    let home = TempDir::new().unwrap();
+    let commands: [&[&str]; 26] = [
+        &[],
+        &["activate"],
+        &["adopt-workspace"],
+        &["detach"],
+        &["unbind"],
+        &["status"],
+        &["query", "windows"],
+        &["which"],
+        &["ps"],
+        &["exec-all", "source", "~/.env"],
+        &["boot"],
+        &["grid", "4"],
+        &["zoom"],
+        &["swap", "--direction", "left"],
+        &["even"],
+        &["rotate"],
+        &["lock-layout"],
+        &["unlock-layout"],
+        &["toggle"],
+        &["pin-output", "DP-1"],
+        &["forward"],
+        &["mount"],
+        &["resume"],
+        &["respawn"],
+        &["edit-terminal"],
+        &["terminal"],
+    ];
+    for args in commands {
+        i3mux(&home)
+            .args(args)
+            .assert()
+            .code(EXIT_WM_UNAVAILABLE)
+            .stderr(predicate::str::contains("No running window manager (i3 or Sway) detected"));
+
+        let output = i3mux(&home).arg("--json").args(args).output().unwrap();
+        assert_eq!(output.status.code(), Some(EXIT_WM_UNAVAILABLE), "{:?}", args);
+        let error = json_error(&output);
+        assert_eq!(error["kind"], "wm_unavailable");
+        assert_eq!(error["exit_code"], EXIT_WM_UNAVAILABLE);
+    }
+}
+
+#[test]
+fn test_offline_sessions_and_kill() {
+    let home = TempDir::new().unwrap();
+    let remote = OfflineHost::new("sessions");
+    i3mux(&home)
+        .args(["--offline", "sessions", "--remote", &remote.host])
+        .assert()
+        .success()
+        .stdout(format!("No sessions on {}\n", remote.host));
+
+    remote.save("dev", include_str!("fixtures/sessions/full.json"));
+    i3mux(&home)
+        .args(["--offline", "sessions", "--remote", &remote.host])
+        .assert()
+        .success()
+        .stdout(format!("Sessions on {}:\n\n  dev - 6 terminals [stale lock]\n", remote.host));
+    i3mux(&home)
+        .args(["--offline", "health", "--remote", &remote.host])
+        .assert()
+        .code(EXIT_FAILURE)
+        .stdout(predicate::str::contains("Session 'dev':\n  storage  OK: ok (revision 7, saved by laptop)\n  lock     PROBLEM: stale"))
+        .stderr(predicate::str::contains("problem(s) found on"));
+    // Nobody holds the lock the session was saved with
+    i3mux(&home)
+        .args(["--offline", "--json", "watch-locks", "--remote", &remote.host, "--once"])
+        .assert()
+        .success()
+        .stdout("{\"class\":\"free\",\"text\":\"\",\"tooltip\":\"\"}\n");
+    // Local sessions are checked in place, not over ssh to a host named "local"
+    i3mux(&home)
+        .args(["--json", "watch-locks", "--remote", "local", "--once"])
+        .assert()
+        .success()
+        .stdout("{\"class\":\"free\",\"text\":\"\",\"tooltip\":\"\"}\n");
+
+    // Terminals of a saved session can be edited while it isn't attached
+    let work = OfflineHost::new("edit");
+    work.save("work", include_str!("fixtures/sessions/full.json"));
+    let edit = |args: &[&str]| {
+        let mut cmd = i3mux(&home);
+        cmd.args(["--offline", "edit-terminal", "--remote", &work.host, "--session", "work"]).args(args);
+        cmd.assert()
+    };
+    edit(&["--socket", "ws3-005"]).success().stdout(predicate::str::starts_with("ws3-005 attaches to pod "));
+    edit(&["--socket", "ws3-005", "--attach", "tmux attach -t api"])
+        .success()
+        .stdout("ws3-005 attaches with: tmux attach -t api\n");
+    edit(&["--socket", "ws3-005"]).success().stdout("ws3-005 attaches with: tmux attach -t api\n");
+    edit(&["--socket", "ws3-004", "--clear"]).success().stdout("ws3-004 attaches through abduco\n");
+    edit(&["--socket", "ws9-001", "--clear"]).code(EXIT_FAILURE).stderr(predicate::str::contains("has no terminal ws9-001"));
+
+    // ...and as JSON, in $EDITOR, checked before it is saved back
+    let edit_json = |editor: &str| {
+        let mut cmd = i3mux(&home);
+        cmd.env("EDITOR", editor).env_remove("VISUAL");
+        cmd.args(["--offline", "edit", "--remote", &work.host, "--session", "work"]).assert()
+    };
+    edit_json("true").success().stdout("No changes\n");
+    edit_json("sed -i s/ws3-003/ws3-002/")
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::contains("Invalid layout: Socket ws3-002 is in the layout more than once"))
+        .stderr(predicate::str::contains("Session not saved; your edit is in"));
+    edit_json("sed -i s/ws3-006/ws3-007/").success().stdout(predicate::str::contains("Session 'work' saved (revision"));
+    edit(&["--socket", "ws3-007"]).success().stdout(predicate::str::starts_with("ws3-007 attaches to container "));
+
+    // Sessions can be checked without restoring them, saved or from a file
+    i3mux(&home)
+        .args(["--offline", "validate", "--remote", &work.host, "--session", "work"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("valid session 'work' (6 terminals)"));
+    let file = home.path().join("generated.json");
+    std::fs::write(&file, include_str!("fixtures/sessions/full.json").replace("ws3-003", "ws3-002")).unwrap();
+    i3mux(&home)
+        .arg("validate")
+        .arg(&file)
+        .assert()
+        .code(EXIT_FAILURE)
+        .stdout(predicate::str::contains("Socket ws3-002 is in the layout more than once (layout.children[1]"))
+        .stderr(predicate::str::contains("1 problem(s) found in"));
+    std::fs::write(&file, "{\n  \"name\": \"work\"\n}\n").unwrap();
+    i3mux(&home)
+        .arg("validate")
+        .arg(&file)
+        .assert()
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::contains("missing field `workspace` at line 3 column 1"));
+
+    // Sessions declared in a spec are saved for a first attach
+    let spec = home.path().join("dev.toml");
+    std::fs::write(&spec, "name = \"dev\"\nlayout = \"h(v(editor,shell),logs)\"\n[terminals.editor]\ncommand = \"nvim\"\n").unwrap();
+    let create = |force: bool| {
+        let mut cmd = i3mux(&home);
+        cmd.args(["--offline", "create", "--remote", &work.host, "--spec"]).arg(&spec);
+        if force {
+            cmd.arg("--force");
+        }
+        cmd.assert()
+    };
+    create(false).success().stdout(predicate::str::contains("Session 'dev' created on").and(predicate::str::contains("(3 terminals)")));
+    create(false).code(EXIT_FAILURE).stderr(predicate::str::contains("already exists"));
+    create(true).success();
+    i3mux(&home)
+        .args(["--offline", "validate", "--remote", &work.host, "--session", "dev"])
+        .assert()
+        .success();
+
+    i3mux(&home)
+        .args(["--offline", "kill", "--remote", &remote.host, "--session", "dev", "--keep-running"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("Session 'dev' deleted from {}", remote.host)));
+    i3mux(&home)
+        .args(["--offline", "sessions", "--remote", &remote.host])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("No sessions"));
+}
+
+#[test]
+fn test_local_state_commands() {
+    let home = TempDir::new().unwrap();
+    i3mux(&home)
+        .args(["state", "dump"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No workspaces are bound"));
+
+    // Renumbering a binding only touches state.json
+    let state_dir = home.path().join("config/i3mux");
+    std::fs::create_dir_all(&state_dir).unwrap();
+    let workspace = serde_json::json!({
+        "session_type": "local", "host": "local", "session_name": null, "next_socket_id": 2,
+        "sockets": { "ws4-001": { "socket_id": "ws4-001" } },
+    });
+    std::fs::write(state_dir.join("state.json"), serde_json::json!({ "workspaces": { "4": workspace } }).to_string()).unwrap();
+    i3mux(&home)
+        .args(["rebind", "--from", "4", "--to", "7"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Workspace 4 is now bound as workspace 7"));
+    i3mux(&home)
+        .args(["rebind", "--from", "4", "--to", "8"])
+        .assert()
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::contains("Workspace 4 is not bound"));
+    let state: Value = serde_json::from_str(&std::fs::read_to_string(state_dir.join("state.json")).unwrap()).unwrap();
+    assert_eq!(state["workspaces"]["7"]["sockets"]["ws4-001"]["socket_id"], "ws4-001");
+
+    // Another instance has a state of its own
+    i3mux(&home)
+        .args(["--instance", "dev", "state", "dump"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No workspaces are bound"));
+    assert!(state_dir.join("instances/dev").is_dir());
+    i3mux(&home)
+        .args(["--instance", "Dev/2", "state", "dump"])
+        .assert()
+        .code(EXIT_FAILURE)
+        .stderr(predicate::str::contains("Invalid instance name 'Dev/2'"));
+
+    i3mux(&home).arg("sync").assert().success().stdout("No sessions are queued\n");
+
+    i3mux(&home)
+        .arg("stats")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("No metrics recorded"));
+}
@@ -7,7 +7,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use super::docker::{ContainerManager, DualContainerManager, TestWmType};
-use super::i3mux::I3muxRunner;
+use super::i3mux::{I3muxRunner, HOOKS_FILE};
 use super::network::NetworkManipulator;
 use super::screenshot::{compare_screenshots, load_golden_image, save_comparison_failure};
 use super::comparison_spec::ComparisonSpec;
@@ -230,12 +230,15 @@ impl TestEnvironment {
             TestWmType::Sway => ("foot", "source /tmp/sway-env.sh &&", "swaymsg"),
         };
 
+        let marked = self.i3mux().hook_count("window-marked")?;
+
         // Launch via WM exec so WM spawns the process
         let launch_cmd = format!(
-            "{} {} 'exec --no-startup-id TERMINAL={} i3mux terminal 2>>/tmp/i3mux-debug.log'",
+            "{} {} 'exec --no-startup-id TERMINAL={} i3mux --test-hooks {} terminal 2>>/tmp/i3mux-debug.log'",
             env_prefix,
             msg_cmd,
-            terminal
+            terminal,
+            HOOKS_FILE
         );
         let output = self.container_mgr.exec_in_wm(&launch_cmd)?;
 
@@ -246,27 +249,18 @@ impl TestEnvironment {
             );
         }
 
-        // Wait for window to appear (SSH connections can be slow)
-        for _ in 0..50 {  // Up to 5 seconds
-            std::thread::sleep(Duration::from_millis(100));
-            let after = self.get_workspace_windows()?.len();
-            if after > before {
-                // Window appeared - now wait for i3mux to finish marking
-                // This is critical: i3mux needs time to mark the window
-                std::thread::sleep(Duration::from_millis(2500));
-
-                // Verify marking succeeded
-                let windows = self.get_workspace_windows()?;
-                if let Some(new_window) = windows.get(windows.len() - 1) {
-                    let info = self.get_window_info(*new_window)?;
-                    println!("New window {} info after launch: {}", new_window, info);
-                }
+        // i3mux reports the window once it has marked it (SSH connections can be slow)
+        self.i3mux()
+            .wait_for_hook("window-marked", marked, Duration::from_secs(10))
+            .context("i3mux terminal window did not appear")?;
 
-                return Ok(());
-            }
+        let windows = self.get_workspace_windows()?;
+        if windows.len() > before {
+            let new_window = windows[windows.len() - 1];
+            println!("New window {} info after launch: {}", new_window, self.get_window_info(new_window)?);
         }
 
-        anyhow::bail!("i3mux terminal window did not appear within timeout")
+        Ok(())
     }
 
     // ==================== Screenshot Operations ====================
@@ -474,7 +468,9 @@ impl TestEnvironment {
             TestWmType::Sway => "source /tmp/sway-env.sh &&",
         };
 
-        let cmd = format!("{} {}", env_prefix, action);
+        // i3mux reads the hooks file from the environment as well as from --test-hooks
+        let cmd = format!("{} I3MUX_TEST_HOOKS={} {}", env_prefix, HOOKS_FILE, action);
+        let marked = self.i3mux().hook_count("window-marked")?;
         let output = self.container_mgr.exec_in_wm(&cmd)?;
 
         if !output.status.success() {
@@ -488,10 +484,9 @@ impl TestEnvironment {
 
         // Wait for action to take effect
         if action.contains("launch_terminal") {
-            std::thread::sleep(Duration::from_secs(3));
-        } else {
-            std::thread::sleep(Duration::from_millis(200));
+            self.i3mux().wait_for_hook("window-marked", marked, Duration::from_secs(10))?;
         }
+        std::thread::sleep(Duration::from_millis(200));
 
         Ok(())
     }
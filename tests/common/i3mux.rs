@@ -1,9 +1,16 @@
 // i3mux command wrappers for testing
 
 use anyhow::{Context, Result};
+use std::time::{Duration, Instant};
 use super::docker::{ContainerManager, TestWmType};
 use super::environment::{Session, ColorScript};
 
+/// File i3mux appends readiness events to (`--test-hooks`), in the WM container
+pub const HOOKS_FILE: &str = "/tmp/i3mux-test-hooks.log";
+
+/// How long a launched terminal may take to be marked (SSH connections can be slow)
+const MARK_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct I3muxRunner<'a> {
     container_mgr: &'a ContainerManager,
 }
@@ -45,12 +52,12 @@ impl<'a> I3muxRunner<'a> {
 
         let cmd = match session {
             Session::Local => format!(
-                "{} {} workspace {} && {} TERMINAL={} i3mux activate",
-                env, msg, workspace, env, term
+                "{} {} workspace {} && {} TERMINAL={} i3mux --test-hooks {} activate",
+                env, msg, workspace, env, term, HOOKS_FILE
             ),
             Session::Remote(host) => format!(
-                "{} {} workspace {} && {} TERMINAL={} i3mux activate --remote {}",
-                env, msg, workspace, env, term, host
+                "{} {} workspace {} && {} TERMINAL={} i3mux --test-hooks {} activate --remote {}",
+                env, msg, workspace, env, term, HOOKS_FILE, host
             ),
         };
 
@@ -68,7 +75,7 @@ impl<'a> I3muxRunner<'a> {
 
     /// Detach current session
     pub fn detach(&self, name: &str) -> Result<()> {
-        let cmd = format!("{} i3mux detach --session {}", self.env_prefix(), name);
+        let cmd = format!("{} i3mux --test-hooks {} detach --session {}", self.env_prefix(), HOOKS_FILE, name);
 
         let output = self.container_mgr.exec_in_wm(&cmd)?;
 
@@ -89,10 +96,13 @@ impl<'a> I3muxRunner<'a> {
         let term = self.default_terminal();
 
         let cmd = match session {
-            Session::Local => format!("{} TERMINAL={} i3mux attach {} --session {}", env, term, force_flag, name),
+            Session::Local => format!(
+                "{} TERMINAL={} i3mux --test-hooks {} attach {} --session {}",
+                env, term, HOOKS_FILE, force_flag, name
+            ),
             Session::Remote(host) => format!(
-                "{} TERMINAL={} i3mux attach --remote {} {} --session {}",
-                env, term, host, force_flag, name
+                "{} TERMINAL={} i3mux --test-hooks {} attach --remote {} {} --session {}",
+                env, term, HOOKS_FILE, host, force_flag, name
             ),
         };
 
@@ -126,12 +136,11 @@ impl<'a> I3muxRunner<'a> {
         };
 
         let cmd = format!(
-            "{} TERMINAL='{}' i3mux terminal -- /opt/i3mux-test/color-scripts/color-fill.sh {} solid",
-            env, term_exec, color_code
+            "{} TERMINAL='{}' i3mux --test-hooks {} terminal -- /opt/i3mux-test/color-scripts/color-fill.sh {} solid",
+            env, term_exec, HOOKS_FILE, color_code
         );
 
-        // Get window count before launch
-        let before = self.get_window_count()?;
+        let marked = self.hook_count("window-marked")?;
 
         // Launch terminal
         let output = self.container_mgr.exec_in_wm(&cmd)?;
@@ -143,16 +152,28 @@ impl<'a> I3muxRunner<'a> {
             );
         }
 
-        // Wait for window to appear
-        for _ in 0..30 {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            let after = self.get_window_count()?;
-            if after > before {
-                return self.get_focused_container_id();
+        // The window is focused by the time i3mux has marked it
+        self.wait_for_hook("window-marked", marked, MARK_TIMEOUT)?;
+        self.get_focused_container_id()
+    }
+
+    /// Number of `event` lines i3mux has reported (e.g. "window-marked")
+    pub fn hook_count(&self, event: &str) -> Result<usize> {
+        let cmd = format!("grep -c '^{} ' {} 2>/dev/null", event, HOOKS_FILE);
+        let output = self.container_mgr.exec_in_wm(&cmd)?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().parse().unwrap_or(0))
+    }
+
+    /// Wait until i3mux reports more than `seen` `event` lines
+    pub fn wait_for_hook(&self, event: &str, seen: usize, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if self.hook_count(event)? > seen {
+                return Ok(());
             }
+            std::thread::sleep(Duration::from_millis(50));
         }
-
-        anyhow::bail!("Terminal window did not appear within timeout")
+        anyhow::bail!("i3mux did not report {} within {:?}", event, timeout)
     }
 
     /// List sessions (kept for potential future session management tests)
@@ -201,22 +222,6 @@ impl<'a> I3muxRunner<'a> {
         Ok(())
     }
 
-    /// Get number of windows/containers in current workspace
-    fn get_window_count(&self) -> Result<usize> {
-        // Count containers with app_id (Sway) or window (i3) properties
-        let cmd = format!(
-            "{} {} -t get_tree | grep -c -E '\"(app_id|window)\"'",
-            self.env_prefix(),
-            self.wm_msg()
-        );
-        let output = self.container_mgr.exec_in_wm(&cmd)?;
-
-        let count_str = String::from_utf8_lossy(&output.stdout);
-        let count = count_str.trim().parse().unwrap_or(0);
-
-        Ok(count)
-    }
-
     /// Get focused container ID
     fn get_focused_container_id(&self) -> Result<u64> {
         // Use container ID (works for both i3 and Sway)